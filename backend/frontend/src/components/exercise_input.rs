@@ -33,6 +33,174 @@ impl std::fmt::Display for DropContent {
 }
 
 
+/// text of a json value, without the quotes of strings
+fn text(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(x) => x.clone(),
+        x => x.to_string(),
+    }
+}
+
+/// the result of a submission as readable text: the status of each test
+/// and, for the failed ones, what happened while running them.
+/// Anything else (for example an error) is shown as it is
+fn render_result(body: &str) -> String {
+    let Ok(value) = serde_json::from_str::<serde_json::Value>(body) else {
+        return body.to_string();
+    };
+    let Some(tests) = value["tests"].as_object() else {
+        return body.to_string();
+    };
+    let mut out = String::new();
+    for (name, test) in tests {
+        // unit variants are strings, the others are objects like {"Error": "..."}
+        let status = |value: &serde_json::Value| match value.as_object() {
+            Some(x) => x
+                .iter()
+                .map(|(k, v)| format!("{}: {}", k, text(v)))
+                .collect::<Vec<_>>()
+                .join(", "),
+            None => text(value),
+        };
+        let compiled = &test["compiled"];
+        let runned = status(&test["runned"]);
+        out += &format!("{}: {} ({} points)\n", name, runned, test["points_given"]);
+        // the errors are rendered as the first value of Error
+        match compiled["Error"][0].as_str() {
+            Some(errors) => out += &format!("  compilation: Error: {}\n", errors),
+            None if compiled["Built"].is_null() => {
+                out += &format!("  compilation: {}\n", status(compiled))
+            }
+            None => {}
+        }
+        let diagnostics = &test["diagnostics"];
+        if runned == "Ok" || diagnostics.is_null() {
+            continue;
+        }
+        if let Some(code) = diagnostics["exit_code"].as_i64() {
+            out += &format!("  exit code {}", code);
+        } else if let Some(signal) = diagnostics["signal"].as_i64() {
+            out += &format!("  killed by signal {}", signal);
+        } else {
+            out += "  did not end";
+        }
+        out += &format!(", after {} ms\n", diagnostics["duration_ms"]);
+        for output in ["stdout", "stderr"] {
+            let content = text(&diagnostics[output]);
+            if !content.is_empty() {
+                out += &format!("  {}:\n", output);
+                for line in content.lines() {
+                    out += &format!("    {}\n", line);
+                }
+            }
+        }
+    }
+    // every test compiles the whole submission, the warnings are the same
+    let mut warnings: Vec<String> = Vec::new();
+    for diagnostic in tests.values().flat_map(diagnostics) {
+        let rendered = text(&diagnostic["rendered"]);
+        if diagnostic["level"] == "warning" && !warnings.contains(&rendered) {
+            warnings.push(rendered);
+        }
+    }
+    if !warnings.is_empty() {
+        out += "warnings:\n";
+        for line in warnings.iter().flat_map(|x| x.lines()) {
+            out += &format!("  {}\n", line);
+        }
+    }
+    out
+}
+
+/// the messages of the compiler of a test, in the result of the compilation:
+/// {"Built": [...]} or {"Error": ["...", [...]]}
+fn diagnostics(test: &serde_json::Value) -> &[serde_json::Value] {
+    let compiled = &test["compiled"];
+    compiled["Built"]
+        .as_array()
+        .or(compiled["Error"][1].as_array())
+        .map(Vec::as_slice)
+        .unwrap_or(&[])
+}
+
+/// a part of the submission the compiler complains about
+#[derive(Clone, Debug, PartialEq)]
+struct Mark {
+    level: String,
+    message: String,
+    /// line and column, from 1
+    start: (usize, usize),
+    /// excluded
+    end: (usize, usize),
+}
+
+/// the primary spans of the messages of the compiler of all the tests
+fn marks(body: &str) -> Vec<Mark> {
+    let Ok(value) = serde_json::from_str::<serde_json::Value>(body) else {
+        return Vec::new();
+    };
+    let mut ret = Vec::new();
+    let tests = value["tests"].as_object().into_iter().flat_map(|x| x.values());
+    for diagnostic in tests.flat_map(diagnostics) {
+        let spans = diagnostic["spans"].as_array().into_iter().flatten();
+        for span in spans.filter(|x| x["primary"] == true) {
+            let number = |name: &str| span[name].as_u64().unwrap_or_default() as usize;
+            let mark = Mark {
+                level: text(&diagnostic["level"]),
+                message: text(&diagnostic["message"]),
+                start: (number("line_start"), number("column_start")),
+                end: (number("line_end"), number("column_end")),
+            };
+            if !ret.contains(&mark) {
+                ret.push(mark);
+            }
+        }
+    }
+    ret
+}
+
+/// the source in parts, each with the mark on it: the errors are shown over the warnings
+fn segments<'a>(source: &str, marks: &'a [Mark]) -> Vec<(String, Option<&'a Mark>)> {
+    let mut ret: Vec<(String, Option<&Mark>)> = Vec::new();
+    for (line, content) in source.split_inclusive('\n').enumerate() {
+        for (column, c) in content.chars().enumerate() {
+            let position = (line + 1, column + 1);
+            let mark = marks
+                .iter()
+                .filter(|x| x.start <= position && position < x.end)
+                .min_by_key(|x| x.level != "error");
+            match ret.last_mut() {
+                Some((text, last)) if *last == mark => text.push(c),
+                _ => ret.push((c.to_string(), mark)),
+            }
+        }
+    }
+    ret
+}
+
+/// the submission with the errors and the warnings of the compiler underlined
+fn annotated_source(source: &str, body: &str) -> Html {
+    let marks = marks(body);
+    if marks.is_empty() {
+        return html!();
+    }
+    let parts = segments(source, &marks).into_iter().map(|(text, mark)| match mark {
+        Some(mark) => {
+            let color = if mark.level == "error" { "red" } else { "orange" };
+            html!(
+                <span
+                    style={format!("text-decoration: underline wavy {};", color)}
+                    title={format!("{}: {}", mark.level, mark.message)}
+                >
+                    {text}
+                </span>
+            )
+        }
+        None => html!(<>{text}</>),
+    });
+    html!(<pre>{for parts}</pre>)
+}
+
 #[function_component]
 pub fn RustInput() -> Html {
     let node = use_node_ref();
@@ -74,13 +242,20 @@ pub fn RustInput() -> Html {
             let mut sub = (*submission).clone();
             submission.set(None);
             let Some(s) = sub.take() else{return Err("".to_string())};
+            let source = s
+                .iter()
+                .find(|(name, _)| name == "source")
+                .map(|(_, source)| source.clone())
+                .unwrap_or_default();
             let local = web_sys::window().unwrap().location().origin().unwrap();
             let local = Url::parse(&local).unwrap();
 
             let client = reqwest::Client::new();
             let request = client.post(local.join("/submit").unwrap()).form(&s).build().unwrap();
             let t = client.execute(request).await.map_err(|x| x.to_string())?;
-            let result = t.text().await.map_err(|x| x.to_string())?;
+            let body = t.text().await.map_err(|x| x.to_string())?;
+            let result = render_result(&body);
+            let annotated = annotated_source(&source, &body);
             backdrop.as_ref().unwrap().open(html!({
                 html!(
                     <Bullseye plain=true>
@@ -93,6 +268,7 @@ pub fn RustInput() -> Html {
                                     {result }
                                 </CodeBlockCode>
                             </CodeBlock>
+                            {annotated}
                         </Modal>
                     </Bullseye>
                 )
@@ -0,0 +1,119 @@
+#![allow(clippy::blocks_in_conditions)]
+use chrono::{DateTime, Utc};
+use orchestrator::{
+    memory::{Admin, AuditAction, AuditEvent, AuditFilter, ExecutorRecord},
+    orchestrator::ReferenceWithoutState,
+    prelude::serde_json,
+};
+use rocket::{
+    get, http::Status, post, routes, serde::json::Json, serde::Deserialize, Route, State,
+};
+
+use crate::{
+    auth::{audit, User},
+    error::ApiError,
+};
+
+/// parse an optional RFC 3339 instant from the query
+fn parse_time(time: Option<&str>) -> Result<Option<DateTime<Utc>>, Status> {
+    time.map(|x| {
+        DateTime::parse_from_rfc3339(x)
+            .map(|x| x.to_utc())
+            .map_err(|_| Status::BadRequest)
+    })
+    .transpose()
+}
+
+#[get("/admin/audit?<actor>&<action>&<from>&<to>")]
+/// query the audit log. Every parameter is optional:
+/// action is the name of an AuditAction (for example LoginFailure), from and to are RFC 3339 instants.
+///
+/// The query is itself recorded in the log
+async fn audit_log(
+    actor: Option<String>,
+    action: Option<&str>,
+    from: Option<&str>,
+    to: Option<&str>,
+    admin: User<Admin>,
+    reference: &State<Box<dyn ReferenceWithoutState>>,
+) -> Result<Json<Vec<AuditEvent>>, ApiError> {
+    let action: Option<AuditAction> = action
+        .map(|x| serde_json::from_value(serde_json::Value::String(x.to_string())))
+        .transpose()
+        .map_err(|_| Status::BadRequest)?;
+    let filter = AuditFilter {
+        actor,
+        action,
+        from: parse_time(from)?,
+        to: parse_time(to)?,
+    };
+    let events = reference
+        .memory()
+        .get_audit_log(&admin.inner, &filter)
+        .await?;
+    audit(
+        reference.as_ref(),
+        Some(&admin.inner.username),
+        AuditAction::AdminAction,
+        "read audit log",
+    )
+    .await;
+    Ok(Json(events))
+}
+
+#[get("/admin/executors")]
+/// list the enabled executors: input, output and data, as stored in memory
+async fn list_executors(
+    _admin: User<Admin>,
+    reference: &State<Box<dyn ReferenceWithoutState>>,
+) -> Result<Json<Vec<ExecutorRecord>>, ApiError> {
+    let executors = reference.memory().export_executors().await?;
+    Ok(Json(executors))
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+/// which executor should be changed, and its new data
+struct ExecutorChange {
+    /// serialized variant of the input state, as listed by /admin/executors
+    input: String,
+    /// new data, only used when replacing it (null if missing)
+    #[serde(default)]
+    data: serde_json::Value,
+}
+
+#[post("/admin/executors/disable", data = "<change>")]
+/// disable the executor triggered by the given input, until it is enabled again
+async fn disable_executor(
+    change: Json<ExecutorChange>,
+    admin: User<Admin>,
+    reference: &State<Box<dyn ReferenceWithoutState>>,
+) -> Result<(), ApiError> {
+    Ok(reference
+        .disable_executor_by_variant(&change.input, Some(&admin.inner.username))
+        .await?)
+}
+
+#[post("/admin/executors/data", data = "<change>")]
+/// replace the data of the executor triggered by the given input.
+/// The data must be accepted by that executor
+async fn replace_executor_data(
+    change: Json<ExecutorChange>,
+    admin: User<Admin>,
+    reference: &State<Box<dyn ReferenceWithoutState>>,
+) -> Result<(), ApiError> {
+    let data = change.data.to_string();
+    Ok(reference
+        .replace_executor_data_by_variant(&change.input, data, Some(&admin.inner.username))
+        .await?)
+}
+
+/// function used to route all admin traffic
+pub fn routes() -> Vec<Route> {
+    routes![
+        audit_log,
+        list_executors,
+        disable_executor,
+        replace_executor_data
+    ]
+}
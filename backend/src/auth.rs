@@ -1,9 +1,11 @@
 #![allow(clippy::blocks_in_conditions)]
 use orchestrator::{
-    memory::{Admin, Authenticated, UserState},
+    memory::{Admin, AuditAction, Authenticated, MemoryError, UserState},
     orchestrator::ReferenceWithoutState,
 };
-use std::error::Error as StdError;
+use rocket::tokio::time::Duration;
+
+use crate::{error::ApiError, notifier::ResetNotifier};
 
 use rocket::{
     form::Form,
@@ -22,8 +24,17 @@ pub enum Error {
     TokenNotFound,
     #[error("Join Error {0}")]
     TokioJoin(#[from] JoinError),
-    #[error("std error")]
-    Std(#[from] Box<dyn StdError>),
+    #[error("memory error: {0}")]
+    Memory(#[from] MemoryError),
+}
+
+/// status of a failed authentication: the memory could answer that the token is not valid,
+/// or it could be not reachable at all
+fn guard_status(err: &MemoryError) -> Status {
+    match err {
+        MemoryError::Backend(_) => Status::InternalServerError,
+        _ => Status::Forbidden,
+    }
 }
 
 ///new type for wrapping Inner User and implementing FromRequest of Rocket
@@ -48,7 +59,7 @@ impl<'r> FromRequest<'r> for User<Authenticated> {
         };
         match reference.memory().get_authenticate(token.value()).await {
             Ok(user) => Outcome::Success(User { inner: user }),
-            Err(err) => Outcome::Error((Status::Forbidden, err.into())),
+            Err(err) => Outcome::Error((guard_status(&err), err.into())),
         }
     }
 }
@@ -68,13 +79,27 @@ impl<'r> FromRequest<'r> for User<Admin> {
             return Outcome::Error((Status::NotAcceptable, Error::TokenNotFound));
         };
 
-        match reference.memory().get_admin(&token.to_string()).await {
+        match reference.memory().get_admin(token.value()).await {
             Ok(user) => Outcome::Success(User { inner: user }),
-            Err(err) => Outcome::Error((Status::Forbidden, err.into())),
+            Err(err) => Outcome::Error((guard_status(&err), err.into())),
         }
     }
 }
 
+/// append an event to the audit log.
+///
+/// A failure is only printed: an unavailable audit log should not lock out every user
+pub(crate) async fn audit(
+    reference: &dyn ReferenceWithoutState,
+    actor: Option<&str>,
+    action: AuditAction,
+    details: &str,
+) {
+    if let Err(err) = reference.memory().audit(actor, action, details).await {
+        eprintln!("could not write audit event {:?}: {}", action, err);
+    }
+}
+
 #[derive(FromForm)]
 /// data required for Login authentication.
 ///
@@ -87,18 +112,38 @@ struct LoginInfo {
 #[post("/login", data = "<info>")]
 /// login request get's routed here.
 ///
-/// It checks if the credentials are ok, and if so authenticate the user modifying his tokens
+/// It checks if the credentials are ok, and if so authenticate the user modifying their tokens
 async fn login(
     info: Form<LoginInfo>,
     reference: &State<Box<dyn ReferenceWithoutState>>,
     jar: &CookieJar<'_>,
-) -> Result<(), Status> {
+) -> Result<(), ApiError> {
     //TODO sanitize data
-    let user = reference
+    let user = match reference
         .memory()
         .login(&info.username, &info.password)
         .await
-        .map_err(|_| Status::Unauthorized)?;
+    {
+        Ok(user) => user,
+        Err(err @ MemoryError::Unauthorized) => {
+            audit(
+                reference.as_ref(),
+                Some(&info.username),
+                AuditAction::LoginFailure,
+                "password",
+            )
+            .await;
+            return Err(err.into());
+        }
+        Err(err) => return Err(err.into()),
+    };
+    audit(
+        reference.as_ref(),
+        Some(&user.username),
+        AuditAction::LoginSuccess,
+        "password",
+    )
+    .await;
 
     let token = user.logged_in_token.as_ref().unwrap().clone();
     jar.add(("auth_token", token));
@@ -112,17 +157,125 @@ async fn login(
 async fn register(
     info: Form<LoginInfo>,
     reference: &State<Box<dyn ReferenceWithoutState>>,
-) -> Result<(), Status> {
+) -> Result<(), ApiError> {
     // TODO mail authorization
-    let _user = reference
+    let user = reference
         .memory()
         .register(&info.username, &info.password)
+        .await?;
+    audit(
+        reference.as_ref(),
+        Some(&user.username),
+        AuditAction::Register,
+        "password",
+    )
+    .await;
+    Ok(())
+}
+
+/// Authentication settings, managed by rocket
+pub struct AuthSettings {
+    /// how long a password reset token remains valid
+    pub reset_validity: Duration,
+}
+
+#[derive(FromForm)]
+/// data required to change the password
+struct ChangePasswordInfo {
+    old_password: String,
+    new_password: String,
+}
+
+#[post("/change_password", data = "<info>")]
+/// change the password of the logged user, the old one is required
+async fn change_password(
+    info: Form<ChangePasswordInfo>,
+    user: User<Authenticated>,
+    reference: &State<Box<dyn ReferenceWithoutState>>,
+) -> Result<(), ApiError> {
+    let username = user.inner.username.clone();
+    reference
+        .memory()
+        .change_password(user.inner, &info.old_password, &info.new_password)
+        .await?;
+    audit(
+        reference.as_ref(),
+        Some(&username),
+        AuditAction::PasswordChange,
+        "change",
+    )
+    .await;
+    Ok(())
+}
+
+#[derive(FromForm)]
+/// data required to request a password reset
+struct ResetRequestInfo {
+    username: String,
+}
+
+#[post("/request_password_reset", data = "<info>")]
+/// generate a reset token and deliver it throught the notifier.
+///
+/// It always succeed, so it can't be used to find out which usernames are registered
+async fn request_password_reset(
+    info: Form<ResetRequestInfo>,
+    reference: &State<Box<dyn ReferenceWithoutState>>,
+    notifier: &State<Box<dyn ResetNotifier>>,
+    settings: &State<AuthSettings>,
+) -> Result<(), ApiError> {
+    let validity = chrono::Duration::from_std(settings.reset_validity)
+        .map_err(|_| Status::InternalServerError)?;
+    let reset = match reference
+        .memory()
+        .create_password_reset(&info.username, validity)
+        .await
+    {
+        Ok(reset) => reset,
+        Err(MemoryError::NotFound(_)) => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+    notifier
+        .notify(&reset)
         .await
-        .map_err(|_| Status::UnprocessableEntity)?;
+        .map_err(|_| Status::InternalServerError)?;
+    Ok(())
+}
+
+#[derive(FromForm)]
+/// data required to reset the password
+struct ResetInfo {
+    token: String,
+    new_password: String,
+}
+
+#[post("/reset_password", data = "<info>")]
+/// consume the reset token and set the new password
+async fn reset_password(
+    info: Form<ResetInfo>,
+    reference: &State<Box<dyn ReferenceWithoutState>>,
+) -> Result<(), ApiError> {
+    reference
+        .memory()
+        .reset_password(&info.token, &info.new_password)
+        .await?;
+    audit(
+        reference.as_ref(),
+        None,
+        AuditAction::PasswordChange,
+        "reset",
+    )
+    .await;
     Ok(())
 }
 
 /// function used to route all authentication traffic
 pub fn routes() -> Vec<route::Route> {
-    routes![login, register]
+    routes![
+        login,
+        register,
+        change_password,
+        request_password_reset,
+        reset_password
+    ]
 }
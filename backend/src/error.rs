@@ -0,0 +1,97 @@
+use orchestrator::{
+    executor::Error as ExecutorError, memory::MemoryError, orchestrator::DynError,
+    prelude::serde_json,
+};
+use rocket::{
+    http::Status,
+    response::{self, Responder},
+    serde::json::Json,
+    Request,
+};
+
+#[derive(Debug)]
+/// Error of a route: it is returned as a status code with a JSON body
+/// `{"error": kind, "message": description}`
+pub struct ApiError {
+    status: Status,
+    kind: &'static str,
+    message: String,
+}
+
+impl ApiError {
+    /// an error with the given status, kind and message
+    pub fn new(status: Status, kind: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+/// each kind of memory error has its own status.
+/// Backend failures are only printed, their details are not sent to the client
+impl From<MemoryError> for ApiError {
+    fn from(value: MemoryError) -> Self {
+        match value {
+            MemoryError::NotFound(_) => Self::new(Status::NotFound, "not_found", value.to_string()),
+            MemoryError::AlreadyExists(_) => {
+                Self::new(Status::Conflict, "already_exists", value.to_string())
+            }
+            MemoryError::Unauthorized => {
+                Self::new(Status::Unauthorized, "unauthorized", value.to_string())
+            }
+            MemoryError::Conflict(_) => Self::new(Status::Conflict, "conflict", value.to_string()),
+            MemoryError::Backend(_) => {
+                eprintln!("memory failure: {}", value);
+                Self::new(
+                    Status::InternalServerError,
+                    "backend",
+                    "the memory is not available",
+                )
+            }
+        }
+    }
+}
+
+/// errors of the orchestrator: the memory ones keep their status
+impl From<DynError> for ApiError {
+    fn from(value: DynError) -> Self {
+        let value = match value.downcast::<MemoryError>() {
+            Ok(x) => return (*x).into(),
+            Err(x) => x,
+        };
+        match value.downcast::<ExecutorError>() {
+            Ok(x) => match *x {
+                ExecutorError::Memory(x) => x.into(),
+                ExecutorError::NotEnabled | ExecutorError::UnknownState(_) => {
+                    Self::new(Status::NotFound, "not_found", x.to_string())
+                }
+                ExecutorError::Json(_) => {
+                    Self::new(Status::UnprocessableEntity, "invalid_data", x.to_string())
+                }
+                x => Self::new(Status::InternalServerError, "execution", x.to_string()),
+            },
+            Err(x) => Self::new(Status::InternalServerError, "execution", x.to_string()),
+        }
+    }
+}
+
+/// plain statuses (for example a forbidden request) get a body too
+impl From<Status> for ApiError {
+    fn from(value: Status) -> Self {
+        Self::new(value, "request", value.reason_lossy())
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let body = serde_json::json!({
+            "error": self.kind,
+            "message": self.message,
+        });
+        let mut response = Json(body).respond_to(request)?;
+        response.set_status(self.status);
+        Ok(response)
+    }
+}
@@ -1,13 +1,25 @@
 //! In this module there is the description of the WebServer
 use orchestrator::prelude::*;
 
-use rocket::fs::{FileServer, NamedFile};
+use rocket::fs::{FileServer, NamedFile, Options};
 use rocket::response::status::NotFound;
 use rocket::tokio::sync::Notify;
+use rocket::tokio::time::Duration;
 use rocket::*;
 
+use auth::AuthSettings;
+use notifier::{LogNotifier, ResetNotifier};
+use oidc::{OidcClient, OidcConfig};
+
+mod admin;
 mod auth;
+mod error;
+/// How password reset tokens get delivered
+pub mod notifier;
+/// OpenID Connect login
+pub mod oidc;
 mod problems;
+mod submissions;
 #[cfg(test)]
 mod test;
 /// Return the index file as a Rocket NamedFile
@@ -28,21 +40,86 @@ async fn fallback() -> Result<NamedFile, NotFound<String>> {
     get_index().await
 }
 
-/// start the server
-pub async fn run_server<S: ExecutorGlobalState>(o: OrchestratorReference<S>) {
+/// build the rocket instance, with all the routes mounted
+pub fn build_rocket<S: ExecutorGlobalState>(
+    o: OrchestratorReference<S>,
+    server: WebServer,
+) -> Rocket<Build> {
     let state: Box<dyn ReferenceWithoutState> = Box::new(o);
-    let _ = rocket::build()
+    let mut rocket = rocket::build()
         .manage(state)
+        .manage(server.notifier)
+        .manage(AuthSettings {
+            reset_validity: server.reset_validity,
+        })
         .mount("/", routes![index, fallback])
-        .mount("/", auth::routes())
         .mount("/", problems::routes())
-        .mount("/static", FileServer::from("./frontend/dist"))
-        .launch()
-        .await;
+        .mount("/", admin::routes())
+        .mount("/", submissions::routes())
+        .mount(
+            "/static",
+            FileServer::new("./frontend/dist", Options::Index | Options::Missing),
+        );
+    if server.password_login {
+        rocket = rocket.mount("/", auth::routes());
+    }
+    if let Some(config) = server.oidc {
+        rocket = rocket
+            .manage(OidcClient::new(config))
+            .mount("/", oidc::routes());
+    }
+    rocket
+}
+
+/// start the server
+pub async fn run_server<S: ExecutorGlobalState>(o: OrchestratorReference<S>, server: WebServer) {
+    let _ = build_rocket(o, server).launch().await;
 }
 
 /// The WebServer Plugin, it serves a rocket webserver.
-pub struct WebServer;
+///
+/// It follows the build pattern
+pub struct WebServer {
+    notifier: Box<dyn ResetNotifier>,
+    reset_validity: Duration,
+    password_login: bool,
+    oidc: Option<OidcConfig>,
+}
+
+impl Default for WebServer {
+    fn default() -> Self {
+        Self {
+            notifier: Box::new(LogNotifier),
+            reset_validity: Duration::from_secs(30 * 60),
+            password_login: true,
+            oidc: None,
+        }
+    }
+}
+
+impl WebServer {
+    /// set how password reset tokens get delivered (by default they are printed on stdout)
+    pub fn set_notifier(mut self, notifier: impl ResetNotifier + 'static) -> Self {
+        self.notifier = Box::new(notifier);
+        self
+    }
+    /// enable the OpenID Connect login (routes /oidc/login and /oidc/callback)
+    pub fn set_oidc(mut self, config: OidcConfig) -> Self {
+        self.oidc = Some(config);
+        self
+    }
+    /// enable or disable the username/password routes (login, register, password change and reset).
+    /// By default they are enabled
+    pub fn set_password_login(mut self, enabled: bool) -> Self {
+        self.password_login = enabled;
+        self
+    }
+    /// set how long a password reset token remains valid (by default 30 minutes)
+    pub fn set_reset_validity(mut self, validity: Duration) -> Self {
+        self.reset_validity = validity;
+        self
+    }
+}
 
 impl<S: ExecutorGlobalState> Plugin<S> for WebServer {
     fn name(&self) -> &str {
@@ -55,7 +132,7 @@ impl<S: ExecutorGlobalState> Plugin<S> for WebServer {
         "
     }
     async fn run(self, o: OrchestratorReference<S>, should_stop: std::sync::Arc<Notify>) {
-        let q = tokio::spawn(async { run_server(o).await });
+        let q = tokio::spawn(async { run_server(o, self).await });
         let _ = q.await;
         should_stop.notify_one();
     }
@@ -0,0 +1,63 @@
+//! How password reset tokens reach the users.
+//!
+//! The backend doesn't know how to contact a user (mail, university portal...), so the delivery is pluggable:
+//! implement ResetNotifier and pass it to the WebServer.
+use std::{error::Error, path::PathBuf};
+
+use orchestrator::memory::PasswordReset;
+use rocket::{
+    async_trait,
+    tokio::{fs::OpenOptions, io::AsyncWriteExt},
+};
+
+#[async_trait]
+/// Delivers a password reset token to its user
+pub trait ResetNotifier: Send + Sync {
+    /// deliver the reset, the token must not be shown to anyone else
+    async fn notify(&self, reset: &PasswordReset) -> Result<(), Box<dyn Error + Send + Sync>>;
+}
+
+/// Prints resets on the standard output. Useful only for local testing
+pub struct LogNotifier;
+
+#[async_trait]
+impl ResetNotifier for LogNotifier {
+    async fn notify(&self, reset: &PasswordReset) -> Result<(), Box<dyn Error + Send + Sync>> {
+        println!(
+            "password reset for {}: token {} (expires at {})",
+            reset.username, reset.token, reset.expires_at
+        );
+        Ok(())
+    }
+}
+
+/// Appends each reset as a line (username token expiration) to a file. Useful only for local testing
+pub struct FileNotifier {
+    path: PathBuf,
+}
+
+impl FileNotifier {
+    /// create a notifier that appends to the given file (it is created if missing)
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[async_trait]
+impl ResetNotifier for FileNotifier {
+    async fn notify(&self, reset: &PasswordReset) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        let line = format!(
+            "{} {} {}\n",
+            reset.username,
+            reset.token,
+            reset.expires_at.to_rfc3339()
+        );
+        file.write_all(line.as_bytes()).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,295 @@
+//! OpenID Connect login, following the authorization code flow.
+//!
+//! The user is redirected to the provider (`/oidc/login`), that sends them back to `/oidc/callback` with a code.
+//! The code is exchanged (server to server) for an access token, which is then used to read the identity
+//! (subject, email, name) from the userinfo endpoint. Because the identity is read directly from the provider,
+//! the id_token doesn't need to be verified.
+//!
+//! The identity is mapped to a User by the memory (see StatelessMemory::login_external).
+use std::collections::HashMap;
+
+use orchestrator::{
+    default_memory::new_token,
+    memory::{AuditAction, ExternalIdentity, MemoryError},
+    orchestrator::ReferenceWithoutState,
+};
+use reqwest::Url;
+use rocket::{
+    get,
+    http::{Cookie, CookieJar, SameSite, Status},
+    response::Redirect,
+    routes,
+    serde::Deserialize,
+    tokio::{
+        sync::{Mutex, OnceCell},
+        time::{Duration, Instant},
+    },
+    Route, State,
+};
+
+use crate::auth::audit;
+
+/// how long a login can be pending on the provider
+const STATE_VALIDITY: Duration = Duration::from_secs(10 * 60);
+
+#[derive(thiserror::Error, Debug)]
+/// errors that can happen while talking with the provider
+pub enum Error {
+    /// http error
+    #[error("Http Error {0}")]
+    Http(#[from] reqwest::Error),
+    /// an url declared by the provider is not valid
+    #[error("invalid url: {0}")]
+    InvalidUrl(String),
+    /// the provider declared another issuer
+    #[error("issuer mismatch: expected {expected}, found {found}")]
+    IssuerMismatch {
+        /// configured issuer
+        expected: String,
+        /// issuer found in the provider metadata
+        found: String,
+    },
+}
+
+#[derive(Debug, Clone)]
+/// Configuration of the OpenID Connect provider
+pub struct OidcConfig {
+    /// issuer url, the metadata are discovered from `<issuer>/.well-known/openid-configuration`
+    pub issuer: String,
+    /// client id, registered on the provider
+    pub client_id: String,
+    /// client secret, registered on the provider
+    pub client_secret: String,
+    /// where the provider sends back the user, it must point to `/oidc/callback`
+    pub redirect_uri: String,
+    /// requested scopes
+    pub scopes: Vec<String>,
+}
+
+impl OidcConfig {
+    /// configuration requesting the standard scopes (openid, email and profile)
+    pub fn new(
+        issuer: impl Into<String>,
+        client_id: impl Into<String>,
+        client_secret: impl Into<String>,
+        redirect_uri: impl Into<String>,
+    ) -> Self {
+        Self {
+            issuer: issuer.into(),
+            client_id: client_id.into(),
+            client_secret: client_secret.into(),
+            redirect_uri: redirect_uri.into(),
+            scopes: vec![
+                "openid".to_string(),
+                "email".to_string(),
+                "profile".to_string(),
+            ],
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+/// The part of the provider metadata we need
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: String,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+/// answer of the token endpoint
+struct TokenResponse {
+    access_token: String,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+/// answer of the userinfo endpoint
+struct UserInfo {
+    sub: String,
+    email: Option<String>,
+    name: Option<String>,
+}
+
+/// Client of the provider, managed by rocket
+pub(crate) struct OidcClient {
+    config: OidcConfig,
+    http: reqwest::Client,
+    /// discovered lazily, on the first login
+    metadata: OnceCell<ProviderMetadata>,
+    /// state of logins pending on the provider, and when they were started
+    pending: Mutex<HashMap<String, Instant>>,
+}
+
+impl OidcClient {
+    pub(crate) fn new(config: OidcConfig) -> Self {
+        Self {
+            config,
+            http: reqwest::Client::new(),
+            metadata: OnceCell::new(),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// get the provider metadata, discovering them if needed
+    async fn metadata(&self) -> Result<&ProviderMetadata, Error> {
+        self.metadata
+            .get_or_try_init(|| async {
+                let issuer = self.config.issuer.trim_end_matches('/');
+                let metadata: ProviderMetadata = self
+                    .http
+                    .get(format!("{issuer}/.well-known/openid-configuration"))
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+                if metadata.issuer.trim_end_matches('/') != issuer {
+                    return Err(Error::IssuerMismatch {
+                        expected: issuer.to_string(),
+                        found: metadata.issuer,
+                    });
+                }
+                Ok(metadata)
+            })
+            .await
+    }
+
+    /// start a new login: returns the url of the provider and the state that identifies the login
+    async fn start_login(&self) -> Result<(Url, String), Error> {
+        let metadata = self.metadata().await?;
+        let state = new_token();
+        let url = Url::parse_with_params(
+            &metadata.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", &self.config.client_id),
+                ("redirect_uri", &self.config.redirect_uri),
+                ("scope", &self.config.scopes.join(" ")),
+                ("state", &state),
+            ],
+        )
+        .map_err(|_| Error::InvalidUrl(metadata.authorization_endpoint.clone()))?;
+        let mut pending = self.pending.lock().await;
+        pending.retain(|_, started| started.elapsed() < STATE_VALIDITY);
+        pending.insert(state.clone(), Instant::now());
+        Ok((url, state))
+    }
+
+    /// consume a pending state, returns false if it is unknown or expired
+    async fn finish_login(&self, state: &str) -> bool {
+        self.pending
+            .lock()
+            .await
+            .remove(state)
+            .is_some_and(|started| started.elapsed() < STATE_VALIDITY)
+    }
+
+    /// exchange the code for an access token, and use it to read the identity
+    async fn identity(&self, code: &str) -> Result<ExternalIdentity, Error> {
+        let metadata = self.metadata().await?;
+        let token: TokenResponse = self
+            .http
+            .post(&metadata.token_endpoint)
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", &self.config.redirect_uri),
+                ("client_id", &self.config.client_id),
+                ("client_secret", &self.config.client_secret),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let info: UserInfo = self
+            .http
+            .get(&metadata.userinfo_endpoint)
+            .bearer_auth(token.access_token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(ExternalIdentity {
+            issuer: metadata.issuer.clone(),
+            subject: info.sub,
+            email: info.email,
+            name: info.name,
+        })
+    }
+}
+
+#[get("/oidc/login")]
+/// redirects the user to the provider.
+///
+/// The state is also saved in a cookie, so the login can be completed only by the same browser
+async fn login(client: &State<OidcClient>, jar: &CookieJar<'_>) -> Result<Redirect, Status> {
+    let (url, state) = client.start_login().await.map_err(|_| Status::BadGateway)?;
+    jar.add(
+        Cookie::build(("oidc_state", state))
+            .http_only(true)
+            .same_site(SameSite::Lax),
+    );
+    Ok(Redirect::to(url.to_string()))
+}
+
+#[get("/oidc/callback?<code>&<state>")]
+/// the provider sends back the user here.
+///
+/// If the login is valid the user gets their auth_token (as with a normal login), and is redirected to the index
+async fn callback(
+    code: &str,
+    state: &str,
+    client: &State<OidcClient>,
+    reference: &State<Box<dyn ReferenceWithoutState>>,
+    jar: &CookieJar<'_>,
+) -> Result<Redirect, Status> {
+    let same_browser = jar.get("oidc_state").is_some_and(|x| x.value() == state);
+    if !same_browser || !client.finish_login(state).await {
+        return Err(Status::Unauthorized);
+    }
+    // removed only now, a forged callback can't cancel the login in progress
+    jar.remove("oidc_state");
+    let identity = client
+        .identity(code)
+        .await
+        .map_err(|_| Status::Unauthorized)?;
+    let details = format!("{} {}", identity.issuer, identity.subject);
+    let user = match reference.memory().login_external(&identity).await {
+        Ok(user) => user,
+        Err(MemoryError::Backend(err)) => {
+            eprintln!("memory failure: {}", err);
+            return Err(Status::InternalServerError);
+        }
+        Err(_) => {
+            audit(
+                reference.as_ref(),
+                None,
+                AuditAction::LoginFailure,
+                &details,
+            )
+            .await;
+            return Err(Status::Unauthorized);
+        }
+    };
+    audit(
+        reference.as_ref(),
+        Some(&user.username),
+        AuditAction::LoginSuccess,
+        &details,
+    )
+    .await;
+    let token = user.logged_in_token.ok_or(Status::InternalServerError)?;
+    jar.add(("auth_token", token));
+    Ok(Redirect::to("/"))
+}
+
+/// function used to route all OpenID Connect traffic
+pub fn routes() -> Vec<Route> {
+    routes![login, callback]
+}
@@ -1,12 +1,12 @@
 // added to make clippy happy, TODO remove it won't be needed anymore
 #![allow(clippy::blocks_in_conditions)]
-use orchestrator::memory::Authenticated;
+use orchestrator::memory::{Authenticated, MemoryError};
 use orchestrator::orchestrator::ReferenceWithoutState;
 use orchestrator::prelude::serde_json;
 use rocket::{form::Form, post, routes, FromForm};
 use rocket::{get, serde::json::Json, Route, State};
 
-use crate::auth::User;
+use crate::{auth::User, error::ApiError};
 
 #[derive(FromForm)]
 
@@ -21,14 +21,9 @@ struct SubmitInfo {
 #[get("/list_problems")]
 async fn list_problems(
     reference: &State<Box<dyn ReferenceWithoutState>>,
-) -> Option<Json<Vec<String>>> {
-    let req = reference.memory().list_exercise_names().await;
-    let req = match req {
-        Ok(x) => x,
-        Err(e) => panic!("{}", e),
-    };
-
-    Some(Json(req))
+) -> Result<Json<Vec<String>>, ApiError> {
+    let req = reference.memory().list_exercise_names().await?;
+    Ok(Json(req))
 }
 
 #[post("/submit", data = "<submission>")]
@@ -36,18 +31,15 @@ async fn submit(
     reference: &State<Box<dyn ReferenceWithoutState>>,
     user: User<Authenticated>,
     submission: Form<SubmitInfo>,
-) -> Result<String, String> {
+) -> Result<String, ApiError> {
     let res = reference
         .process_exercise(
             submission.problem.clone(),
             submission.source.clone(),
             user.inner,
         )
-        .await;
-    match res {
-        Ok(x) => Ok(serde_json::to_string(&x).map_err(|x| x.to_string())?),
-        Err(x) => Err(x.to_string()),
-    }
+        .await?;
+    serde_json::to_string(&res).map_err(|x| MemoryError::from(x).into())
 }
 
 /// function used to route all problem- related traffic
@@ -0,0 +1,106 @@
+#![allow(clippy::blocks_in_conditions)]
+use orchestrator::{
+    memory::{Authenticated, MemoryError, Submission, SubmissionFilter},
+    orchestrator::ReferenceWithoutState,
+};
+use rocket::{get, http::Status, routes, serde::json::Json, Route, State};
+
+use crate::{auth::User, error::ApiError};
+
+/// page size used when the limit is not specified
+const DEFAULT_LIMIT: usize = 20;
+/// max page size
+const MAX_LIMIT: usize = 100;
+
+/// error returned when the user has no submission for the exercise
+fn no_submission(exercise: &str) -> ApiError {
+    MemoryError::NotFound(format!("submission for {}", exercise)).into()
+}
+
+/// users can only read their own submissions, admins can read everything
+fn target_user(user: &User<Authenticated>, requested: Option<i64>) -> Result<i64, Status> {
+    match requested {
+        Some(id) if id != user.inner.user_id && !user.inner.is_admin => Err(Status::Forbidden),
+        Some(id) => Ok(id),
+        None => Ok(user.inner.user_id),
+    }
+}
+
+#[get("/submissions?<exercise>&<user_id>&<offset>&<limit>")]
+/// list the submissions (with their results) of the logged user, ordered by id.
+///
+/// Admins can list the submissions of another user
+async fn list_submissions(
+    exercise: Option<String>,
+    user_id: Option<i64>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+    user: User<Authenticated>,
+    reference: &State<Box<dyn ReferenceWithoutState>>,
+) -> Result<Json<Vec<Submission>>, ApiError> {
+    let filter = SubmissionFilter {
+        user_id: Some(target_user(&user, user_id)?),
+        exercise_name: exercise,
+        offset: offset.unwrap_or(0),
+        limit: Some(limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT)),
+    };
+    let submissions = reference.memory().list_submissions(&filter).await?;
+    Ok(Json(submissions))
+}
+
+#[get("/submissions/<submission_id>")]
+/// get a submission with its source and result
+async fn get_submission(
+    submission_id: i64,
+    user: User<Authenticated>,
+    reference: &State<Box<dyn ReferenceWithoutState>>,
+) -> Result<Json<Submission>, ApiError> {
+    let submission = reference.memory().get_submission(submission_id).await?;
+    // other users' submissions are not revealed
+    if submission.user_id != user.inner.user_id && !user.inner.is_admin {
+        return Err(MemoryError::NotFound(format!("submission {}", submission_id)).into());
+    }
+    Ok(Json(submission))
+}
+
+#[get("/submissions/latest/<exercise>?<user_id>")]
+/// the last submission of the logged user for the exercise
+async fn latest_submission(
+    exercise: &str,
+    user_id: Option<i64>,
+    user: User<Authenticated>,
+    reference: &State<Box<dyn ReferenceWithoutState>>,
+) -> Result<Json<Submission>, ApiError> {
+    let user_id = target_user(&user, user_id)?;
+    let submission = reference
+        .memory()
+        .get_latest_submission(user_id, exercise)
+        .await?;
+    submission.map(Json).ok_or_else(|| no_submission(exercise))
+}
+
+#[get("/submissions/best/<exercise>?<user_id>")]
+/// the executed submission of the logged user with the most points
+async fn best_submission(
+    exercise: &str,
+    user_id: Option<i64>,
+    user: User<Authenticated>,
+    reference: &State<Box<dyn ReferenceWithoutState>>,
+) -> Result<Json<Submission>, ApiError> {
+    let user_id = target_user(&user, user_id)?;
+    let submission = reference
+        .memory()
+        .get_best_submission(user_id, exercise)
+        .await?;
+    submission.map(Json).ok_or_else(|| no_submission(exercise))
+}
+
+/// function used to route all submission history traffic
+pub fn routes() -> Vec<Route> {
+    routes![
+        list_submissions,
+        get_submission,
+        latest_submission,
+        best_submission
+    ]
+}
@@ -1,16 +1,21 @@
 use orchestrator::{default_memory::DefaultMemory, prelude::*, GenerateState};
-use reqwest::Client;
+use reqwest::Client as ReqwestClient;
 use rocket::async_test;
-use std::error::Error;
+use rocket::http::{ContentType, Status};
+use rocket::local::asynchronous::{Client, LocalResponse};
+use rocket::tokio::sync::Mutex;
+use std::{error::Error, sync::Arc};
 
-use crate::WebServer;
+use crate::notifier::ResetNotifier;
+use crate::oidc::OidcConfig;
+use crate::{build_rocket, WebServer};
 struct BackendTest {
     url: String,
-    client: Client,
+    client: ReqwestClient,
 }
 impl BackendTest {
     fn new(url: &str) -> Self {
-        let client = Client::builder().cookie_store(true).build().unwrap();
+        let client = ReqwestClient::builder().cookie_store(true).build().unwrap();
         Self {
             url: url.to_string(),
             client,
@@ -24,7 +29,7 @@ impl TestInterface for BackendTest {
         let params = [("username", username), ("password", password)];
         let res = self
             .client
-            .post(&format!("{}/register", self.url))
+            .post(format!("{}/register", self.url))
             .form(&params)
             .send()
             .await
@@ -36,7 +41,7 @@ impl TestInterface for BackendTest {
         let params = [("username", username), ("password", password)];
         let res = self
             .client
-            .post(&format!("{}/login", self.url))
+            .post(format!("{}/login", self.url))
             .form(&params)
             .send()
             .await
@@ -54,7 +59,7 @@ impl TestInterface for BackendTest {
         let params = [("problem", problem), ("source", source)];
         let res = self
             .client
-            .post(&format!("{}/submit", self.url))
+            .post(format!("{}/submit", self.url))
             .form(&params)
             .send()
             .await
@@ -81,6 +86,521 @@ async fn test_backend() {
     let test = BackendTest::new("http://localhost:8000");
     let def = DefaultTest::new(test);
     o.add_plugin(def).await.unwrap();
-    o.add_plugin(WebServer).await.unwrap();
+    o.add_plugin(WebServer::default()).await.unwrap();
     let _ = o.run().await;
 }
+
+/// notifier that keeps the resets, so the test can read the tokens
+struct SavedNotifier(Arc<Mutex<Vec<PasswordReset>>>);
+
+#[async_trait]
+impl ResetNotifier for SavedNotifier {
+    async fn notify(&self, reset: &PasswordReset) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.0.lock().await.push(reset.clone());
+        Ok(())
+    }
+}
+
+#[async_test]
+async fn test_password_reset() {
+    GenerateState!(ExerciseResult, DummyExercise);
+    let o: Orchestrator<State> = Orchestrator::new(1, false, DefaultMemory::init());
+    let resets = Arc::new(Mutex::new(Vec::new()));
+    let server = WebServer::default().set_notifier(SavedNotifier(resets.clone()));
+    let client = Client::tracked(build_rocket(o.as_ref(), server))
+        .await
+        .unwrap();
+    let post = |uri: &'static str, body: &'static str| {
+        client
+            .post(uri)
+            .header(ContentType::Form)
+            .body(body)
+            .dispatch()
+    };
+
+    assert_eq!(
+        post("/register", "username=ciao&password=mondo")
+            .await
+            .status(),
+        Status::Ok
+    );
+    assert_eq!(
+        post("/login", "username=ciao&password=mondo")
+            .await
+            .status(),
+        Status::Ok
+    );
+    assert_eq!(
+        post("/change_password", "old_password=wrong&new_password=nuovo")
+            .await
+            .status(),
+        Status::Unauthorized
+    );
+    assert_eq!(
+        post("/change_password", "old_password=mondo&new_password=nuovo")
+            .await
+            .status(),
+        Status::Ok
+    );
+
+    // unknown users are not revealed
+    assert_eq!(
+        post("/request_password_reset", "username=nobody")
+            .await
+            .status(),
+        Status::Ok
+    );
+    assert!(resets.lock().await.is_empty());
+    assert_eq!(
+        post("/request_password_reset", "username=ciao")
+            .await
+            .status(),
+        Status::Ok
+    );
+    let token = resets.lock().await[0].token.clone();
+    let body = format!("token={}&new_password=ultima", token);
+    let reset = |body: String| {
+        client
+            .post("/reset_password")
+            .header(ContentType::Form)
+            .body(body)
+            .dispatch()
+    };
+    assert_eq!(reset(body.clone()).await.status(), Status::Ok);
+    // single use
+    assert_eq!(reset(body).await.status(), Status::Unauthorized);
+    assert_eq!(
+        post("/login", "username=ciao&password=ultima")
+            .await
+            .status(),
+        Status::Ok
+    );
+}
+
+/// minimal OpenID Connect provider: it authorizes everyone as the same user
+mod provider {
+    use rocket::{
+        form::Form,
+        get, post, routes,
+        serde::json::{json, Value},
+        FromForm, Route, State,
+    };
+
+    pub struct Issuer(pub String);
+
+    #[get("/.well-known/openid-configuration")]
+    fn discovery(issuer: &State<Issuer>) -> Value {
+        json!({
+            "issuer": issuer.0,
+            "authorization_endpoint": format!("{}/authorize", issuer.0),
+            "token_endpoint": format!("{}/token", issuer.0),
+            "userinfo_endpoint": format!("{}/userinfo", issuer.0),
+        })
+    }
+
+    #[derive(FromForm)]
+    struct TokenRequest<'r> {
+        code: &'r str,
+        client_secret: &'r str,
+    }
+
+    #[post("/token", data = "<req>")]
+    fn token(req: Form<TokenRequest<'_>>) -> Option<Value> {
+        (req.code == "valid_code" && req.client_secret == "secret")
+            .then(|| json!({"access_token": "access", "token_type": "Bearer"}))
+    }
+
+    #[get("/userinfo")]
+    fn userinfo() -> Value {
+        json!({"sub": "1234", "email": "ciao@example.com", "name": "Ciao"})
+    }
+
+    pub fn routes() -> Vec<Route> {
+        routes![discovery, token, userinfo]
+    }
+}
+
+#[async_test]
+async fn test_oidc_login() {
+    GenerateState!(ExerciseResult, DummyExercise);
+    // start the provider on a free port
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let issuer = format!("http://127.0.0.1:{}", port);
+    let config = rocket::Config {
+        port,
+        address: std::net::Ipv4Addr::LOCALHOST.into(),
+        log_level: rocket::config::LogLevel::Off,
+        ..rocket::Config::debug_default()
+    };
+    let provider = rocket::custom(config)
+        .manage(provider::Issuer(issuer.clone()))
+        .mount("/", provider::routes());
+    rocket::tokio::spawn(provider.launch());
+    let discovery = format!("{}/.well-known/openid-configuration", issuer);
+    while reqwest::get(&discovery).await.is_err() {
+        rocket::tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+
+    let o = Orchestrator::<State>::new(1, false, DefaultMemory::init()).as_ref();
+    let server = WebServer::default()
+        .set_password_login(false)
+        .set_oidc(OidcConfig::new(
+            issuer.clone(),
+            "client",
+            "secret",
+            "http://localhost/oidc/callback",
+        ));
+    let client = Client::tracked(build_rocket(o.clone(), server))
+        .await
+        .unwrap();
+
+    // password login is disabled
+    let res = client
+        .post("/login")
+        .header(ContentType::Form)
+        .body("username=ciao&password=mondo")
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::NotFound);
+
+    for _ in 0..2 {
+        let res = client.get("/oidc/login").dispatch().await;
+        assert_eq!(res.status(), Status::SeeOther);
+        let location = reqwest::Url::parse(res.headers().get_one("Location").unwrap()).unwrap();
+        assert!(location
+            .as_str()
+            .starts_with(&format!("{}/authorize", issuer)));
+        let state = location
+            .query_pairs()
+            .find(|(k, _)| k == "state")
+            .unwrap()
+            .1
+            .to_string();
+
+        // a state not generated by the backend is refused
+        let res = client
+            .get("/oidc/callback?code=valid_code&state=forged")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Unauthorized);
+
+        let res = client
+            .get(format!("/oidc/callback?code=valid_code&state={}", state))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::SeeOther);
+        assert!(res.cookies().get("auth_token").is_some());
+        // a state can be used only once
+        let res = client
+            .get(format!("/oidc/callback?code=valid_code&state={}", state))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Unauthorized);
+    }
+    // both logins are mapped to the same user
+    let users = o.memory().get_all_users().await.unwrap();
+    assert_eq!(users.len(), 1);
+    assert_eq!(users[0].username, "ciao@example.com");
+}
+
+#[async_test]
+async fn test_audit_log() {
+    GenerateState!(ExerciseResult, DummyExercise);
+    let o: Orchestrator<State> = Orchestrator::new(1, false, DefaultMemory::init());
+    let client = Client::tracked(build_rocket(o.as_ref(), WebServer::default()))
+        .await
+        .unwrap();
+    let post = |uri: &'static str, body: &'static str| {
+        client
+            .post(uri)
+            .header(ContentType::Form)
+            .body(body)
+            .dispatch()
+    };
+    post("/register", "username=ciao&password=mondo").await;
+    assert_eq!(
+        post("/login", "username=ciao&password=wrong")
+            .await
+            .status(),
+        Status::Unauthorized
+    );
+    // only admins can read the log
+    assert_ne!(
+        client.get("/admin/audit").dispatch().await.status(),
+        Status::Ok
+    );
+    assert_eq!(
+        post("/login", "username=ciao&password=mondo")
+            .await
+            .status(),
+        Status::Ok
+    );
+
+    let events: Vec<AuditEvent> = client
+        .get("/admin/audit")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    let actions: Vec<_> = events.iter().map(|x| x.action).collect();
+    assert_eq!(
+        actions,
+        [
+            AuditAction::Register,
+            AuditAction::LoginFailure,
+            AuditAction::LoginSuccess
+        ]
+    );
+    assert!(events.iter().all(|x| x.actor.as_deref() == Some("ciao")));
+
+    let events: Vec<AuditEvent> = client
+        .get("/admin/audit?action=LoginFailure&actor=ciao")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].action, AuditAction::LoginFailure);
+
+    // the previous reads have been recorded
+    let events: Vec<AuditEvent> = client
+        .get(format!(
+            "/admin/audit?action=AdminAction&from={}",
+            events[0]
+                .time
+                .to_rfc3339_opts(chrono::SecondsFormat::Micros, true)
+        ))
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(
+        client
+            .get("/admin/audit?action=NotAnAction")
+            .dispatch()
+            .await
+            .status(),
+        Status::BadRequest
+    );
+}
+
+#[async_test]
+async fn test_submission_history() {
+    GenerateState!(ExerciseResult, DummyExercise);
+    let mut o: Orchestrator<State> = Orchestrator::new(1, false, DefaultMemory::init());
+    // registers the DummyExercise
+    o.add_plugin(DefaultTest::new_default()).await.unwrap();
+    let client = Client::tracked(build_rocket(o.as_ref(), WebServer::default()))
+        .await
+        .unwrap();
+    let post = |uri: &'static str, body: String| {
+        client
+            .post(uri)
+            .header(ContentType::Form)
+            .body(body)
+            .dispatch()
+    };
+    post("/register", "username=ciao&password=mondo".to_string()).await;
+    post("/login", "username=ciao&password=mondo".to_string()).await;
+    let long_source = "x".repeat(1000);
+    for source in ["first", long_source.as_str()] {
+        let res = post(
+            "/submit",
+            format!("problem=DummyExercise&source={}", source),
+        )
+        .await;
+        assert_eq!(res.status(), Status::Ok);
+    }
+
+    let submissions: Vec<Submission> = client
+        .get("/submissions?exercise=DummyExercise")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(submissions.len(), 2);
+    assert!(submissions.iter().all(|x| x.result.is_some()));
+    assert_eq!(submissions[1].source, long_source);
+
+    let page: Vec<Submission> = client
+        .get("/submissions?offset=1&limit=5")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(page, submissions[1..]);
+
+    let single: Submission = client
+        .get(format!("/submissions/{}", submissions[0].submission_id))
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(single, submissions[0]);
+    assert_eq!(
+        client.get("/submissions/1000").dispatch().await.status(),
+        Status::NotFound
+    );
+
+    let latest: Submission = client
+        .get("/submissions/latest/DummyExercise")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(latest, submissions[1]);
+    // same points, the latest wins
+    let best: Submission = client
+        .get("/submissions/best/DummyExercise")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(best, submissions[1]);
+    assert_eq!(
+        client
+            .get("/submissions/best/NotAnExercise")
+            .dispatch()
+            .await
+            .status(),
+        Status::NotFound
+    );
+}
+
+#[async_test]
+async fn test_executor_management() {
+    GenerateState!(ExerciseResult, DummyExercise);
+    let mut o: Orchestrator<State> = Orchestrator::new(1, false, DefaultMemory::init());
+    // registers and enables the DummyExercise executor
+    o.add_plugin(DefaultTest::new_default()).await.unwrap();
+    let client = Client::tracked(build_rocket(o.as_ref(), WebServer::default()))
+        .await
+        .unwrap();
+    let input = State::DummyExercise(DummyExercise {}).serialize_variant();
+    let change = |uri: &'static str, data: serde_json::Value| {
+        client
+            .post(uri)
+            .header(ContentType::JSON)
+            .body(serde_json::json!({"input": input, "data": data}).to_string())
+            .dispatch()
+    };
+    // only admins can manage executors
+    assert_ne!(
+        change("/admin/executors/disable", serde_json::Value::Null)
+            .await
+            .status(),
+        Status::Ok
+    );
+    for uri in ["/register", "/login"] {
+        client
+            .post(uri)
+            .header(ContentType::Form)
+            .body("username=ciao&password=mondo")
+            .dispatch()
+            .await;
+    }
+
+    let executors: Vec<ExecutorRecord> = client
+        .get("/admin/executors")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(executors.len(), 1);
+    assert_eq!(executors[0].input, input);
+
+    // the executor takes (), so only null is accepted
+    assert_eq!(
+        change("/admin/executors/data", serde_json::json!(5))
+            .await
+            .status(),
+        Status::UnprocessableEntity
+    );
+    assert_eq!(
+        change("/admin/executors/data", serde_json::Value::Null)
+            .await
+            .status(),
+        Status::Ok
+    );
+    assert_eq!(
+        change("/admin/executors/disable", serde_json::Value::Null)
+            .await
+            .status(),
+        Status::Ok
+    );
+    assert_eq!(
+        change("/admin/executors/disable", serde_json::Value::Null)
+            .await
+            .status(),
+        Status::NotFound
+    );
+    let executors: Vec<ExecutorRecord> = client
+        .get("/admin/executors")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert!(executors.is_empty());
+}
+
+/// the status and the "error" field of the JSON body
+async fn error(res: LocalResponse<'_>) -> (Status, String) {
+    let status = res.status();
+    let body: serde_json::Value = res.into_json().await.unwrap();
+    (status, body["error"].as_str().unwrap().to_string())
+}
+
+#[async_test]
+async fn test_error_responses() {
+    GenerateState!(ExerciseResult, DummyExercise);
+    let o: Orchestrator<State> = Orchestrator::new(1, false, DefaultMemory::init());
+    let client = Client::tracked(build_rocket(o.as_ref(), WebServer::default()))
+        .await
+        .unwrap();
+    let post = |uri: &'static str, body: &'static str| {
+        client
+            .post(uri)
+            .header(ContentType::Form)
+            .body(body)
+            .dispatch()
+    };
+
+    post("/register", "username=ciao&password=mondo").await;
+    assert_eq!(
+        error(post("/register", "username=ciao&password=altro").await).await,
+        (Status::Conflict, "already_exists".to_string())
+    );
+    assert_eq!(
+        error(post("/login", "username=ciao&password=altro").await).await,
+        (Status::Unauthorized, "unauthorized".to_string())
+    );
+    assert_eq!(
+        post("/login", "username=ciao&password=mondo")
+            .await
+            .status(),
+        Status::Ok
+    );
+    assert_eq!(
+        error(post("/submit", "problem=NotAnExercise&source=x").await).await,
+        (Status::NotFound, "not_found".to_string())
+    );
+    assert_eq!(
+        error(client.get("/submissions/1000").dispatch().await).await,
+        (Status::NotFound, "not_found".to_string())
+    );
+}
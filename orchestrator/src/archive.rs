@@ -0,0 +1,434 @@
+//! Portable archive of a memory, used for backups and to move data between backends.
+//!
+//! The archive is a JSON lines file: the first line is a header with the format version,
+//! every other line is a record tagged by its kind (user, exercise, executor or submission).
+//! ```
+//! use orchestrator::archive::{export, import, Archive};
+//! use orchestrator::conformance::ConformanceState;
+//! use orchestrator::default_memory::DefaultMemory;
+//! use orchestrator::prelude::*;
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let from = DefaultMemory::init::<ConformanceState>();
+//! from.register("ciao", "mondo").await.unwrap();
+//!
+//! let mut file = Vec::new();
+//! export(from.as_stateless()).await.unwrap().write(&mut file).unwrap();
+//!
+//! let into = DefaultMemory::init::<ConformanceState>();
+//! let archive = Archive::read(file.as_slice()).unwrap();
+//! import(into.as_stateless(), &archive).await.unwrap();
+//! assert!(into.login("ciao", "mondo").await.is_ok());
+//! # }
+//! ```
+//! Ids are not preserved: the target memory assigns new ones, and every reference
+//! is remapped consistently. Tokens, password resets, external identities and the
+//! audit log are not archived.
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error as StdError,
+    io::{BufRead, Write},
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::default_memory::has_cycles;
+use crate::prelude::*;
+
+/// version of the archive format written by this crate
+pub const FORMAT_VERSION: u32 = 1;
+
+#[derive(Error, Debug)]
+/// Errors of a malformed or inconsistent archive
+pub enum ArchiveError {
+    /// the file could not be read or written
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// a line is not a valid record
+    #[error("invalid record at line {line}: {source}")]
+    Json {
+        /// 1-based line number
+        line: usize,
+        /// parse error
+        source: serde_json::Error,
+    },
+
+    /// the first line is not a header
+    #[error("missing header")]
+    MissingHeader,
+
+    /// a second header, at the given line
+    #[error("unexpected header at line {0}")]
+    UnexpectedHeader(usize),
+
+    /// written by a newer release
+    #[error("unsupported format version {0} (known {FORMAT_VERSION})")]
+    UnsupportedVersion(u32),
+
+    /// two users with the same username
+    #[error("duplicated username {0}")]
+    DuplicatedUser(String),
+
+    /// a submission of a user that is not in the archive
+    #[error("submission {submission_id} refers to unknown user {user_id}")]
+    UnknownUser {
+        /// id of the submission in the archive
+        submission_id: i64,
+        /// id of the user in the archive
+        user_id: i64,
+    },
+
+    /// the enabled executors contain a cycle
+    #[error("cycle detected between executors")]
+    CycleDetected,
+
+    /// a variant that the state can't deserialize
+    #[error("unknown variant {0}")]
+    UnknownVariant(String),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+/// a line of the archive
+enum Line {
+    Header {
+        format_version: u32,
+        created_at: DateTime<Utc>,
+    },
+    User(UserRecord),
+    Exercise(ExerciseRecord),
+    Executor(ExecutorRecord),
+    Submission(Submission),
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// Content of a memory, ids are the ones of the exported memory
+pub struct Archive {
+    /// when it was exported
+    pub created_at: DateTime<Utc>,
+    /// users, ordered by id
+    pub users: Vec<UserRecord>,
+    /// exercises, with their serialized variant
+    pub exercises: Vec<ExerciseRecord>,
+    /// enabled executors
+    pub executors: Vec<ExecutorRecord>,
+    /// submissions with their results, ordered by id
+    pub submissions: Vec<Submission>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// How the ids of the archive have been remapped by an import
+pub struct ImportReport {
+    /// archive user id -> new user id
+    pub users: HashMap<i64, i64>,
+    /// archive submission id -> new submission id
+    pub submissions: HashMap<i64, i64>,
+}
+
+impl Archive {
+    /// writes the archive as JSON lines, header first
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<(), ArchiveError> {
+        let mut line = |x: Line| -> Result<(), ArchiveError> {
+            serde_json::to_writer(&mut writer, &x).map_err(std::io::Error::from)?;
+            writer.write_all(b"\n")?;
+            Ok(())
+        };
+        line(Line::Header {
+            format_version: FORMAT_VERSION,
+            created_at: self.created_at,
+        })?;
+        for x in &self.users {
+            line(Line::User(x.clone()))?;
+        }
+        for x in &self.exercises {
+            line(Line::Exercise(x.clone()))?;
+        }
+        for x in &self.executors {
+            line(Line::Executor(x.clone()))?;
+        }
+        for x in &self.submissions {
+            line(Line::Submission(x.clone()))?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// reads an archive written by write, empty lines are ignored
+    pub fn read<R: BufRead>(reader: R) -> Result<Self, ArchiveError> {
+        let mut ret: Option<Archive> = None;
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let parsed: Line =
+                serde_json::from_str(&line).map_err(|source| ArchiveError::Json {
+                    line: i + 1,
+                    source,
+                })?;
+            let Some(archive) = ret.as_mut() else {
+                let Line::Header {
+                    format_version,
+                    created_at,
+                } = parsed
+                else {
+                    return Err(ArchiveError::MissingHeader);
+                };
+                if format_version > FORMAT_VERSION {
+                    return Err(ArchiveError::UnsupportedVersion(format_version));
+                }
+                ret = Some(Archive {
+                    created_at,
+                    ..Default::default()
+                });
+                continue;
+            };
+            match parsed {
+                Line::Header { .. } => return Err(ArchiveError::UnexpectedHeader(i + 1)),
+                Line::User(x) => archive.users.push(x),
+                Line::Exercise(x) => archive.exercises.push(x),
+                Line::Executor(x) => archive.executors.push(x),
+                Line::Submission(x) => archive.submissions.push(x),
+            }
+        }
+        ret.ok_or(ArchiveError::MissingHeader)
+    }
+
+    /// checks that the archive is consistent: usernames are unique,
+    /// every submission has its user and executors have no cycles
+    pub fn validate(&self) -> Result<(), ArchiveError> {
+        let mut usernames = HashSet::new();
+        for x in &self.users {
+            if !usernames.insert(&x.username) {
+                return Err(ArchiveError::DuplicatedUser(x.username.clone()));
+            }
+        }
+        let ids: HashSet<i64> = self.users.iter().map(|x| x.user_id).collect();
+        if let Some(x) = self.submissions.iter().find(|x| !ids.contains(&x.user_id)) {
+            return Err(ArchiveError::UnknownUser {
+                submission_id: x.submission_id,
+                user_id: x.user_id,
+            });
+        }
+        let map: HashMap<String, String> = self
+            .executors
+            .iter()
+            .map(|x| (x.input.clone(), x.output.clone()))
+            .collect();
+        if has_cycles(&map) {
+            return Err(ArchiveError::CycleDetected);
+        }
+        Ok(())
+    }
+
+    /// checks that every variant (of exercises and executors) is known by the state
+    pub fn validate_variants<S: ExecutorGlobalState>(&self) -> Result<(), ArchiveError> {
+        let variants = self
+            .exercises
+            .iter()
+            .map(|x| &x.variant)
+            .chain(self.executors.iter().flat_map(|x| [&x.input, &x.output]));
+        for x in variants {
+            if S::deserialize_variant(x).is_err() {
+                return Err(ArchiveError::UnknownVariant(x.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// reads the whole content of a memory
+pub async fn export(memory: &dyn StatelessMemory) -> Result<Archive, Box<dyn StdError>> {
+    let mut submissions = memory
+        .list_submissions(&SubmissionFilter::default())
+        .await?;
+    submissions.sort_by_key(|x| x.submission_id);
+    // every result is bound before the next await, so that the future stays Send
+    let users = memory.export_users().await?;
+    let exercises = memory.export_exercises().await?;
+    let executors = memory.export_executors().await?;
+    Ok(Archive {
+        created_at: Utc::now(),
+        users,
+        exercises,
+        executors,
+        submissions,
+    })
+}
+
+/// writes the archive into a memory, that should be empty.
+///
+/// The archive is validated and usernames are checked before writing anything,
+/// exercises and executors already present with the same name (input) are replaced.
+/// If the memory fails midway, what has been imported so far is not rolled back
+pub async fn import(
+    memory: &dyn StatelessMemory,
+    archive: &Archive,
+) -> Result<ImportReport, Box<dyn StdError>> {
+    archive.validate()?;
+    for x in &archive.users {
+        if memory.get_by_username(&x.username).await.is_ok() {
+            Err(format!("user {} already present", x.username))?;
+        }
+    }
+    for x in &archive.exercises {
+        memory.import_exercise(x).await?;
+    }
+    for x in &archive.executors {
+        memory.import_executor(x).await?;
+    }
+    let mut report = ImportReport::default();
+    for x in &archive.users {
+        let id = memory.import_user(x).await?;
+        report.users.insert(x.user_id, id);
+    }
+    for x in &archive.submissions {
+        let submission = Submission {
+            user_id: report.users[&x.user_id],
+            ..x.clone()
+        };
+        let id = memory.import_submission(&submission).await?;
+        report.submissions.insert(x.submission_id, id);
+    }
+    Ok(report)
+}
+
+/// like import, but it also checks that every variant is known by the state of the memory
+pub async fn import_checked<S: ExecutorGlobalState>(
+    memory: &dyn Memory<S>,
+    archive: &Archive,
+) -> Result<ImportReport, Box<dyn StdError>> {
+    archive.validate_variants::<S>()?;
+    import(memory.as_stateless(), archive).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::{export, import, import_checked, Archive, ArchiveError};
+    use crate::conformance::{ConformanceState, Source};
+    use crate::default_memory::DefaultMemory;
+    use crate::prelude::*;
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let from = DefaultMemory::init::<ConformanceState>();
+        from.register("skip", "me").await.unwrap();
+        let user = from.register("ciao", "mondo").await.unwrap();
+        let logged = from.login("ciao", "mondo").await.unwrap();
+        from.add_exercise(
+            "somma".to_string(),
+            ConformanceState::Source(Source),
+            "fn main(){}".to_string(),
+        )
+        .await
+        .unwrap();
+        from.import_executor(&ExecutorRecord {
+            input: "Source".to_string(),
+            output: "Compiled".to_string(),
+            data: "{}".to_string(),
+        })
+        .await
+        .unwrap();
+        from.add_submission("somma".to_string(), "a".to_string(), logged.clone())
+            .await
+            .unwrap();
+        let graded = from
+            .add_submission("somma".to_string(), "b".to_string(), logged.clone())
+            .await
+            .unwrap();
+        from.add_exercise_result(graded, logged, ExerciseResult::default())
+            .await
+            .unwrap();
+
+        let archive = export(from.as_stateless()).await.unwrap();
+        let mut file = Vec::new();
+        archive.write(&mut file).unwrap();
+        let read = Archive::read(file.as_slice()).unwrap();
+        assert_eq!(read, archive);
+
+        // the target already has a user, so ids are shifted
+        let into = DefaultMemory::init::<ConformanceState>();
+        into.register("other", "user").await.unwrap();
+        let report = import_checked(into.as_ref(), &read).await.unwrap();
+        let new_id = report.users[&user.user_id];
+        assert_ne!(new_id, user.user_id);
+        assert_eq!(into.login("ciao", "mondo").await.unwrap().user_id, new_id);
+
+        let submission = into
+            .get_submission(report.submissions[&graded])
+            .await
+            .unwrap();
+        assert_eq!(submission.user_id, new_id);
+        assert_eq!(submission.source, "b");
+        assert_eq!(submission.result, Some(ExerciseResult::default()));
+        assert_eq!(
+            into.export_executors().await.unwrap(),
+            from.export_executors().await.unwrap()
+        );
+
+        // users are never imported twice
+        assert!(import(into.as_stateless(), &read).await.is_err());
+    }
+
+    #[test]
+    fn test_send() {
+        // plugins run on a multithreaded runtime
+        fn assert_send<T: Send>(_: &T) {}
+        let memory = DefaultMemory::init::<ConformanceState>();
+        let archive = Archive::default();
+        assert_send(&export(memory.as_stateless()));
+        assert_send(&import(memory.as_stateless(), &archive));
+        assert_send(&import_checked(memory.as_ref(), &archive));
+    }
+
+    #[test]
+    fn test_invalid_archives() {
+        assert!(matches!(
+            Archive::read("".as_bytes()),
+            Err(ArchiveError::MissingHeader)
+        ));
+        let user =
+            r#"{"kind":"user","user_id":0,"username":"a","password_hash":"","is_admin":false}"#;
+        assert!(matches!(
+            Archive::read(user.as_bytes()),
+            Err(ArchiveError::MissingHeader)
+        ));
+        let header = r#"{"kind":"header","format_version":99,"created_at":"2024-01-01T00:00:00Z"}"#;
+        assert!(matches!(
+            Archive::read(header.as_bytes()),
+            Err(ArchiveError::UnsupportedVersion(99))
+        ));
+        let header = header.replace("99", "1");
+        assert!(matches!(
+            Archive::read(format!("{header}\n{{}}").as_bytes()),
+            Err(ArchiveError::Json { line: 2, .. })
+        ));
+        let archive = Archive::read(format!("{header}\n\n{user}\n{user}").as_bytes()).unwrap();
+        assert!(matches!(
+            archive.validate(),
+            Err(ArchiveError::DuplicatedUser(_))
+        ));
+
+        let mut archive = Archive::default();
+        for (input, output) in [("Source", "Compiled"), ("Compiled", "Source")] {
+            archive.executors.push(ExecutorRecord {
+                input: input.to_string(),
+                output: output.to_string(),
+                data: String::new(),
+            });
+        }
+        assert!(matches!(
+            archive.validate(),
+            Err(ArchiveError::CycleDetected)
+        ));
+        archive.executors[1].output = "Unknown".to_string();
+        assert!(archive.validate().is_ok());
+        assert!(matches!(
+            archive.validate_variants::<ConformanceState>(),
+            Err(ArchiveError::UnknownVariant(_))
+        ));
+    }
+}
@@ -0,0 +1,471 @@
+//! Read-through cache for exercises and execution plans.
+//!
+//! Every processed submission reads its exercise and the execution plan, that change rarely.
+//! CachedMemory wraps any memory and keeps them, everything else is forwarded as it is.
+//! ```
+//! use orchestrator::cached_memory::CachedMemory;
+//! use orchestrator::conformance::ConformanceState;
+//! use orchestrator::default_memory::DefaultMemory;
+//! use orchestrator::memory::Memory;
+//!
+//! let cached = CachedMemory::new(DefaultMemory::init::<ConformanceState>());
+//! // the counters are shared, so they can be read after the memory is boxed
+//! let stats = cached.stats();
+//! let memory: Box<dyn Memory<ConformanceState>> = Box::new(cached);
+//! assert_eq!(stats.exercises().hit_rate(), None);
+//! ```
+//! The cache is invalidated by the writes that go through it: if another process
+//! shares the same database, call invalidate when it changes exercises or executors.
+use std::{
+    any::TypeId,
+    collections::HashMap,
+    fmt::Display,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+};
+
+use async_trait::async_trait;
+use chrono::Duration;
+
+use crate::prelude::*;
+
+/// a step of an execution plan: input, output, executor data
+type Step = (TypeId, TypeId, String);
+
+#[derive(Debug, Default)]
+/// hits and misses of a single cache
+pub struct Counter {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl Counter {
+    /// lookups served by the cache
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+    /// lookups forwarded to the memory
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+    /// hits over lookups, None if there was no lookup
+    pub fn hit_rate(&self) -> Option<f64> {
+        let (hits, misses) = (self.hits(), self.misses());
+        (hits + misses > 0).then(|| hits as f64 / (hits + misses) as f64)
+    }
+    fn record(&self, hit: bool) {
+        let counter = if hit { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Display for Counter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} hits, {} misses", self.hits(), self.misses())?;
+        if let Some(rate) = self.hit_rate() {
+            write!(f, " ({:.1}%)", rate * 100.0)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+/// Statistics of a CachedMemory
+pub struct CacheStats {
+    exercises: Counter,
+    plans: Counter,
+}
+
+impl CacheStats {
+    /// get_exercise lookups
+    pub fn exercises(&self) -> &Counter {
+        &self.exercises
+    }
+    /// get_execution_plan lookups
+    pub fn plans(&self) -> &Counter {
+        &self.plans
+    }
+}
+
+impl Display for CacheStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "exercises: {}, plans: {}", self.exercises, self.plans)
+    }
+}
+
+/// a map that is invalidated as a whole by increasing its generation.
+///
+/// A value read from the memory is inserted only if no invalidation happened meanwhile,
+/// otherwise it could be older than the write that caused the invalidation
+struct Cache<V> {
+    map: RwLock<HashMap<String, V>>,
+    generation: AtomicU64,
+}
+
+impl<V: Clone> Cache<V> {
+    fn new() -> Self {
+        Self {
+            map: RwLock::new(HashMap::new()),
+            generation: AtomicU64::new(0),
+        }
+    }
+    fn get(&self, key: &str) -> Option<V> {
+        self.map.read().unwrap().get(key).cloned()
+    }
+    fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+    fn insert(&self, key: String, value: V, generation: u64) {
+        let mut map = self.map.write().unwrap();
+        if self.generation() == generation {
+            map.insert(key, value);
+        }
+    }
+    fn clear(&self) {
+        let mut map = self.map.write().unwrap();
+        map.clear();
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
+}
+
+/// Memory wrapper that caches exercises and execution plans
+pub struct CachedMemory<S: ExecutorGlobalState> {
+    inner: Box<dyn Memory<S>>,
+    /// name, (type, source)
+    exercises: Cache<(TypeId, String)>,
+    /// serialized input variant, plan
+    plans: Cache<Vec<Step>>,
+    stats: Arc<CacheStats>,
+}
+
+impl<S: ExecutorGlobalState> CachedMemory<S> {
+    /// wraps a memory, the cache starts empty
+    pub fn new(inner: Box<dyn Memory<S>>) -> Self {
+        Self {
+            inner,
+            exercises: Cache::new(),
+            plans: Cache::new(),
+            stats: Arc::new(CacheStats::default()),
+        }
+    }
+    /// hits and misses, updated on each lookup
+    pub fn stats(&self) -> Arc<CacheStats> {
+        self.stats.clone()
+    }
+    /// drops everything cached, the next lookups read from the memory
+    pub fn invalidate(&self) {
+        self.exercises.clear();
+        self.plans.clear();
+    }
+}
+
+#[async_trait]
+impl<S: ExecutorGlobalState> StatelessMemory for CachedMemory<S> {
+    async fn register(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<User<Unauthenticated>, MemoryError> {
+        self.inner.register(username, password).await
+    }
+
+    async fn login(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<User<Authenticated>, MemoryError> {
+        self.inner.login(username, password).await
+    }
+
+    async fn login_external(
+        &self,
+        identity: &ExternalIdentity,
+    ) -> Result<User<Authenticated>, MemoryError> {
+        self.inner.login_external(identity).await
+    }
+
+    async fn change_password(
+        &self,
+        user: User<Authenticated>,
+        old_password: &str,
+        new_password: &str,
+    ) -> Result<(), MemoryError> {
+        self.inner
+            .change_password(user, old_password, new_password)
+            .await
+    }
+
+    async fn create_password_reset(
+        &self,
+        username: &str,
+        validity: Duration,
+    ) -> Result<PasswordReset, MemoryError> {
+        self.inner.create_password_reset(username, validity).await
+    }
+
+    async fn reset_password(&self, token: &str, new_password: &str) -> Result<(), MemoryError> {
+        self.inner.reset_password(token, new_password).await
+    }
+
+    async fn get_by_username(&self, username: &str) -> Result<User<Unauthenticated>, MemoryError> {
+        self.inner.get_by_username(username).await
+    }
+
+    async fn get_authenticate(&self, token: &str) -> Result<User<Authenticated>, MemoryError> {
+        self.inner.get_authenticate(token).await
+    }
+
+    async fn get_admin(&self, token: &str) -> Result<User<Admin>, MemoryError> {
+        self.inner.get_admin(token).await
+    }
+
+    async fn get_all_users(&self) -> Result<Vec<User<Unauthenticated>>, MemoryError> {
+        self.inner.get_all_users().await
+    }
+
+    async fn list_exercise_names(&self) -> Result<Vec<String>, MemoryError> {
+        self.inner.list_exercise_names().await
+    }
+
+    async fn add_submission(
+        &self,
+        exercise_name: String,
+        source: String,
+        user: User<Authenticated>,
+    ) -> Result<i64, MemoryError> {
+        self.inner.add_submission(exercise_name, source, user).await
+    }
+
+    async fn get_submission(&self, submission_id: i64) -> Result<Submission, MemoryError> {
+        self.inner.get_submission(submission_id).await
+    }
+
+    async fn list_submissions(
+        &self,
+        filter: &SubmissionFilter,
+    ) -> Result<Vec<Submission>, MemoryError> {
+        self.inner.list_submissions(filter).await
+    }
+
+    async fn get_latest_submission(
+        &self,
+        user_id: i64,
+        exercise_name: &str,
+    ) -> Result<Option<Submission>, MemoryError> {
+        self.inner
+            .get_latest_submission(user_id, exercise_name)
+            .await
+    }
+
+    async fn get_best_submission(
+        &self,
+        user_id: i64,
+        exercise_name: &str,
+    ) -> Result<Option<Submission>, MemoryError> {
+        self.inner.get_best_submission(user_id, exercise_name).await
+    }
+
+    async fn add_exercise_result(
+        &self,
+        submission_id: i64,
+        user: User<Authenticated>,
+        result: ExerciseResult,
+    ) -> Result<(), MemoryError> {
+        self.inner
+            .add_exercise_result(submission_id, user, result)
+            .await
+    }
+
+    async fn audit(
+        &self,
+        actor: Option<&str>,
+        action: AuditAction,
+        details: &str,
+    ) -> Result<(), MemoryError> {
+        self.inner.audit(actor, action, details).await
+    }
+
+    async fn get_audit_log(
+        &self,
+        admin: &User<Admin>,
+        filter: &AuditFilter,
+    ) -> Result<Vec<AuditEvent>, MemoryError> {
+        self.inner.get_audit_log(admin, filter).await
+    }
+
+    async fn export_users(&self) -> Result<Vec<UserRecord>, MemoryError> {
+        self.inner.export_users().await
+    }
+
+    async fn export_exercises(&self) -> Result<Vec<ExerciseRecord>, MemoryError> {
+        self.inner.export_exercises().await
+    }
+
+    async fn export_executors(&self) -> Result<Vec<ExecutorRecord>, MemoryError> {
+        self.inner.export_executors().await
+    }
+
+    async fn import_user(&self, user: &UserRecord) -> Result<i64, MemoryError> {
+        self.inner.import_user(user).await
+    }
+
+    async fn import_exercise(&self, exercise: &ExerciseRecord) -> Result<(), MemoryError> {
+        let ret = self.inner.import_exercise(exercise).await;
+        self.exercises.clear();
+        ret
+    }
+
+    async fn import_executor(&self, executor: &ExecutorRecord) -> Result<(), MemoryError> {
+        let ret = self.inner.import_executor(executor).await;
+        self.plans.clear();
+        ret
+    }
+
+    async fn import_submission(&self, submission: &Submission) -> Result<i64, MemoryError> {
+        self.inner.import_submission(submission).await
+    }
+}
+
+#[async_trait]
+impl<S: ExecutorGlobalState> StateMemory<S> for CachedMemory<S> {
+    /// any executor could be part of any plan, so all plans are dropped
+    async fn enable_executor(
+        &self,
+        input: &S,
+        output: &S,
+        data: String,
+    ) -> Result<(), MemoryError> {
+        let ret = self.inner.enable_executor(input, output, data).await;
+        self.plans.clear();
+        ret
+    }
+
+    async fn get_execution_plan(&self, input: &S) -> Result<Vec<Step>, MemoryError> {
+        let key = input.serialize_variant();
+        let cached = self.plans.get(&key);
+        self.stats.plans.record(cached.is_some());
+        if let Some(plan) = cached {
+            return Ok(plan);
+        }
+        let generation = self.plans.generation();
+        let plan = self.inner.get_execution_plan(input).await?;
+        self.plans.insert(key, plan.clone(), generation);
+        Ok(plan)
+    }
+
+    async fn disable_executor(&self, input: &S) -> Result<(), MemoryError> {
+        let ret = self.inner.disable_executor(input).await;
+        self.plans.clear();
+        ret
+    }
+
+    async fn replace_executor_data(&self, input: &S, data: String) -> Result<(), MemoryError> {
+        let ret = self.inner.replace_executor_data(input, data).await;
+        self.plans.clear();
+        ret
+    }
+
+    async fn list_enabled_executors(&self) -> Result<Vec<Step>, MemoryError> {
+        self.inner.list_enabled_executors().await
+    }
+
+    async fn add_exercise(
+        &self,
+        name: String,
+        exercise_type: S,
+        source: String,
+    ) -> Result<(), MemoryError> {
+        let ret = self.inner.add_exercise(name, exercise_type, source).await;
+        self.exercises.clear();
+        ret
+    }
+
+    async fn get_exercise(&self, name: String) -> Result<(TypeId, String), MemoryError> {
+        let cached = self.exercises.get(&name);
+        self.stats.exercises.record(cached.is_some());
+        if let Some(exercise) = cached {
+            return Ok(exercise);
+        }
+        let generation = self.exercises.generation();
+        let exercise = self.inner.get_exercise(name.clone()).await?;
+        self.exercises.insert(name, exercise.clone(), generation);
+        Ok(exercise)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CachedMemory;
+    use crate::conformance::{assert_conformance, ConformanceState, Source};
+    use crate::default_memory::DefaultMemory;
+    use crate::prelude::*;
+
+    #[tokio::test]
+    async fn test_conformance() {
+        assert_conformance(|| async {
+            let memory: Box<dyn Memory<ConformanceState>> =
+                Box::new(CachedMemory::new(DefaultMemory::init::<ConformanceState>()));
+            memory
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_hits_and_invalidation() {
+        let memory = CachedMemory::new(DefaultMemory::init::<ConformanceState>());
+        let stats = memory.stats();
+        let source = ConformanceState::Source(Source);
+        memory
+            .add_exercise("somma".to_string(), source.clone(), "a".to_string())
+            .await
+            .unwrap();
+        for _ in 0..3 {
+            assert_eq!(
+                memory.get_exercise("somma".to_string()).await.unwrap().1,
+                "a"
+            );
+        }
+        assert_eq!(
+            (stats.exercises().hits(), stats.exercises().misses()),
+            (2, 1)
+        );
+
+        memory
+            .add_exercise("somma".to_string(), source.clone(), "b".to_string())
+            .await
+            .unwrap();
+        assert_eq!(
+            memory.get_exercise("somma".to_string()).await.unwrap().1,
+            "b"
+        );
+        assert_eq!(stats.exercises().misses(), 2);
+        // errors are not cached
+        assert!(memory.get_exercise("prodotto".to_string()).await.is_err());
+        assert!(memory.get_exercise("prodotto".to_string()).await.is_err());
+        assert_eq!(stats.exercises().misses(), 4);
+
+        assert!(memory.get_execution_plan(&source).await.unwrap().is_empty());
+        memory
+            .import_executor(&ExecutorRecord {
+                input: "Source".to_string(),
+                output: "Compiled".to_string(),
+                data: String::new(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(memory.get_execution_plan(&source).await.unwrap().len(), 1);
+        assert_eq!(memory.get_execution_plan(&source).await.unwrap().len(), 1);
+        assert_eq!((stats.plans().hits(), stats.plans().misses()), (1, 2));
+        assert_eq!(stats.plans().hit_rate(), Some(1.0 / 3.0));
+
+        memory.invalidate();
+        memory.get_execution_plan(&source).await.unwrap();
+        assert_eq!(stats.plans().misses(), 3);
+        assert_eq!(
+            stats.to_string(),
+            "exercises: 2 hits, 4 misses (33.3%), plans: 1 hits, 3 misses (25.0%)"
+        );
+    }
+}
@@ -0,0 +1,799 @@
+//! Conformance suite for Memory implementations.
+//!
+//! Every backend must behave in the same way, the orchestrator and the plugins rely on it.
+//! A new implementation can be checked with:
+//! ```
+//! use orchestrator::conformance::{assert_conformance, ConformanceState};
+//! use orchestrator::default_memory::DefaultMemory;
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! assert_conformance(|| async { DefaultMemory::init::<ConformanceState>() }).await;
+//! # }
+//! ```
+//! The constructor is called once per check, and it must return an empty memory.
+use std::{any::TypeId, collections::HashMap, error::Error as StdError, future::Future};
+
+use chrono::{DateTime, Duration};
+
+use crate::prelude::*;
+
+/// first state of the conformance pipeline
+#[derive(Debug, Clone, Default)]
+pub struct Source;
+
+/// intermediate state of the conformance pipeline
+#[derive(Debug, Clone, Default)]
+pub struct Compiled;
+
+#[derive(Debug, Clone)]
+/// State used by the conformance suite: Source -> Compiled -> ExerciseResult
+pub enum ConformanceState {
+    /// first state
+    Source(Source),
+    /// intermediate state
+    Compiled(Compiled),
+    /// final state
+    ExerciseResult(ExerciseResult),
+}
+
+impl TryFrom<ConformanceState> for ExerciseResult {
+    type Error = ();
+    fn try_from(value: ConformanceState) -> Result<Self, Self::Error> {
+        match value {
+            ConformanceState::ExerciseResult(x) => Ok(x),
+            _ => Err(()),
+        }
+    }
+}
+
+impl ExecutorGlobalState for ConformanceState {
+    fn serialize_variant(&self) -> String {
+        match self {
+            ConformanceState::Source(_) => "Source",
+            ConformanceState::Compiled(_) => "Compiled",
+            ConformanceState::ExerciseResult(_) => "ExerciseResult",
+        }
+        .to_string()
+    }
+    fn deserialize_variant(s: &str) -> Result<TypeId, Box<dyn StdError + Send + Sync + 'static>> {
+        match s {
+            "Source" => Ok(TypeId::of::<Source>()),
+            "Compiled" => Ok(TypeId::of::<Compiled>()),
+            "ExerciseResult" => Ok(TypeId::of::<ExerciseResult>()),
+            _ => Err(format!("unknown variant {s}"))?,
+        }
+    }
+}
+
+/// memory under test
+type Tested = Box<dyn Memory<ConformanceState>>;
+/// result of a single check
+type CheckResult = Result<(), Box<dyn StdError>>;
+
+/// fails the check with the message if the condition is false
+fn ensure(condition: bool, message: &str) -> CheckResult {
+    if condition {
+        Ok(())
+    } else {
+        Err(message)?
+    }
+}
+
+/// registers and logs in a user
+async fn logged(m: &Tested, username: &str) -> Result<User<Authenticated>, MemoryError> {
+    m.register(username, "password").await?;
+    m.login(username, "password").await
+}
+
+/// a result worth the given points
+fn result(points: f64) -> ExerciseResult {
+    ExerciseResult {
+        tests: HashMap::from([(
+            "test".to_string(),
+            TestResult {
+                points_given: points,
+                ..Default::default()
+            },
+        )]),
+    }
+}
+
+/// adds an exercise of type Source
+async fn add_exercise(m: &Tested, name: &str) -> Result<(), MemoryError> {
+    m.add_exercise(
+        name.to_string(),
+        ConformanceState::Source(Source),
+        format!("source of {name}"),
+    )
+    .await
+}
+
+async fn register_and_login(m: Tested) -> CheckResult {
+    let user = m.register("ciao", "mondo").await?;
+    ensure(user.username == "ciao", "wrong username")?;
+    ensure(user.password_hash != "mondo", "the password is not hashed")?;
+    ensure(
+        user.logged_in_token.is_none(),
+        "registered users are not logged",
+    )?;
+    let logged = m.login("ciao", "mondo").await?;
+    ensure(
+        logged.user_id == user.user_id,
+        "login returned another user",
+    )?;
+    ensure(logged.logged_in_token.is_some(), "login without token")?;
+    ensure(logged.logged_in_time.is_some(), "login without time")?;
+    let stored = m.get_by_username("ciao").await?;
+    ensure(
+        stored.user_id == user.user_id,
+        "get_by_username returned another user",
+    )?;
+    m.register("altro", "mondo").await?;
+    let mut names: Vec<String> = m
+        .get_all_users()
+        .await?
+        .into_iter()
+        .map(|x| x.username)
+        .collect();
+    names.sort();
+    ensure(names == ["altro", "ciao"], "get_all_users is incomplete")
+}
+
+async fn duplicate_registration(m: Tested) -> CheckResult {
+    m.register("ciao", "mondo").await?;
+    ensure(
+        matches!(
+            m.register("ciao", "altra").await,
+            Err(MemoryError::AlreadyExists(_))
+        ),
+        "the same username was registered twice",
+    )?;
+    ensure(
+        m.login("ciao", "mondo").await.is_ok(),
+        "the duplicate registration changed the password",
+    )?;
+    ensure(m.get_all_users().await?.len() == 1, "duplicate user saved")
+}
+
+async fn wrong_password(m: Tested) -> CheckResult {
+    m.register("ciao", "mondo").await?;
+    ensure(
+        matches!(
+            m.login("ciao", "sbagliata").await,
+            Err(MemoryError::Unauthorized)
+        ),
+        "login with a wrong password",
+    )?;
+    ensure(
+        matches!(
+            m.login("nessuno", "mondo").await,
+            Err(MemoryError::Unauthorized)
+        ),
+        "login of an unknown user",
+    )?;
+    ensure(
+        matches!(
+            m.get_by_username("nessuno").await,
+            Err(MemoryError::NotFound(_))
+        ),
+        "get_by_username found an unknown user",
+    )
+}
+
+async fn token_lookup(m: Tested) -> CheckResult {
+    let first = logged(&m, "ciao").await?;
+    let token = first.logged_in_token.clone().unwrap_or_default();
+    let found = m.get_authenticate(&token).await?;
+    ensure(found.user_id == first.user_id, "token of another user")?;
+    ensure(
+        matches!(
+            m.get_authenticate("not a valid token").await,
+            Err(MemoryError::Unauthorized)
+        ),
+        "invalid token accepted",
+    )?;
+    ensure(
+        m.get_admin(&token).await.is_ok() == found.is_admin,
+        "get_admin does not follow is_admin",
+    )?;
+    // a new login replaces the token
+    let second = m.login("ciao", "password").await?;
+    ensure(
+        second.logged_in_token != first.logged_in_token,
+        "the token was not renewed",
+    )?;
+    ensure(
+        m.get_authenticate(&token).await.is_err(),
+        "the old token is still valid",
+    )
+}
+
+async fn change_password(m: Tested) -> CheckResult {
+    let user = logged(&m, "ciao").await?;
+    ensure(
+        matches!(
+            m.change_password(user.clone(), "sbagliata", "nuova").await,
+            Err(MemoryError::Unauthorized)
+        ),
+        "password changed without the old one",
+    )?;
+    m.change_password(user, "password", "nuova").await?;
+    ensure(
+        m.login("ciao", "password").await.is_err(),
+        "old password still valid",
+    )?;
+    m.login("ciao", "nuova").await?;
+    Ok(())
+}
+
+async fn password_reset(m: Tested) -> CheckResult {
+    let user = logged(&m, "ciao").await?;
+    let token = user.logged_in_token.unwrap_or_default();
+    ensure(
+        matches!(
+            m.create_password_reset("nessuno", Duration::minutes(5))
+                .await,
+            Err(MemoryError::NotFound(_))
+        ),
+        "reset of an unknown user",
+    )?;
+    let reset = m
+        .create_password_reset("ciao", Duration::minutes(5))
+        .await?;
+    ensure(reset.username == "ciao", "reset of another user")?;
+    m.reset_password(&reset.token, "nuova").await?;
+    ensure(
+        matches!(
+            m.reset_password(&reset.token, "ancora").await,
+            Err(MemoryError::Unauthorized)
+        ),
+        "reset token used twice",
+    )?;
+    ensure(
+        m.get_authenticate(&token).await.is_err(),
+        "the user is still logged in after the reset",
+    )?;
+    m.login("ciao", "nuova").await?;
+    let expired = m
+        .create_password_reset("ciao", Duration::minutes(-5))
+        .await?;
+    ensure(
+        matches!(
+            m.reset_password(&expired.token, "scaduta").await,
+            Err(MemoryError::Unauthorized)
+        ),
+        "expired reset token accepted",
+    )?;
+    ensure(
+        matches!(
+            m.reset_password("not a valid token", "nuova").await,
+            Err(MemoryError::Unauthorized)
+        ),
+        "invalid reset token accepted",
+    )
+}
+
+async fn external_login(m: Tested) -> CheckResult {
+    m.register("ciao@example.com", "mondo").await?;
+    let identity = ExternalIdentity {
+        issuer: "https://issuer.example.com".to_string(),
+        subject: "1234".to_string(),
+        email: Some("ciao@example.com".to_string()),
+        name: None,
+    };
+    let first = m.login_external(&identity).await?;
+    ensure(
+        first.username != "ciao@example.com",
+        "the external identity took over an existing user",
+    )?;
+    let second = m.login_external(&identity).await?;
+    ensure(
+        first.user_id == second.user_id,
+        "the external identity was registered twice",
+    )?;
+    ensure(
+        m.get_authenticate(second.logged_in_token.as_deref().unwrap_or_default())
+            .await?
+            .user_id
+            == first.user_id,
+        "external login without a valid token",
+    )?;
+    ensure(
+        m.login(&first.username, "").await.is_err(),
+        "external users can't log in with a password",
+    )?;
+    ensure(
+        matches!(
+            m.create_password_reset(&first.username, Duration::minutes(5))
+                .await,
+            Err(MemoryError::NotFound(_))
+        ),
+        "reset of an external user, it would get a password",
+    )
+}
+
+async fn exercises(m: Tested) -> CheckResult {
+    add_exercise(&m, "somma").await?;
+    add_exercise(&m, "prodotto").await?;
+    let (ty, source) = m.get_exercise("somma".to_string()).await?;
+    ensure(ty == TypeId::of::<Source>(), "wrong exercise type")?;
+    ensure(source == "source of somma", "wrong exercise source")?;
+    ensure(
+        matches!(
+            m.get_exercise("divisione".to_string()).await,
+            Err(MemoryError::NotFound(_))
+        ),
+        "unknown exercise found",
+    )?;
+    let mut names = m.list_exercise_names().await?;
+    names.sort();
+    ensure(
+        names == ["prodotto", "somma"],
+        "list_exercise_names is wrong",
+    )?;
+    // adding it again replaces it
+    m.add_exercise(
+        "somma".to_string(),
+        ConformanceState::Compiled(Compiled),
+        "new source".to_string(),
+    )
+    .await?;
+    let (ty, source) = m.get_exercise("somma".to_string()).await?;
+    ensure(
+        ty == TypeId::of::<Compiled>() && source == "new source",
+        "the exercise was not updated",
+    )?;
+    ensure(
+        m.list_exercise_names().await?.len() == 2,
+        "the updated exercise is duplicated",
+    )
+}
+
+async fn execution_plan(m: Tested) -> CheckResult {
+    let source = ConformanceState::Source(Source);
+    let compiled = ConformanceState::Compiled(Compiled);
+    let result = ConformanceState::ExerciseResult(ExerciseResult::default());
+    ensure(
+        m.get_execution_plan(&source).await?.is_empty(),
+        "plan without enabled executors",
+    )?;
+    m.enable_executor(&compiled, &result, "second".to_string())
+        .await?;
+    m.enable_executor(&source, &compiled, "first".to_string())
+        .await?;
+    let plan = m.get_execution_plan(&source).await?;
+    let expected = vec![
+        (
+            TypeId::of::<Source>(),
+            TypeId::of::<Compiled>(),
+            "first".to_string(),
+        ),
+        (
+            TypeId::of::<Compiled>(),
+            TypeId::of::<ExerciseResult>(),
+            "second".to_string(),
+        ),
+    ];
+    ensure(plan == expected, "wrong execution plan")?;
+    ensure(
+        m.get_execution_plan(&result).await?.is_empty(),
+        "plan from the last state",
+    )?;
+    // enabling another executor from the same state replaces the previous one
+    m.enable_executor(&source, &result, "direct".to_string())
+        .await?;
+    let plan = m.get_execution_plan(&source).await?;
+    ensure(
+        plan == [(
+            TypeId::of::<Source>(),
+            TypeId::of::<ExerciseResult>(),
+            "direct".to_string(),
+        )],
+        "the executor was not replaced",
+    )
+}
+
+async fn cycle_rejection(m: Tested) -> CheckResult {
+    let source = ConformanceState::Source(Source);
+    let compiled = ConformanceState::Compiled(Compiled);
+    let result = ConformanceState::ExerciseResult(ExerciseResult::default());
+    ensure(
+        matches!(
+            m.enable_executor(&source, &source, String::new()).await,
+            Err(MemoryError::Conflict(_))
+        ),
+        "self loop accepted",
+    )?;
+    m.enable_executor(&source, &compiled, String::new()).await?;
+    m.enable_executor(&compiled, &result, String::new()).await?;
+    ensure(
+        matches!(
+            m.enable_executor(&result, &source, String::new()).await,
+            Err(MemoryError::Conflict(_))
+        ),
+        "cycle accepted",
+    )?;
+    // the rejected executor must not be saved
+    ensure(
+        m.get_execution_plan(&source).await?.len() == 2,
+        "the rejected executor changed the plan",
+    )?;
+    ensure(
+        m.get_execution_plan(&result).await?.is_empty(),
+        "the rejected executor was saved",
+    )
+}
+
+async fn executor_management(m: Tested) -> CheckResult {
+    let source = ConformanceState::Source(Source);
+    let compiled = ConformanceState::Compiled(Compiled);
+    let result = ConformanceState::ExerciseResult(ExerciseResult::default());
+    ensure(
+        m.list_enabled_executors().await?.is_empty(),
+        "executors enabled in an empty memory",
+    )?;
+    ensure(
+        matches!(
+            m.disable_executor(&source).await,
+            Err(MemoryError::NotFound(_))
+        ),
+        "disabled an executor never enabled",
+    )?;
+    ensure(
+        matches!(
+            m.replace_executor_data(&source, "new".to_string()).await,
+            Err(MemoryError::NotFound(_))
+        ),
+        "replaced the data of an executor never enabled",
+    )?;
+    m.enable_executor(&source, &compiled, "first".to_string())
+        .await?;
+    m.enable_executor(&compiled, &result, "second".to_string())
+        .await?;
+    m.replace_executor_data(&source, "changed".to_string())
+        .await?;
+    let expected = vec![
+        (
+            TypeId::of::<Compiled>(),
+            TypeId::of::<ExerciseResult>(),
+            "second".to_string(),
+        ),
+        (
+            TypeId::of::<Source>(),
+            TypeId::of::<Compiled>(),
+            "changed".to_string(),
+        ),
+    ];
+    ensure(
+        m.list_enabled_executors().await? == expected,
+        "wrong enabled executors",
+    )?;
+    m.disable_executor(&compiled).await?;
+    ensure(
+        m.get_execution_plan(&source).await?
+            == [(
+                TypeId::of::<Source>(),
+                TypeId::of::<Compiled>(),
+                "changed".to_string(),
+            )],
+        "the disabled executor is still in the plan",
+    )?;
+    ensure(
+        matches!(
+            m.disable_executor(&compiled).await,
+            Err(MemoryError::NotFound(_))
+        ),
+        "disabled the same executor twice",
+    )?;
+    // a disabled executor does not take part in the cycle check anymore
+    m.enable_executor(&result, &compiled, String::new()).await?;
+    ensure(
+        m.list_enabled_executors().await?.len() == 2,
+        "wrong number of enabled executors",
+    )
+}
+
+async fn submission_ownership(m: Tested) -> CheckResult {
+    add_exercise(&m, "somma").await?;
+    let owner = logged(&m, "ciao").await?;
+    let other = logged(&m, "altro").await?;
+    ensure(
+        matches!(
+            m.add_submission("divisione".to_string(), String::new(), owner.clone())
+                .await,
+            Err(MemoryError::NotFound(_))
+        ),
+        "submission of an unknown exercise",
+    )?;
+    let id = m
+        .add_submission("somma".to_string(), "x".repeat(10_000), owner.clone())
+        .await?;
+    ensure(
+        matches!(
+            m.add_exercise_result(id, other, result(1.0)).await,
+            Err(MemoryError::Unauthorized)
+        ),
+        "result added by another user",
+    )?;
+    ensure(
+        m.get_submission(id).await?.result.is_none(),
+        "the rejected result was saved",
+    )?;
+    ensure(
+        matches!(
+            m.add_exercise_result(id + 1000, owner.clone(), result(1.0))
+                .await,
+            Err(MemoryError::NotFound(_))
+        ),
+        "result of an unknown submission",
+    )?;
+    m.add_exercise_result(id, owner, result(1.0)).await?;
+    let submission = m.get_submission(id).await?;
+    ensure(
+        submission.source.len() == 10_000,
+        "the source was not kept whole",
+    )?;
+    ensure(
+        submission.result == Some(result(1.0)),
+        "the result was not reconstructed",
+    )
+}
+
+async fn submission_history(m: Tested) -> CheckResult {
+    add_exercise(&m, "somma").await?;
+    add_exercise(&m, "prodotto").await?;
+    let user = logged(&m, "ciao").await?;
+    let other = logged(&m, "altro").await?;
+    let mut ids = Vec::new();
+    for (name, points) in [
+        ("somma", 2.0),
+        ("somma", 5.0),
+        ("prodotto", 9.0),
+        ("somma", 5.0),
+    ] {
+        let id = m
+            .add_submission(name.to_string(), String::new(), user.clone())
+            .await?;
+        m.add_exercise_result(id, user.clone(), result(points))
+            .await?;
+        ids.push(id);
+    }
+    let pending = m
+        .add_submission("somma".to_string(), String::new(), user.clone())
+        .await?;
+    m.add_submission("somma".to_string(), String::new(), other.clone())
+        .await?;
+
+    let filter = SubmissionFilter {
+        user_id: Some(user.user_id),
+        exercise_name: Some("somma".to_string()),
+        offset: 1,
+        limit: Some(2),
+    };
+    let page: Vec<i64> = m
+        .list_submissions(&filter)
+        .await?
+        .into_iter()
+        .map(|x| x.submission_id)
+        .collect();
+    ensure(page == [ids[1], ids[3]], "wrong page of submissions")?;
+    ensure(
+        m.list_submissions(&SubmissionFilter::default())
+            .await?
+            .len()
+            == 6,
+        "the default filter does not list everything",
+    )?;
+    let latest = m.get_latest_submission(user.user_id, "somma").await?;
+    ensure(
+        latest.map(|x| x.submission_id) == Some(pending),
+        "wrong latest submission",
+    )?;
+    let best = m.get_best_submission(user.user_id, "somma").await?;
+    ensure(
+        best.map(|x| x.submission_id) == Some(ids[3]),
+        "wrong best submission, ties are won by the latest",
+    )?;
+    ensure(
+        m.get_best_submission(other.user_id, "somma")
+            .await?
+            .is_none(),
+        "best submission without results",
+    )?;
+    ensure(
+        matches!(
+            m.get_submission(pending + 1000).await,
+            Err(MemoryError::NotFound(_))
+        ),
+        "unknown submission found",
+    )
+}
+
+async fn archive_records(m: Tested) -> CheckResult {
+    let user = UserRecord {
+        user_id: 1000,
+        username: "ciao".to_string(),
+        password_hash: crate::password::PasswordPolicy::default().hash("mondo")?,
+        is_admin: true,
+    };
+    let exercise = ExerciseRecord {
+        name: "somma".to_string(),
+        variant: "Source".to_string(),
+        source: "source of somma".to_string(),
+    };
+    let executor = ExecutorRecord {
+        input: "Source".to_string(),
+        output: "Compiled".to_string(),
+        data: "{}".to_string(),
+    };
+    m.import_exercise(&exercise).await?;
+    m.import_executor(&executor).await?;
+    let user_id = m.import_user(&user).await?;
+    let logged = m.login("ciao", "mondo").await?;
+    ensure(
+        logged.user_id == user_id && logged.is_admin,
+        "the user was not imported as it is",
+    )?;
+    // whole seconds, every backend keeps them
+    let submitted_at = DateTime::from_timestamp(1_700_000_000, 0).ok_or("invalid time")?;
+    let mut imported = Vec::new();
+    for graded in [Some(result(2.0)), None] {
+        let submission = Submission {
+            submission_id: 1000,
+            user_id,
+            exercise_name: "somma".to_string(),
+            source: "x".to_string(),
+            submitted_at,
+            result: graded,
+        };
+        let id = m.import_submission(&submission).await?;
+        imported.push(Submission {
+            submission_id: id,
+            ..submission
+        });
+    }
+    ensure(
+        imported[0].submission_id != imported[1].submission_id,
+        "imported submissions share the id",
+    )?;
+    let filter = SubmissionFilter::default();
+    ensure(
+        m.list_submissions(&filter).await? == imported,
+        "the submissions were not imported as they are",
+    )?;
+    ensure(
+        m.export_users().await?
+            == vec![UserRecord {
+                user_id,
+                ..user.clone()
+            }],
+        "wrong exported users",
+    )?;
+    ensure(
+        m.export_exercises().await? == vec![exercise],
+        "wrong exported exercises",
+    )?;
+    let replaced = ExecutorRecord {
+        output: "ExerciseResult".to_string(),
+        ..executor
+    };
+    m.import_executor(&replaced).await?;
+    ensure(
+        m.export_executors().await? == vec![replaced],
+        "the imported executor did not replace the old one",
+    )?;
+    ensure(
+        matches!(
+            m.import_user(&user).await,
+            Err(MemoryError::AlreadyExists(_))
+        ),
+        "imported a duplicate username",
+    )
+}
+
+async fn audit_log(m: Tested) -> CheckResult {
+    m.register("admin", "mondo").await?;
+    let admin: User<Admin> = m.login("admin", "mondo").await?.transmute();
+    m.audit(Some("ciao"), AuditAction::LoginFailure, "").await?;
+    m.audit(None, AuditAction::ExerciseAdded, "somma").await?;
+    let all = m.get_audit_log(&admin, &AuditFilter::default()).await?;
+    ensure(all.len() == 2, "events are missing")?;
+    ensure(all[0].id < all[1].id, "events are not ordered")?;
+    ensure(
+        all[0].actor.as_deref() == Some("ciao") && all[1].actor.is_none(),
+        "wrong actors",
+    )?;
+    let filter = AuditFilter {
+        action: Some(AuditAction::ExerciseAdded),
+        from: Some(all[0].time),
+        ..Default::default()
+    };
+    let added = m.get_audit_log(&admin, &filter).await?;
+    ensure(
+        added.len() == 1 && added[0].details == "somma",
+        "wrong filter by action",
+    )?;
+    let filter = AuditFilter {
+        to: Some(all[0].time),
+        ..Default::default()
+    };
+    ensure(
+        m.get_audit_log(&admin, &filter).await?.is_empty(),
+        "the upper bound is not exclusive",
+    )
+}
+
+/// A check that failed
+#[derive(Debug)]
+pub struct Failure {
+    /// name of the check
+    pub check: &'static str,
+    /// why it failed
+    pub error: String,
+}
+
+/// runs every check on a new memory, and returns the failed ones
+pub async fn run_conformance<F, Fut>(mut new_memory: F) -> Vec<Failure>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Tested>,
+{
+    macro_rules! checks {
+        ($($check:ident),+) => {{
+            let mut failures = Vec::new();
+            $(
+                if let Err(e) = $check(new_memory().await).await {
+                    failures.push(Failure {
+                        check: stringify!($check),
+                        error: e.to_string(),
+                    });
+                }
+            )+
+            failures
+        }};
+    }
+    checks!(
+        register_and_login,
+        duplicate_registration,
+        wrong_password,
+        token_lookup,
+        change_password,
+        password_reset,
+        external_login,
+        exercises,
+        execution_plan,
+        cycle_rejection,
+        executor_management,
+        submission_ownership,
+        submission_history,
+        archive_records,
+        audit_log
+    )
+}
+
+/// runs every check, and panics listing the failed ones
+pub async fn assert_conformance<F, Fut>(new_memory: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Tested>,
+{
+    let failures = run_conformance(new_memory).await;
+    if !failures.is_empty() {
+        let list: Vec<String> = failures
+            .iter()
+            .map(|x| format!("{}: {}", x.check, x.error))
+            .collect();
+        panic!("the memory is not conformant:\n{}", list.join("\n"));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{assert_conformance, ConformanceState};
+    use crate::default_memory::DefaultMemory;
+
+    #[tokio::test]
+    async fn test_default_memory() {
+        assert_conformance(|| async { DefaultMemory::init::<ConformanceState>() }).await;
+    }
+}
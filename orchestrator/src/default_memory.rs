@@ -1,15 +1,15 @@
 //! In this module we defined the DefaultMemory, and some helper function.
 //! This implementation must be taken as an example
+use crate::password::{PasswordPolicy, Verification, DISABLED_PASSWORD};
 use crate::prelude::*;
 
 use async_trait::async_trait;
-use chrono::Local;
+use chrono::{DateTime, Duration, Local, Utc};
 use rand::{distributions::Alphanumeric, Rng};
 use std::{
     any::TypeId,
     cell::RefCell,
     collections::{HashMap, HashSet},
-    error::Error as StdError,
 };
 use tokio::sync::Mutex;
 
@@ -20,52 +20,49 @@ struct InnerMemory {
     exercises: HashMap<String, (String, String)>,
     /// from, (into, data)
     activated_executors: HashMap<String, (String, String)>,
-    /// submission:
-    /// access by id (usize), user_id, problem_name, source, Option<Result>
-    submissions: Vec<(i64, String, String, Option<ExerciseResult>)>,
+    /// submissions, accessed by id (usize)
+    submissions: Vec<Submission>,
+    /// token, (username, expiration, already used)
+    password_resets: HashMap<String, (String, DateTime<Utc>, bool)>,
+    /// (issuer, subject), username
+    external_identities: HashMap<(String, String), String>,
+    /// append only
+    audit_log: Vec<AuditEvent>,
 }
 
 /// MUST be used only for testing, not recomended in production
 ///
-/// saves password as it is, and doesn't do any sanification...
+/// passwords are hashed following the PasswordPolicy, but everything else is kept in memory and doesn't do any sanification...
 ///
 /// in addition to that all users are admin by default
 pub struct DefaultMemory {
     inner: Mutex<RefCell<InnerMemory>>,
-}
-
-#[derive(thiserror::Error, Debug)]
-/// All errors that can be provided by this implementation
-pub enum Error {
-    /// if something is already present:
-    #[error("Already present")]
-    AlreadyPresent,
-
-    /// if something is not found
-    #[error("Not found")]
-    NotFound,
-
-    /// if it is not authorized ()
-    #[error("unauthorized")]
-    Unauthoraized,
-
-    /// if by activating an executor a cycle is detected
-    #[error("cycle detected")]
-    CycleDetected,
+    policy: PasswordPolicy,
 }
 
 impl DefaultMemory {
     /// Generates a new DefaultMemory
     pub fn init<S: ExecutorGlobalState>() -> Box<dyn Memory<S>> {
-        Box::new(Self {
+        Self::init_with_policy(PasswordPolicy::default())
+    }
+    /// Generates a new DefaultMemory, that hashes passwords following the given policy
+    pub fn init_with_policy<S: ExecutorGlobalState>(policy: PasswordPolicy) -> Box<dyn Memory<S>> {
+        Box::new(Self::new(policy))
+    }
+    fn new(policy: PasswordPolicy) -> Self {
+        Self {
             inner: Mutex::new(RefCell::new(InnerMemory {
                 id: 0,
                 users: HashMap::new(),
                 exercises: HashMap::new(),
                 activated_executors: HashMap::new(),
                 submissions: Vec::new(),
+                password_resets: HashMap::new(),
+                external_identities: HashMap::new(),
+                audit_log: Vec::new(),
             })),
-        })
+            policy,
+        }
     }
 }
 /// generate a new random token, it has a lenght of 20 alphanumeric characters
@@ -82,11 +79,12 @@ impl StatelessMemory for DefaultMemory {
         &self,
         username: &str,
         password: &str,
-    ) -> Result<User<Unauthenticated>, Box<dyn StdError>> {
+    ) -> Result<User<Unauthenticated>, MemoryError> {
+        let password_hash = self.policy.hash_async(password).await?;
         let mut binding = self.inner.lock().await;
         let inner = binding.get_mut();
         if inner.users.contains_key(username) {
-            Err(Error::AlreadyPresent)?;
+            Err(MemoryError::AlreadyExists(format!("user {username}")))?;
         }
         let user_id = inner.id;
         inner.id += 1;
@@ -95,7 +93,7 @@ impl StatelessMemory for DefaultMemory {
             ph: std::marker::PhantomData,
             user_id,
             username: username.to_string(),
-            password_hash: password.to_string(),
+            password_hash,
             logged_in_time: None,
             logged_in_token: None,
             is_admin: true,
@@ -109,44 +107,174 @@ impl StatelessMemory for DefaultMemory {
         &self,
         username: &str,
         password: &str,
-    ) -> Result<User<Authenticated>, Box<dyn StdError>> {
-        let user = self.get_by_username(username).await?;
-        if user.password_hash != password {
-            Err(Error::Unauthoraized)?
+    ) -> Result<User<Authenticated>, MemoryError> {
+        // an unknown user is not told apart from a wrong password
+        let user = self
+            .get_by_username(username)
+            .await
+            .map_err(|x| match x {
+                MemoryError::NotFound(_) => MemoryError::Unauthorized,
+                x => x,
+            })?;
+        let rehash = match self
+            .policy
+            .verify_async(password, &user.password_hash)
+            .await?
+        {
+            Verification::Invalid => Err(MemoryError::Unauthorized)?,
+            Verification::Valid => None,
+            Verification::NeedsRehash => Some(self.policy.hash_async(password).await?),
+        };
+        let mut binding = self.inner.lock().await;
+        let inner = binding.get_mut();
+        let stored = inner
+            .users
+            .get_mut(username)
+            .ok_or_else(|| MemoryError::NotFound(format!("user {username}")))?;
+        // the password could have been changed while hashing, it is not overwritten
+        if let Some(password_hash) = rehash {
+            if stored.password_hash == user.password_hash {
+                stored.password_hash = password_hash;
+            }
         }
-        let mut user = user.clone().transmute();
+        stored.logged_in_time = Some(Local::now().to_utc());
+        stored.logged_in_token = Some(new_token());
+        Ok(stored.clone().transmute())
+    }
+
+    async fn login_external(
+        &self,
+        identity: &ExternalIdentity,
+    ) -> Result<User<Authenticated>, MemoryError> {
+        let mut binding = self.inner.lock().await;
+        let inner = binding.get_mut();
+        let key = (identity.issuer.clone(), identity.subject.clone());
+        let username = match inner.external_identities.get(&key) {
+            Some(username) => username.clone(),
+            None => {
+                // first login, register it
+                let mut username = identity.preferred_username();
+                if inner.users.contains_key(&username) {
+                    username = format!("{} ({})", username, identity.subject);
+                }
+                if inner.users.contains_key(&username) {
+                    Err(MemoryError::AlreadyExists(format!("user {username}")))?
+                }
+                let user = User {
+                    ph: std::marker::PhantomData,
+                    user_id: inner.id,
+                    username: username.clone(),
+                    password_hash: DISABLED_PASSWORD.to_string(),
+                    logged_in_time: None,
+                    logged_in_token: None,
+                    is_admin: true,
+                };
+                inner.id += 1;
+                inner.users.insert(username.clone(), user);
+                inner.external_identities.insert(key, username.clone());
+                username
+            }
+        };
+        let user = inner
+            .users
+            .get_mut(&username)
+            .ok_or_else(|| MemoryError::NotFound(format!("user {username}")))?;
         user.logged_in_time = Some(Local::now().to_utc());
         user.logged_in_token = Some(new_token());
+        Ok(user.clone().transmute())
+    }
+
+    async fn change_password(
+        &self,
+        user: User<Authenticated>,
+        old_password: &str,
+        new_password: &str,
+    ) -> Result<(), MemoryError> {
+        let stored = self.get_by_username(&user.username).await?;
+        if !self
+            .policy
+            .verify_async(old_password, &stored.password_hash)
+            .await?
+            .is_valid()
+        {
+            Err(MemoryError::Unauthorized)?
+        }
+        let password_hash = self.policy.hash_async(new_password).await?;
         let mut binding = self.inner.lock().await;
         let inner = binding.get_mut();
-        let _ = inner
+        let stored = inner
             .users
-            .insert(user.username.clone(), user.clone().transmute())
-            .unwrap();
-        Ok(user)
+            .get_mut(&user.username)
+            .ok_or_else(|| MemoryError::NotFound(format!("user {}", user.username)))?;
+        stored.password_hash = password_hash;
+        Ok(())
     }
 
-    async fn get_by_username(
+    async fn create_password_reset(
         &self,
         username: &str,
-    ) -> Result<User<Unauthenticated>, Box<dyn StdError>> {
+        validity: Duration,
+    ) -> Result<PasswordReset, MemoryError> {
+        let mut binding = self.inner.lock().await;
+        let inner = binding.get_mut();
+        let has_password = inner
+            .users
+            .get(username)
+            .is_some_and(|x| x.password_hash != DISABLED_PASSWORD);
+        if !has_password {
+            Err(MemoryError::NotFound(format!("user {username}")))?
+        }
+        let reset = PasswordReset {
+            username: username.to_string(),
+            token: new_token(),
+            expires_at: Local::now().to_utc() + validity,
+        };
+        inner.password_resets.insert(
+            reset.token.clone(),
+            (reset.username.clone(), reset.expires_at, false),
+        );
+        Ok(reset)
+    }
+
+    async fn reset_password(&self, token: &str, new_password: &str) -> Result<(), MemoryError> {
+        let password_hash = self.policy.hash_async(new_password).await?;
+        let mut binding = self.inner.lock().await;
+        let inner = binding.get_mut();
+        let (username, expires_at, used) = inner
+            .password_resets
+            .get_mut(token)
+            .ok_or(MemoryError::Unauthorized)?;
+        if *used || *expires_at < Local::now().to_utc() {
+            Err(MemoryError::Unauthorized)?
+        }
+        *used = true;
+        let user = inner
+            .users
+            .get_mut(username)
+            .ok_or_else(|| MemoryError::NotFound(format!("user {username}")))?;
+        user.password_hash = password_hash;
+        user.logged_in_token = None;
+        Ok(())
+    }
+
+    async fn get_by_username(&self, username: &str) -> Result<User<Unauthenticated>, MemoryError> {
         let mut binding = self.inner.lock().await;
         let inner = binding.get_mut();
-        let user = inner.users.get(username).ok_or(Error::NotFound)?;
+        let user = inner
+            .users
+            .get(username)
+            .ok_or_else(|| MemoryError::NotFound(format!("user {username}")))?;
         Ok(user.clone())
     }
 
-    async fn get_all_users(&self) -> Result<Vec<User<Unauthenticated>>, Box<dyn StdError>> {
+    async fn get_all_users(&self) -> Result<Vec<User<Unauthenticated>>, MemoryError> {
         let mut binding = self.inner.lock().await;
         let inner = binding.get_mut();
         let user: Vec<User<Unauthenticated>> = inner.users.values().cloned().collect();
         Ok(user)
     }
     ///check if the given token is valid, and if so returns the correct user
-    async fn get_authenticate(
-        &self,
-        token: &str,
-    ) -> Result<User<Authenticated>, Box<dyn StdError>> {
+    async fn get_authenticate(&self, token: &str) -> Result<User<Authenticated>, MemoryError> {
         let mut binding = self.inner.lock().await;
         let inner = binding.get_mut();
         //let token = token.to_string();
@@ -165,20 +293,20 @@ impl StatelessMemory for DefaultMemory {
                 }
             })
             .next()
-            .ok_or(Error::Unauthoraized)?;
+            .ok_or(MemoryError::Unauthorized)?;
         Ok(user)
     }
 
-    async fn get_admin(&self, token: &str) -> Result<User<Admin>, Box<dyn StdError>> {
+    async fn get_admin(&self, token: &str) -> Result<User<Admin>, MemoryError> {
         let user = self.get_authenticate(token).await?;
         if user.is_admin {
             Ok(user.transmute())
         } else {
-            Err(Error::Unauthoraized.into())
+            Err(MemoryError::Unauthorized)
         }
     }
 
-    async fn list_exercise_names(&self) -> Result<Vec<String>, Box<dyn StdError>> {
+    async fn list_exercise_names(&self) -> Result<Vec<String>, MemoryError> {
         let mut lock = self.inner.lock().await;
         Ok(lock.get_mut().exercises.keys().cloned().collect())
     }
@@ -189,16 +317,23 @@ impl StatelessMemory for DefaultMemory {
         exercise_name: String,
         source: String,
         user: User<Authenticated>,
-    ) -> Result<i64, Box<dyn StdError + Send + Sync>> {
+    ) -> Result<i64, MemoryError> {
         let mut lock = self.inner.lock().await;
         if !lock.get_mut().exercises.contains_key(&exercise_name) {
-            Err("unknown problem")?
+            Err(MemoryError::NotFound(format!("exercise {exercise_name}")))?
         }
         let submissions = &mut lock.get_mut().submissions;
 
-        submissions.push((user.user_id, exercise_name, source, None));
-        let len = submissions.len() - 1;
-        Ok(len as i64)
+        let submission_id = submissions.len() as i64;
+        submissions.push(Submission {
+            submission_id,
+            user_id: user.user_id,
+            exercise_name,
+            source,
+            submitted_at: Utc::now(),
+            result: None,
+        });
+        Ok(submission_id)
     }
 
     ///add exercise result
@@ -207,19 +342,229 @@ impl StatelessMemory for DefaultMemory {
         submission_id: i64,
         user: User<Authenticated>,
         result: ExerciseResult,
-    ) -> Result<(), Box<dyn StdError + Send + Sync>> {
+    ) -> Result<(), MemoryError> {
         let mut lock = self.inner.lock().await;
         let submission = lock
             .get_mut()
             .submissions
             .get_mut(submission_id as usize)
-            .ok_or(format!("invalid submission id ({})", submission_id).as_str())?;
-        if submission.0 != user.user_id {
-            Err("incorrect user id")?
+            .ok_or_else(|| MemoryError::NotFound(format!("submission {submission_id}")))?;
+        if submission.user_id != user.user_id {
+            Err(MemoryError::Unauthorized)?
         }
-        submission.3 = Some(result);
+        submission.result = Some(result);
+        Ok(())
+    }
+
+    async fn get_submission(&self, submission_id: i64) -> Result<Submission, MemoryError> {
+        let mut lock = self.inner.lock().await;
+        let submission = usize::try_from(submission_id)
+            .ok()
+            .and_then(|x| lock.get_mut().submissions.get(x))
+            .ok_or_else(|| MemoryError::NotFound(format!("submission {submission_id}")))?;
+        Ok(submission.clone())
+    }
+
+    async fn list_submissions(
+        &self,
+        filter: &SubmissionFilter,
+    ) -> Result<Vec<Submission>, MemoryError> {
+        let mut lock = self.inner.lock().await;
+        Ok(lock
+            .get_mut()
+            .submissions
+            .iter()
+            .filter(|x| filter.matches(x))
+            .skip(filter.offset)
+            .take(filter.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect())
+    }
+
+    async fn get_latest_submission(
+        &self,
+        user_id: i64,
+        exercise_name: &str,
+    ) -> Result<Option<Submission>, MemoryError> {
+        let mut lock = self.inner.lock().await;
+        Ok(lock
+            .get_mut()
+            .submissions
+            .iter()
+            .rev()
+            .find(|x| x.user_id == user_id && x.exercise_name == exercise_name)
+            .cloned())
+    }
+
+    async fn get_best_submission(
+        &self,
+        user_id: i64,
+        exercise_name: &str,
+    ) -> Result<Option<Submission>, MemoryError> {
+        let mut lock = self.inner.lock().await;
+        Ok(lock
+            .get_mut()
+            .submissions
+            .iter()
+            .filter(|x| x.user_id == user_id && x.exercise_name == exercise_name)
+            .filter_map(|x| Some((x.result.as_ref()?.points(), x)))
+            // max_by returns the last of the equal elements
+            .max_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, x)| x.clone()))
+    }
+
+    async fn audit(
+        &self,
+        actor: Option<&str>,
+        action: AuditAction,
+        details: &str,
+    ) -> Result<(), MemoryError> {
+        let mut lock = self.inner.lock().await;
+        let log = &mut lock.get_mut().audit_log;
+        log.push(AuditEvent {
+            id: log.len() as i64,
+            time: Utc::now(),
+            actor: actor.map(|x| x.to_string()),
+            action,
+            details: details.to_string(),
+        });
         Ok(())
     }
+
+    async fn get_audit_log(
+        &self,
+        _admin: &User<Admin>,
+        filter: &AuditFilter,
+    ) -> Result<Vec<AuditEvent>, MemoryError> {
+        let mut lock = self.inner.lock().await;
+        Ok(lock
+            .get_mut()
+            .audit_log
+            .iter()
+            .filter(|x| filter.matches(x))
+            .cloned()
+            .collect())
+    }
+
+    async fn export_users(&self) -> Result<Vec<UserRecord>, MemoryError> {
+        let mut lock = self.inner.lock().await;
+        let mut users: Vec<UserRecord> = lock
+            .get_mut()
+            .users
+            .values()
+            .map(|x| UserRecord {
+                user_id: x.user_id,
+                username: x.username.clone(),
+                password_hash: x.password_hash.clone(),
+                is_admin: x.is_admin,
+            })
+            .collect();
+        users.sort_by_key(|x| x.user_id);
+        Ok(users)
+    }
+
+    async fn export_exercises(&self) -> Result<Vec<ExerciseRecord>, MemoryError> {
+        let mut lock = self.inner.lock().await;
+        let mut exercises: Vec<ExerciseRecord> = lock
+            .get_mut()
+            .exercises
+            .iter()
+            .map(|(name, (variant, source))| ExerciseRecord {
+                name: name.clone(),
+                variant: variant.clone(),
+                source: source.clone(),
+            })
+            .collect();
+        exercises.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(exercises)
+    }
+
+    async fn export_executors(&self) -> Result<Vec<ExecutorRecord>, MemoryError> {
+        let mut lock = self.inner.lock().await;
+        let mut executors: Vec<ExecutorRecord> = lock
+            .get_mut()
+            .activated_executors
+            .iter()
+            .map(|(input, (output, data))| ExecutorRecord {
+                input: input.clone(),
+                output: output.clone(),
+                data: data.clone(),
+            })
+            .collect();
+        executors.sort_by(|a, b| a.input.cmp(&b.input));
+        Ok(executors)
+    }
+
+    async fn import_user(&self, user: &UserRecord) -> Result<i64, MemoryError> {
+        let mut binding = self.inner.lock().await;
+        let inner = binding.get_mut();
+        if inner.users.contains_key(&user.username) {
+            Err(MemoryError::AlreadyExists(format!(
+                "user {}",
+                user.username
+            )))?;
+        }
+        let user_id = inner.id;
+        inner.id += 1;
+        inner.users.insert(
+            user.username.clone(),
+            User {
+                ph: std::marker::PhantomData,
+                user_id,
+                username: user.username.clone(),
+                password_hash: user.password_hash.clone(),
+                logged_in_time: None,
+                logged_in_token: None,
+                is_admin: user.is_admin,
+            },
+        );
+        Ok(user_id)
+    }
+
+    async fn import_exercise(&self, exercise: &ExerciseRecord) -> Result<(), MemoryError> {
+        let mut lock = self.inner.lock().await;
+        lock.get_mut().exercises.insert(
+            exercise.name.clone(),
+            (exercise.variant.clone(), exercise.source.clone()),
+        );
+        Ok(())
+    }
+
+    async fn import_executor(&self, executor: &ExecutorRecord) -> Result<(), MemoryError> {
+        let mut lock = self.inner.lock().await;
+        lock.get_mut().activated_executors.insert(
+            executor.input.clone(),
+            (executor.output.clone(), executor.data.clone()),
+        );
+        Ok(())
+    }
+
+    async fn import_submission(&self, submission: &Submission) -> Result<i64, MemoryError> {
+        let mut binding = self.inner.lock().await;
+        let inner = binding.get_mut();
+        if !inner.exercises.contains_key(&submission.exercise_name) {
+            Err(MemoryError::NotFound(format!(
+                "exercise {}",
+                submission.exercise_name
+            )))?
+        }
+        if !inner
+            .users
+            .values()
+            .any(|x| x.user_id == submission.user_id)
+        {
+            Err(MemoryError::NotFound(format!(
+                "user {}",
+                submission.user_id
+            )))?
+        }
+        let submission_id = inner.submissions.len() as i64;
+        inner.submissions.push(Submission {
+            submission_id,
+            ..submission.clone()
+        });
+        Ok(submission_id)
+    }
 }
 
 #[async_trait]
@@ -231,7 +576,7 @@ impl<S: ExecutorGlobalState> StateMemory<S> for DefaultMemory {
         input: &S,
         output: &S,
         data: String,
-    ) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+    ) -> Result<(), MemoryError> {
         let mut lock = self.inner.lock().await;
         //get all correspondence
         let t = &mut lock.get_mut().activated_executors;
@@ -246,7 +591,7 @@ impl<S: ExecutorGlobalState> StateMemory<S> for DefaultMemory {
             .collect();
         // if adding this element we get a cycle, we return an error
         if has_cycles(&map) {
-            return Err(Error::CycleDetected.into());
+            return Err(MemoryError::Conflict("cycle detected".to_string()));
         }
         t.insert(inp, (out, data));
         Ok(())
@@ -254,26 +599,56 @@ impl<S: ExecutorGlobalState> StateMemory<S> for DefaultMemory {
     async fn get_execution_plan(
         &self,
         input: &S,
-    ) -> Result<Vec<(TypeId, TypeId, String)>, Box<dyn StdError + Send + Sync + 'static>> {
+    ) -> Result<Vec<(TypeId, TypeId, String)>, MemoryError> {
         let mut lock = self.inner.lock().await;
         let t = &lock.get_mut().activated_executors;
         let mut cur = input.serialize_variant();
         let mut ret = Vec::new();
         while let Some((next, data)) = t.get(&cur) {
-            let cur_ty = S::deserialize_variant(&cur).map_err(|_| "Not deserializable")?;
-            let next_ty = S::deserialize_variant(next).map_err(|_| "Not deserializable")?;
+            let cur_ty = S::deserialize_variant(&cur).map_err(MemoryError::Backend)?;
+            let next_ty = S::deserialize_variant(next).map_err(MemoryError::Backend)?;
             ret.push((cur_ty, next_ty, data.clone()));
             cur.clone_from(next);
         }
         Ok(ret)
     }
+    async fn disable_executor(&self, input: &S) -> Result<(), MemoryError> {
+        let mut lock = self.inner.lock().await;
+        lock.get_mut()
+            .activated_executors
+            .remove(&input.serialize_variant())
+            .ok_or_else(|| MemoryError::NotFound("executor".to_string()))?;
+        Ok(())
+    }
+    async fn replace_executor_data(&self, input: &S, data: String) -> Result<(), MemoryError> {
+        let mut lock = self.inner.lock().await;
+        let (_, old) = lock
+            .get_mut()
+            .activated_executors
+            .get_mut(&input.serialize_variant())
+            .ok_or_else(|| MemoryError::NotFound("executor".to_string()))?;
+        *old = data;
+        Ok(())
+    }
+    async fn list_enabled_executors(&self) -> Result<Vec<(TypeId, TypeId, String)>, MemoryError> {
+        let mut lock = self.inner.lock().await;
+        let mut enabled: Vec<_> = lock.get_mut().activated_executors.iter().collect();
+        enabled.sort_by(|a, b| a.0.cmp(b.0));
+        let mut ret = Vec::new();
+        for (from, (into, data)) in enabled {
+            let from_ty = S::deserialize_variant(from).map_err(MemoryError::Backend)?;
+            let into_ty = S::deserialize_variant(into).map_err(MemoryError::Backend)?;
+            ret.push((from_ty, into_ty, data.clone()));
+        }
+        Ok(ret)
+    }
     /// add an exercise to memory
     async fn add_exercise(
         &self,
         name: String,
         exercise_type: S,
         source: String,
-    ) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+    ) -> Result<(), MemoryError> {
         let ex_type = exercise_type.serialize_variant();
         let mut lock = self.inner.lock().await;
         lock.get_mut().exercises.insert(name, (ex_type, source));
@@ -282,17 +657,14 @@ impl<S: ExecutorGlobalState> StateMemory<S> for DefaultMemory {
 
     /// get an exercise from memory
     /// type, source
-    async fn get_exercise(
-        &self,
-        name: String,
-    ) -> Result<(TypeId, String), Box<dyn StdError + Send + Sync + 'static>> {
+    async fn get_exercise(&self, name: String) -> Result<(TypeId, String), MemoryError> {
         let mut lock = self.inner.lock().await;
         let (ty, source) = lock
             .get_mut()
             .exercises
             .get(&name)
-            .ok_or("not found in memory")?;
-        let ty = S::deserialize_variant(ty)?;
+            .ok_or_else(|| MemoryError::NotFound(format!("exercise {name}")))?;
+        let ty = S::deserialize_variant(ty).map_err(MemoryError::Backend)?;
         Ok((ty, source.clone()))
     }
 }
@@ -327,7 +699,111 @@ pub fn has_cycles(vertex: &HashMap<String, String>) -> bool {
 mod test {
     use std::collections::HashMap;
 
-    use super::has_cycles;
+    use super::{has_cycles, DefaultMemory};
+    use crate as orchestrator;
+    use crate::password::{HashAlgorithm, PasswordPolicy, Verification};
+    use crate::prelude::{
+        AuditAction, AuditFilter, ExerciseResult, ExternalIdentity, StatelessMemory,
+        SubmissionFilter, TestResult,
+    };
+    use crate::GenerateState;
+    use chrono::Duration;
+
+    GenerateState!(ExerciseResult);
+
+    #[tokio::test]
+    async fn test_change_password() {
+        let m = DefaultMemory::init::<State>();
+        let user = m.register("ciao", "mondo").await.unwrap();
+        assert_ne!(user.password_hash, "mondo");
+        let user = m.login("ciao", "mondo").await.unwrap();
+
+        assert!(m
+            .change_password(user.clone(), "wrong", "nuovo")
+            .await
+            .is_err());
+        m.change_password(user, "mondo", "nuovo").await.unwrap();
+        assert!(m.login("ciao", "mondo").await.is_err());
+        m.login("ciao", "nuovo").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reset_password() {
+        let m = DefaultMemory::init::<State>();
+        m.register("ciao", "mondo").await.unwrap();
+        let logged = m.login("ciao", "mondo").await.unwrap();
+        assert!(m
+            .create_password_reset("unknown", Duration::minutes(5))
+            .await
+            .is_err());
+
+        let reset = m
+            .create_password_reset("ciao", Duration::minutes(5))
+            .await
+            .unwrap();
+        assert!(m.reset_password("not a token", "nuovo").await.is_err());
+        m.reset_password(&reset.token, "nuovo").await.unwrap();
+        // single use
+        assert!(m.reset_password(&reset.token, "altro").await.is_err());
+        // old session is closed
+        assert!(m
+            .get_authenticate(logged.logged_in_token.as_ref().unwrap())
+            .await
+            .is_err());
+        m.login("ciao", "nuovo").await.unwrap();
+
+        // expired
+        let reset = m
+            .create_password_reset("ciao", Duration::minutes(-1))
+            .await
+            .unwrap();
+        assert!(m.reset_password(&reset.token, "altro").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_login_external() {
+        let m = DefaultMemory::init::<State>();
+        m.register("ciao@example.com", "mondo").await.unwrap();
+        let identity = ExternalIdentity {
+            issuer: "https://idp.example.com".to_string(),
+            subject: "1234".to_string(),
+            email: Some("ciao@example.com".to_string()),
+            name: None,
+        };
+        let first = m.login_external(&identity).await.unwrap();
+        // the password account with the same email is not taken over
+        assert_eq!(first.username, "ciao@example.com (1234)");
+        let second = m.login_external(&identity).await.unwrap();
+        assert_eq!(first.user_id, second.user_id);
+        assert_ne!(first.logged_in_token, second.logged_in_token);
+        assert_eq!(m.get_all_users().await.unwrap().len(), 2);
+        // it can't log in with a password
+        assert!(m.login(&first.username, "").await.is_err());
+        assert!(m.login(&first.username, "!").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rehash_on_login() {
+        let mut m = DefaultMemory::new(PasswordPolicy::default());
+        m.register("ciao", "mondo").await.unwrap();
+        let old_hash = m.get_by_username("ciao").await.unwrap().password_hash;
+
+        // same users, but stronger policy
+        m.policy = PasswordPolicy {
+            algorithm: HashAlgorithm::Scrypt {
+                log_n: 11,
+                r: 8,
+                p: 1,
+            },
+        };
+        m.login("ciao", "mondo").await.unwrap();
+        let new_hash = m.get_by_username("ciao").await.unwrap().password_hash;
+        assert_ne!(old_hash, new_hash);
+        assert_eq!(
+            m.policy.verify("mondo", &new_hash).unwrap(),
+            Verification::Valid
+        );
+    }
 
     #[test]
     fn test_has_cycles() {
@@ -341,7 +817,7 @@ mod test {
             .into_iter()
             .map(|(a, b)| (a.to_string(), b.to_string()))
             .collect();
-        assert_eq!(has_cycles(&to), false);
+        assert!(!has_cycles(&to));
         t.insert("6", "2");
         t.insert("3", "4");
         let to: HashMap<String, String> = t
@@ -349,6 +825,141 @@ mod test {
             .into_iter()
             .map(|(a, b)| (a.to_string(), b.to_string()))
             .collect();
-        assert_eq!(has_cycles(&to), true);
+        assert!(has_cycles(&to));
+    }
+
+    #[tokio::test]
+    async fn test_audit_log() {
+        let m = DefaultMemory::init::<State>();
+        m.register("admin", "mondo").await.unwrap();
+        let admin = m.login("admin", "mondo").await.unwrap();
+        let admin = m
+            .get_admin(admin.logged_in_token.as_ref().unwrap())
+            .await
+            .unwrap();
+        m.audit(Some("admin"), AuditAction::LoginSuccess, "")
+            .await
+            .unwrap();
+        m.audit(Some("ciao"), AuditAction::LoginFailure, "")
+            .await
+            .unwrap();
+        m.audit(None, AuditAction::ExerciseAdded, "somma")
+            .await
+            .unwrap();
+
+        let all = m
+            .get_audit_log(&admin, &AuditFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(all.len(), 3);
+        assert!(all.windows(2).all(|x| x[0].id < x[1].id));
+
+        let filter = AuditFilter {
+            actor: Some("ciao".to_string()),
+            ..Default::default()
+        };
+        let failures = m.get_audit_log(&admin, &filter).await.unwrap();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].action, AuditAction::LoginFailure);
+
+        let filter = AuditFilter {
+            action: Some(AuditAction::ExerciseAdded),
+            from: Some(all[0].time),
+            to: Some(all[2].time + Duration::seconds(1)),
+            ..Default::default()
+        };
+        let added = m.get_audit_log(&admin, &filter).await.unwrap();
+        assert_eq!(added.len(), 1);
+        assert_eq!(added[0].details, "somma");
+
+        let filter = AuditFilter {
+            to: Some(all[0].time),
+            ..Default::default()
+        };
+        assert!(m.get_audit_log(&admin, &filter).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_submission_history() {
+        let m = DefaultMemory::init::<State>();
+        m.register("ciao", "mondo").await.unwrap();
+        let user = m.login("ciao", "mondo").await.unwrap();
+        for exercise in ["somma", "prodotto"] {
+            let state = State::ExerciseResult(ExerciseResult::default());
+            m.add_exercise(exercise.to_string(), state, String::new())
+                .await
+                .unwrap();
+        }
+        let result = |points| ExerciseResult {
+            tests: HashMap::from([(
+                "test".to_string(),
+                TestResult {
+                    points_given: points,
+                    ..Default::default()
+                },
+            )]),
+        };
+        let mut ids = Vec::new();
+        for (exercise, points) in [
+            ("somma", 2.0),
+            ("somma", 5.0),
+            ("prodotto", 1.0),
+            ("somma", 5.0),
+        ] {
+            let id = m
+                .add_submission(
+                    exercise.to_string(),
+                    format!("{exercise} {points}"),
+                    user.clone(),
+                )
+                .await
+                .unwrap();
+            m.add_exercise_result(id, user.clone(), result(points))
+                .await
+                .unwrap();
+            ids.push(id);
+        }
+        // not graded yet
+        let pending = m
+            .add_submission("somma".to_string(), "pending".to_string(), user.clone())
+            .await
+            .unwrap();
+
+        let submission = m.get_submission(ids[1]).await.unwrap();
+        assert_eq!(submission.source, "somma 5");
+        assert_eq!(submission.result, Some(result(5.0)));
+        assert!(m.get_submission(pending).await.unwrap().result.is_none());
+        assert!(m.get_submission(1000).await.is_err());
+
+        let filter = SubmissionFilter {
+            exercise_name: Some("somma".to_string()),
+            offset: 1,
+            limit: Some(2),
+            ..Default::default()
+        };
+        let page = m.list_submissions(&filter).await.unwrap();
+        assert_eq!(
+            page.iter().map(|x| x.submission_id).collect::<Vec<_>>(),
+            vec![ids[1], ids[3]]
+        );
+        let filter = SubmissionFilter {
+            user_id: Some(user.user_id + 1),
+            ..Default::default()
+        };
+        assert!(m.list_submissions(&filter).await.unwrap().is_empty());
+
+        let latest = m
+            .get_latest_submission(user.user_id, "somma")
+            .await
+            .unwrap();
+        assert_eq!(latest.unwrap().submission_id, pending);
+        // ties are won by the latest graded submission
+        let best = m.get_best_submission(user.user_id, "somma").await.unwrap();
+        assert_eq!(best.unwrap().submission_id, ids[3]);
+        assert!(m
+            .get_best_submission(user.user_id, "divisione")
+            .await
+            .unwrap()
+            .is_none());
     }
 }
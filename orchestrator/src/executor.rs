@@ -1,6 +1,6 @@
 //! This module contains the definition of executor and States
 use std::{
-    any::{Any, TypeId},
+    any::{type_name, Any, TypeId},
     error::Error as StdError,
     pin::Pin,
 };
@@ -115,6 +115,9 @@ pub trait AddExecutor<Input: ExecutorState, Out: ExecutorState> {
     ) -> impl Future<Output = Result<(), Error>>;
 }
 
+/// checks that a serialized data can be given to an executor
+pub type DataCheck = fn(&str) -> serde_json::Result<()>;
+
 /// Type definition to simplify additional types:
 /// It is dynamic Future that return a state or an boxed error.
 pub type ExecutorFuture<S> = Pin<
@@ -168,8 +171,19 @@ where
         }
         // check if it is working
 
-        self.executors
-            .insert((TypeId::of::<Input>(), TypeId::of::<Output>()), Box::new(f));
+        let key = (TypeId::of::<Input>(), TypeId::of::<Output>());
+        self.executors.insert(key, Box::new(f));
+        self.state_names
+            .insert(TypeId::of::<Input>(), type_name::<Input>());
+        self.state_names
+            .insert(TypeId::of::<Output>(), type_name::<Output>());
+        // known also when the executor has been enabled before (or imported)
+        let input: S = Input::async_default().await.into();
+        let output: S = Output::async_default().await.into();
+        self.known_states.insert(input.serialize_variant(), input);
+        self.known_states.insert(output.serialize_variant(), output);
+        let check: DataCheck = |data| serde_json::from_str::<Data>(data).map(|_| ());
+        self.data_checks.insert(key, check);
         Ok(())
     }
 
@@ -188,10 +202,21 @@ where
             return Err(Error::UnregisteredExecutor);
         }
         let data_string = serde_json::to_string(&data)?;
+        let details = format!(
+            "{} -> {}: {}",
+            i.serialize_variant(),
+            o.serialize_variant(),
+            data_string
+        );
         self.memory()
             .enable_executor(&i, &o, data_string)
             .await
-            .map_err(|_| Error::CycleDetected)?;
+            .map_err(|x| match x {
+                MemoryError::Conflict(_) => Error::CycleDetected,
+                x => Error::Memory(x),
+            })?;
+        self.audit(None, AuditAction::ExecutorEnabled, &details)
+            .await;
         Ok(())
     }
 }
@@ -226,9 +251,18 @@ pub enum Error {
     /// Not found an implementation fot that particular executor
     #[error("Not a registered executor")]
     UnregisteredExecutor,
+    /// No executor is enabled from that state
+    #[error("executor not enabled")]
+    NotEnabled,
+    /// The state has never been seen by the orchestrator
+    #[error("unknown state: {0}")]
+    UnknownState(String),
     /// Impossible to serialize, something is wrong
     #[error("Json serialize Error: {0}")]
     Json(#[from] serde_json::Error),
+    /// The memory failed
+    #[error("Memory Error: {0}")]
+    Memory(#[from] MemoryError),
 }
 #[cfg(test)]
 mod test {
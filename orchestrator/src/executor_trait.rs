@@ -36,13 +36,19 @@ pub trait ExerciseDef: Any + Send + Sync {
     fn list(&self) -> Vec<TestDefinition>;
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 /// Results of a common Exercise. It contains a list of the results of each test.
 pub struct ExerciseResult {
     /// list of the result of each tests:
     /// The key is the name, and TestResult contains all other informations
     pub tests: HashMap<String, TestResult>,
 }
+impl ExerciseResult {
+    /// total of the points awarded by all tests
+    pub fn points(&self) -> f64 {
+        self.tests.values().map(|x| x.points_given).sum()
+    }
+}
 impl Display for ExerciseResult {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let _ = writeln!(f, "ExerciseResult:");
@@ -59,7 +65,7 @@ impl Display for ExerciseResult {
             x.0.cmp(&x.0)
         });
         for (name, result) in v {
-            if let CompilationResult::Error(x) = result.compiled {
+            if let CompilationResult::Error(x, _) = result.compiled {
                 let _ = write!(
                     f,
                     "   {}: {} {}",
@@ -67,11 +73,17 @@ impl Display for ExerciseResult {
                     "Compilation Error:".red(),
                     x
                 );
-            } else if let RunResult::Error(x) = result.runned {
-                let _ = write!(f, "   {}: {} {}", name.green(), "Run Error:".red(), x);
+            } else if let RunResult::Error(x) = &result.runned {
+                let _ = writeln!(f, "   {}: {} {}", name.green(), "Run Error:".red(), x);
             } else {
                 let _ = write!(f, "   {}: {}", name, result);
             }
+            // the output is shown only for failed tests
+            if let Some(diagnostics) = &result.diagnostics {
+                if result.runned != RunResult::Ok {
+                    let _ = write!(f, "{}", diagnostics);
+                }
+            }
         }
         write!(f, "")
     }
@@ -86,6 +98,9 @@ pub struct TestResult {
     pub runned: RunResult,
     /// Points awarded
     pub points_given: f64,
+    /// what happened while it was running, None if it was not run
+    #[serde(default)]
+    pub diagnostics: Option<RunDiagnostics>,
 }
 impl Eq for TestResult {}
 impl Ord for TestResult {
@@ -119,13 +134,84 @@ impl Display for TestResult {
     }
 }
 
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// Details of an execution, to understand why a test failed
+pub struct RunDiagnostics {
+    /// standard output, it could be truncated
+    pub stdout: String,
+    /// standard error, it could be truncated
+    pub stderr: String,
+    /// the panic, if it panicked
+    pub panic: Option<PanicReport>,
+    /// exit code, if it exited by itself
+    pub exit_code: Option<i32>,
+    /// signal that terminated it
+    pub signal: Option<i32>,
+    /// wall-clock time of the execution, in milliseconds
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// A panic, as reported by the default panic hook
+pub struct PanicReport {
+    /// the message of the panic
+    pub message: String,
+    /// file of the panic, the submission when it's in the code of the student
+    pub file: String,
+    /// line of the panic
+    pub line: u32,
+    /// column of the panic
+    pub column: u32,
+    /// it is in the code of the exercise, so its location is not shown
+    #[serde(default)]
+    pub internal: bool,
+}
+
+impl Display for PanicReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.internal {
+            return write!(f, "panicked in the code of the exercise: {}", self.message);
+        }
+        write!(
+            f,
+            "panicked at {}:{}:{}: {}",
+            self.file, self.line, self.column, self.message
+        )
+    }
+}
+
+impl Display for RunDiagnostics {
+    /// an indented block, shown below the result of the test
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(panic) = &self.panic {
+            writeln!(f, "      {}", panic.to_string().red())?;
+        }
+        let status = match (self.exit_code, self.signal) {
+            (Some(code), _) => format!("exit code {}", code),
+            (None, Some(signal)) => format!("killed by signal {}", signal),
+            (None, None) => "did not end".to_string(),
+        };
+        writeln!(f, "      {}, after {} ms", status, self.duration_ms)?;
+        for (name, output) in [("stdout", &self.stdout), ("stderr", &self.stderr)] {
+            if output.is_empty() {
+                continue;
+            }
+            writeln!(f, "      {}:", name.yellow())?;
+            for line in output.lines() {
+                writeln!(f, "        {}", line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
 /// Status of a compilation
 pub enum CompilationResult {
-    /// If the compilation succeded
-    Built,
-    /// if we get an error, the string of the error
-    Error(String),
+    /// If the compilation succeded, with the warnings of the compiler
+    Built(Vec<CompilerDiagnostic>),
+    /// if we get an error: the errors as rendered by the compiler, and all its messages
+    Error(String, Vec<CompilerDiagnostic>),
     /// not yet built
     #[default]
     NotBuilt,
@@ -137,16 +223,26 @@ impl Ord for CompilationResult {
         use CompilationResult::*;
         use Ordering::*;
         match (self, other) {
-            (Built, Built) => Equal,
-            (Built, _) => Less,
-            (Error(_), Built) => Greater,
-            (Error(x), Error(y)) => x.cmp(y),
-            (Error(_), NotBuilt) => Less,
+            (Built(_), Built(_)) => Equal,
+            (Built(_), _) => Less,
+            (Error(..), Built(_)) => Greater,
+            (Error(x, _), Error(y, _)) => x.cmp(y),
+            (Error(..), NotBuilt) => Less,
             (NotBuilt, NotBuilt) => Equal,
             (NotBuilt, _) => Greater,
         }
     }
 }
+impl CompilationResult {
+    /// the errors and the warnings of the compiler
+    pub fn diagnostics(&self) -> &[CompilerDiagnostic] {
+        match self {
+            CompilationResult::Built(x) | CompilationResult::Error(_, x) => x,
+            CompilationResult::NotBuilt => &[],
+        }
+    }
+}
+
 impl PartialOrd for CompilationResult {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         Some(self.cmp(other))
@@ -156,12 +252,61 @@ impl PartialOrd for CompilationResult {
 impl Display for CompilationResult {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            CompilationResult::Built => write!(f, "{}", "Built".green()),
-            CompilationResult::Error(_) => write!(f, "{}", "Error".red()),
+            CompilationResult::Built(_) => write!(f, "{}", "Built".green()),
+            CompilationResult::Error(..) => write!(f, "{}", "Error".red()),
             CompilationResult::NotBuilt => write!(f, "{}", "Not built".yellow()),
         }
     }
 }
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// A message of the compiler, on the code of the submission
+pub struct CompilerDiagnostic {
+    /// error, warning...
+    pub level: String,
+    /// code of the error, like E0382
+    pub code: Option<String>,
+    /// the message, without the code
+    pub message: String,
+    /// where it is in the submission, the primary spans first
+    pub spans: Vec<DiagnosticSpan>,
+    /// changes to the submission proposed by the compiler
+    pub suggestions: Vec<Suggestion>,
+    /// notes and help of the compiler
+    pub notes: Vec<String>,
+    /// it is in the code of the exercise, so it's not shown
+    pub internal: bool,
+    /// the message as shown by the compiler
+    pub rendered: String,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// Part of the submission, lines and columns start from 1 (columns in characters), the end is excluded
+pub struct DiagnosticSpan {
+    /// first line
+    pub line_start: usize,
+    /// first column
+    pub column_start: usize,
+    /// last line
+    pub line_end: usize,
+    /// column after the end
+    pub column_end: usize,
+    /// it is where the problem is, the others explain it
+    pub primary: bool,
+    /// what the compiler says about this part
+    pub label: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// A change proposed by the compiler
+pub struct Suggestion {
+    /// why
+    pub message: String,
+    /// the part to replace
+    pub span: DiagnosticSpan,
+    /// the new code
+    pub replacement: String,
+}
+
 #[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
 /// Status of an execution
 pub enum RunResult {
@@ -169,24 +314,43 @@ pub enum RunResult {
     Ok,
     /// it did not execute correctly, the returned error is:
     Error(String),
+    /// it ended correctly, but its output is not the expected one: the difference
+    WrongOutput(String),
+    /// it exceeded its wall-clock or cpu time
+    Timeout,
+    /// it exceeded its memory
+    MemoryLimitExceeded,
+    /// it was terminated by this signal
+    Killed(i32),
     /// not yet run
     #[default]
     NotRun,
 }
 
+impl RunResult {
+    /// position in the ordering: first ok, then the failures, then not run
+    fn rank(&self) -> u8 {
+        match self {
+            RunResult::Ok => 0,
+            RunResult::WrongOutput(_) => 1,
+            RunResult::Error(_) => 2,
+            RunResult::Timeout => 3,
+            RunResult::MemoryLimitExceeded => 4,
+            RunResult::Killed(_) => 5,
+            RunResult::NotRun => 6,
+        }
+    }
+}
+
 impl Ord for RunResult {
-    /// first built, then error, then not_built
+    /// first ok, then wrong output, error, timeout, memory limit, killed, then not_run
     fn cmp(&self, other: &Self) -> Ordering {
-        use Ordering::*;
         use RunResult::*;
         match (self, other) {
-            (Ok, Ok) => Equal,
-            (Ok, _) => Less,
-            (Error(_), Ok) => Greater,
             (Error(x), Error(y)) => x.cmp(y),
-            (Error(_), NotRun) => Less,
-            (NotRun, NotRun) => Equal,
-            (NotRun, _) => Greater,
+            (WrongOutput(x), WrongOutput(y)) => x.cmp(y),
+            (Killed(x), Killed(y)) => x.cmp(y),
+            _ => self.rank().cmp(&other.rank()),
         }
     }
 }
@@ -201,6 +365,10 @@ impl Display for RunResult {
         match self {
             RunResult::Ok => write!(f, "{}", "Ok".green()),
             RunResult::Error(_) => write!(f, "{}", "Error".red()),
+            RunResult::WrongOutput(_) => write!(f, "{}", "Wrong output".red()),
+            RunResult::Timeout => write!(f, "{}", "Timeout".red()),
+            RunResult::MemoryLimitExceeded => write!(f, "{}", "Memory limit".red()),
+            RunResult::Killed(signal) => write!(f, "{}", format!("Killed ({})", signal).red()),
             RunResult::NotRun => write!(f, "{}", "Not run".yellow()),
         }
     }
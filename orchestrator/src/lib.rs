@@ -8,9 +8,13 @@ pub mod prelude;
 //pub mod rust_executor;
 pub mod orchestrator;
 
+pub mod archive;
+pub mod cached_memory;
+pub mod conformance;
 pub mod default_memory;
 pub mod executor;
 pub mod memory;
+pub mod password;
 
 pub mod plugin;
 mod test;
@@ -11,7 +11,7 @@
 use std::{any::TypeId, error::Error, fmt::Debug, marker::PhantomData};
 
 pub use async_trait::async_trait;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 
 use crate::prelude::*;
 
@@ -21,6 +21,48 @@ mod private {
     pub trait Privatizer {}
 }
 
+#[derive(Debug, thiserror::Error)]
+/// Error returned by every memory method, whatever the implementation:
+/// it tells the caller what went wrong (for example an username already taken)
+/// and not only that something did
+pub enum MemoryError {
+    /// the requested resource does not exist, it says which one
+    #[error("{0} not found")]
+    NotFound(String),
+    /// the resource is already present (for example an username), it says which one
+    #[error("{0} already exists")]
+    AlreadyExists(String),
+    /// wrong credentials, invalid token, or the user is not allowed to do it
+    #[error("unauthorized")]
+    Unauthorized,
+    /// the request is not compatible with what is stored (for example an executor that creates a cycle)
+    #[error("conflict: {0}")]
+    Conflict(String),
+    /// the memory itself failed (database unreachable, corrupted data...)
+    #[error("memory failure: {0}")]
+    Backend(#[source] Box<dyn Error + Send + Sync>),
+}
+
+impl MemoryError {
+    /// wraps an error of the underlying storage
+    pub fn backend(err: impl Into<Box<dyn Error + Send + Sync>>) -> Self {
+        Self::Backend(err.into())
+    }
+}
+
+impl From<crate::password::PasswordError> for MemoryError {
+    fn from(value: crate::password::PasswordError) -> Self {
+        Self::backend(value)
+    }
+}
+
+/// stored data that can't be (de)serialized is a failure of the memory
+impl From<serde_json::Error> for MemoryError {
+    fn from(value: serde_json::Error) -> Self {
+        Self::backend(value)
+    }
+}
+
 /// A valid UserVariant
 pub trait UserState: Debug + Privatizer + Clone {}
 #[derive(Debug, Clone)]
@@ -75,6 +117,180 @@ impl<S: UserState> User<S> {
     }
 }
 
+#[derive(Debug, Clone)]
+/// A password reset request, generated by the memory and delivered to the user by a notifier
+pub struct PasswordReset {
+    /// user that requested the reset
+    pub username: String,
+    /// single use token, it must be provided to reset the password
+    pub token: String,
+    /// after this instant the token is no longer valid
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// An identity asserted by an external provider (for example an OpenID Connect provider)
+pub struct ExternalIdentity {
+    /// who asserts this identity
+    pub issuer: String,
+    /// univoque identifier of the user, inside the issuer
+    pub subject: String,
+    /// email, if provided
+    pub email: Option<String>,
+    /// full name, if provided
+    pub name: Option<String>,
+}
+
+impl ExternalIdentity {
+    /// username to be used when the user logs in for the first time
+    pub fn preferred_username(&self) -> String {
+        self.email
+            .clone()
+            .or_else(|| self.name.clone())
+            .unwrap_or_else(|| self.subject.clone())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// A stored submission
+pub struct Submission {
+    /// univoque identifier, returned by add_submission
+    pub submission_id: i64,
+    /// who submitted it
+    pub user_id: i64,
+    /// which exercise
+    pub exercise_name: String,
+    /// submitted source
+    pub source: String,
+    /// when it was submitted
+    pub submitted_at: DateTime<Utc>,
+    /// None until the execution completes
+    pub result: Option<ExerciseResult>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+/// Which submissions should be listed, every field set must match.
+/// Submissions are ordered by id, and the page is selected with offset and limit
+pub struct SubmissionFilter {
+    /// only submissions of this user
+    pub user_id: Option<i64>,
+    /// only submissions of this exercise
+    pub exercise_name: Option<String>,
+    /// how many submissions to skip
+    pub offset: usize,
+    /// max number of submissions returned, None returns all
+    pub limit: Option<usize>,
+}
+
+impl SubmissionFilter {
+    /// does the submission pass the filter? (offset and limit are not considered)
+    pub fn matches(&self, submission: &Submission) -> bool {
+        self.user_id.is_none_or(|x| submission.user_id == x)
+            && self
+                .exercise_name
+                .as_ref()
+                .is_none_or(|x| &submission.exercise_name == x)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// A user as it is stored, used to move users between memories
+pub struct UserRecord {
+    /// identifier in the memory it comes from
+    pub user_id: i64,
+    /// univoque username
+    pub username: String,
+    /// hash (PHC string), the password is never known
+    pub password_hash: String,
+    /// can it access admin functionalities?
+    pub is_admin: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// An exercise as it is stored, the variant is the serialized one
+pub struct ExerciseRecord {
+    /// univoque name
+    pub name: String,
+    /// variant of the state, as returned by serialize_variant
+    pub variant: String,
+    /// exercise source
+    pub source: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// An enabled executor, the variants are the serialized ones
+pub struct ExecutorRecord {
+    /// variant that triggers the executor
+    pub input: String,
+    /// variant produced by the executor
+    pub output: String,
+    /// executor configuration
+    pub data: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+/// Which action is recorded by an audit event
+pub enum AuditAction {
+    /// an user logged in (with a password or with an external provider)
+    LoginSuccess,
+    /// a login attempt failed
+    LoginFailure,
+    /// a new user has been registered
+    Register,
+    /// a password has been changed or reset
+    PasswordChange,
+    /// a new exercise has been added
+    ExerciseAdded,
+    /// an already present exercise has been replaced
+    ExerciseUpdated,
+    /// an executor has been enabled (or its configuration changed)
+    ExecutorEnabled,
+    /// an executor has been disabled
+    ExecutorDisabled,
+    /// any other action performed by an admin (for example a grade override)
+    AdminAction,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// A recorded audit event. Events can only be appended, never modified
+pub struct AuditEvent {
+    /// univoque and increasing identifier
+    pub id: i64,
+    /// when the event was recorded
+    pub time: DateTime<Utc>,
+    /// who did it (username), None if it was done by the system (for example a plugin at startup)
+    pub actor: Option<String>,
+    /// what has been done
+    pub action: AuditAction,
+    /// free text, usually on which object (exercise name, executor...)
+    pub details: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+/// Filter for the audit log query, every field set must match
+pub struct AuditFilter {
+    /// only events of this actor
+    pub actor: Option<String>,
+    /// only events of this action
+    pub action: Option<AuditAction>,
+    /// only events recorded at or after this instant
+    pub from: Option<DateTime<Utc>>,
+    /// only events recorded before this instant
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl AuditFilter {
+    /// does the event pass the filter?
+    pub fn matches(&self, event: &AuditEvent) -> bool {
+        self.actor
+            .as_ref()
+            .is_none_or(|x| event.actor.as_ref() == Some(x))
+            && self.action.is_none_or(|x| event.action == x)
+            && self.from.is_none_or(|x| event.time >= x)
+            && self.to.is_none_or(|x| event.time < x)
+    }
+}
+
 #[async_trait]
 /// This is the trait that contains all method of the memory that does not require knowing the state
 pub trait StatelessMemory: Sync + Send {
@@ -85,32 +301,60 @@ pub trait StatelessMemory: Sync + Send {
         &self,
         username: &str,
         password: &str,
-    ) -> Result<User<Unauthenticated>, Box<dyn Error>>;
+    ) -> Result<User<Unauthenticated>, MemoryError>;
 
     /// try to log in the relative user
     async fn login(
         &self,
         username: &str,
         password: &str,
-    ) -> Result<User<Authenticated>, Box<dyn Error>>;
+    ) -> Result<User<Authenticated>, MemoryError>;
 
-    /// search an user from his username
-    async fn get_by_username(
+    /// log in an user authenticated by an external provider.
+    ///
+    /// The user is found by (issuer, subject), and it is registered on the first login.
+    /// Its username is the preferred one if available, otherwise the subject is appended.
+    /// Users registered this way can't log in with a password.
+    async fn login_external(
+        &self,
+        identity: &ExternalIdentity,
+    ) -> Result<User<Authenticated>, MemoryError>;
+
+    /// change the password of an authenticated user, the old password must match
+    async fn change_password(
+        &self,
+        user: User<Authenticated>,
+        old_password: &str,
+        new_password: &str,
+    ) -> Result<(), MemoryError>;
+
+    /// generate a single use token that permits to reset the password of the user, valid for the given duration.
+    ///
+    /// Users without a password (the ones of an external identity) are not found
+    async fn create_password_reset(
         &self,
         username: &str,
-    ) -> Result<User<Unauthenticated>, Box<dyn Error>>;
+        validity: Duration,
+    ) -> Result<PasswordReset, MemoryError>;
+
+    /// consume a reset token (if valid and not expired) and set the new password.
+    /// It also logs out the user
+    async fn reset_password(&self, token: &str, new_password: &str) -> Result<(), MemoryError>;
+
+    /// search an user from his username
+    async fn get_by_username(&self, username: &str) -> Result<User<Unauthenticated>, MemoryError>;
 
     /// get the authenticated user from his token
-    async fn get_authenticate(&self, token: &str) -> Result<User<Authenticated>, Box<dyn Error>>;
+    async fn get_authenticate(&self, token: &str) -> Result<User<Authenticated>, MemoryError>;
 
     /// get an authenticated admin from his token
-    async fn get_admin(&self, token: &str) -> Result<User<Admin>, Box<dyn Error>>;
+    async fn get_admin(&self, token: &str) -> Result<User<Admin>, MemoryError>;
 
     /// prints out all users
-    async fn get_all_users(&self) -> Result<Vec<User<Unauthenticated>>, Box<dyn Error>>;
+    async fn get_all_users(&self) -> Result<Vec<User<Unauthenticated>>, MemoryError>;
 
     ///list exercises names
-    async fn list_exercise_names(&self) -> Result<Vec<String>, Box<dyn Error>>;
+    async fn list_exercise_names(&self) -> Result<Vec<String>, MemoryError>;
 
     ///add submission (on success returns submission id)
     async fn add_submission(
@@ -118,7 +362,30 @@ pub trait StatelessMemory: Sync + Send {
         exercise_name: String,
         source: String,
         user: User<Authenticated>,
-    ) -> Result<i64, Box<dyn Error + Send + Sync>>;
+    ) -> Result<i64, MemoryError>;
+
+    /// get a submission, with its result if available
+    async fn get_submission(&self, submission_id: i64) -> Result<Submission, MemoryError>;
+
+    /// list submissions (with their results)
+    async fn list_submissions(
+        &self,
+        filter: &SubmissionFilter,
+    ) -> Result<Vec<Submission>, MemoryError>;
+
+    /// the last submission of the user for the exercise
+    async fn get_latest_submission(
+        &self,
+        user_id: i64,
+        exercise_name: &str,
+    ) -> Result<Option<Submission>, MemoryError>;
+
+    /// the executed submission of the user with the most points (the latest one on ties)
+    async fn get_best_submission(
+        &self,
+        user_id: i64,
+        exercise_name: &str,
+    ) -> Result<Option<Submission>, MemoryError>;
 
     ///add exercise result
     async fn add_exercise_result(
@@ -126,24 +393,73 @@ pub trait StatelessMemory: Sync + Send {
         submission_id: i64,
         user: User<Authenticated>,
         result: ExerciseResult,
-    ) -> Result<(), Box<dyn Error + Send + Sync>>;
+    ) -> Result<(), MemoryError>;
+
+    //AUDIT
+
+    /// append an event to the audit log, the time is set by the memory
+    async fn audit(
+        &self,
+        actor: Option<&str>,
+        action: AuditAction,
+        details: &str,
+    ) -> Result<(), MemoryError>;
+
+    /// read the audit log (ordered by time), only admins are allowed
+    async fn get_audit_log(
+        &self,
+        admin: &User<Admin>,
+        filter: &AuditFilter,
+    ) -> Result<Vec<AuditEvent>, MemoryError>;
+
+    //ARCHIVE
+
+    /// all stored users, with their hash
+    async fn export_users(&self) -> Result<Vec<UserRecord>, MemoryError>;
+
+    /// all stored exercises
+    async fn export_exercises(&self) -> Result<Vec<ExerciseRecord>, MemoryError>;
+
+    /// all enabled executors
+    async fn export_executors(&self) -> Result<Vec<ExecutorRecord>, MemoryError>;
+
+    /// store a user as it is (hash and admin flag included), its id is ignored.
+    /// Returns the new id
+    async fn import_user(&self, user: &UserRecord) -> Result<i64, MemoryError>;
+
+    /// store an exercise, replacing the one with the same name.
+    /// The variant is not checked
+    async fn import_exercise(&self, exercise: &ExerciseRecord) -> Result<(), MemoryError>;
+
+    /// enable an executor, replacing the one with the same input.
+    /// Neither variants nor cycles are checked
+    async fn import_executor(&self, executor: &ExecutorRecord) -> Result<(), MemoryError>;
+
+    /// store a submission with its time and result, its id is ignored
+    /// (the user id must already be the one in this memory).
+    /// Returns the new id
+    async fn import_submission(&self, submission: &Submission) -> Result<i64, MemoryError>;
 }
 #[async_trait]
 /// This is the trait that contains all method of the memory that does require knowing the state.
 /// Is not always available
 pub trait StateMemory<S: ExecutorGlobalState> {
     /// used to enable a particular executor
-    async fn enable_executor(
-        &self,
-        input: &S,
-        output: &S,
-        data: String,
-    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>>;
+    async fn enable_executor(&self, input: &S, output: &S, data: String)
+        -> Result<(), MemoryError>;
     /// from a particular state, which executor will be triggered? in which order?
     async fn get_execution_plan(
         &self,
         input: &S,
-    ) -> Result<Vec<(TypeId, TypeId, String)>, Box<dyn Error + Send + Sync + 'static>>;
+    ) -> Result<Vec<(TypeId, TypeId, String)>, MemoryError>;
+    /// disable the executor triggered by input.
+    /// It fails if no executor is enabled from that state
+    async fn disable_executor(&self, input: &S) -> Result<(), MemoryError>;
+    /// change the data given to the executor triggered by input, its output is kept.
+    /// It fails if no executor is enabled from that state
+    async fn replace_executor_data(&self, input: &S, data: String) -> Result<(), MemoryError>;
+    /// all enabled executors: input, output, data. Ordered by input variant
+    async fn list_enabled_executors(&self) -> Result<Vec<(TypeId, TypeId, String)>, MemoryError>;
 
     /// add an exercise to memory
     async fn add_exercise(
@@ -151,14 +467,11 @@ pub trait StateMemory<S: ExecutorGlobalState> {
         name: String,
         exercise_type: S,
         source: String,
-    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>>;
+    ) -> Result<(), MemoryError>;
 
     /// get an exercise from memory
     /// type, source
-    async fn get_exercise(
-        &self,
-        name: String,
-    ) -> Result<(TypeId, String), Box<dyn Error + Send + Sync + 'static>>;
+    async fn get_exercise(&self, name: String) -> Result<(TypeId, String), MemoryError>;
 }
 /// auto trait that rapresent the union of stateless and state Memory
 pub trait Memory<S: ExecutorGlobalState>: StateMemory<S> + StatelessMemory {
@@ -1,11 +1,21 @@
 //! This is the main module, and contains the definition of the orchestrator
 use std::{
-    any::TypeId, collections::HashMap, error::Error, future::Future, marker::PhantomData, mem,
-    ops::Deref, pin::Pin, sync::Arc,
+    any::{type_name, TypeId},
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt,
+    future::Future,
+    marker::PhantomData,
+    mem,
+    ops::Deref,
+    pin::Pin,
+    sync::{Arc, RwLock},
 };
 
+use crate::executor::Error as ExecutorError;
 use crate::prelude::*;
 use async_trait::async_trait;
+use futures_util::future::join_all;
 use tokio::sync::{Notify, Semaphore};
 
 #[derive(Debug, thiserror::Error)]
@@ -21,6 +31,92 @@ pub enum OrchestratorError {
     /// Execution Error
     #[error("Execution Error: {0}")]
     ExecutionError(#[from] Box<dyn Error + Send + Sync>),
+
+    /// The solution of an exercise doesn't get all the points
+    #[error("can't get all the points. Returned this result {0}")]
+    FailingSolution(ExerciseResult),
+}
+
+#[derive(Debug)]
+/// Outcome of an exercise given to [`Orchestrator::add_exercises`]
+pub enum AddOutcome {
+    /// the exercise has been added
+    Added,
+    /// the solution doesn't get all the points, the result shows the failing tests
+    FailedTests(ExerciseResult),
+    /// the exercise could not be generated, executed or saved
+    Failed(DynError),
+}
+
+impl AddOutcome {
+    /// true if the exercise has been added
+    pub fn is_added(&self) -> bool {
+        matches!(self, AddOutcome::Added)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+/// A configuration problem found by [`Orchestrator::validate`]
+pub enum ValidationProblem {
+    /// the plan of the generated exercises stops before reaching the result
+    #[error("exercises of {generator} stop at {state}: no executor is enabled from it")]
+    NoEnabledExecutor {
+        /// type of the exercise definition
+        generator: String,
+        /// last state reached
+        state: String,
+    },
+    /// an edge of the plan is enabled in memory, but its executor is not registered
+    #[error("exercises of {generator} need the executor {from} -> {to}, that is not registered")]
+    UnregisteredExecutor {
+        /// type of the exercise definition
+        generator: String,
+        /// input of the executor
+        from: String,
+        /// output of the executor
+        to: String,
+    },
+    /// the exercise is stored with a type that has no generator
+    #[error("exercise {exercise} has no generator for its type")]
+    MissingGenerator {
+        /// name of the exercise
+        exercise: String,
+    },
+    /// the exercise is stored, but its generator can't reach the result
+    #[error("exercise {exercise} can't be executed, its generator {generator} is not valid")]
+    InvalidGenerator {
+        /// name of the exercise
+        exercise: String,
+        /// type of the exercise definition
+        generator: String,
+    },
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// Problems found validating the orchestrator, one for each line when displayed
+pub struct ValidationReport {
+    /// the problems, empty if every exercise can be executed
+    pub problems: Vec<ValidationProblem>,
+}
+
+impl ValidationReport {
+    /// true if no problem has been found
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_ok() {
+            return write!(f, "every exercise can reach its result");
+        }
+        write!(f, "{} configuration problems:", self.problems.len())?;
+        for problem in &self.problems {
+            write!(f, "\n - {}", problem)?;
+        }
+        Ok(())
+    }
 }
 
 /// which result should a complete execution return?
@@ -69,6 +165,20 @@ pub struct Orchestrator<S: ExecutorGlobalState> {
 
     execise_definition: HashMap<TypeId, ExerciseDefinitionFunction>,
 
+    /// generated exercises, so that templates are not parsed on each submission.
+    /// name, (type, template, generated state)
+    generated: RwLock<HashMap<String, (TypeId, String, S)>>,
+
+    /// states of the registered executors, by serialized variant:
+    /// used to manage executors knowing only their name
+    pub(crate) known_states: HashMap<String, S>,
+    /// how to check the data of each registered executor
+    pub(crate) data_checks: HashMap<(TypeId, TypeId), DataCheck>,
+    /// for each generator the state from which the plan starts (the definition with the source)
+    plan_starts: HashMap<TypeId, TypeId>,
+    /// readable names of the registered states, used in the validation report
+    pub(crate) state_names: HashMap<TypeId, &'static str>,
+
     pub check_when_add: bool,
     /// saved plugin, runned with run method
     plugins: Vec<Box<dyn InnerPlugin<S>>>,
@@ -84,6 +194,11 @@ impl<S: ExecutorGlobalState> Orchestrator<S> {
             executors: HashMap::new(),
             exercise_generators: HashMap::new(),
             execise_definition: HashMap::new(),
+            generated: RwLock::new(HashMap::new()),
+            known_states: HashMap::new(),
+            data_checks: HashMap::new(),
+            plan_starts: HashMap::new(),
+            state_names: HashMap::new(),
             check_when_add,
             memory,
             plugins: Vec::new(),
@@ -141,23 +256,70 @@ impl<S: ExecutorGlobalState> Orchestrator<S> {
                 .map_err(|_| "not found an exercise result")?;
 
             //check if we get all the points
-            let all_ok = results
-                .tests
-                .values()
-                .all(|x| x.compiled == CompilationResult::Built && x.runned == RunResult::Ok);
+            let all_ok = results.tests.values().all(|x| {
+                matches!(x.compiled, CompilationResult::Built(_)) && x.runned == RunResult::Ok
+            });
             if !all_ok {
-                Err(format!(
-                    "can't get all the points. Returned this result {:?}",
-                    results
-                ))?
+                Err(OrchestratorError::FailingSolution(results))?
             }
         }
 
+        let action = match self.memory.get_exercise(name.to_string()).await {
+            Ok(_) => Some(AuditAction::ExerciseUpdated),
+            Err(MemoryError::NotFound(_)) => Some(AuditAction::ExerciseAdded),
+            // it's not known if the exercise is new, there is no audit event
+            Err(err) => {
+                eprintln!("could not check if the exercise {} exists: {}", name, err);
+                None
+            }
+        };
         self.memory
-            .add_exercise(name.to_string(), exercise_def, source.to_string())
+            .add_exercise(name.to_string(), exercise_def.clone(), source.to_string())
             .await?;
+        self.generated.write().unwrap().insert(
+            name.to_string(),
+            (
+                TypeId::of::<ExerciseType>(),
+                source.to_string(),
+                exercise_def,
+            ),
+        );
+        if let Some(action) = action {
+            self.audit(None, action, name).await;
+        }
         Ok(())
     }
+    /// add many exercises (name, source) of the same type, checking them concurrently.
+    ///
+    /// At most as many exercises as the execution permits are checked at the same time.
+    /// A failing exercise is not added, but it doesn't stop the others:
+    /// the outcomes are returned in the same order
+    pub async fn add_exercises<ExerciseType: ExerciseDef + ExecutorState>(
+        &self,
+        exercises: &[(&str, &str)],
+    ) -> Vec<(String, AddOutcome)> {
+        let add = exercises.iter().map(|(name, source)| async move {
+            let outcome = match self.execution_semaphore.acquire().await {
+                Ok(_permit) => self.add_exercise::<ExerciseType>(name, source).await,
+                Err(err) => Err(err.into()),
+            };
+            let outcome = match outcome {
+                Ok(()) => AddOutcome::Added,
+                Err(err) => match err.downcast::<OrchestratorError>() {
+                    Ok(err) => match *err {
+                        OrchestratorError::FailingSolution(result) => {
+                            AddOutcome::FailedTests(result)
+                        }
+                        err => AddOutcome::Failed(err.into()),
+                    },
+                    Err(err) => AddOutcome::Failed(err),
+                },
+            };
+            (name.to_string(), outcome)
+        });
+        join_all(add).await
+    }
+
     ///get and execute plan
     pub async fn run_state(&self, mut cur: S) -> Result<S, DynError> {
         let plan = self.memory.get_execution_plan(&cur).await?;
@@ -174,13 +336,15 @@ impl<S: ExecutorGlobalState> Orchestrator<S> {
     /// adds an exercise generator to the orchestrator
     ///
     /// NB: it does not check if it's correct or not
-    /// if a generator is already present it gets overriten
-    pub async fn add_exercise_generators<Definition, DefinitionWithSource, F, F2>(
+    /// if a generator is already present it gets overriten.
+    /// The exercise generator can keep a state (like a cache of the exercises)
+    pub async fn add_exercise_generators<Definition, DefinitionWithSource, G, F, F2>(
         &mut self,
-        exercise_gen: fn(String) -> F,
+        exercise_gen: G,
         source_add: fn(Definition, String) -> F2,
     ) where
         Definition: ExecutorState + ExerciseDef + Into<S> + TryFrom<S>,
+        G: Fn(String) -> F + Send + Sync + 'static,
         DefinitionWithSource: ExecutorState + Into<S>,
         F: Future<Output = Result<Definition, Box<dyn Error + Send + Sync + 'static>>>
             + 'static
@@ -192,10 +356,12 @@ impl<S: ExecutorGlobalState> Orchestrator<S> {
             + Sync,
     {
         // wrap in a generic function
-        let e = exercise_gen;
+        let exercise_gen = Arc::new(exercise_gen);
+        let e = exercise_gen.clone();
         let exercise_def = move |template: String| {
+            let t = e(template);
             let t: ExerciseDefinitionFuture = Box::pin(async move {
-                let t = e(template).await?;
+                let t = t.await?;
                 let t: Box<dyn ExerciseDef> = Box::new(t);
                 Ok(t)
             });
@@ -204,8 +370,9 @@ impl<S: ExecutorGlobalState> Orchestrator<S> {
         self.execise_definition
             .insert(TypeId::of::<Definition>(), Box::new(exercise_def));
         let exercise_gen = move |template: String| {
+            let ret = exercise_gen(template);
             let t: ExerciseGeneratorFuture<S> = Box::pin(async move {
-                let ret = exercise_gen(template).await?;
+                let ret = ret.await?;
                 let ret: S = ret.into();
                 Ok::<S, Box<dyn Error + Send + Sync + 'static>>(ret)
             });
@@ -226,12 +393,43 @@ impl<S: ExecutorGlobalState> Orchestrator<S> {
             TypeId::of::<Definition>(),
             (Box::new(exercise_gen), Box::new(source_add)),
         );
+        self.plan_starts.insert(
+            TypeId::of::<Definition>(),
+            TypeId::of::<DefinitionWithSource>(),
+        );
+        self.state_names
+            .insert(TypeId::of::<Definition>(), type_name::<Definition>());
+        self.state_names.insert(
+            TypeId::of::<DefinitionWithSource>(),
+            type_name::<DefinitionWithSource>(),
+        );
     }
-    /// generate an exercise from a name and a source-code
+    /// generate an exercise from a name and a source-code.
+    ///
+    /// The generated exercise is reused while the stored template does not change
     async fn generate_exercise(&self, name: String, source: String) -> Result<S, DynError> {
-        let (ty, template) = self.memory.get_exercise(name).await?;
+        let (ty, template) = self.memory.get_exercise(name.clone()).await?;
         let (generator, source_adder) = self.exercise_generators.get(&ty).ok_or("not found")?;
-        let generated = generator(template).await?;
+        let cached = self
+            .generated
+            .read()
+            .unwrap()
+            .get(&name)
+            .filter(|(cached_ty, cached_template, _)| {
+                *cached_ty == ty && *cached_template == template
+            })
+            .map(|(_, _, generated)| generated.clone());
+        let generated = match cached {
+            Some(generated) => generated,
+            None => {
+                let generated = generator(template.clone()).await?;
+                self.generated
+                    .write()
+                    .unwrap()
+                    .insert(name, (ty, template, generated.clone()));
+                generated
+            }
+        };
         let added = source_adder(generated, source).await?;
         Ok(added)
     }
@@ -250,7 +448,14 @@ impl<S: ExecutorGlobalState> Orchestrator<S> {
     }
 
     /// Runs the Orchestrator.
+    ///
+    /// The configuration is validated first, problems are printed but do not stop it
     pub async fn run(mut self) -> OrchestratorReference<S> {
+        match self.validate().await {
+            Ok(report) if !report.is_ok() => eprintln!("{}", report),
+            Ok(_) => {}
+            Err(err) => eprintln!("could not validate the orchestrator: {}", err),
+        }
         let mut to_run = Vec::new();
         mem::swap(&mut to_run, &mut self.plugins);
         let o = self.as_ref();
@@ -266,11 +471,101 @@ impl<S: ExecutorGlobalState> Orchestrator<S> {
         n.notified().await;
         o
     }
+    /// checks that every exercise can be executed: for each generator, and each stored exercise,
+    /// the enabled executors must be registered and must lead to the ExerciseResult
+    pub async fn validate(&self) -> Result<ValidationReport, MemoryError> {
+        let enabled: HashMap<TypeId, TypeId> = self
+            .memory
+            .list_enabled_executors()
+            .await?
+            .into_iter()
+            .map(|(from, to, _)| (from, to))
+            .collect();
+        let mut report = ValidationReport::default();
+        let mut invalid = HashSet::new();
+        let mut generators: Vec<_> = self.plan_starts.iter().collect();
+        generators.sort_by_key(|(generator, _)| self.state_name(**generator));
+        for (generator, start) in generators {
+            let problem = self.check_plan(*generator, *start, &enabled);
+            if let Some(problem) = problem {
+                invalid.insert(*generator);
+                report.problems.push(problem);
+            }
+        }
+        let mut names = self.memory.list_exercise_names().await?;
+        names.sort();
+        for exercise in names {
+            let (ty, _) = self.memory.get_exercise(exercise.clone()).await?;
+            if !self.exercise_generators.contains_key(&ty) {
+                report
+                    .problems
+                    .push(ValidationProblem::MissingGenerator { exercise });
+            } else if invalid.contains(&ty) {
+                report.problems.push(ValidationProblem::InvalidGenerator {
+                    exercise,
+                    generator: self.state_name(ty),
+                });
+            }
+        }
+        Ok(report)
+    }
+
+    /// follows the enabled executors from start, the first problem found is returned
+    fn check_plan(
+        &self,
+        generator: TypeId,
+        start: TypeId,
+        enabled: &HashMap<TypeId, TypeId>,
+    ) -> Option<ValidationProblem> {
+        let result = TypeId::of::<ExerciseResult>();
+        let mut visited = HashSet::new();
+        let mut cur = start;
+        while cur != result {
+            // the memory rejects cycles, but a broken one should not hang the startup
+            let next = enabled.get(&cur).filter(|_| visited.insert(cur));
+            let Some(next) = next else {
+                return Some(ValidationProblem::NoEnabledExecutor {
+                    generator: self.state_name(generator),
+                    state: self.state_name(cur),
+                });
+            };
+            if !self.executors.contains_key(&(cur, *next)) {
+                return Some(ValidationProblem::UnregisteredExecutor {
+                    generator: self.state_name(generator),
+                    from: self.state_name(cur),
+                    to: self.state_name(*next),
+                });
+            }
+            cur = *next;
+        }
+        None
+    }
+
+    /// readable name of a registered state
+    fn state_name(&self, ty: TypeId) -> String {
+        if ty == TypeId::of::<ExerciseResult>() {
+            return type_name::<ExerciseResult>().to_string();
+        }
+        match self.state_names.get(&ty) {
+            Some(name) => name.to_string(),
+            None => format!("{:?}", ty),
+        }
+    }
+
     /// get a reference to the internal memory
     pub fn memory(&self) -> &dyn Memory<S> {
         self.memory.as_ref()
     }
 
+    /// append an event to the audit log, once its action has been performed.
+    ///
+    /// A failure is only printed: the action took effect, so it should not be reported as failed
+    pub(crate) async fn audit(&self, actor: Option<&str>, action: AuditAction, details: &str) {
+        if let Err(err) = self.memory.audit(actor, action, details).await {
+            eprintln!("could not write audit event {:?}: {}", action, err);
+        }
+    }
+
     /// Enables a particular executor
     pub async fn enable_executor<
         Input: ExecutorState + TryFrom<S> + Into<S>,
@@ -289,6 +584,71 @@ impl<S: ExecutorGlobalState> Orchestrator<S> {
         .await?;
         Ok(())
     }
+
+    /// Disables the executor triggered by Input, until it is enabled again
+    pub async fn disable_executor<Input: ExecutorState + Into<S>>(&self) -> Result<(), DynError> {
+        self.disable_state(&Input::async_default().await.into(), None)
+            .await
+    }
+
+    /// Changes the data given to the executor triggered by Input.
+    /// The data must be accepted by the executor that is enabled
+    pub async fn replace_executor_data<Input: ExecutorState + Into<S>, Data: Serialize>(
+        &self,
+        data: Data,
+    ) -> Result<(), DynError> {
+        let data = serde_json::to_string(&data)?;
+        self.replace_state_data(&Input::async_default().await.into(), data, None)
+            .await
+    }
+
+    /// state from its serialized variant, only states of registered executors are known
+    fn known_state(&self, variant: &str) -> Result<&S, ExecutorError> {
+        self.known_states
+            .get(variant)
+            .ok_or_else(|| ExecutorError::UnknownState(variant.to_string()))
+    }
+
+    /// the executor enabled from input: input, output, data
+    async fn enabled_from(&self, input: &S) -> Result<(TypeId, TypeId, String), DynError> {
+        let input = S::deserialize_variant(&input.serialize_variant())?;
+        let enabled = self.memory.list_enabled_executors().await?;
+        let found = enabled.into_iter().find(|(from, _, _)| *from == input);
+        Ok(found.ok_or(ExecutorError::NotEnabled)?)
+    }
+
+    async fn disable_state(&self, input: &S, actor: Option<&str>) -> Result<(), DynError> {
+        self.enabled_from(input).await?;
+        self.memory.disable_executor(input).await?;
+        self.audit(
+            actor,
+            AuditAction::ExecutorDisabled,
+            &input.serialize_variant(),
+        )
+        .await;
+        Ok(())
+    }
+
+    async fn replace_state_data(
+        &self,
+        input: &S,
+        data: String,
+        actor: Option<&str>,
+    ) -> Result<(), DynError> {
+        let (from, into, _) = self.enabled_from(input).await?;
+        let check = self
+            .data_checks
+            .get(&(from, into))
+            .ok_or(ExecutorError::UnregisteredExecutor)?;
+        check(&data).map_err(ExecutorError::Json)?;
+        self.memory
+            .replace_executor_data(input, data.clone())
+            .await?;
+        let details = format!("{}: {}", input.serialize_variant(), data);
+        self.audit(actor, AuditAction::ExecutorEnabled, &details)
+            .await;
+        Ok(())
+    }
 }
 
 #[derive(Clone)]
@@ -323,6 +683,21 @@ pub trait ReferenceWithoutState: Send + Sync + 'static {
     ) -> Result<ExerciseResult, DynError>;
     /// returns a memory reference (without state)
     fn memory(&self) -> &dyn StatelessMemory;
+    /// disables the executor triggered by the state with this serialized variant.
+    /// The actor is recorded in the audit log
+    async fn disable_executor_by_variant(
+        &self,
+        input: &str,
+        actor: Option<&str>,
+    ) -> Result<(), DynError>;
+    /// changes the data (serialized in json) given to the executor triggered by the state with this serialized variant.
+    /// The actor is recorded in the audit log
+    async fn replace_executor_data_by_variant(
+        &self,
+        input: &str,
+        data: String,
+        actor: Option<&str>,
+    ) -> Result<(), DynError>;
     //fn deref(&self) -> &Orchestrator<impl ExecutorState>;
 }
 #[async_trait]
@@ -342,17 +717,367 @@ impl<S: ExecutorGlobalState> ReferenceWithoutState for OrchestratorReference<S>
     ) -> Result<ExerciseResult, DynError> {
         Ok(self.inner.process_exercise(name, s, user).await?)
     }
+
+    async fn disable_executor_by_variant(
+        &self,
+        input: &str,
+        actor: Option<&str>,
+    ) -> Result<(), DynError> {
+        let input = self.inner.known_state(input)?;
+        self.inner.disable_state(input, actor).await
+    }
+
+    async fn replace_executor_data_by_variant(
+        &self,
+        input: &str,
+        data: String,
+        actor: Option<&str>,
+    ) -> Result<(), DynError> {
+        let input = self.inner.known_state(input)?;
+        self.inner.replace_state_data(input, data, actor).await
+    }
 }
 
 #[cfg(test)]
 mod tests {
 
+    use std::{
+        error::Error,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
     use crate as orchestrator;
     use crate::{
-        prelude::{Orchestrator, OrchestratorReference},
+        default_memory::DefaultMemory,
+        memory::{Admin, AuditAction, AuditFilter, User},
+        prelude::{
+            DummyExercise, DynError, ExecutorGlobalState, ExerciseResult, Orchestrator,
+            OrchestratorReference, ReferenceWithoutState,
+        },
         GenerateState,
     };
-    GenerateState!(ExerciseResult);
+    GenerateState!(DummyExercise, ExerciseResult);
+
+    /// how many times the template has been parsed
+    static GENERATED: AtomicUsize = AtomicUsize::new(0);
+    async fn counting_generator(_: String) -> Result<DummyExercise, Box<dyn Error + Send + Sync>> {
+        GENERATED.fetch_add(1, Ordering::SeqCst);
+        Ok(DummyExercise {})
+    }
+    async fn add_source(
+        _: DummyExercise,
+        _: String,
+    ) -> Result<ExerciseResult, Box<dyn Error + Send + Sync>> {
+        Ok(ExerciseResult::default())
+    }
+
+    #[tokio::test]
+    async fn test_generated_cache() {
+        let mut o: Orchestrator<State> = Orchestrator::new(1, false, DefaultMemory::init());
+        o.add_exercise_generators(counting_generator, add_source)
+            .await;
+        o.add_exercise::<DummyExercise>("somma", "v1")
+            .await
+            .unwrap();
+        assert_eq!(GENERATED.load(Ordering::SeqCst), 1);
+        o.memory().register("ciao", "mondo").await.unwrap();
+        let user = o.memory().login("ciao", "mondo").await.unwrap();
+        for _ in 0..3 {
+            o.process_exercise("somma".to_string(), String::new(), user.clone())
+                .await
+                .unwrap();
+        }
+        assert_eq!(GENERATED.load(Ordering::SeqCst), 1);
+
+        // updated through the orchestrator: generated once, when added
+        o.add_exercise::<DummyExercise>("somma", "v2")
+            .await
+            .unwrap();
+        o.process_exercise("somma".to_string(), String::new(), user.clone())
+            .await
+            .unwrap();
+        assert_eq!(GENERATED.load(Ordering::SeqCst), 2);
+
+        // updated directly in memory: the template changed, so it is generated again
+        o.memory()
+            .add_exercise(
+                "somma".to_string(),
+                State::DummyExercise(DummyExercise {}),
+                "v3".to_string(),
+            )
+            .await
+            .unwrap();
+        for _ in 0..2 {
+            o.process_exercise("somma".to_string(), String::new(), user.clone())
+                .await
+                .unwrap();
+        }
+        assert_eq!(GENERATED.load(Ordering::SeqCst), 3);
+    }
+
+    async fn scaled(
+        _: DummyExercise,
+        _: u32,
+    ) -> Result<ExerciseResult, Box<dyn Error + Send + Sync>> {
+        Ok(ExerciseResult::default())
+    }
+
+    #[tokio::test]
+    async fn test_executor_management() {
+        use crate::executor::{AddExecutor, Error as ExecutorError};
+        let mut o: Orchestrator<State> = Orchestrator::new(1, false, DefaultMemory::init());
+        o.add_executor(scaled, 1).await.unwrap();
+        o.enable_executor::<DummyExercise, ExerciseResult, _>(1u32)
+            .await
+            .unwrap();
+        let o = o.as_ref();
+        let input = State::DummyExercise(DummyExercise {}).serialize_variant();
+        let reference: &dyn ReferenceWithoutState = &o;
+        let is = |err: DynError, expected: &str| err.to_string() == expected;
+
+        reference
+            .replace_executor_data_by_variant(&input, "2".to_string(), Some("admin"))
+            .await
+            .unwrap();
+        let err = reference
+            .replace_executor_data_by_variant(&input, "\"two\"".to_string(), Some("admin"))
+            .await
+            .unwrap_err();
+        assert!(err.downcast_ref::<ExecutorError>().is_some());
+        let enabled = Orchestrator::memory(&o)
+            .list_enabled_executors()
+            .await
+            .unwrap();
+        assert_eq!(enabled[0].2, "2");
+
+        let err = reference
+            .disable_executor_by_variant("\"Unknown\"", None)
+            .await
+            .unwrap_err();
+        assert!(is(err, "unknown state: \"Unknown\""));
+        reference
+            .disable_executor_by_variant(&input, Some("admin"))
+            .await
+            .unwrap();
+        let err = o.disable_executor::<DummyExercise>().await.unwrap_err();
+        assert!(is(err, "executor not enabled"));
+        let err = o
+            .replace_executor_data::<DummyExercise, _>(3)
+            .await
+            .unwrap_err();
+        assert!(is(err, "executor not enabled"));
+
+        let admin: User<Admin> = {
+            o.memory().register("admin", "mondo").await.unwrap();
+            o.memory()
+                .login("admin", "mondo")
+                .await
+                .unwrap()
+                .transmute()
+        };
+        let filter = AuditFilter {
+            actor: Some("admin".to_string()),
+            ..Default::default()
+        };
+        let events = o.memory().get_audit_log(&admin, &filter).await.unwrap();
+        let actions: Vec<_> = events.iter().map(|x| x.action).collect();
+        assert_eq!(
+            actions,
+            [AuditAction::ExecutorEnabled, AuditAction::ExecutorDisabled]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_executor_enabled_before() {
+        use crate::{executor::AddExecutor, memory::StateMemory};
+        // enabled in a previous run (or imported), never by this orchestrator
+        let memory = DefaultMemory::init();
+        let input = State::DummyExercise(DummyExercise {});
+        let output = State::ExerciseResult(ExerciseResult::default());
+        StateMemory::enable_executor(memory.as_ref(), &input, &output, "1".to_string())
+            .await
+            .unwrap();
+        let mut o: Orchestrator<State> = Orchestrator::new(1, false, memory);
+        o.add_executor(scaled, 1).await.unwrap();
+        let o = o.as_ref();
+        let reference: &dyn ReferenceWithoutState = &o;
+        let input = input.serialize_variant();
+        reference
+            .replace_executor_data_by_variant(&input, "2".to_string(), None)
+            .await
+            .unwrap();
+        reference
+            .disable_executor_by_variant(&input, None)
+            .await
+            .unwrap();
+        let enabled = Orchestrator::memory(&o)
+            .list_enabled_executors()
+            .await
+            .unwrap();
+        assert!(enabled.is_empty());
+    }
+
+    async fn keep_source(
+        _: DummyExercise,
+        _: String,
+    ) -> Result<DummyExercise, Box<dyn Error + Send + Sync>> {
+        Ok(DummyExercise {})
+    }
+
+    #[tokio::test]
+    async fn test_validate() {
+        use crate::{executor::AddExecutor, prelude::ValidationProblem};
+        let mut o: Orchestrator<State> = Orchestrator::new(1, false, DefaultMemory::init());
+        o.add_exercise_generators(counting_generator, keep_source)
+            .await;
+        // stored with a type that has no generator
+        o.memory()
+            .add_exercise(
+                "orphan".to_string(),
+                State::ExerciseResult(ExerciseResult::default()),
+                String::new(),
+            )
+            .await
+            .unwrap();
+        o.memory()
+            .add_exercise(
+                "somma".to_string(),
+                State::DummyExercise(DummyExercise {}),
+                String::new(),
+            )
+            .await
+            .unwrap();
+        let generator = std::any::type_name::<DummyExercise>().to_string();
+        let result = std::any::type_name::<ExerciseResult>().to_string();
+        let missing = ValidationProblem::MissingGenerator {
+            exercise: "orphan".to_string(),
+        };
+        let invalid = ValidationProblem::InvalidGenerator {
+            exercise: "somma".to_string(),
+            generator: generator.clone(),
+        };
+
+        let report = o.validate().await.unwrap();
+        let stops = ValidationProblem::NoEnabledExecutor {
+            generator: generator.clone(),
+            state: generator.clone(),
+        };
+        assert_eq!(report.problems, [stops, missing.clone(), invalid.clone()]);
+
+        // enabled in memory (for example by a previous run), but not registered
+        o.memory()
+            .enable_executor(
+                &State::DummyExercise(DummyExercise {}),
+                &State::ExerciseResult(ExerciseResult::default()),
+                "1".to_string(),
+            )
+            .await
+            .unwrap();
+        let report = o.validate().await.unwrap();
+        let unregistered = ValidationProblem::UnregisteredExecutor {
+            generator: generator.clone(),
+            from: generator,
+            to: result,
+        };
+        assert_eq!(report.problems, [unregistered, missing.clone(), invalid]);
+        assert!(report.to_string().starts_with("3 configuration problems:"));
+
+        o.add_executor(scaled, 1).await.unwrap();
+        let report = o.validate().await.unwrap();
+        assert_eq!(report.problems, [missing]);
+    }
+
+    /// separated, so it has its own State
+    mod bulk {
+        use std::{
+            error::Error,
+            sync::atomic::{AtomicUsize, Ordering},
+        };
+
+        use crate as orchestrator;
+        use crate::{default_memory::DefaultMemory, GenerateState};
+
+        /// exercises checked at the same time, and the max reached
+        static CHECKING: AtomicUsize = AtomicUsize::new(0);
+        static MAX_CHECKING: AtomicUsize = AtomicUsize::new(0);
+        /// the template is the only field of the definition
+        #[derive(Clone, Default)]
+        struct Template(String);
+        impl crate::prelude::ExerciseDef for Template {
+            fn description(&self) -> &str {
+                "template used to test add_exercises"
+            }
+            fn get_generator_src(&self) -> &str {
+                &self.0
+            }
+            fn list(&self) -> Vec<crate::prelude::TestDefinition> {
+                Vec::new()
+            }
+        }
+        GenerateState!(Template, ExerciseResult);
+        async fn template_generator(
+            template: String,
+        ) -> Result<Template, Box<dyn Error + Send + Sync>> {
+            if template == "broken" {
+                return Err("not a template".into());
+            }
+            Ok(Template(template))
+        }
+        /// the solution passes only if the template is "ok"
+        async fn slow_check(
+            template: Template,
+            _: String,
+        ) -> Result<ExerciseResult, Box<dyn Error + Send + Sync>> {
+            use crate::prelude::{CompilationResult, RunResult, TestResult};
+            let checking = CHECKING.fetch_add(1, Ordering::SeqCst) + 1;
+            MAX_CHECKING.fetch_max(checking, Ordering::SeqCst);
+            for _ in 0..10 {
+                tokio::task::yield_now().await;
+            }
+            CHECKING.fetch_sub(1, Ordering::SeqCst);
+            let runned = if template.0 == "ok" {
+                RunResult::Ok
+            } else {
+                RunResult::Error("wrong".to_string())
+            };
+            let mut result = ExerciseResult::default();
+            let test = TestResult {
+                compiled: CompilationResult::Built(Vec::new()),
+                runned,
+                points_given: 1.0,
+                diagnostics: None,
+            };
+            result.tests.insert("test".to_string(), test);
+            Ok(result)
+        }
+
+        #[tokio::test]
+        async fn test_add_exercises() {
+            use crate::prelude::AddOutcome;
+            let mut o: Orchestrator<State> = Orchestrator::new(2, true, DefaultMemory::init());
+            o.add_exercise_generators(template_generator, slow_check)
+                .await;
+            let exercises = [
+                ("first", "ok"),
+                ("failing", "wrong"),
+                ("broken", "broken"),
+                ("second", "ok"),
+                ("third", "ok"),
+            ];
+            let report = o.add_exercises::<Template>(&exercises).await;
+            let names: Vec<_> = report.iter().map(|(name, _)| name.as_str()).collect();
+            assert_eq!(names, ["first", "failing", "broken", "second", "third"]);
+            assert!(report[0].1.is_added());
+            assert!(matches!(&report[1].1, AddOutcome::FailedTests(x) if x.tests.len() == 1));
+            assert!(matches!(report[2].1, AddOutcome::Failed(_)));
+            assert!(report[3].1.is_added() && report[4].1.is_added());
+            assert_eq!(MAX_CHECKING.load(Ordering::SeqCst), 2);
+
+            let mut added = o.memory().list_exercise_names().await.unwrap();
+            added.sort();
+            assert_eq!(added, ["first", "second", "third"]);
+        }
+    }
 
     #[test]
     fn test_syncness() {
@@ -0,0 +1,279 @@
+//! Password hashing shared by all Memory implementations.
+//!
+//! A [PasswordPolicy] describes which algorithm (and with which parameters) is used to generate new hashes.
+//! Hashes are saved in the PHC string format, so they carry their own algorithm and parameters:
+//! this permits to verify old hashes after the policy changes, and to detect when a hash should be regenerated.
+//!
+//! The suggested usage (and what the provided implementations do) is to rehash on login:
+//! ```
+//! use orchestrator::password::{PasswordPolicy, Verification};
+//! let policy = PasswordPolicy::default();
+//! let hash = policy.hash("secret").unwrap();
+//! match policy.verify("secret", &hash).unwrap() {
+//!     Verification::Valid => {}
+//!     Verification::NeedsRehash => { /* save policy.hash("secret") */ }
+//!     Verification::Invalid => panic!("wrong password"),
+//! }
+//! ```
+use argon2::Argon2;
+use rand::thread_rng;
+use scrypt::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Scrypt,
+};
+use serde::{Deserialize, Serialize};
+use tokio::task::{spawn_blocking, JoinError};
+
+/// Stored in place of a hash when the user can't log in with a password (for example users of external providers).
+/// No password matches it
+pub const DISABLED_PASSWORD: &str = "!";
+
+#[derive(thiserror::Error, Debug)]
+/// Errors that can be generated while hashing or verifying a password
+pub enum PasswordError {
+    /// The hash is malformed, or the parameters are not valid
+    #[error("Hash Error {0}")]
+    Hash(#[from] scrypt::password_hash::Error),
+
+    /// Invalid argon2 parameters
+    #[error("Argon2 Error {0}")]
+    Argon2(#[from] argon2::Error),
+
+    /// Invalid scrypt parameters
+    #[error("Scrypt Error {0}")]
+    Scrypt(#[from] scrypt::errors::InvalidParams),
+
+    /// The stored hash uses an algorithm that is not supported
+    #[error("unsupported hash algorithm: {0}")]
+    UnsupportedAlgorithm(String),
+
+    /// Join Error from tokio
+    #[error("Join Error {0}")]
+    TokioJoin(#[from] JoinError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// Algorithm (and its parameters) used to generate new hashes
+pub enum HashAlgorithm {
+    /// scrypt, cost is expressed as log2(N)
+    Scrypt {
+        /// log2 of the CPU/memory cost
+        log_n: u8,
+        /// block size
+        r: u32,
+        /// parallelization
+        p: u32,
+    },
+    /// argon2id
+    Argon2 {
+        /// memory cost in KiB
+        memory_kib: u32,
+        /// number of iterations
+        iterations: u32,
+        /// degree of parallelism
+        parallelism: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Outcome of a password verification
+pub enum Verification {
+    /// the password does not match
+    Invalid,
+    /// the password matches, and the hash respects the current policy
+    Valid,
+    /// the password matches, but the hash was generated with another algorithm or other parameters
+    NeedsRehash,
+}
+
+impl Verification {
+    /// does the password match?
+    pub fn is_valid(&self) -> bool {
+        !matches!(self, Verification::Invalid)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// Which algorithm should be used to hash passwords.
+///
+/// The default is scrypt with log_n=10, the same parameters historically used by the Postgres memory.
+pub struct PasswordPolicy {
+    /// algorithm used for new hashes
+    pub algorithm: HashAlgorithm,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            algorithm: HashAlgorithm::Scrypt {
+                log_n: 10,
+                r: scrypt::Params::RECOMMENDED_R,
+                p: scrypt::Params::RECOMMENDED_P,
+            },
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// policy using argon2id with the recommended parameters
+    pub fn argon2() -> Self {
+        Self {
+            algorithm: HashAlgorithm::Argon2 {
+                memory_kib: argon2::Params::DEFAULT_M_COST,
+                iterations: argon2::Params::DEFAULT_T_COST,
+                parallelism: argon2::Params::DEFAULT_P_COST,
+            },
+        }
+    }
+
+    /// hash a password following this policy, returns a PHC string
+    pub fn hash(&self, password: &str) -> Result<String, PasswordError> {
+        let salt = SaltString::generate(thread_rng());
+        let hash = match self.algorithm {
+            HashAlgorithm::Scrypt { log_n, r, p } => {
+                let params = scrypt::Params::new(log_n, r, p, scrypt::Params::RECOMMENDED_LEN)?;
+                Scrypt.hash_password_customized(password.as_bytes(), None, None, params, &salt)?
+            }
+            HashAlgorithm::Argon2 { .. } => self
+                .argon2_hasher()?
+                .hash_password(password.as_bytes(), &salt)?,
+        };
+        Ok(hash.to_string())
+    }
+
+    /// verify a password against a PHC string, and check if the hash respects this policy
+    pub fn verify(&self, password: &str, hash: &str) -> Result<Verification, PasswordError> {
+        if hash == DISABLED_PASSWORD {
+            return Ok(Verification::Invalid);
+        }
+        let parsed = PasswordHash::new(hash)?;
+        let algorithm = parsed.algorithm.as_str();
+        let verified = match algorithm {
+            "scrypt" => Scrypt.verify_password(password.as_bytes(), &parsed),
+            "argon2id" | "argon2i" | "argon2d" => {
+                Argon2::default().verify_password(password.as_bytes(), &parsed)
+            }
+            _ => return Err(PasswordError::UnsupportedAlgorithm(algorithm.to_string())),
+        };
+        match verified {
+            Ok(()) if self.is_up_to_date(&parsed) => Ok(Verification::Valid),
+            Ok(()) => Ok(Verification::NeedsRehash),
+            Err(scrypt::password_hash::Error::Password) => Ok(Verification::Invalid),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// same as hash, but executed on a blocking thread (hashing is cpu expensive)
+    pub async fn hash_async(&self, password: &str) -> Result<String, PasswordError> {
+        let policy = *self;
+        let password = password.to_string();
+        spawn_blocking(move || policy.hash(&password)).await?
+    }
+
+    /// same as verify, but executed on a blocking thread (hashing is cpu expensive)
+    pub async fn verify_async(
+        &self,
+        password: &str,
+        hash: &str,
+    ) -> Result<Verification, PasswordError> {
+        let policy = *self;
+        let password = password.to_string();
+        let hash = hash.to_string();
+        spawn_blocking(move || policy.verify(&password, &hash)).await?
+    }
+
+    fn argon2_hasher(&self) -> Result<Argon2<'static>, PasswordError> {
+        let HashAlgorithm::Argon2 {
+            memory_kib,
+            iterations,
+            parallelism,
+        } = self.algorithm
+        else {
+            return Err(PasswordError::UnsupportedAlgorithm("scrypt".to_string()));
+        };
+        let params = argon2::Params::new(memory_kib, iterations, parallelism, None)?;
+        Ok(Argon2::new(
+            argon2::Algorithm::Argon2id,
+            argon2::Version::V0x13,
+            params,
+        ))
+    }
+
+    /// was this hash generated with the current algorithm and parameters?
+    fn is_up_to_date(&self, parsed: &PasswordHash) -> bool {
+        match self.algorithm {
+            HashAlgorithm::Scrypt { log_n, r, p } => {
+                parsed.algorithm.as_str() == "scrypt"
+                    && scrypt::Params::try_from(parsed)
+                        .map(|x| x.log_n() == log_n && x.r() == r && x.p() == p)
+                        .unwrap_or(false)
+            }
+            HashAlgorithm::Argon2 {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => {
+                parsed.algorithm.as_str() == "argon2id"
+                    && argon2::Params::try_from(parsed)
+                        .map(|x| {
+                            x.m_cost() == memory_kib
+                                && x.t_cost() == iterations
+                                && x.p_cost() == parallelism
+                        })
+                        .unwrap_or(false)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{HashAlgorithm, PasswordPolicy, Verification};
+
+    #[test]
+    fn test_hash_and_verify() {
+        for policy in [PasswordPolicy::default(), PasswordPolicy::argon2()] {
+            let hash = policy.hash("mondo").unwrap();
+            assert_eq!(policy.verify("mondo", &hash).unwrap(), Verification::Valid);
+            assert_eq!(
+                policy.verify("not mondo", &hash).unwrap(),
+                Verification::Invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_needs_rehash() {
+        let old = PasswordPolicy::default();
+        let hash = old.hash("mondo").unwrap();
+
+        // other parameters
+        let stronger = PasswordPolicy {
+            algorithm: HashAlgorithm::Scrypt {
+                log_n: 11,
+                r: 8,
+                p: 1,
+            },
+        };
+        assert_eq!(
+            stronger.verify("mondo", &hash).unwrap(),
+            Verification::NeedsRehash
+        );
+        // other algorithm
+        let argon = PasswordPolicy::argon2();
+        assert_eq!(
+            argon.verify("mondo", &hash).unwrap(),
+            Verification::NeedsRehash
+        );
+        assert_eq!(argon.verify("ciao", &hash).unwrap(), Verification::Invalid);
+    }
+
+    #[test]
+    fn test_disabled() {
+        let policy = PasswordPolicy::default();
+        assert_eq!(
+            policy.verify("", super::DISABLED_PASSWORD).unwrap(),
+            Verification::Invalid
+        );
+    }
+}
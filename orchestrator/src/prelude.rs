@@ -1,7 +1,7 @@
 //! Contains some reexport from tis crate and other dependencies.
 //! use:
 //! ```
-//! use orchestrator::prelude::*
+//! use orchestrator::prelude::*;
 //! ```
 //! to have all thats needed in this crate
 
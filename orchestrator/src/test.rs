@@ -42,16 +42,18 @@ async fn add_source(
     let mut d = ExerciseResult::default();
     use crate::executor_trait::CompilationResult::*;
     let t1 = TestResult {
-        compiled: Built,
+        compiled: Built(Vec::new()),
         runned: RunResult::Ok,
         points_given: 1.0,
+        diagnostics: None,
     };
     d.tests.insert("test1".to_string(), t1);
 
     let t2 = TestResult {
-        compiled: Built,
+        compiled: Built(Vec::new()),
         runned: RunResult::Ok,
         points_given: 1.0,
+        diagnostics: None,
     };
     d.tests.insert("test2".to_string(), t2);
 
@@ -149,7 +151,7 @@ impl<S: ExecutorGlobalState> TestInterface for DefaultInterface<S> {
             .await
     }
     async fn list_exercise(&mut self) -> Result<Vec<String>, Box<dyn Error + 'static>> {
-        self.o.memory().list_exercise_names().await
+        Ok(self.o.memory().list_exercise_names().await?)
     }
 }
 
@@ -20,7 +20,7 @@ impl Fold for Sanitizer{
 pub fn magic_macro(_attr: TokenStream, item: TokenStream) -> TokenStream {
     let input: ItemMod = parse(item).unwrap();
     let mut ret = input.clone();
-    let input_content = input.content.map(|(_, a)| a).unwrap_or(Vec::new());
+    let input_content = input.content.map(|(_, a)| a).unwrap_or_default();
     let file = File{
         shebang: None,
         attrs: input.attrs,
@@ -33,10 +33,10 @@ pub fn magic_macro(_attr: TokenStream, item: TokenStream) -> TokenStream {
 
 
     
-    if ret.content.is_none(){
-        ret.content = Some((Brace::default(), Vec::new()));
+    if let Some((_, content)) = ret.content.as_mut(){
+        content.clear();
     }else{
-        ret.content.as_mut().unwrap().1.clear();
+        ret.content = Some((Brace::default(), Vec::new()));
     }
     let ret_item = ret.content.as_mut().map(|(_, a)| a).unwrap();
 
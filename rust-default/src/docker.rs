@@ -143,9 +143,10 @@ impl DockerCompile for RustGeneratedFiles {
             .into_iter()
             .map(|(name, (_, points))| {
                 let test_result = TestResult {
-                    compiled: CompilationResult::Built,
+                    compiled: CompilationResult::Built(Vec::new()),
                     runned: RunResult::NotRun,
                     points_given: points,
+                    diagnostics: None,
                 };
                 (name, test_result)
             })
@@ -238,7 +239,7 @@ impl DockerRun for RustCompiled {
                 compilation_output
             }); */
             join.spawn(async move {
-                if let CompilationResult::Built = test_result.compiled {
+                if let CompilationResult::Built(_) = test_result.compiled {
                     if let StartExecResults::Attached { mut output, .. } =
                         docker2.start_exec(&exec, None).await.unwrap()
                     {
@@ -62,12 +62,12 @@ pub fn parse_errors(inp: &str, tests: &mut HashMap<String, TestResult>) {
                 return None;
             }
             if let Some(test_result) = tests.get_mut(name) {
-                if let CompilationResult::Error(msg) = &mut test_result.compiled {
+                if let CompilationResult::Error(msg, _) = &mut test_result.compiled {
                     msg.push('\n');
                     *msg += &rendered;
                 } else {
                     test_result.points_given = 0.0;
-                    test_result.compiled = CompilationResult::Error(rendered)
+                    test_result.compiled = CompilationResult::Error(rendered, Vec::new())
                 }
             }
             Some(())
@@ -127,9 +127,10 @@ impl RustGeneratedFiles {
             .into_iter()
             .map(|(name, (_, points))| {
                 let test_result = TestResult {
-                    compiled: CompilationResult::Built,
+                    compiled: CompilationResult::Built(Vec::new()),
                     runned: RunResult::NotRun,
                     points_given: points,
+                    diagnostics: None,
                 };
                 (name, test_result)
             })
@@ -165,9 +166,10 @@ impl AsyncDefault for RustCompiled {
         results.insert(
             "test_nothing".to_string(),
             TestResult {
-                compiled: CompilationResult::Built,
+                compiled: CompilationResult::Built(Vec::new()),
                 runned: RunResult::NotRun,
                 points_given: 1.0,
+                diagnostics: None,
             },
         );
         RustCompiled {
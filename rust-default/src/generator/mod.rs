@@ -239,9 +239,10 @@ mod tests {
         let v = vec![(
             "test_print".to_string(),
             TestResult {
-                compiled: CompilationResult::Built,
+                compiled: CompilationResult::Built(Vec::new()),
                 runned: RunResult::Ok,
                 points_given: 1.0,
+                diagnostics: None,
             },
         )];
         assert_eq!(t.tests, v.into_iter().collect());
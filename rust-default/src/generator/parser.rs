@@ -4,11 +4,11 @@ rules:
  * #[runtest<(Optional_points)>]
  * #[default_impl] used to decorate default impl that should be used in tests
  * #[refers_to(paths)] which default impl should I use (at least one path should be provided)
-    In a correct exercise only the overridden impl should be deleted
+   In a correct exercise only the overridden impl should be deleted
 
-    Because of that there are two passes:
-    take file input and Parse it obtaining a Parser struct (visit)
-    Transform it in an RustExercise, composed of multiple RustTests which describes how to compute an exercise (which impl to remove and which to add).
+   Because of that there are two passes:
+   take file input and Parse it obtaining a Parser struct (visit)
+   Transform it in an RustExercise, composed of multiple RustTests which describes how to compute an exercise (which impl to remove and which to add).
  */
 
 use std::collections::HashMap;
@@ -22,7 +22,7 @@ impl RustCompiled {
         for (name, mut test_result) in self.results {
             let exec = self.path.join("target").join("debug").join(&name);
             set.spawn(async move {
-                if let CompilationResult::Built = test_result.compiled {
+                if let CompilationResult::Built(_) = test_result.compiled {
                     //let t = Command::new(exec).output().await?;
                     match Command::new(&exec).output().await {
                         Ok(output) if output.status.success() => {
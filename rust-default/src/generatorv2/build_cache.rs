@@ -0,0 +1,348 @@
+//! Dependencies of the exercises, built once and shared by every submission.
+//!
+//! For each set of dependencies (and toolchain) the cache has a project that only declares them.
+//! It is built once, while holding a file lock, and then it is only read:
+//! the projects of the submissions don't declare the dependencies, they get the built libraries
+//! with `--extern`, so cargo only compiles the code of the student.
+//! Many submissions, and many servers, can use the same cache at the same time.
+use std::{
+    collections::HashMap,
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+};
+
+use orchestrator::prelude::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{fs, process::Command, task::spawn_blocking};
+
+use super::compiled::create_cargo_project;
+
+/// written when the dependencies are built, it's what the submissions read
+const MANIFEST: &str = "prebuilt.json";
+
+/// A directory with the prebuilt dependencies of the exercises
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "orchestrator::prelude::serde")]
+pub struct BuildCache {
+    /// where the dependencies are built, it is created if missing
+    pub root: PathBuf,
+    /// the dependencies are taken from the local registry, without downloading them
+    #[serde(default)]
+    pub offline: bool,
+}
+
+/// The built dependencies of an exercise
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "orchestrator::prelude::serde")]
+pub struct Prebuilt {
+    /// all the built libraries, the transitive dependencies too
+    deps: PathBuf,
+    /// name in the code and library of each dependency
+    externs: Vec<(String, PathBuf)>,
+}
+
+impl Prebuilt {
+    /// flags for rustc, in the format of CARGO_ENCODED_RUSTFLAGS
+    pub fn rustflags(&self) -> String {
+        let mut flags = vec![
+            "-L".to_string(),
+            format!("dependency={}", self.deps.display()),
+        ];
+        for (name, lib) in &self.externs {
+            flags.push("--extern".to_string());
+            flags.push(format!("{}={}", name, lib.display()));
+        }
+        flags.join("\x1f")
+    }
+}
+
+impl BuildCache {
+    /// a cache in the given directory
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            offline: false,
+        }
+    }
+
+    /// the built dependencies, they are built if it's the first time they are requested.
+    /// None if there is nothing to build
+    pub async fn prebuild(&self, dependencies: &[String]) -> io::Result<Option<Prebuilt>> {
+        if dependencies.is_empty() {
+            return Ok(None);
+        }
+        let key = digest(&toolchain().await?, dependencies);
+        let dir = self.root.join(format!("{:016x}", key));
+        let manifest = dir.join(MANIFEST);
+        if let Some(prebuilt) = read_manifest(&manifest).await {
+            return Ok(Some(prebuilt));
+        }
+
+        fs::create_dir_all(&dir).await?;
+        let _lock = lock(dir.join(".lock")).await?;
+        // it could have been built while waiting for the lock
+        if let Some(prebuilt) = read_manifest(&manifest).await {
+            return Ok(Some(prebuilt));
+        }
+        let project = dir.join("project");
+        create_cargo_project(&project, dependencies)
+            .await
+            .map_err(io::Error::other)?;
+        fs::write(project.join("src").join("lib.rs"), "").await?;
+        let build = self
+            .cargo(&project, "build")
+            .arg("--lib")
+            .arg("--message-format=json")
+            .output()
+            .await?;
+        if !build.status.success() {
+            return Err(io::Error::other(format!(
+                "the dependencies can't be built: {}",
+                String::from_utf8_lossy(&build.stderr)
+            )));
+        }
+        let metadata = self
+            .cargo(&project, "metadata")
+            .arg("--format-version=1")
+            .arg("--offline")
+            .output()
+            .await?;
+        let prebuilt = Prebuilt {
+            deps: project.join("target").join("debug").join("deps"),
+            externs: externs(&build.stdout, &metadata.stdout)
+                .ok_or_else(|| io::Error::other("unexpected output of cargo"))?,
+        };
+
+        // the submissions see it only when it's complete
+        let tmp = dir.join(format!("{}.tmp", MANIFEST));
+        fs::write(&tmp, serde_json::to_vec(&prebuilt)?).await?;
+        fs::rename(&tmp, &manifest).await?;
+        Ok(Some(prebuilt))
+    }
+
+    fn cargo(&self, project: &Path, command: &str) -> Command {
+        let mut cargo = Command::new("cargo");
+        cargo
+            .arg("+nightly")
+            .arg(command)
+            .arg("--manifest-path")
+            .arg(project.join("Cargo.toml"));
+        if self.offline {
+            cargo.env("CARGO_NET_OFFLINE", "true");
+        }
+        cargo
+    }
+}
+
+/// version of the compiler: libraries built by another version can't be used
+/// FNV-1a of the toolchain and of the dependencies, one per line.
+/// Unlike the std hasher it doesn't change between versions of rust, and the cache is shared
+fn digest(toolchain: &str, dependencies: &[String]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for line in std::iter::once(toolchain).chain(dependencies.iter().map(String::as_str)) {
+        for byte in line.bytes().chain(std::iter::once(b'\n')) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+async fn toolchain() -> io::Result<String> {
+    let output = Command::new("rustc")
+        .arg("+nightly")
+        .arg("-vV")
+        .output()
+        .await?;
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+async fn read_manifest(path: &Path) -> Option<Prebuilt> {
+    let prebuilt: Prebuilt = serde_json::from_slice(&fs::read(path).await.ok()?).ok()?;
+    // the libraries could have been deleted
+    prebuilt.deps.exists().then_some(prebuilt)
+}
+
+/// exclusive lock on the file, it's released when the file is dropped
+async fn lock(path: PathBuf) -> io::Result<File> {
+    spawn_blocking(move || {
+        let file = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?;
+        file.lock()?;
+        Ok(file)
+    })
+    .await?
+}
+
+/// the libraries of the direct dependencies, with the name they have in the code.
+///
+/// The libraries are in the artifacts printed by cargo build,
+/// and the direct dependencies are in the resolve of cargo metadata
+fn externs(build: &[u8], metadata: &[u8]) -> Option<Vec<(String, PathBuf)>> {
+    let mut libraries = HashMap::new();
+    for line in build.split(|x| *x == b'\n') {
+        let Ok(message) = serde_json::from_slice::<Value>(line) else {
+            continue;
+        };
+        if message["reason"] != "compiler-artifact" {
+            continue;
+        }
+        // rlib and rmeta for libraries, a dynamic library for procedural macros
+        let library = message["filenames"]
+            .as_array()?
+            .iter()
+            .filter_map(Value::as_str)
+            .find(|x| !x.ends_with(".rmeta"));
+        if let (Some(id), Some(library)) = (message["package_id"].as_str(), library) {
+            libraries.insert(id.to_string(), PathBuf::from(library));
+        }
+    }
+
+    let metadata: Value = serde_json::from_slice(metadata).ok()?;
+    let resolve = &metadata["resolve"];
+    let root = resolve["nodes"]
+        .as_array()?
+        .iter()
+        .find(|x| x["id"] == resolve["root"])?;
+    Some(
+        root["deps"]
+            .as_array()?
+            .iter()
+            .filter_map(|dep| {
+                let library = libraries.get(dep["pkg"].as_str()?)?;
+                Some((dep["name"].as_str()?.to_string(), library.clone()))
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use orchestrator::{default_memory::DefaultMemory, prelude::*, GenerateState};
+    use tempdir::TempDir;
+
+    use super::{digest, BuildCache, MANIFEST};
+    use crate::{
+        generatorv2::{
+            compiled::{CompileConfig, RustCompiled},
+            file_generator::GeneratedFiles,
+        },
+        prelude::*,
+    };
+
+    GenerateState!(
+        RustExercise2,
+        GeneratedFiles2,
+        RustCompiled2,
+        ExerciseResult
+    );
+
+    #[test]
+    fn test_digest() {
+        // the name of the directory must be the same for every build of the servers
+        let dependencies = vec!["cfg-if = \"1\"".to_string()];
+        assert_eq!(digest("rustc 1.0", &dependencies), 0x0bda7aa0c7ea0035);
+        assert_ne!(digest("rustc 1.1", &dependencies), 0x0bda7aa0c7ea0035);
+    }
+
+    #[tokio::test]
+    async fn test_prebuild() {
+        let dir = TempDir::new("build_cache").unwrap();
+        // the dependency is already in the local registry
+        let cache = BuildCache {
+            offline: true,
+            ..BuildCache::new(dir.path())
+        };
+        let dependencies = vec!["cfg-if = \"1\"\n".to_string()];
+        assert_eq!(cache.prebuild(&[]).await.unwrap(), None);
+        let prebuilt = cache.prebuild(&dependencies).await.unwrap().unwrap();
+        assert_eq!(prebuilt.externs.len(), 1);
+        assert_eq!(prebuilt.externs[0].0, "cfg_if");
+        // the second time it's read from the cache
+        assert_eq!(cache.prebuild(&dependencies).await.unwrap(), Some(prebuilt));
+
+        let source = "cfg_if::cfg_if! { if #[cfg(unix)] { fn main() {} } else { fn main() {} } }";
+        let generated = GeneratedFiles {
+            files: HashMap::from([("test_1".to_string(), (source.to_string(), 1.0))]),
+            limits: HashMap::new(),
+            dependencies,
+            ..Default::default()
+        };
+        let config = CompileConfig {
+            path: None,
+            build_cache: Some(cache),
+            ..Default::default()
+        };
+        let compiled = RustCompiled::compile(generated, config).await.unwrap();
+        assert!(matches!(
+            compiled.results["test_1"].compiled,
+            CompilationResult::Built(_)
+        ));
+        let exec = compiled.path.join("target/debug/test_1");
+        assert!(std::process::Command::new(exec).status().unwrap().success());
+        // only the code of the student was compiled
+        let deps = std::fs::read_dir(compiled.path.join("target/debug/deps")).unwrap();
+        assert!(deps
+            .map(|x| x.unwrap().file_name().to_string_lossy().into_owned())
+            .all(|x| !x.contains("cfg_if")));
+    }
+
+    #[tokio::test]
+    async fn test_cache_error() {
+        let dir = TempDir::new("build_cache").unwrap();
+        // the cache can't be created inside a file
+        let root = dir.path().join("file");
+        std::fs::write(&root, "").unwrap();
+        let generated = GeneratedFiles {
+            files: HashMap::from([("test_1".to_string(), ("fn main() {}".to_string(), 1.0))]),
+            limits: HashMap::new(),
+            dependencies: vec!["cfg-if = \"1\"\n".to_string()],
+            ..Default::default()
+        };
+        let config = CompileConfig {
+            path: Some(dir.path().join("project")),
+            build_cache: Some(BuildCache::new(root)),
+            ..Default::default()
+        };
+        let compiled = RustCompiled::compile(generated, config).await.unwrap();
+        let diagnostics = compiled.results["test_1"].compiled.diagnostics();
+        assert!(diagnostics
+            .iter()
+            .any(|x| x.internal && x.message.starts_with("build cache not used")));
+    }
+
+    #[tokio::test]
+    async fn test_exercise_added() {
+        let dir = TempDir::new("build_cache").unwrap();
+        let cache = BuildCache {
+            offline: true,
+            ..BuildCache::new(dir.path())
+        };
+        let mut o: Orchestrator<State> = Orchestrator::new(16, true, DefaultMemory::init());
+        let plugin = RustDefaultPlugin2::default()
+            .set_activate_default()
+            .set_build_cache(cache);
+        o.add_plugin(plugin).await.unwrap();
+        let template = r#"
+            #![dependency("cfg-if = \"1\"\n")]
+            #[runtest(1.0)]
+            fn test_1() {}
+        "#;
+        o.add_exercise::<RustExercise2>("es1", template)
+            .await
+            .unwrap();
+        // the dependencies are built before the first submission
+        let built: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|x| x.unwrap().path().join(MANIFEST))
+            .collect();
+        assert_eq!(built.len(), 1);
+        assert!(built[0].exists());
+    }
+}
@@ -1,11 +1,26 @@
-use std::{collections::HashMap, path::{Path, PathBuf}, string::FromUtf8Error};
 use copy_dir::copy_dir;
-use orchestrator::prelude::{AsyncDefault, CompilationResult, RunResult, TestResult};
+use orchestrator::prelude::{
+    AsyncDefault, CompilationResult, CompilerDiagnostic, Deserialize, RunResult, Serialize,
+    TestResult,
+};
 use serde_json::Value;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    string::FromUtf8Error,
+};
 use tempdir::TempDir;
 use tokio::{fs, process::Command};
 
-use super::file_generator::GeneratedFiles;
+use super::{
+    build_cache::BuildCache,
+    file_generator::GeneratedFiles,
+    harness::{Harness, HARNESS},
+    iotest::IoTest,
+    isolation::{error_lines, isolate},
+    run::TestLimits,
+    source_map::{diagnostic, is_shown, SourceMap},
+};
 
 /// Error that can get generated in a compilation with cargo
 #[derive(Debug, thiserror::Error)]
@@ -28,6 +43,17 @@ pub struct RustCompiled {
     pub path: PathBuf,
     /// results of the compilation
     pub results: HashMap<String, TestResult>,
+    /// limits set by the tests, see GeneratedFiles
+    pub limits: HashMap<String, TestLimits>,
+    /// input and expected output of the tests of the output, see GeneratedFiles
+    pub io: HashMap<String, IoTest>,
+    /// the binary with all the tests, it runs the test given as argument.
+    /// None if each test has its own binary
+    pub binary: Option<String>,
+    /// the items of the template in the file of each test, see GeneratedFiles
+    pub(crate) template: HashMap<String, HashSet<String>>,
+    /// the code of the student, the panics are shown on it (see source_map)
+    pub(crate) submission: Option<String>,
 }
 impl AsyncDefault for RustCompiled{
     async fn async_default() -> Self {
@@ -36,6 +62,11 @@ impl AsyncDefault for RustCompiled{
             _tmpdir: None,
             path: PathBuf::new(),
             results: HashMap::new(),
+            limits: HashMap::new(),
+            io: HashMap::new(),
+            binary: None,
+            template: HashMap::new(),
+            submission: None,
         }
     }
 }
@@ -55,45 +86,80 @@ impl Clone for RustCompiled {
             _tmpdir,
             path: self.path.clone(),
             results: self.results.clone(),
+            limits: self.limits.clone(),
+            io: self.io.clone(),
+            binary: self.binary.clone(),
+            template: self.template.clone(),
+            submission: self.submission.clone(),
         }
     }
 }
 
 /**
-    it extracts the various error from the output
+    it extracts the errors and the warnings from the output,
+    they are shown on the code of the student when it's known (see source_map)
 */
-fn parse_errors(inp: &str, tests: &mut HashMap<String, TestResult>) {
+fn parse_errors(
+    inp: &str,
+    tests: &mut HashMap<String, TestResult>,
+    files: &HashMap<String, String>,
+    generated: &GeneratedFiles,
+) {
+    let mut maps: HashMap<String, Option<SourceMap>> = HashMap::new();
     let _: Vec<Option<()>> = inp
         .lines()
         .map(|t| -> Option<()> {
             let error = serde_json::from_str::<Value>(t).ok()?;
-            let error = error.as_object()?;
-            let message = error.get("message")?.as_object()?;
-            let level = message.get("level")?;
-            let rendered = message.get("rendered")?.as_str()?.to_string();
-            let rendered = rendered.replace("\\n", std::str::from_utf8(b"\n").unwrap());
-
-            let target = error.get("target")?.as_object()?;
-            let name = target.get("name")?.as_str()?;
-            if level != "error" {
+            let message = error.get("message")?;
+            let name = error.get("target")?.get("name")?.as_str()?;
+            if !is_shown(message) {
                 return None;
             }
-            if let Some(test_result) = tests.get_mut(name) {
-                if let CompilationResult::Error(msg) = &mut test_result.compiled {
-                    msg.push('\n');
-                    *msg += &rendered;
-                } else {
-                    test_result.points_given = 0.0;
-                    test_result.compiled = CompilationResult::Error(rendered)
-                }
+            let test_result = tests.get_mut(name)?;
+            let map = maps.entry(name.to_string()).or_insert_with(|| {
+                let template = generated.template.get(name).cloned().unwrap_or_default();
+                SourceMap::new(files.get(name)?, generated.submission.as_ref()?, &template)
+            });
+            let source = map.as_ref().zip(generated.submission.as_deref());
+            let mut diagnostic = diagnostic(message, &format!("/{}.rs", name), source);
+            if source.is_none() {
+                diagnostic.rendered = diagnostic.rendered.replace("\\n", "\n");
             }
+            add_diagnostic(test_result, diagnostic);
             Some(())
         })
         .collect();
 }
 
+/// adds the message of the compiler to the result of the test, an error fails it
+fn add_diagnostic(test_result: &mut TestResult, diagnostic: CompilerDiagnostic) {
+    if diagnostic.level == "error" {
+        if let CompilationResult::Built(warnings) = &mut test_result.compiled {
+            let warnings = std::mem::take(warnings);
+            test_result.points_given = 0.0;
+            test_result.compiled = CompilationResult::Error(String::new(), warnings);
+        }
+    }
+    match &mut test_result.compiled {
+        CompilationResult::Error(msg, diagnostics) => {
+            if diagnostic.level == "error" {
+                if !msg.is_empty() {
+                    msg.push('\n');
+                }
+                *msg += &diagnostic.rendered;
+            }
+            diagnostics.push(diagnostic);
+        }
+        CompilationResult::Built(diagnostics) => diagnostics.push(diagnostic),
+        CompilationResult::NotBuilt => {}
+    }
+}
+
 /// function used to create a valid Cargo Project
-async fn create_cargo_project(path: &Path, dependencies: &[String]) -> Result<(), CompileError> {
+pub(crate) async fn create_cargo_project(
+    path: &Path,
+    dependencies: &[String],
+) -> Result<(), CompileError> {
     //TODO clean well (delete target, overwrite other files)
     if path.exists() {
         fs::remove_dir_all(path).await?;
@@ -108,10 +174,33 @@ async fn create_cargo_project(path: &Path, dependencies: &[String]) -> Result<()
     Ok(())
 }
 
+/// How the tests are compiled, it is the data of the executor from GeneratedFiles to RustCompiled
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "orchestrator::prelude::serde", default)]
+pub struct CompileConfig {
+    /// where the project is created, by default in a temporary directory
+    pub path: Option<PathBuf>,
+    /// where the dependencies are built once for all the submissions,
+    /// by default each submission builds them
+    pub build_cache: Option<BuildCache>,
+    /// all the tests in a single binary, see harness. It's compiled once,
+    /// instead of once for each test
+    pub single_binary: bool,
+    /// the tests that don't compile because of items they don't use are compiled again
+    /// without them, see isolation. Not used with a single binary
+    pub isolate_errors: bool,
+}
+
 impl RustCompiled {
-    /// Compiles each files creating a project in a temporary directory, or in path if specified
-    pub async fn compile(generated: GeneratedFiles, path: Option<PathBuf>) -> Result<Self, CompileError> {
-        let (tmpdir, path) = if let Some(path) = path {
+    /// Compiles each files creating a project in a temporary directory, or in path if specified.
+    ///
+    /// With a build cache the dependencies are not compiled again: the first submission
+    /// (usually the check of the solution, when the exercise is added) builds them in the cache
+    pub async fn compile(
+        generated: GeneratedFiles,
+        config: CompileConfig,
+    ) -> Result<Self, CompileError> {
+        let (tmpdir, path) = if let Some(path) = config.path {
             (None, path)
         } else {
             let tmp_dir = TempDir::new("tmp_compile")?;
@@ -119,47 +208,247 @@ impl RustCompiled {
             (Some(tmp_dir), path)
         };
 
-        //generate crate
-        create_cargo_project(&path, generated.dependencies.as_slice()).await?;
+        // without the cache the project builds the dependencies, so their errors are shown anyway
+        let (prebuilt, cache_error) = match &config.build_cache {
+            Some(cache) => match cache.prebuild(&generated.dependencies).await {
+                Ok(prebuilt) => (prebuilt, None),
+                Err(err) => (None, Some(format!("build cache not used: {}", err))),
+            },
+            None => (None, None),
+        };
 
-        for (name, (content, _)) in &generated.files {
-            
-            fs::write(
-                path.join("src").join("bin").join(name.clone() + ".rs"),
-                content,
-            )
-            .await?;
-        }
-        let compilation_output = Command::new("cargo")
-            .arg("+nightly")
-            .arg("build")
-            .arg("--bins")
-            .arg("--manifest-path")
-            .arg(path.join("Cargo.toml"))
-            .arg("--keep-going")
-            .arg("--message-format=json")
-            .output()
-            .await?;
-        let message = String::from_utf8(compilation_output.stdout)?;
+        //generate crate, the prebuilt dependencies are given directly to rustc
+        let dependencies = if prebuilt.is_some() {
+            &[]
+        } else {
+            generated.dependencies.as_slice()
+        };
+        create_cargo_project(&path, dependencies).await?;
+
+        let build = |targets: &[&str]| {
+            let mut cargo = Command::new("cargo");
+            cargo
+                .arg("+nightly")
+                .arg("build")
+                .args(targets)
+                .arg("--manifest-path")
+                .arg(path.join("Cargo.toml"))
+                .arg("--keep-going")
+                .arg("--message-format=json");
+            if let Some(prebuilt) = &prebuilt {
+                cargo.env("CARGO_ENCODED_RUSTFLAGS", prebuilt.rustflags());
+            }
+            cargo
+        };
         let mut results: HashMap<String, TestResult> = generated
             .files
-            .into_iter()
+            .iter()
             .map(|(name, (_, points))| {
                 let test_result = TestResult {
-                    compiled: CompilationResult::Built,
+                    compiled: CompilationResult::Built(Vec::new()),
                     runned: RunResult::NotRun,
-                    points_given: points as f64,
+                    points_given: *points as f64,
+                    diagnostics: None,
                 };
-                (name, test_result)
+                (name.clone(), test_result)
             })
             .collect();
+        if let Some(message) = cache_error {
+            let diagnostic = CompilerDiagnostic {
+                level: "warning".to_string(),
+                internal: true,
+                ..error_diagnostic(message)
+            };
+            for test_result in results.values_mut() {
+                add_diagnostic(test_result, diagnostic.clone());
+            }
+        }
 
-        //println!("{} {}", message, String::from_utf8(compilation_output.stderr)?);
-        parse_errors(&message, &mut results);
+        let binary = if config.single_binary {
+            let diagnostics = compile_harness(&path, &generated, build).await?;
+            for (name, diagnostics) in diagnostics {
+                let Some(test_result) = results.get_mut(&name) else {
+                    continue;
+                };
+                for diagnostic in diagnostics {
+                    add_diagnostic(test_result, diagnostic);
+                }
+            }
+            Some(HARNESS.to_string())
+        } else {
+            for (name, (content, _)) in &generated.files {
+                fs::write(
+                    path.join("src").join("bin").join(name.clone() + ".rs"),
+                    content,
+                )
+                .await?;
+            }
+            let compilation_output = build(&["--bins"]).output().await?;
+            let message = String::from_utf8(compilation_output.stdout)?;
+            //println!("{} {}", message, String::from_utf8(compilation_output.stderr)?);
+            let files = generated
+                .files
+                .iter()
+                .map(|(name, (content, _))| (name.clone(), content.clone()))
+                .collect();
+            parse_errors(&message, &mut results, &files, &generated);
+            if config.isolate_errors {
+                isolate_errors(&path, &generated, message, &mut results, build).await?;
+            }
+            None
+        };
         Ok(RustCompiled {
             _tmpdir: tmpdir,
             path,
             results,
+            limits: generated.limits,
+            io: generated.io,
+            binary,
+            template: generated.template,
+            submission: generated.submission,
         })
     }
-}
\ No newline at end of file
+}
+
+/// compiles the tests in a single binary, a test with errors is left out and the others are compiled again.
+/// It returns the errors of each test
+async fn compile_harness(
+    path: &Path,
+    generated: &GeneratedFiles,
+    build: impl Fn(&[&str]) -> Command,
+) -> Result<HashMap<String, Vec<CompilerDiagnostic>>, CompileError> {
+    let mut tests: HashMap<&String, &String> = generated
+        .files
+        .iter()
+        .map(|(name, (content, _))| (name, content))
+        .collect();
+    let (mut harness, errors) = Harness::new(tests.iter().map(|(a, b)| (*a, *b)));
+    let mut diagnostics: HashMap<String, Vec<CompilerDiagnostic>> = errors
+        .into_iter()
+        .map(|(name, error)| (name, vec![error_diagnostic(error)]))
+        .collect();
+    tests.retain(|name, _| !diagnostics.contains_key(*name));
+    let has_errors = |x: &Vec<CompilerDiagnostic>| x.iter().any(|x| x.level == "error");
+    while !tests.is_empty() {
+        fs::write(
+            path.join("src").join("bin").join(format!("{}.rs", HARNESS)),
+            &harness.source,
+        )
+        .await?;
+        let output = build(&["--bins"]).output().await?;
+        let message = String::from_utf8(output.stdout)?;
+        let mut found = harness.diagnostics(&message, generated);
+        if output.status.success() {
+            // only the warnings
+            for name in harness.tests() {
+                let warnings = found.remove(&Some(name.clone())).unwrap_or_default();
+                diagnostics.insert(name.clone(), warnings);
+            }
+            break;
+        }
+        let global = found.remove(&None).filter(has_errors).or_else(|| {
+            // it failed without errors in the tests
+            (!found.values().any(has_errors)).then(|| {
+                vec![error_diagnostic(
+                    String::from_utf8_lossy(&output.stderr).into_owned(),
+                )]
+            })
+        });
+        if let Some(global) = global {
+            for name in harness.tests() {
+                let mut test = found.remove(&Some(name.clone())).unwrap_or_default();
+                test.extend(global.iter().cloned());
+                diagnostics.insert(name.clone(), test);
+            }
+            break;
+        }
+        for (name, test) in found {
+            let name = name.expect("the errors out of the tests are handled above");
+            // the warnings of the others are found again in the next compilation
+            if has_errors(&test) {
+                tests.remove(&name);
+                diagnostics.insert(name, test);
+            }
+        }
+        harness = Harness::new(tests.iter().map(|(a, b)| (*a, *b))).0;
+    }
+    Ok(diagnostics)
+}
+
+/// an error not given by rustc
+fn error_diagnostic(message: String) -> CompilerDiagnostic {
+    CompilerDiagnostic {
+        level: "error".to_string(),
+        rendered: message.clone(),
+        message,
+        ..Default::default()
+    }
+}
+
+/// the errors that can be isolated are looked for again after each compilation,
+/// since some errors are found only when the others are fixed
+const ISOLATION_ROUNDS: usize = 3;
+
+/// compiles again the tests with errors only in items of the student they don't use,
+/// replacing those items in their files
+async fn isolate_errors(
+    path: &Path,
+    generated: &GeneratedFiles,
+    mut message: String,
+    results: &mut HashMap<String, TestResult>,
+    build: impl Fn(&[&str]) -> Command,
+) -> Result<(), CompileError> {
+    let no_uses = HashSet::new();
+    let mut files: HashMap<String, String> = generated
+        .files
+        .iter()
+        .map(|(name, (content, _))| (name.clone(), content.clone()))
+        .collect();
+    for _ in 0..ISOLATION_ROUNDS {
+        let lines = error_lines(&message);
+        let mut isolated = Vec::new();
+        for (name, content) in files.iter_mut() {
+            if !matches!(
+                results.get(name),
+                Some(TestResult {
+                    compiled: CompilationResult::Error(..),
+                    ..
+                })
+            ) {
+                continue;
+            }
+            let Some(lines) = lines.get(name) else {
+                continue;
+            };
+            let uses = generated.uses.get(name).unwrap_or(&no_uses);
+            let Some(replaced) = isolate(content, lines, uses, &generated.defaults) else {
+                continue;
+            };
+            fs::write(
+                path.join("src").join("bin").join(format!("{}.rs", name)),
+                &replaced,
+            )
+            .await?;
+            *content = replaced;
+            isolated.push(name.clone());
+        }
+        if isolated.is_empty() {
+            break;
+        }
+        let targets: Vec<&str> = isolated
+            .iter()
+            .flat_map(|name| ["--bin", name.as_str()])
+            .collect();
+        message = String::from_utf8(build(&targets).output().await?.stdout)?;
+        for name in &isolated {
+            if let (Some(test_result), Some((_, points))) =
+                (results.get_mut(name), generated.files.get(name))
+            {
+                test_result.compiled = CompilationResult::Built(Vec::new());
+                test_result.points_given = *points as f64;
+            }
+        }
+        parse_errors(&message, results, &files, generated);
+    }
+    Ok(())
+}
@@ -0,0 +1,408 @@
+//! Tests that compare a function of the student with the one of the template on random inputs.
+//!
+//! ```ignore
+//! #[difftest(1.0, function = add, generator = random_pair, cases = 200, seed = 42)]
+//! fn test_add() {}
+//!
+//! fn random_pair(rng: &mut difftest::Rng) -> (i32, i32) {
+//!     (rng.range(-100, 100) as i32, rng.range(-100, 100) as i32)
+//! }
+//! fn add(a: i32, b: i32) -> i32 { a + b }
+//! ```
+//! The generator returns the arguments of the function (a tuple when they are more than one),
+//! the ones taken by reference are given as references to what it returns. The function of the
+//! template is copied in the file of the test with another name, next to the one of the student.
+//!
+//! The inputs grow with the cases (see `Rng::size`), and after a failure smaller ones are tried,
+//! so the input shown is the smallest found. Without a seed a new one is chosen at every run: it
+//! is printed, and with it the same inputs are generated again.
+use std::collections::HashMap;
+
+use quote::format_ident;
+use syn::{
+    parse_quote,
+    visit_mut::{visit_expr_path_mut, VisitMut},
+    Expr, ExprPath, FnArg, Ident, Item, ItemFn, Pat, Path, Type,
+};
+
+use super::{error::RustError, parser::ImplementationPath};
+
+/// cases when they are not given
+pub const DEFAULT_CASES: u64 = 100;
+
+/// The arguments of a difftest
+#[derive(Debug, Clone, Default)]
+pub struct DiffTest {
+    /// the function of the student compared with the one of the template
+    pub function: Option<Path>,
+    /// the function of the template that generates the inputs
+    pub generator: Option<Path>,
+    /// how many inputs are tried
+    pub cases: u64,
+    /// chosen at every run if missing
+    pub seed: Option<u64>,
+}
+
+/// the key of the function at the path, like the ones of the default implementations
+fn key(path: &Path, ident: &Ident) -> ImplementationPath {
+    let modules = path
+        .segments
+        .iter()
+        .take(path.segments.len().saturating_sub(1))
+        .cloned()
+        .collect();
+    ImplementationPath::from_fn(&parse_quote!(fn #ident() {}), &modules)
+}
+
+impl DiffTest {
+    /// writes the body of the test, and returns the items it needs in its file:
+    /// the copy of the reference, the generator and the support module
+    pub(crate) fn generate(
+        &self,
+        test: &mut ItemFn,
+        default_impl: &HashMap<ImplementationPath, Item>,
+    ) -> Result<Vec<(ImplementationPath, Item)>, RustError> {
+        let missing = || {
+            syn::Error::new_spanned(&test.sig.ident, "difftest needs a function and a generator")
+        };
+        let function = self.function.as_ref().ok_or_else(missing)?;
+        let generator = self.generator.as_ref().ok_or_else(missing)?;
+        let find = |path: &Path| {
+            let ident = &path.segments.last().ok_or_else(missing)?.ident;
+            let key = key(path, ident);
+            match default_impl.get(&key) {
+                Some(Item::Fn(item)) => Ok((key, item.clone())),
+                _ => Err(RustError::MatchNotFound(format!(
+                    "the function {} of the difftest is not in the template",
+                    ident
+                ))),
+            }
+        };
+        let (_, mut reference) = find(function)?;
+        let generator_item = find(generator)?;
+
+        // the reference, renamed: when it is recursive it calls itself, not the student
+        let mut rename = Rename {
+            from: reference.sig.ident.clone(),
+            to: format_ident!("__reference_{}", reference.sig.ident),
+        };
+        rename.visit_block_mut(&mut reference.block);
+        reference.sig.ident = rename.to;
+        let mut reference_path = function.clone();
+        if let Some(last) = reference_path.segments.last_mut() {
+            last.ident = reference.sig.ident.clone();
+        }
+
+        let mut args: Vec<Pat> = Vec::new();
+        let mut call: Vec<Expr> = Vec::new();
+        for (i, input) in reference.sig.inputs.iter().enumerate() {
+            let FnArg::Typed(input) = input else {
+                return Err(
+                    syn::Error::new_spanned(input, "difftest can't compare methods").into(),
+                );
+            };
+            let arg = format_ident!("__arg_{}", i);
+            match &*input.ty {
+                Type::Reference(reference) if reference.mutability.is_some() => {
+                    args.push(parse_quote!(mut #arg));
+                    call.push(parse_quote!(&mut #arg));
+                }
+                Type::Reference(_) => {
+                    args.push(parse_quote!(#arg));
+                    call.push(parse_quote!(&#arg));
+                }
+                _ => {
+                    args.push(parse_quote!(#arg));
+                    call.push(parse_quote!(#arg));
+                }
+            }
+        }
+        let args: Pat = match args.as_slice() {
+            [arg] => arg.clone(),
+            args => parse_quote!((#(#args),*)),
+        };
+        let seed: Expr = match self.seed {
+            Some(seed) => parse_quote!(Some(#seed)),
+            None => parse_quote!(None),
+        };
+        let cases = self.cases;
+        test.block = parse_quote!({
+            difftest::run(
+                #seed,
+                #cases,
+                #generator,
+                |#args| #function(#(#call),*),
+                |#args| #reference_path(#(#call),*),
+            );
+        });
+
+        let reference_key = key(function, &reference.sig.ident);
+        let support_key = ImplementationPath::from_fn(
+            &parse_quote!(
+                fn difftest() {}
+            ),
+            &Default::default(),
+        );
+        Ok(vec![
+            (reference_key, reference.into()),
+            (generator_item.0, generator_item.1.into()),
+            (support_key, support()),
+        ])
+    }
+}
+
+/// renames the calls of a function
+struct Rename {
+    from: Ident,
+    to: Ident,
+}
+
+impl VisitMut for Rename {
+    fn visit_expr_path_mut(&mut self, i: &mut ExprPath) {
+        if i.qself.is_none() && i.path.is_ident(&self.from) {
+            i.path.segments[0].ident = self.to.clone();
+        }
+        visit_expr_path_mut(self, i);
+    }
+}
+
+/// the module with the random numbers and the comparison, in the file of the test
+fn support() -> Item {
+    parse_quote! {
+        #[allow(dead_code)]
+        mod difftest {
+            use std::fmt::Debug;
+            use std::panic::{self, AssertUnwindSafe};
+
+            /// the biggest size of the inputs
+            const MAX_SIZE: u64 = 100;
+            /// inputs tried for each smaller size, after a failure
+            const SHRINK_ATTEMPTS: u64 = 20;
+
+            /// Random values for the generators, from the seed of the test
+            pub struct Rng {
+                state: u64,
+                size: u64,
+            }
+
+            impl Rng {
+                fn new(seed: u64, case: u64, size: u64) -> Self {
+                    Self {
+                        state: seed ^ case.wrapping_mul(0x9E37_79B9_7F4A_7C15),
+                        size,
+                    }
+                }
+
+                /// a random number (splitmix64)
+                pub fn next_u64(&mut self) -> u64 {
+                    self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+                    let mut z = self.state;
+                    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+                    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+                    z ^ (z >> 31)
+                }
+
+                /// a number from low to high, both included
+                pub fn range(&mut self, low: i64, high: i64) -> i64 {
+                    if high <= low {
+                        return low;
+                    }
+                    match (high.abs_diff(low)).checked_add(1) {
+                        Some(span) => low.wrapping_add((self.next_u64() % span) as i64),
+                        None => self.next_u64() as i64,
+                    }
+                }
+
+                /// a number from 0 to 1, 1 excluded
+                pub fn float(&mut self) -> f64 {
+                    (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+                }
+
+                pub fn bool(&mut self) -> bool {
+                    self.next_u64() & 1 == 1
+                }
+
+                /// how big the input should be, from 1 to 100: it grows with the cases
+                pub fn size(&self) -> u64 {
+                    self.size
+                }
+
+                /// a length from 0 to the size
+                pub fn length(&mut self) -> usize {
+                    self.range(0, self.size as i64) as usize
+                }
+            }
+
+            /// what the function returned, or the message of its panic
+            fn call<I, O>(function: &impl Fn(I) -> O, input: I) -> Result<O, String> {
+                // the panic is shown with the input
+                let hook = panic::take_hook();
+                panic::set_hook(Box::new(|_| {}));
+                let result = panic::catch_unwind(AssertUnwindSafe(|| function(input)));
+                panic::set_hook(hook);
+                result.map_err(|x| {
+                    x.downcast_ref::<&str>()
+                        .map(|x| x.to_string())
+                        .or_else(|| x.downcast_ref::<String>().cloned())
+                        .unwrap_or_default()
+                })
+            }
+
+            /// compares the student with the reference on the generated inputs,
+            /// it panics with the smallest input found where they differ
+            pub fn run<I: Clone + Debug, O: PartialEq + Debug>(
+                seed: Option<u64>,
+                cases: u64,
+                generator: impl Fn(&mut Rng) -> I,
+                student: impl Fn(I) -> O,
+                reference: impl Fn(I) -> O,
+            ) {
+                let seed = seed.unwrap_or_else(|| {
+                    std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .map_or(0, |x| x.as_nanos() as u64)
+                });
+                println!("seed: {}", seed);
+                // the expected result and the one of the student, if they differ.
+                // The inputs the reference can't handle are skipped
+                let differ = |input: &I| {
+                    let expected = call(&reference, input.clone()).ok()?;
+                    let actual = call(&student, input.clone());
+                    (!matches!(&actual, Ok(x) if *x == expected)).then_some((expected, actual))
+                };
+                let mut failure = None;
+                for case in 0..cases {
+                    let size = 1 + case * MAX_SIZE / cases;
+                    let input = generator(&mut Rng::new(seed, case, size));
+                    if let Some(difference) = differ(&input) {
+                        failure = Some((size, input, difference));
+                        break;
+                    }
+                }
+                let Some((size, mut input, mut difference)) = failure else {
+                    return;
+                };
+                'shrink: for smaller in 1..size {
+                    for attempt in 0..SHRINK_ATTEMPTS {
+                        let case = cases + smaller * SHRINK_ATTEMPTS + attempt;
+                        let candidate = generator(&mut Rng::new(seed, case, smaller));
+                        if let Some(smaller) = differ(&candidate) {
+                            input = candidate;
+                            difference = smaller;
+                            break 'shrink;
+                        }
+                    }
+                }
+                // the failure found, the function of the student could not fail again on it
+                let (expected, actual) = difference;
+                match actual {
+                    Ok(actual) => panic!(
+                        "wrong result for the input {:?} (seed {})\nexpected: {:?}\n     got: {:?}",
+                        input, seed, expected, actual
+                    ),
+                    Err(message) => panic!(
+                        "panic for the input {:?} (seed {}): {}\nexpected: {:?}",
+                        input, seed, message, expected
+                    ),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use orchestrator::prelude::RunResult;
+
+    use crate::generatorv2::{
+        compiled::{CompileConfig, RustCompiled},
+        file_generator::GeneratedFiles,
+        parser::RustExercise,
+        run::RunConfig,
+    };
+
+    const TEMPLATE: &str = "
+        #[difftest(2, function = add, generator = pair, cases = 50, seed = 42)]
+        fn test_add() {}
+        #[difftest(function = largest, generator = numbers)]
+        fn test_largest() {}
+        #[difftest(function = fib, generator = small)]
+        fn test_fib() {}
+
+        fn pair(rng: &mut difftest::Rng) -> (i64, i64) {
+            let size = rng.size() as i64;
+            (rng.range(0, size), rng.range(0, size))
+        }
+        fn numbers(rng: &mut difftest::Rng) -> Vec<i32> {
+            (0..rng.length()).map(|_| rng.range(-10, 10) as i32).collect()
+        }
+        fn small(rng: &mut difftest::Rng) -> u64 {
+            rng.range(0, 15) as u64
+        }
+        fn add(a: i64, b: i64) -> i64 { a + b }
+        fn largest(v: &[i32]) -> Option<i32> { v.iter().copied().max() }
+        fn fib(n: u64) -> u64 { if n < 2 { n } else { fib(n - 1) + fib(n - 2) } }
+    ";
+
+    async fn run(student: &str) -> std::collections::HashMap<String, RunResult> {
+        let exercise = RustExercise::parse(TEMPLATE).unwrap();
+        let generated = GeneratedFiles::generate(exercise, student.to_string()).unwrap();
+        // the reference calls itself
+        assert!(generated.files["test_fib"]
+            .0
+            .contains("__reference_fib(n - 1)"));
+        let compiled = RustCompiled::compile(generated, CompileConfig::default())
+            .await
+            .unwrap();
+        let results = compiled.run(RunConfig::default()).await.unwrap().tests;
+        results.into_iter().map(|(k, v)| (k, v.runned)).collect()
+    }
+
+    #[tokio::test]
+    async fn test_difftest() {
+        let student = "
+            fn add(a: i64, b: i64) -> i64 { b + a }
+            fn largest(v: &[i32]) -> Option<i32> { v.iter().max().copied() }
+            fn fib(n: u64) -> u64 { (0..n).fold((0, 1), |(a, b), _| (b, a + b)).0 }
+        ";
+        let results = run(student).await;
+        assert_eq!(results["test_add"], RunResult::Ok);
+        assert_eq!(results["test_largest"], RunResult::Ok);
+        assert_eq!(results["test_fib"], RunResult::Ok);
+
+        let student = "
+            fn add(a: i64, b: i64) -> i64 { if a > 20 { a - b } else { a + b } }
+            fn largest(v: &[i32]) -> Option<i32> { Some(*v.iter().max().unwrap()) }
+            fn fib(n: u64) -> u64 { n }
+        ";
+        let results = run(student).await;
+        let RunResult::Error(error) = &results["test_add"] else {
+            panic!("{:?}", results["test_add"]);
+        };
+        assert!(error.contains("wrong result for the input ("), "{}", error);
+        assert!(error.contains("(seed 42)"), "{}", error);
+        let RunResult::Error(error) = &results["test_largest"] else {
+            panic!("{:?}", results["test_largest"]);
+        };
+        // the smallest input
+        assert!(error.contains("panic for the input []"), "{}", error);
+        assert!(error.contains("expected: None"), "{}", error);
+        assert!(matches!(results["test_fib"], RunResult::Error(_)));
+
+        // it fails only the first time, the failure is still reported
+        let student = "
+            fn add(a: i64, b: i64) -> i64 {
+                static CALLED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+                if CALLED.swap(true, std::sync::atomic::Ordering::Relaxed) { a + b } else { a + b + 1 }
+            }
+            fn largest(v: &[i32]) -> Option<i32> { v.iter().max().copied() }
+            fn fib(n: u64) -> u64 { (0..n).fold((0, 1), |(a, b), _| (b, a + b)).0 }
+        ";
+        let results = run(student).await;
+        let RunResult::Error(error) = &results["test_add"] else {
+            panic!("{:?}", results["test_add"]);
+        };
+        assert!(error.contains("wrong result for the input ("), "{}", error);
+        assert_eq!(results["test_largest"], RunResult::Ok);
+    }
+}
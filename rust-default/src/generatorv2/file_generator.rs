@@ -1,223 +1,258 @@
-use std::collections::HashMap;
-use std::iter::once;
+use std::collections::{HashMap, HashSet};
 
-use quote::quote;
-use syn::fold::{fold_file, fold_item_mod, Fold};
+use proc_macro2::Literal;
+use quote::{format_ident, ToTokens};
 use syn::punctuated::Punctuated;
-use syn::token::{Brace, PathSep};
-use syn::{parse2, parse_quote, parse_str, File, Ident, Item, ItemMod, Path, PathSegment, Token, Type};
+use syn::{parse_quote, File, Item, Path, PathSegment, Token, Type};
 
-use super::test_definition::TestDefinition;
+use super::test_definition::{SendableTestDefinition, TemplateItem};
 
 use super::error::RustError;
+use super::iotest::IoTest;
+use super::isolation::parse_lenient;
 use super::parser::{extract_fn, ImplementationPath, RustExercise};
+use super::run::TestLimits;
+
+/// the macro written where an item of the template goes, replaced by its source
+const PLACEHOLDER: &str = "__template_item";
 
 #[derive(Clone, Default, Debug)]
 pub struct GeneratedFiles {
     pub files: HashMap<String, (String, f32)>,
+    /// limits set by each test in the template
+    pub limits: HashMap<String, TestLimits>,
+    /// input and expected output of the tests that run the main of the student
+    pub io: HashMap<String, IoTest>,
     pub(crate) dependencies: Vec<String>,
+    /// identifiers used by each test and by the items it overwrites, see isolation
+    pub(crate) uses: HashMap<String, HashSet<String>>,
+    /// default implementations of the template, see RustExercise
+    pub(crate) defaults: HashMap<String, String>,
+    /// the items of the template in the file of each test, see source_map
+    pub(crate) template: HashMap<String, HashSet<String>>,
+    /// the code of the student, the errors are shown on it (see source_map)
+    pub(crate) submission: Option<String>,
 }
 
 impl GeneratedFiles {
+    /// only the code of the student is parsed, the template is already written in the definitions
     pub fn generate(def: RustExercise, user: String) -> Result<Self, RustError> {
-        let user: File = parse_str(&user)?;
+        let submission = user;
+        let user: File = parse_lenient(&submission)?;
 
-        let tests: Vec<TestDefinition> = def
-            .tests
-            .into_iter()
-            .map( TestDefinition::try_from)
-            .collect::<Result<Vec<TestDefinition>, RustError>>()?;
-        let files: HashMap<String, (String, f32)> = tests
-            .into_iter()
-            .map(|test| {
-                let points = test.points;
-                let mut s = Substitute::new(test);
-                let solution = s.fold_file(user.clone());
-                let file = prettyplease::unparse(&solution);
-                (s.name, (file, points ))
-            })
-            .collect();
-        //let def = TestDefinition::try_from(def)?;
+        let mut files = HashMap::new();
+        let mut limits = HashMap::new();
+        let mut io = HashMap::new();
+        let mut uses = HashMap::new();
+        let mut template = HashMap::new();
+        for test in def.tests {
+            let mut overwritten: HashSet<String> = test.to_overwrite.keys().cloned().collect();
+            let mut used = test.uses.clone();
+            match &test.io {
+                // the test runs the main of the student, what it uses is found by isolation
+                Some(_) => {
+                    used.insert("main".to_string());
+                }
+                // the test is the main function
+                None => {
+                    let main = ImplementationPath::from_fn(
+                        &parse_quote!(
+                            fn main() {}
+                        ),
+                        &Punctuated::new(),
+                    );
+                    overwritten.insert(main.to_token_stream().to_string());
+                }
+            }
+            let mut s = Substitute::new(&test);
+            let file = s.write(user.clone());
+            limits.insert(test.name.clone(), test.limits);
+            if let Some(test_io) = test.io {
+                io.insert(test.name.clone(), test_io);
+            }
+            uses.insert(test.name.clone(), used);
+            template.insert(test.name.clone(), overwritten);
+            files.insert(test.name, (file, test.points));
+        }
 
-        Ok(GeneratedFiles { files, dependencies: def.dependencies})
+        Ok(GeneratedFiles {
+            files,
+            limits,
+            io,
+            dependencies: def.dependencies,
+            uses,
+            defaults: def.defaults,
+            template,
+            submission: Some(submission),
+        })
     }
 }
 
-struct Substitute {
-    name: String,
-    def: TestDefinition,
+/// The file of a test: the code of the student, with the items of the template in place of
+/// the ones with the same key, and the test as its main.
+///
+/// The items of the template are written as placeholders, replaced by their source
+/// once the file is formatted
+struct Substitute<'a> {
+    def: &'a SendableTestDefinition,
+    /// keys of the items of the template not written yet
+    missing: HashSet<&'a str>,
+    /// sources of the placeholders, in order
+    written: Vec<&'a str>,
     mod_path: Punctuated<PathSegment, Token![::]>,
 }
-impl Substitute {
-    fn new(def: TestDefinition) -> Self {
+impl<'a> Substitute<'a> {
+    fn new(def: &'a SendableTestDefinition) -> Self {
         Self {
-            name: String::new(),
             def,
+            missing: def.to_overwrite.keys().map(String::as_str).collect(),
+            written: Vec::new(),
             mod_path: Punctuated::new(),
         }
     }
-}
 
-fn is_sub_module(
-    root: &Punctuated<PathSegment, PathSep>,
-    bigger: &(ImplementationPath, Item),
-) -> Option<(ImplementationPath, Item)> {
-    if root.iter().zip(bigger.0.path.iter()).all(|(a, b)| a == b) {
-        let mut bigger = bigger.clone();
-        bigger.0.path = bigger.0.path.into_iter().skip(root.len()).collect();
-        Some(bigger)
-    } else {
-        None
-    }
-}
-
-fn build_submodules(mut v: Vec<(ImplementationPath, Item)>) -> Result<Vec<Item>, RustError> {
-    let mut ret: Vec<Item> = v
-        .iter()
-        .filter_map(|(path, imp)| {
-            if path.path.is_empty() {
-                Some(imp)
-            } else {
-                None
-            }
-        })
-        .cloned()
-        .collect();
-    v.retain(|(p, _)| !p.path.is_empty());
-    while !v.is_empty() {
-        // safe to unwrap because we know there is at least one element
-        let (key, _) = v.first().unwrap();
-        // it is ok to unwrap because we already filtered out the empty elements
-        let t = key.path.first().unwrap().clone();
-        let mut new_mod = parse2::<ItemMod>(quote!(mod #t {  }))?;
-        let sub_path: Punctuated<PathSegment, PathSep> = once(t).collect();
-        let next_v: Vec<_> = v
-            .iter()
-            .filter_map(|key| is_sub_module(&sub_path, key))
-            .collect();
-        v.retain(|key| is_sub_module(&sub_path, key).is_none());
-        let t = build_submodules(next_v)?;
-        new_mod.content = Some((Brace::default(), t));
-        ret.push(new_mod.into());
-    }
-    //let t: Vec<ImplementationPath> = v.into_iter().filter_map(|(key, _)| is_sub_itermodule(&self.mod_path, key)).collect();
-    Ok(ret)
-}
-
-impl Fold for Substitute {
-    fn fold_item_mod(&mut self, i: syn::ItemMod) -> syn::ItemMod {
-        self.mod_path.push(i.ident.clone().into());
-        let mut module = fold_item_mod(self, i);
-        // build submodules
-        let v: Vec<(ImplementationPath, Item)> = self
-            .def
-            .to_overwrite
-            .iter()
-            .filter_map(|(a, b)| is_sub_module(&self.mod_path, &(a.clone(), b.clone())))
-            .collect();
-        self.def
-            .to_overwrite
-            .retain(|k, v| is_sub_module(&self.mod_path, &(k.clone(), v.clone())).is_none());
-        let t = build_submodules(v).unwrap();
-        if t.is_empty() {
-            return module;
-        }
-        if let Some((_, b)) = &mut module.content {
-            b.extend(t);
-        } else {
-            module.content = Some((Brace::default(), t));
-        }
-        self.mod_path.pop();
-        self.mod_path.pop_punct();
-        module
-    }
-
-    fn fold_item_fn(&mut self, i: syn::ItemFn) -> syn::ItemFn {
-        let key = ImplementationPath::from_fn(&i, &self.mod_path);
-        self.def.to_overwrite.remove(&key).and_then(|item| if let Item::Fn(f) = item{
-            Some(f)
-        }else{
-            None
-        }).unwrap_or(i)
-    }
-    fn fold_file(&mut self, mut i: syn::File) -> syn::File {
-        i.attrs.retain(|x| !x.path().is_ident("dependency"));
+    fn write(&mut self, mut file: File) -> String {
+        file.attrs.retain(|x| !x.path().is_ident("dependency"));
         let path: Path = parse_quote!(procedural::magic_macro);
-        i.attrs.retain(|x| *x.path()!=path);
+        file.attrs.retain(|x| *x.path() != path);
         // removed the attributed function
-        i.items.retain(|x| {
+        file.items.retain(|x| {
             if let Item::Fn(x) = &x {
                 extract_fn(x).is_none()
             } else {
                 true
             }
         });
+        file.items = self.items(file.items);
+        // add test, a test of the output uses the main of the student
+        if self.def.io.is_none() {
+            file.items.push(self.placeholder(&self.def.test));
+        }
 
-        //recursively explore file
-        let mut file = fold_file(self, i);
-        // add the thing we havent fount nowere
-        let v: Vec<(ImplementationPath, Item)> = self
-            .def
-            .to_overwrite
-            .iter()
-            .filter_map(|(a, b)| is_sub_module(&self.mod_path, &(a.clone(), b.clone())))
-            .collect();
-        self.def
-            .to_overwrite
-            .retain(|k, v| is_sub_module(&self.mod_path, &(k.clone(), v.clone())).is_none());
-        let tmp = build_submodules(v).unwrap();
-        file.items.extend(tmp);
-        // add test
-        self.name = self.def.test.sig.ident.to_string();
-        self.def.test.sig.ident = parse_str::<Ident>("main").unwrap();
-        file.items.push(self.def.test.clone().into());
-        file
+        let mut ret = String::new();
+        for line in prettyplease::unparse(&file).lines() {
+            let index = line.trim_start().strip_prefix(PLACEHOLDER).and_then(|x| {
+                x.strip_prefix("!(")?
+                    .strip_suffix(");")?
+                    .parse::<usize>()
+                    .ok()
+            });
+            match index.and_then(|x| self.written.get(x)) {
+                Some(source) => ret += source,
+                None => {
+                    ret += line;
+                    ret.push('\n');
+                }
+            }
+        }
+        ret
+    }
+
+    fn placeholder(&mut self, source: &'a str) -> Item {
+        let macro_ = format_ident!("{}", PLACEHOLDER);
+        let index = Literal::usize_unsuffixed(self.written.len());
+        self.written.push(source);
+        parse_quote!(#macro_!(#index);)
     }
 
-    fn fold_item_impl(&mut self, mut i: syn::ItemImpl) -> syn::ItemImpl {
-        if i.trait_.is_some() {
-            let key = ImplementationPath::from_impl(&i, &self.mod_path);
+    /// the item of the template with the key, if it is not written yet
+    fn take(&mut self, key: &ImplementationPath) -> Option<Item> {
+        let key = key.to_token_stream().to_string();
+        let (key, item) = self.def.to_overwrite.get_key_value(&key)?;
+        self.missing
+            .remove(key.as_str())
+            .then(|| self.placeholder(&item.source))
+    }
+
+    /// the items of a module, the ones of the template that are not found are added at its end
+    fn items(&mut self, items: Vec<Item>) -> Vec<Item> {
+        let mut ret: Vec<Item> = items.into_iter().map(|x| self.item(x)).collect();
+        let module: Vec<String> = self.mod_path.iter().map(|x| x.ident.to_string()).collect();
+        ret.extend(self.missing_in(&module));
+        ret
+    }
 
-            self.def
-                .to_overwrite
-                .remove(&key)
-                .and_then(|x| {
-                    if let syn::Item::Impl(x) = x {
-                        Some(x.clone())
+    fn item(&mut self, item: Item) -> Item {
+        match item {
+            Item::Fn(function) => {
+                let key = ImplementationPath::from_fn(&function, &self.mod_path);
+                self.take(&key).unwrap_or(Item::Fn(function))
+            }
+            Item::Impl(implementation) if implementation.trait_.is_some() => {
+                let key = ImplementationPath::from_impl(&implementation, &self.mod_path);
+                self.take(&key).unwrap_or(Item::Impl(implementation))
+            }
+            Item::Impl(mut implementation) => {
+                let key = ImplementationPath::from_impl(&implementation, &self.mod_path);
+                implementation.items.retain(|elem| {
+                    let mut key = key.clone();
+                    let ident = match elem {
+                        syn::ImplItem::Const(impl_item_const) => &impl_item_const.ident,
+                        syn::ImplItem::Fn(impl_item_fn) => &impl_item_fn.sig.ident,
+                        _ => return false,
+                    };
+                    // assuming that only path inherent type can be overloaded
+                    if let Type::Path(p) = &mut key.type_ {
+                        p.path.segments.push(ident.clone().into());
+                        !self
+                            .def
+                            .to_overwrite
+                            .contains_key(&key.to_token_stream().to_string())
                     } else {
-                        None
+                        false
                     }
-                })
-                .unwrap_or(i)
-        } else {
-            let key = ImplementationPath::from_impl(&i, &self.mod_path);
-            i.items.retain(|elem| {
-                let mut key = key.clone();
-                match elem {
-                    syn::ImplItem::Const(impl_item_const) => {
-                        // assuming that only path inherent type can be overloadeditems
-                        if let Type::Path(p) = &mut key.type_ {
-                            p.path.segments.push(impl_item_const.ident.clone().into());
-                            !self.def.to_overwrite.contains_key(&key)
-                        } else {
-                            false
-                        }
-                    }
-                    syn::ImplItem::Fn(impl_item_fn) => {
-                        key.path = self.mod_path.clone();
-                        // assuming that only path inherent type can be overloaded
-                        if let Type::Path(p) = &mut key.type_ {
-                            p.path.segments.push(impl_item_fn.sig.ident.clone().into());
-                            !self.def.to_overwrite.contains_key(&key)
-                        } else {
-                            false
-                        }
-                    }
-                    _ => false,
+                });
+                Item::Impl(implementation)
+            }
+            Item::Mod(mut module) => {
+                self.mod_path.push(module.ident.clone().into());
+                let (brace, items) = module.content.take().unzip();
+                let items = self.items(items.unwrap_or_default());
+                if brace.is_some() || !items.is_empty() {
+                    module.content = Some((brace.unwrap_or_default(), items));
                 }
-            });
-            i
+                self.mod_path.pop();
+                self.mod_path.pop_punct();
+                Item::Mod(module)
+            }
+            item => item,
+        }
+    }
+
+    /// the items of the template not written yet, in the module or in its submodules
+    fn missing_in(&mut self, module: &[String]) -> Vec<Item> {
+        let mut keys: Vec<&'a str> = self
+            .missing
+            .iter()
+            .filter(|x| self.item_of(x).module.starts_with(module))
+            .copied()
+            .collect();
+        keys.sort();
+        let mut ret = Vec::new();
+        let mut submodules: Vec<&'a str> = Vec::new();
+        for key in keys {
+            let item = self.item_of(key);
+            match item.module.get(module.len()) {
+                None => {
+                    self.missing.remove(key);
+                    ret.push(self.placeholder(&item.source));
+                }
+                Some(name) if !submodules.contains(&name.as_str()) => submodules.push(name),
+                Some(_) => {}
+            }
+        }
+        for name in submodules {
+            let mut path = module.to_vec();
+            path.push(name.to_string());
+            let items = self.missing_in(&path);
+            let name = format_ident!("{}", name);
+            ret.push(parse_quote!(mod #name { #(#items)* }));
         }
+        ret
+    }
+
+    fn item_of(&self, key: &str) -> &'a TemplateItem {
+        &self.def.to_overwrite[key]
     }
 }
 
@@ -291,5 +326,40 @@ mod tests{
         (prettyplease::unparse(&test_1), 1.0));
         h.insert("test_2".to_string(), (prettyplease::unparse(&test_2), 1.0));
         assert_eq!(h, res.files);
+
+        // the items of the student are replaced where they are
+        let student = "
+            mod hidden {
+                mod hidden2 {
+                    fn point_me() { todo!() }
+                    fn other() {}
+                }
+                struct Dummy;
+                impl Dummy {
+                    fn print() { todo!() }
+                }
+            }
+        ";
+        let t = RustExercise2::parse(&q).unwrap();
+        let res = GeneratedFiles2::generate(t, student.to_string()).unwrap();
+        let test_1: File = parse_quote!{
+            mod hidden {
+                mod hidden2 {
+                    fn point_me() {}
+                    fn other() {}
+                }
+                struct Dummy;
+                impl Dummy {
+                    fn print() { todo!() }
+                }
+            }
+            /// comment
+            fn main() {
+                use rand;
+                ///magic test
+                struct lol;
+            }
+        };
+        assert_eq!(res.files["test_1"].0, prettyplease::unparse(&test_1));
     }
 }
\ No newline at end of file
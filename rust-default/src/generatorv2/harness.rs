@@ -0,0 +1,322 @@
+//! All the tests of an exercise in a single binary.
+//!
+//! Each file generated for a test becomes a module of the crate, so the overwritten items of a
+//! test are not seen by the others, and the binary runs the test with the name given as argument.
+//! The crate is compiled once, the errors are attributed to the tests by the lines of their
+//! module: the tests with errors are left out and the others are compiled again.
+use std::{collections::HashMap, ops::Range};
+
+use orchestrator::prelude::CompilerDiagnostic;
+use proc_macro2::{Group, Punct, Spacing, TokenStream, TokenTree};
+use quote::format_ident;
+use serde_json::Value;
+use syn::{parse_file, parse_quote, File, Ident, Item};
+
+use super::{
+    file_generator::GeneratedFiles,
+    isolation::parse_lenient,
+    source_map::{diagnostic, is_shown, SourceMap},
+};
+
+/// name of the binary with all the tests
+pub const HARNESS: &str = "harness";
+
+/// the items of the module of the test, in the source of the binary
+pub(crate) fn test_items(source: &str, test: &str) -> Option<Vec<Item>> {
+    parse_file(source)
+        .ok()?
+        .items
+        .into_iter()
+        .find_map(|x| match x {
+            Item::Mod(module) if module.ident == test => module.content.map(|(_, items)| items),
+            _ => None,
+        })
+}
+
+/// The source of the binary with the tests and the lines of each test
+pub(crate) struct Harness {
+    /// content of the binary
+    pub source: String,
+    /// lines (starting from 1) of the module of each test
+    modules: Vec<(String, Range<usize>)>,
+}
+
+impl Harness {
+    /// the tests that can't be placed in a module are returned with the error
+    pub fn new<'a>(
+        tests: impl IntoIterator<Item = (&'a String, &'a String)>,
+    ) -> (Self, HashMap<String, String>) {
+        let mut source = String::new();
+        let mut line = 1;
+        let mut modules = Vec::new();
+        let mut errors = HashMap::new();
+        let mut names = Vec::new();
+        let mut labels = Vec::new();
+        for (name, content) in tests {
+            let module = match module(name, content) {
+                Ok(module) => module,
+                Err(err) => {
+                    errors.insert(name.clone(), err.to_string());
+                    continue;
+                }
+            };
+            let module = prettyplease::unparse(&File {
+                shebang: None,
+                attrs: Vec::new(),
+                items: vec![module],
+            });
+            let lines = module.lines().count();
+            modules.push((name.clone(), line..line + lines));
+            line += lines;
+            source += &module;
+            names.push(format_ident!("{}", name));
+            labels.push(name.as_str());
+        }
+        let main: File = parse_quote! {
+            fn main() -> std::process::ExitCode {
+                use std::process::Termination;
+                match std::env::args().nth(1).as_deref() {
+                    #(Some(#labels) => #names::__harness_test().report(),)*
+                    name => {
+                        eprintln!("unknown test {:?}", name);
+                        std::process::ExitCode::FAILURE
+                    }
+                }
+            }
+        };
+        source += &prettyplease::unparse(&main);
+        (Self { source, modules }, errors)
+    }
+
+    /// the tests in the binary
+    pub fn tests(&self) -> impl Iterator<Item = &String> {
+        self.modules.iter().map(|(name, _)| name)
+    }
+
+    /// the test whose module contains the line
+    fn test_at(&self, line: usize) -> Option<&String> {
+        self.modules
+            .iter()
+            .find(|(_, lines)| lines.contains(&line))
+            .map(|(name, _)| name)
+    }
+
+    /// the map from the module of the test to the submission
+    fn source_map(&self, test: &str, generated: &GeneratedFiles) -> Option<SourceMap> {
+        let submission = parse_lenient(generated.submission.as_ref()?).ok()?;
+        let items = test_items(&self.source, test)?;
+        let template = generated.template.get(test).cloned().unwrap_or_default();
+        Some(SourceMap::from_items(&items, &submission, &template))
+    }
+
+    /// the errors and warnings of the compilation of the binary, for each test, shown on the
+    /// submission. The ones outside of the modules (like the errors of the linker) are in the
+    /// None entry
+    pub fn diagnostics(
+        &self,
+        output: &str,
+        generated: &GeneratedFiles,
+    ) -> HashMap<Option<String>, Vec<CompilerDiagnostic>> {
+        let file = format!("/{}.rs", HARNESS);
+        let mut maps: HashMap<String, Option<SourceMap>> = HashMap::new();
+        let mut diagnostics: HashMap<Option<String>, Vec<CompilerDiagnostic>> = HashMap::new();
+        for line in output.lines() {
+            let Ok(message) = serde_json::from_str::<Value>(line) else {
+                continue;
+            };
+            let message = &message["message"];
+            if !is_shown(message) {
+                continue;
+            }
+            let test = message["spans"]
+                .as_array()
+                .and_then(|x| x.iter().find(|x| x["is_primary"] == true))
+                .and_then(|x| source_line(x, &file))
+                .and_then(|x| self.test_at(x))
+                .cloned();
+            let map = test.as_ref().and_then(|test| {
+                maps.entry(test.clone())
+                    .or_insert_with(|| self.source_map(test, generated))
+                    .as_ref()
+            });
+            let source = map.zip(generated.submission.as_deref());
+            diagnostics
+                .entry(test)
+                .or_default()
+                .push(diagnostic(message, &file, source));
+        }
+        diagnostics
+    }
+}
+
+/// the span in the file, following the macro expansions
+pub(crate) fn file_span<'a>(span: &'a Value, file: &str) -> Option<&'a Value> {
+    if span["file_name"].as_str()?.ends_with(file) {
+        return Some(span);
+    }
+    file_span(&span["expansion"]["span"], file)
+}
+
+/// the line of the span in the file, see file_span
+pub(crate) fn source_line(span: &Value, file: &str) -> Option<usize> {
+    file_span(span, file)?["line_start"]
+        .as_u64()
+        .map(|x| x as usize)
+}
+
+/// the generated file in a module named as the test, with the function that runs the test
+fn module(name: &str, content: &str) -> syn::Result<Item> {
+    let name: Ident = syn::parse_str(name)?;
+    let tokens = crate_paths(syn::parse_str(content)?, &name);
+    let file: File = syn::parse2(tokens)?;
+    let attrs = file.attrs;
+    let items = file.items;
+    Ok(parse_quote! {
+        mod #name {
+            #(#attrs)*
+            #(#items)*
+            pub(crate) fn __harness_test() -> impl std::process::Termination {
+                main()
+            }
+        }
+    })
+}
+
+/// The root of the file of the test is now a module: `crate::` paths are moved in it.
+/// It's done on the tokens, so the paths in the macros are moved too (but not `$crate`)
+fn crate_paths(tokens: TokenStream, module: &Ident) -> TokenStream {
+    let mut ret = Vec::new();
+    let mut tokens = tokens.into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        match token {
+            TokenTree::Group(group) => {
+                let mut new = Group::new(group.delimiter(), crate_paths(group.stream(), module));
+                new.set_span(group.span());
+                ret.push(new.into());
+            }
+            TokenTree::Ident(ident) if ident == "crate" => {
+                let dollar = matches!(ret.last(), Some(TokenTree::Punct(x)) if x.as_char() == '$');
+                let path = matches!(tokens.peek(), Some(TokenTree::Punct(x)) if x.as_char() == ':' && x.spacing() == Spacing::Joint);
+                ret.push(ident.into());
+                if path && !dollar {
+                    ret.extend(tokens.next());
+                    ret.extend(tokens.next());
+                    ret.push(module.clone().into());
+                    ret.push(Punct::new(':', Spacing::Joint).into());
+                    ret.push(Punct::new(':', Spacing::Alone).into());
+                }
+            }
+            token => ret.push(token),
+        }
+    }
+    ret.into_iter().collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use orchestrator::prelude::{CompilationResult, RunResult};
+
+    use super::Harness;
+    use crate::generatorv2::{
+        compiled::{CompileConfig, RustCompiled},
+        file_generator::GeneratedFiles,
+        run::RunConfig,
+    };
+
+    #[test]
+    fn test_harness() {
+        let files = HashMap::from([
+            (
+                "test_1".to_string(),
+                "mod a { pub fn f() -> u8 { 1 } }\nfn main() { assert_eq!(crate::a::f(), 1); }"
+                    .to_string(),
+            ),
+            (
+                "test_2".to_string(),
+                "fn main() { let x: u8 = \"a\"; }".to_string(),
+            ),
+            ("test_3".to_string(), "fn main( {".to_string()),
+        ]);
+        let (harness, errors) = Harness::new(&files);
+        assert_eq!(errors.keys().collect::<Vec<_>>(), vec!["test_3"]);
+        assert!(harness.source.contains("::test_1::a::f()"));
+        let line = harness
+            .source
+            .lines()
+            .position(|x| x.contains("let x: u8"))
+            .unwrap()
+            + 1;
+        let output = format!(
+            r#"{{"reason":"compiler-message","message":{{"level":"error","message":"mismatched types","rendered":"mismatched types","spans":[{{"file_name":"src/bin/harness.rs","line_start":{},"is_primary":true,"expansion":null}}]}}}}
+{{"reason":"compiler-message","message":{{"level":"error","message":"aborting due to 1 previous error","rendered":"aborting due to 1 previous error","spans":[]}}}}
+{{"reason":"compiler-message","message":{{"level":"error","message":"linking failed","rendered":"linking failed","spans":[]}}}}"#,
+            line
+        );
+        let errors = harness.diagnostics(&output, &GeneratedFiles::default());
+        let rendered = |test: Option<&str>| -> Vec<&str> {
+            errors[&test.map(str::to_string)]
+                .iter()
+                .map(|x| x.rendered.as_str())
+                .collect()
+        };
+        assert_eq!(rendered(Some("test_2")), vec!["mismatched types"]);
+        assert_eq!(rendered(None), vec!["linking failed"]);
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_single_binary() {
+        // the same function is overwritten in a different way by each test
+        let files = [
+            (
+                "test_1",
+                "fn value() -> u8 { 1 }\nfn main() { assert_eq!(value(), 1); }",
+            ),
+            (
+                "test_2",
+                "fn value() -> u8 { \"1\" }\nfn main() { value(); }",
+            ),
+            (
+                "test_3",
+                "fn value() -> u8 { 2 }\nfn main() { assert_eq!(value(), 1); }",
+            ),
+        ];
+        let generated = GeneratedFiles {
+            files: files
+                .iter()
+                .map(|(name, content)| (name.to_string(), (content.to_string(), 1.0)))
+                .collect(),
+            limits: HashMap::new(),
+            ..Default::default()
+        };
+        let config = CompileConfig {
+            single_binary: true,
+            ..Default::default()
+        };
+        let compiled = RustCompiled::compile(generated, config).await.unwrap();
+        assert_eq!(compiled.binary.as_deref(), Some("harness"));
+        // one binary for all the tests
+        assert!(!compiled.path.join("target/debug/test_1").exists());
+        let results = compiled.run(RunConfig::default()).await.unwrap().tests;
+
+        assert!(matches!(
+            results["test_1"].compiled,
+            CompilationResult::Built(_)
+        ));
+        assert_eq!(results["test_1"].runned, RunResult::Ok);
+        assert_eq!(results["test_1"].points_given, 1.0);
+        let CompilationResult::Error(error, _) = &results["test_2"].compiled else {
+            panic!("test_2 should not compile");
+        };
+        assert!(error.contains("mismatched types"));
+        assert_eq!(results["test_2"].runned, RunResult::NotRun);
+        assert!(matches!(
+            results["test_3"].compiled,
+            CompilationResult::Built(_)
+        ));
+        assert!(matches!(results["test_3"].runned, RunResult::Error(_)));
+        assert_eq!(results["test_3"].points_given, 0.0);
+    }
+}
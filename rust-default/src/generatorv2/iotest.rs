@@ -0,0 +1,265 @@
+//! Tests that run the `main` of the student with an input and compare what it prints.
+//!
+//! In a template the function of the test only names it, its body is not used:
+//! ```ignore
+//! #[iotest(1.0, stdin = "1 2\n", stdout = "3\n", compare = "tokens")]
+//! fn test_sum() {}
+//! ```
+//! The limits are the same of runtest. `compare` is one of `exact` (the default), `trim`,
+//! `tokens` and `float`, the last one uses `tolerance` (a tolerance alone implies `float`).
+use std::hash::{Hash, Hasher};
+
+/// tolerance of `float` when it's not given
+pub const DEFAULT_TOLERANCE: f64 = 1e-6;
+
+/// how many lines of each output are shown in the difference
+const MAX_DIFF_LINES: usize = 20;
+
+/// lines shown before the first difference
+const CONTEXT_LINES: usize = 2;
+
+/// How the output of the student is compared with the expected one
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Comparison {
+    /// the same bytes
+    #[default]
+    Exact,
+    /// the same lines, without the whitespace at their end and the empty lines at the end
+    TrimEnd,
+    /// the same words, separated by any whitespace
+    Tokens,
+    /// the same words, the numbers can differ by the tolerance
+    /// (absolute, or relative to the expected number when it is bigger than 1)
+    Float(f64),
+}
+
+impl Hash for Comparison {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        if let Comparison::Float(tolerance) = self {
+            tolerance.to_bits().hash(state);
+        }
+    }
+}
+
+impl Comparison {
+    /// the comparison named in the template, an unknown name is exact
+    pub fn parse(name: Option<&str>, tolerance: Option<f64>) -> Self {
+        match name {
+            Some("trim") => Comparison::TrimEnd,
+            Some("tokens") => Comparison::Tokens,
+            Some("float") => Comparison::Float(tolerance.unwrap_or(DEFAULT_TOLERANCE)),
+            None => tolerance.map_or(Comparison::Exact, Comparison::Float),
+            _ => Comparison::Exact,
+        }
+    }
+
+    /// the two outputs are the same
+    pub fn matches(&self, expected: &str, actual: &str) -> bool {
+        match self {
+            Comparison::Exact => expected == actual,
+            Comparison::TrimEnd => trimmed_lines(expected) == trimmed_lines(actual),
+            Comparison::Tokens => expected.split_whitespace().eq(actual.split_whitespace()),
+            Comparison::Float(tolerance) => {
+                let expected: Vec<&str> = expected.split_whitespace().collect();
+                let actual: Vec<&str> = actual.split_whitespace().collect();
+                expected.len() == actual.len()
+                    && expected
+                        .iter()
+                        .zip(&actual)
+                        .all(|(x, y)| same_number(x, y, *tolerance))
+            }
+        }
+    }
+
+    /// the lines are the same, they are compared like the whole outputs
+    fn same_line(&self, expected: &str, actual: &str) -> bool {
+        match self {
+            Comparison::Exact => expected == actual,
+            Comparison::TrimEnd => expected.trim_end() == actual.trim_end(),
+            Comparison::Tokens | Comparison::Float(_) => self.matches(expected, actual),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Comparison::Exact => "exactly".to_string(),
+            Comparison::TrimEnd => "ignoring the whitespace at the end of the lines".to_string(),
+            Comparison::Tokens => "word by word".to_string(),
+            Comparison::Float(tolerance) => {
+                format!("word by word, numbers within {}", tolerance)
+            }
+        }
+    }
+}
+
+fn trimmed_lines(output: &str) -> Vec<&str> {
+    let mut lines: Vec<&str> = output.lines().map(str::trim_end).collect();
+    while lines.last() == Some(&"") {
+        lines.pop();
+    }
+    lines
+}
+
+fn same_number(expected: &str, actual: &str, tolerance: f64) -> bool {
+    if expected == actual {
+        return true;
+    }
+    match (expected.parse::<f64>(), actual.parse::<f64>()) {
+        (Ok(x), Ok(y)) => (x - y).abs() <= tolerance * x.abs().max(1.0),
+        _ => false,
+    }
+}
+
+/// A test on the input and the output of the program of the student
+#[derive(Debug, Clone, Default, PartialEq, Hash)]
+pub struct IoTest {
+    /// given to the program
+    pub stdin: String,
+    /// what it should print
+    pub stdout: String,
+    pub comparison: Comparison,
+}
+
+impl IoTest {
+    /// None if the output is the expected one, otherwise the difference between them
+    pub fn check(&self, stdout: &str) -> Option<String> {
+        if self.comparison.matches(&self.stdout, stdout) {
+            return None;
+        }
+        Some(format!(
+            "the output is not the expected one (compared {})\n{}",
+            self.comparison.describe(),
+            diff(&self.stdout, stdout, &self.comparison)
+        ))
+    }
+}
+
+/// the lines that differ, with `-` the expected ones and with `+` the printed ones,
+/// after some lines they have in common
+fn diff(expected: &str, actual: &str, comparison: &Comparison) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    let same = |(x, y): &(&&str, &&str)| comparison.same_line(x, y);
+    let prefix = expected.iter().zip(&actual).take_while(same).count();
+    let suffix = expected[prefix..]
+        .iter()
+        .rev()
+        .zip(actual[prefix..].iter().rev())
+        .take_while(same)
+        .count();
+    let expected_lines = &expected[prefix..expected.len() - suffix];
+    let actual_lines = &actual[prefix..actual.len() - suffix];
+    if expected_lines.is_empty() && actual_lines.is_empty() {
+        return "the lines are the same, they differ in how they end".to_string();
+    }
+    let mut ret = format!("first difference at line {}:\n", prefix + 1);
+    for line in &expected[prefix.saturating_sub(CONTEXT_LINES)..prefix] {
+        ret += &format!("  {}\n", line);
+    }
+    for (sign, lines) in [("-", expected_lines), ("+", actual_lines)] {
+        for line in lines.iter().take(MAX_DIFF_LINES) {
+            ret += &format!("{} {}\n", sign, line);
+        }
+        if lines.len() > MAX_DIFF_LINES {
+            ret += &format!("{} ... {} more lines\n", sign, lines.len() - MAX_DIFF_LINES);
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use orchestrator::prelude::{CompilationResult, RunResult};
+
+    use super::{Comparison, IoTest};
+    use crate::generatorv2::{
+        compiled::{CompileConfig, RustCompiled},
+        file_generator::GeneratedFiles,
+        parser::RustExercise,
+        run::RunConfig,
+    };
+
+    #[test]
+    fn test_comparison() {
+        assert!(Comparison::Exact.matches("1\n", "1\n"));
+        assert!(!Comparison::Exact.matches("1\n", "1"));
+        assert!(Comparison::TrimEnd.matches("1\n2\n", "1  \n2\n\n"));
+        assert!(!Comparison::TrimEnd.matches("1 2", " 1 2"));
+        assert!(Comparison::Tokens.matches("1 2\n3", " 1\n2   3\n"));
+        assert!(!Comparison::Tokens.matches("1 2 3", "1 2"));
+        let float = Comparison::parse(None, Some(0.01));
+        assert_eq!(float, Comparison::Float(0.01));
+        assert!(float.matches("x 3.14", "x 3.141"));
+        assert!(float.matches("1000", "1005"));
+        assert!(!float.matches("3.14", "3.2"));
+        assert!(!float.matches("x", "y"));
+        assert_eq!(Comparison::parse(Some("other"), None), Comparison::Exact);
+
+        let test = IoTest {
+            stdout: "a\nb\nc\nd\n".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(test.check("a\nb\nc\nd\n"), None);
+        let diff = test.check("a\nb\nx\nd\n").unwrap();
+        assert!(
+            diff.ends_with("first difference at line 3:\n  a\n  b\n- c\n+ x\n"),
+            "{}",
+            diff
+        );
+        let diff = test.check("a\nb\nc\nd").unwrap();
+        assert!(diff.ends_with("they differ in how they end"), "{}", diff);
+    }
+
+    #[tokio::test]
+    async fn test_iotest() {
+        let template = "
+            #[iotest(2, stdin = \"1 2\\n3 4\\n\", stdout = \"3\\n7\\n\")]
+            fn test_sum() {}
+            #[iotest(stdin = \"1 2\\n\", stdout = \"0.5\", compare = \"float\", tolerance = 0.1)]
+            fn test_ratio() {}
+            #[iotest(stdin = \"5 5\\n\", stdout = \"9\\n\")]
+            fn test_wrong() {}
+        ";
+        let student = "
+            use std::io::BufRead;
+            fn sum(line: &str) -> u32 {
+                line.split_whitespace().map(|x| x.parse::<u32>().unwrap()).sum()
+            }
+            fn main() {
+                for line in std::io::stdin().lock().lines() {
+                    println!(\"{}\", sum(&line.unwrap()));
+                }
+            }
+        ";
+        let exercise = RustExercise::parse(template).unwrap();
+        assert_eq!(exercise.tests[0].points, 2.0);
+        let generated = GeneratedFiles::generate(exercise, student.to_string()).unwrap();
+        for single_binary in [false, true] {
+            let config = CompileConfig {
+                single_binary,
+                ..Default::default()
+            };
+            let compiled = RustCompiled::compile(generated.clone(), config)
+                .await
+                .unwrap();
+            let results = compiled.run(RunConfig::default()).await.unwrap().tests;
+            assert!(matches!(
+                results["test_sum"].compiled,
+                CompilationResult::Built(_)
+            ));
+            assert_eq!(results["test_sum"].runned, RunResult::Ok);
+            assert_eq!(results["test_sum"].points_given, 2.0);
+            // it prints 3 instead of 0.5
+            let RunResult::WrongOutput(diff) = &results["test_ratio"].runned else {
+                panic!("{:?}", results["test_ratio"].runned);
+            };
+            assert!(diff.contains("- 0.5\n+ 3\n"), "{}", diff);
+            let RunResult::WrongOutput(diff) = &results["test_wrong"].runned else {
+                panic!("{:?}", results["test_wrong"].runned);
+            };
+            assert!(diff.contains("- 9\n+ 10\n"), "{}", diff);
+            assert_eq!(results["test_wrong"].points_given, 0.0);
+        }
+    }
+}
@@ -0,0 +1,472 @@
+//! Compile errors of the student confined to the items that contain them.
+//!
+//! Every generated file has the whole submission, so a mistake in a function makes every test
+//! fail to compile. The items of the student with errors that a test doesn't use are replaced,
+//! in the file of that test, with the default implementation of the template or with a
+//! `todo!()`, and the test is compiled again: it can still pass if it never calls them.
+//!
+//! Syntax errors are confined too: a block that can't be parsed becomes a `compile_error!`,
+//! so it is an error of the item that contains it.
+use std::collections::{HashMap, HashSet};
+
+use proc_macro2::{Delimiter, Group, LineColumn, Span, TokenStream, TokenTree};
+use quote::{quote_spanned, ToTokens};
+use serde_json::Value;
+use syn::{
+    parse2, parse_file, parse_quote, parse_str, punctuated::Punctuated, spanned::Spanned, File,
+    ImplItem, Item, ItemImpl, PathSegment, Token, Type,
+};
+
+use super::{harness::source_line, parser::ImplementationPath};
+
+/// how many blocks are replaced before giving up on a file that can't be parsed
+const MAX_REPAIRS: usize = 16;
+
+/// the file of the student, the blocks that can't be parsed are replaced with a `compile_error!`.
+/// If it still can't be parsed the first error is returned
+pub(crate) fn parse_lenient(source: &str) -> syn::Result<File> {
+    let first = match parse_str::<File>(source) {
+        Ok(file) => return Ok(file),
+        Err(err) => err,
+    };
+    let Ok(mut tokens) = source.parse::<TokenStream>() else {
+        return Err(first);
+    };
+    for _ in 0..MAX_REPAIRS {
+        let err = match parse2::<File>(tokens.clone()) {
+            Ok(file) => return Ok(file),
+            Err(err) => err,
+        };
+        match replace_block(tokens, err.span().start(), &err.to_string()) {
+            Some(repaired) => tokens = repaired,
+            None => break,
+        }
+    }
+    Err(first)
+}
+
+fn contains(span: Span, position: LineColumn) -> bool {
+    let (start, end) = (span.start(), span.end());
+    (start.line, start.column) <= (position.line, position.column)
+        && (position.line, position.column) <= (end.line, end.column)
+}
+
+/// replaces the innermost block containing the position, that is not already a `compile_error!`
+fn replace_block(tokens: TokenStream, position: LineColumn, message: &str) -> Option<TokenStream> {
+    let mut trees: Vec<TokenTree> = tokens.into_iter().collect();
+    for tree in trees.iter_mut() {
+        let TokenTree::Group(group) = tree else {
+            continue;
+        };
+        if !contains(group.span(), position) {
+            continue;
+        }
+        let is_stub = matches!(group.stream().into_iter().next(), Some(TokenTree::Ident(x)) if x == "compile_error");
+        let stream = replace_block(group.stream(), position, message).or_else(|| {
+            (group.delimiter() == Delimiter::Brace && !is_stub)
+                .then(|| quote_spanned!(group.span() => compile_error!(#message);))
+        })?;
+        let mut replaced = Group::new(group.delimiter(), stream);
+        replaced.set_span(group.span());
+        *tree = replaced.into();
+        return Some(trees.into_iter().collect());
+    }
+    None
+}
+
+/// all the identifiers in the tokens
+pub(crate) fn identifiers(tokens: TokenStream) -> HashSet<String> {
+    let mut ret = HashSet::new();
+    for tree in tokens {
+        match tree {
+            TokenTree::Ident(ident) => {
+                ret.insert(ident.to_string());
+            }
+            TokenTree::Group(group) => ret.extend(identifiers(group.stream())),
+            _ => {}
+        }
+    }
+    ret
+}
+
+/// the identifiers used by the items named in uses, and by the items they use in turn.
+///
+/// An item is named by its identifier (the type or the trait for an implementation,
+/// every identifier for a use): the items with the same name are all taken
+fn used(items: &[Item], mut uses: HashSet<String>) -> HashSet<String> {
+    let mut named = Vec::new();
+    named_items(items, &mut named);
+    loop {
+        let before = uses.len();
+        named.retain(|(names, identifiers): &(Vec<String>, HashSet<String>)| {
+            if !names.iter().any(|x| uses.contains(x)) {
+                return true;
+            }
+            uses.extend(identifiers.iter().cloned());
+            false
+        });
+        if uses.len() == before {
+            return uses;
+        }
+    }
+}
+
+/// names and identifiers of the items, in the modules and in the inherent implementations too
+fn named_items(items: &[Item], ret: &mut Vec<(Vec<String>, HashSet<String>)>) {
+    for item in items {
+        let names = match item {
+            Item::Mod(module) => {
+                if let Some((_, items)) = &module.content {
+                    named_items(items, ret);
+                }
+                continue;
+            }
+            Item::Impl(implementation) if implementation.trait_.is_none() => {
+                let type_ = identifiers(implementation.self_ty.to_token_stream());
+                for item in &implementation.items {
+                    let name = match item {
+                        ImplItem::Fn(function) => &function.sig.ident,
+                        ImplItem::Const(constant) => &constant.ident,
+                        ImplItem::Type(type_) => &type_.ident,
+                        _ => continue,
+                    };
+                    let mut used = identifiers(item.to_token_stream());
+                    used.extend(type_.iter().cloned());
+                    ret.push((vec![name.to_string()], used));
+                }
+                continue;
+            }
+            Item::Impl(implementation) => {
+                let trait_ = implementation
+                    .trait_
+                    .as_ref()
+                    .and_then(|(_, path, _)| path.segments.last());
+                let type_ = match &*implementation.self_ty {
+                    Type::Path(type_) => type_.path.segments.last(),
+                    _ => None,
+                };
+                trait_
+                    .into_iter()
+                    .chain(type_)
+                    .map(|x| x.ident.to_string())
+                    .collect()
+            }
+            Item::Use(_) => identifiers(item.to_token_stream()).into_iter().collect(),
+            Item::Fn(function) => vec![function.sig.ident.to_string()],
+            Item::Struct(x) => vec![x.ident.to_string()],
+            Item::Enum(x) => vec![x.ident.to_string()],
+            Item::Union(x) => vec![x.ident.to_string()],
+            Item::Trait(x) => vec![x.ident.to_string()],
+            Item::Type(x) => vec![x.ident.to_string()],
+            Item::Const(x) => vec![x.ident.to_string()],
+            Item::Static(x) => vec![x.ident.to_string()],
+            Item::Macro(x) => x.ident.iter().map(ToString::to_string).collect(),
+            _ => continue,
+        };
+        ret.push((names, identifiers(item.to_token_stream())));
+    }
+}
+
+/// lines of the errors in the output of cargo, for each binary
+pub(crate) fn error_lines(output: &str) -> HashMap<String, Vec<usize>> {
+    let mut ret: HashMap<String, Vec<usize>> = HashMap::new();
+    for line in output.lines() {
+        let Ok(message) = serde_json::from_str::<Value>(line) else {
+            continue;
+        };
+        let Some(name) = message["target"]["name"].as_str() else {
+            continue;
+        };
+        let message = &message["message"];
+        if message["level"] != "error" {
+            continue;
+        }
+        let lines = ret.entry(name.to_string()).or_default();
+        let spans = message["spans"]
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or(&[]);
+        match spans.iter().find(|x| x["is_primary"] == true) {
+            // an error without a line can't be isolated
+            Some(span) => lines.push(source_line(span, &format!("/{}.rs", name)).unwrap_or(0)),
+            None if !message["rendered"]
+                .as_str()
+                .is_some_and(|x| x.starts_with("aborting due to")) =>
+            {
+                lines.push(0)
+            }
+            None => {}
+        }
+    }
+    ret
+}
+
+/// the generated file of a test, without the items of the student with errors.
+/// None if some errors are in items the test uses (directly or through other items),
+/// or they can't be replaced
+pub(crate) fn isolate(
+    content: &str,
+    lines: &[usize],
+    uses: &HashSet<String>,
+    defaults: &HashMap<String, String>,
+) -> Option<String> {
+    let mut file = parse_file(content).ok()?;
+    let uses = used(&file.items, uses.clone());
+    let mut isolation = Isolation {
+        lines: lines.to_vec(),
+        uses: &uses,
+        defaults,
+        mod_path: Punctuated::new(),
+        failed: false,
+    };
+    isolation.items(&mut file.items, true);
+    if isolation.failed || !isolation.lines.is_empty() {
+        return None;
+    }
+    let isolated = prettyplease::unparse(&file);
+    (isolated != content).then_some(isolated)
+}
+
+struct Isolation<'a> {
+    /// lines of the errors that are not in a replaced item yet
+    lines: Vec<usize>,
+    uses: &'a HashSet<String>,
+    defaults: &'a HashMap<String, String>,
+    mod_path: Punctuated<PathSegment, Token![::]>,
+    /// an error can't be isolated
+    failed: bool,
+}
+
+impl Isolation<'_> {
+    /// the item has errors, they are removed from the lines to handle
+    fn has_errors(&mut self, span: Span) -> bool {
+        let lines = span.start().line..=span.end().line;
+        let before = self.lines.len();
+        self.lines.retain(|x| !lines.contains(x));
+        self.lines.len() != before
+    }
+
+    fn is_used(&self, ident: impl ToString) -> bool {
+        self.uses.contains(&ident.to_string())
+    }
+
+    fn default(&self, key: &ImplementationPath) -> Option<Item> {
+        parse_str(self.defaults.get(&key.to_token_stream().to_string())?).ok()
+    }
+
+    fn items(&mut self, items: &mut [Item], root: bool) {
+        for item in items {
+            match item {
+                Item::Mod(module) if module.content.is_some() => {
+                    self.mod_path.push(module.ident.clone().into());
+                    if let Some((_, items)) = &mut module.content {
+                        self.items(items, false);
+                    }
+                    self.mod_path.pop();
+                    self.mod_path.pop_punct();
+                }
+                Item::Impl(implementation) if implementation.trait_.is_none() => {
+                    self.methods(implementation)
+                }
+                item => {
+                    if !self.has_errors(item.span()) {
+                        continue;
+                    }
+                    self.replace(item, root);
+                }
+            }
+        }
+    }
+
+    fn replace(&mut self, item: &mut Item, root: bool) {
+        match item {
+            // the test itself
+            Item::Fn(function) if root && function.sig.ident == "main" => self.failed = true,
+            Item::Fn(function) if self.is_used(&function.sig.ident) => self.failed = true,
+            Item::Fn(function) => {
+                let key = ImplementationPath::from_fn(function, &self.mod_path);
+                match self.default(&key) {
+                    Some(Item::Fn(default)) => *function = default,
+                    _ => function.block = parse_quote!({ todo!() }),
+                }
+            }
+            Item::Impl(implementation) => {
+                let trait_ = implementation
+                    .trait_
+                    .as_ref()
+                    .and_then(|(_, path, _)| path.segments.last());
+                if trait_.is_some_and(|x| self.is_used(&x.ident))
+                    || self.type_is_used(implementation)
+                {
+                    self.failed = true;
+                    return;
+                }
+                let key = ImplementationPath::from_impl(implementation, &self.mod_path);
+                match self.default(&key) {
+                    Some(Item::Impl(default)) => *implementation = default,
+                    _ => {
+                        for item in implementation.items.iter_mut() {
+                            if let ImplItem::Fn(function) = item {
+                                function.block = parse_quote!({ todo!() });
+                            }
+                        }
+                    }
+                }
+            }
+            _ => self.failed = true,
+        }
+    }
+
+    fn type_is_used(&self, implementation: &ItemImpl) -> bool {
+        let Type::Path(type_) = &*implementation.self_ty else {
+            return false;
+        };
+        type_
+            .path
+            .segments
+            .last()
+            .is_some_and(|x| self.is_used(&x.ident))
+    }
+
+    /// methods of an inherent implementation, replaced one by one like in the template
+    fn methods(&mut self, implementation: &mut ItemImpl) {
+        let skeleton = ImplementationPath::from_impl(implementation, &self.mod_path);
+        for item in implementation.items.iter_mut() {
+            if !self.has_errors(item.span()) {
+                continue;
+            }
+            let ImplItem::Fn(function) = item else {
+                self.failed = true;
+                continue;
+            };
+            if self.is_used(&function.sig.ident) {
+                self.failed = true;
+                continue;
+            }
+            let mut key = skeleton.clone();
+            if let Type::Path(p) = &mut key.type_ {
+                p.path.segments.push(function.sig.ident.clone().into());
+            }
+            let default = self.default(&key).and_then(|default| match default {
+                Item::Impl(default) => default.items.into_iter().find_map(|x| match x {
+                    ImplItem::Fn(x) => Some(x),
+                    _ => None,
+                }),
+                _ => None,
+            });
+            match default {
+                Some(default) => *function = default,
+                None => function.block = parse_quote!({ todo!() }),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use orchestrator::prelude::{CompilationResult, RunResult};
+    use quote::ToTokens;
+
+    use super::parse_lenient;
+    use crate::generatorv2::{
+        compiled::{CompileConfig, RustCompiled},
+        file_generator::GeneratedFiles,
+        parser::RustExercise,
+        run::RunConfig,
+    };
+
+    #[test]
+    fn test_parse_lenient() {
+        let file = parse_lenient("fn a() -> u8 { let = 1; }\nfn b() -> u8 { 2 }").unwrap();
+        let a = file.items[0].to_token_stream().to_string();
+        assert!(a.contains("compile_error"));
+        assert!(!file.items[1].to_token_stream().to_string().contains("compile_error"));
+        // nothing to replace
+        assert!(parse_lenient("fn a() {").is_err());
+        assert!(parse_lenient("struct A x").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_isolate_errors() {
+        let template = "
+            #[runtest(1.0)]
+            fn test_good() { assert_eq!(good(), 1); }
+            #[runtest(1.0)]
+            fn test_broken() { assert_eq!(broken(), 2); }
+            fn good() -> u8 { 1 }
+            fn broken() -> u8 { 2 }
+            fn other() -> u8 { 3 }
+        ";
+        let student = "
+            fn good() -> u8 { 1 }
+            fn broken() -> u8 { \"2\" }
+            fn other() -> u8 { let = 3; }
+        ";
+        let exercise = RustExercise::parse(template).unwrap();
+        let generated = GeneratedFiles::generate(exercise, student.to_string()).unwrap();
+
+        let compiled = RustCompiled::compile(generated.clone(), CompileConfig::default())
+            .await
+            .unwrap();
+        assert!(matches!(
+            compiled.results["test_good"].compiled,
+            CompilationResult::Error(..)
+        ));
+
+        let config = CompileConfig {
+            isolate_errors: true,
+            ..Default::default()
+        };
+        let compiled = RustCompiled::compile(generated, config).await.unwrap();
+        let results = compiled.run(RunConfig::default()).await.unwrap().tests;
+        assert!(matches!(
+            results["test_good"].compiled,
+            CompilationResult::Built(_)
+        ));
+        assert_eq!(results["test_good"].runned, RunResult::Ok);
+        assert_eq!(results["test_good"].points_given, 1.0);
+        let CompilationResult::Error(error, _) = &results["test_broken"].compiled else {
+            panic!("test_broken uses the function with errors");
+        };
+        assert!(error.contains("mismatched types"));
+        assert_eq!(results["test_broken"].points_given, 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_isolate_indirect() {
+        let template = "
+            #[runtest(1.0)]
+            fn test_outer() { assert_eq!(outer(), 2); }
+            #[runtest(1.0)]
+            fn test_other() { assert_eq!(other(), 3); }
+            fn outer() -> u8 { inner() }
+            fn inner() -> u8 { 2 }
+            fn other() -> u8 { 3 }
+        ";
+        // outer is right, but the inner it calls doesn't compile
+        let student = "
+            fn outer() -> u8 { inner() }
+            fn inner() -> u8 { \"2\" }
+            fn other() -> u8 { 3 }
+        ";
+        let exercise = RustExercise::parse(template).unwrap();
+        let generated = GeneratedFiles::generate(exercise, student.to_string()).unwrap();
+        let config = CompileConfig {
+            isolate_errors: true,
+            ..Default::default()
+        };
+        let compiled = RustCompiled::compile(generated, config).await.unwrap();
+        let results = compiled.run(RunConfig::default()).await.unwrap().tests;
+        assert!(matches!(
+            results["test_other"].compiled,
+            CompilationResult::Built(_)
+        ));
+        assert_eq!(results["test_other"].points_given, 1.0);
+        // the inner of the template is not used in its place
+        assert!(matches!(
+            results["test_outer"].compiled,
+            CompilationResult::Error(..)
+        ));
+        assert_eq!(results["test_outer"].points_given, 0.0);
+    }
+}
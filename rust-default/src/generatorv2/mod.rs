@@ -1,6 +1,13 @@
+pub mod build_cache;
 pub mod compiled;
+pub mod difftest;
 pub mod error;
 pub mod file_generator;
+pub mod harness;
+pub mod iotest;
+pub mod isolation;
 pub mod parser;
+pub mod sandbox;
+pub mod source_map;
 pub mod test_definition;
 pub mod run;
\ No newline at end of file
@@ -14,11 +14,14 @@ use syn::{
     visit::{visit_file, visit_item_mod, Visit},
 };
 use syn::{
-    parse_str, Attribute, Expr, ExprLit, File, Generics, Ident, Item, ItemFn, ItemImpl, Lifetime,
-    Lit, LitFloat, LitInt, LitStr, Meta, Path, PathSegment, Token, Type, TypePath,
+    parse_str, Attribute, Expr, ExprAssign, ExprLit, File, Generics, Ident, Item, ItemFn, ItemImpl,
+    Lifetime, Lit, LitStr, Meta, Path, PathSegment, Token, Type, TypePath,
 };
 
+use super::difftest::{DiffTest, DEFAULT_CASES};
 use super::error::RustError;
+use super::iotest::{Comparison, IoTest};
+use super::run::TestLimits;
 use super::test_definition::SendableTestDefinition;
 use super::test_definition::UnfinishedTestDefinition;
 /// impl trait for type <in path> IGNORED GENERICS, not supported yet
@@ -154,6 +157,9 @@ pub struct RustExercise {
     pub dependencies: Vec<String>,
     description: String,
     pub tests: Vec<SendableTestDefinition>,
+    /// the default implementations of the template, as strings like in SendableTestDefinition.
+    /// They replace the items of the student that don't compile, see isolation
+    pub(crate) defaults: HashMap<String, String>,
 }
 
 impl ExerciseDef for RustExercise {
@@ -183,6 +189,16 @@ impl RustExercise {
         let file = parse_str::<File>(s)?;
         let mut v = Visiter::default();
         v.visit_file(&file);
+        let defaults = v
+            .default_impls
+            .iter()
+            .map(|(a, b)| {
+                (
+                    a.to_token_stream().to_string(),
+                    b.to_token_stream().to_string(),
+                )
+            })
+            .collect();
         let tests = v
             .tests
             .into_iter()
@@ -193,6 +209,7 @@ impl RustExercise {
             dependencies: v.dependencies,
             description: v.description.unwrap_or(String::new()),
             tests,
+            defaults,
         })
     }
 }
@@ -242,35 +259,98 @@ fn extract_dependencies<'a>(value: impl Iterator<Item = &'a Attribute>) -> Vec<S
         .collect()
 }
 
+/// arguments of runtest, iotest and difftest: the points (1 by default), then the limits as
+/// `name = value`. iotest has also the input, the output and how they are compared (see iotest),
+/// difftest the functions it uses, the cases and the seed (see difftest).
+/// Unknown or invalid arguments are ignored
+fn parse_test(attribute: &Attribute) -> (f32, TestLimits, Option<IoTest>, Option<DiffTest>) {
+    let mut points = 1.0;
+    let mut limits = TestLimits::default();
+    let mut io = attribute.path().is_ident("iotest").then(IoTest::default);
+    let mut diff = attribute.path().is_ident("difftest").then(|| DiffTest {
+        cases: DEFAULT_CASES,
+        ..Default::default()
+    });
+    let (mut compare, mut tolerance) = (None, None);
+    let args = attribute
+        .parse_args_with(Punctuated::<Expr, Token![,]>::parse_terminated)
+        .unwrap_or_default();
+    for arg in args {
+        match arg {
+            // is a float?
+            Expr::Lit(ExprLit {
+                lit: Lit::Float(f), ..
+            }) => points = f.base10_parse().unwrap_or(points),
+            // is it an int?
+            Expr::Lit(ExprLit {
+                lit: Lit::Int(i), ..
+            }) => points = i.base10_parse().unwrap_or(points),
+            Expr::Assign(ExprAssign { left, right, .. }) => {
+                let Expr::Path(name) = *left else {
+                    continue;
+                };
+                let Some(name) = name.path.get_ident().map(Ident::to_string) else {
+                    continue;
+                };
+                let lit = match (*right, &mut diff) {
+                    (Expr::Lit(ExprLit { lit, .. }), _) => lit,
+                    (Expr::Path(path), Some(diff)) => {
+                        match name.as_str() {
+                            "function" => diff.function = Some(path.path),
+                            "generator" => diff.generator = Some(path.path),
+                            _ => {}
+                        }
+                        continue;
+                    }
+                    _ => continue,
+                };
+                match (name.as_str(), lit) {
+                    ("timeout_ms", Lit::Int(value)) => {
+                        limits.timeout_ms = value.base10_parse().ok()
+                    }
+                    ("cpu_ms", Lit::Int(value)) => limits.cpu_ms = value.base10_parse().ok(),
+                    ("memory_mb", Lit::Int(value)) => limits.memory_mb = value.base10_parse().ok(),
+                    ("compare", Lit::Str(value)) => compare = Some(value.value()),
+                    ("tolerance", Lit::Float(value)) => tolerance = value.base10_parse().ok(),
+                    ("tolerance", Lit::Int(value)) => tolerance = value.base10_parse().ok(),
+                    (name, Lit::Str(value)) => match (&mut io, name) {
+                        (Some(io), "stdin") => io.stdin = value.value(),
+                        (Some(io), "stdout") => io.stdout = value.value(),
+                        _ => {}
+                    },
+                    (name, Lit::Int(value)) => match (&mut diff, name) {
+                        (Some(diff), "cases") => {
+                            diff.cases = value.base10_parse().unwrap_or(diff.cases)
+                        }
+                        (Some(diff), "seed") => diff.seed = value.base10_parse().ok(),
+                        _ => {}
+                    },
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some(io) = &mut io {
+        io.comparison = Comparison::parse(compare.as_deref(), tolerance);
+    }
+    (points, limits, io, diff)
+}
+
+/// the attribute of a test
+fn is_test(attribute: &Attribute) -> bool {
+    ["runtest", "iotest", "difftest"]
+        .iter()
+        .any(|x| attribute.path().is_ident(x))
+}
+
 pub fn extract_fn(func: &ItemFn) -> Option<UnfinishedTestDefinition> {
     let description = extract_documentation(func.attrs.iter()).unwrap_or(String::new());
-    let points = func
+    let (points, limits, io, diff) = func
         .attrs
         .iter()
-        .filter_map(|attribute| {
-            if !attribute.path().is_ident("runtest") {
-                return None;
-            }
-            if let Some(f) = attribute
-                .parse_args()
-                .ok()
-                .and_then(|f: LitFloat| f.base10_parse().ok())
-            {
-                // is a float?
-                return Some(f);
-            }
-            if let Some(f) = attribute
-                .parse_args()
-                .ok()
-                .and_then(|f: LitInt| f.base10_parse().ok())
-            {
-                // is it an int?
-                return Some(f);
-            }
-            //let's set 1 as default
-            Some(1.0)
-        })
-        .next()?;
+        .find(|attribute| is_test(attribute))
+        .map(parse_test)?;
     let to_overwrite: Vec<ImplementationPath> = func
         .attrs
         .iter()
@@ -283,12 +363,15 @@ pub fn extract_fn(func: &ItemFn) -> Option<UnfinishedTestDefinition> {
         .collect();
     let mut test = func.clone();
     test.attrs
-        .retain(|x| !x.path().is_ident("overwrite") && !x.path().is_ident("runtest"));
+        .retain(|x| !x.path().is_ident("overwrite") && !is_test(x));
     Some(UnfinishedTestDefinition {
         to_overwrite,
         test,
         description,
         points,
+        limits,
+        io,
+        diff,
     })
 }
 
@@ -373,7 +456,7 @@ impl<'a> Visit<'a> for Visiter {
 mod tests {
     use std::collections::HashMap;
 
-    use super::{ImplementationPath, Visiter};
+    use super::{ImplementationPath, TestLimits, Visiter};
     use quote::{quote, ToTokens};
     use syn::parse_str;
     use syn::punctuated::Punctuated;
@@ -472,6 +555,29 @@ mod tests {
         }").unwrap());*/
     }
     #[test]
+    fn test_runtest_limits() {
+        let q = quote! {
+            #[runtest(2.5, timeout_ms = 500, memory_mb = 64, unknown = 1)]
+            fn test_1(){}
+            #[runtest(cpu_ms = 200)]
+            fn test_2(){}
+        };
+        let file = parse2::<File>(q).unwrap();
+        let mut v = Visiter::default();
+        v.visit_file(&file);
+        assert_eq!(v.tests[0].points, 2.5);
+        assert_eq!(
+            v.tests[0].limits,
+            TestLimits {
+                timeout_ms: Some(500),
+                cpu_ms: None,
+                memory_mb: Some(64)
+            }
+        );
+        assert_eq!(v.tests[1].points, 1.0);
+        assert_eq!(v.tests[1].limits.cpu_ms, Some(200));
+    }
+    #[test]
     fn test_implementation_path() {
         let t = "impl Derive for a in b :: c";
         let p = parse_str::<ImplementationPath>(t).unwrap();
@@ -1,47 +1,341 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    process::{ExitStatus, Stdio},
+    time::{Duration, Instant},
+};
 
-use orchestrator::prelude::{CompilationResult, ExerciseResult, RunResult, TestResult};
+use orchestrator::prelude::{
+    CompilationResult, Deserialize, ExerciseResult, PanicReport, RunDiagnostics, RunResult,
+    Serialize, TestResult,
+};
+use syn::parse_file;
 use tokio::{
+    fs,
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
     process::Command,
     task::{JoinError, JoinSet},
+    time::timeout,
 };
 
-use super::compiled::RustCompiled;
+use super::{
+    compiled::RustCompiled,
+    harness::{test_items, HARNESS},
+    isolation::parse_lenient,
+    sandbox::Runner,
+    source_map::{locate_panic, SourceMap},
+};
 #[derive(thiserror::Error, Debug)]
 /// all the errors that could be generated by execution
 pub enum RunError {
     #[error("JoinError: {0}")]
     JoinError(#[from] JoinError),
 }
+
+/// Limits of a test, the missing ones are taken from the global limits.
+///
+/// In a template they are set with `#[runtest(1, timeout_ms = 500, cpu_ms = 200, memory_mb = 64)]`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(crate = "orchestrator::prelude::serde")]
+pub struct TestLimits {
+    /// wall-clock time, in milliseconds
+    pub timeout_ms: Option<u64>,
+    /// cpu time, in milliseconds. The kernel counts whole seconds, so it's rounded up
+    pub cpu_ms: Option<u64>,
+    /// address space, in MiB
+    pub memory_mb: Option<u64>,
+}
+
+impl TestLimits {
+    /// the limits of the test, the missing ones are taken from global
+    pub fn or(self, global: &TestLimits) -> TestLimits {
+        TestLimits {
+            timeout_ms: self.timeout_ms.or(global.timeout_ms),
+            cpu_ms: self.cpu_ms.or(global.cpu_ms),
+            memory_mb: self.memory_mb.or(global.memory_mb),
+        }
+    }
+}
+
+/// How the tests are executed, it is the data of the executor from RustCompiled to ExerciseResult
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "orchestrator::prelude::serde", default)]
+pub struct RunConfig {
+    /// where the tests are executed
+    pub runner: Runner,
+    /// limits of the tests that don't set their own
+    pub limits: TestLimits,
+    /// how much of stdout and stderr is kept, in bytes
+    pub max_output_bytes: usize,
+}
+
+impl Default for RunConfig {
+    fn default() -> Self {
+        Self {
+            runner: Runner::default(),
+            limits: TestLimits {
+                timeout_ms: Some(10_000),
+                cpu_ms: Some(10_000),
+                memory_mb: Some(512),
+            },
+            max_output_bytes: 16 * 1024,
+        }
+    }
+}
+
+/// how long the output is still read after the test ended, children of the test could keep it open
+const OUTPUT_GRACE: Duration = Duration::from_secs(1);
+
+/// reads the pipe until it is closed, only the first max bytes are kept
+async fn capture(pipe: Option<impl AsyncRead + Unpin>, max: usize) -> String {
+    let Some(mut pipe) = pipe else {
+        return String::new();
+    };
+    let mut kept = Vec::new();
+    let mut truncated = false;
+    let mut buf = [0; 8192];
+    while let Ok(n) = pipe.read(&mut buf).await {
+        if n == 0 {
+            break;
+        }
+        let space = max - kept.len();
+        truncated |= n > space;
+        kept.extend_from_slice(&buf[..n.min(space)]);
+    }
+    let mut output = String::from_utf8_lossy(&kept).into_owned();
+    if truncated {
+        output += "\n[output truncated]";
+    }
+    output
+}
+
+/// the first panic printed by the default hook:
+/// `thread 'main' panicked at src/bin/test.rs:10:5:` followed by the message
+/// (newer versions print the id of the thread after its name)
+fn parse_panic(stderr: &str) -> Option<PanicReport> {
+    let (_, rest) = stderr.split_once(" panicked at ")?;
+    let (location, rest) = rest.split_once(":\n")?;
+    let mut parts = location.rsplitn(3, ':');
+    let column = parts.next()?.parse().ok()?;
+    let line = parts.next()?.parse().ok()?;
+    let file = parts.next()?.to_string();
+    let message = rest
+        .lines()
+        .take_while(|x| !x.starts_with("note: ") && !x.starts_with("stack backtrace:"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    Some(PanicReport {
+        message,
+        file,
+        line,
+        column,
+        internal: false,
+    })
+}
+
+/// verdict of a test that ended: a test killed by the cpu limit gets SIGXCPU,
+/// and one without memory aborts after the allocation error is printed
+fn verdict(status: ExitStatus, diagnostics: &RunDiagnostics) -> RunResult {
+    if status.success() {
+        return RunResult::Ok;
+    }
+    match diagnostics.signal {
+        #[cfg(unix)]
+        Some(libc::SIGXCPU) => return RunResult::Timeout,
+        #[cfg(unix)]
+        Some(libc::SIGABRT) if diagnostics.stderr.contains("memory allocation of") => {
+            return RunResult::MemoryLimitExceeded
+        }
+        Some(signal) => return RunResult::Killed(signal),
+        None => {}
+    }
+    match (&diagnostics.panic, status.code()) {
+        (Some(panic), _) => RunResult::Error(panic.to_string()),
+        (None, Some(code)) => RunResult::Error(format!("exit code {}", code)),
+        (None, None) => RunResult::Error(status.to_string()),
+    }
+}
+
+/// runs the command until it ends or its wall-clock time is over, in that case it is killed.
+/// The test leads its own process group, so the processes it started are killed with it.
+///
+/// The input is written to its stdin, without it stdin is empty.
+/// Only the first max_output bytes of stdout and stderr are kept
+pub(crate) async fn execute(
+    command: &mut Command,
+    limits: &TestLimits,
+    stdin: Option<String>,
+    max_output: usize,
+) -> (RunResult, Option<RunDiagnostics>) {
+    let start = Instant::now();
+    #[cfg(unix)]
+    command.process_group(0);
+    let child = command
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(err) => return (RunResult::Error(err.to_string()), None),
+    };
+    if let Some((input, mut pipe)) = stdin.zip(child.stdin.take()) {
+        // the test could end without reading all of it
+        tokio::spawn(async move { pipe.write_all(input.as_bytes()).await });
+    }
+    let stdout = tokio::spawn(capture(child.stdout.take(), max_output));
+    let stderr = tokio::spawn(capture(child.stderr.take(), max_output));
+    let (stop_stdout, stop_stderr) = (stdout.abort_handle(), stderr.abort_handle());
+    #[cfg(unix)]
+    let group = child.id();
+
+    let status = match limits.timeout_ms {
+        Some(ms) => timeout(Duration::from_millis(ms), child.wait()).await.ok(),
+        None => Some(child.wait().await),
+    };
+    #[cfg(unix)]
+    if let Some(group) = group {
+        // SAFETY: only sends a signal, it fails if the group is already empty
+        unsafe {
+            libc::kill(-(group as i32), libc::SIGKILL);
+        }
+    }
+    if status.is_none() {
+        let _ = child.kill().await;
+    }
+    let duration_ms = start.elapsed().as_millis() as u64;
+    let output = timeout(OUTPUT_GRACE, async { (stdout.await, stderr.await) }).await;
+    stop_stdout.abort();
+    stop_stderr.abort();
+    let (stdout, stderr) = match output {
+        Ok((stdout, stderr)) => (stdout.unwrap_or_default(), stderr.unwrap_or_default()),
+        Err(_) => Default::default(),
+    };
+
+    let mut diagnostics = RunDiagnostics {
+        panic: parse_panic(&stderr),
+        stdout,
+        stderr,
+        duration_ms,
+        ..Default::default()
+    };
+    let status = match status {
+        Some(Ok(status)) => status,
+        Some(Err(err)) => return (RunResult::Error(err.to_string()), Some(diagnostics)),
+        None => return (RunResult::Timeout, Some(diagnostics)),
+    };
+    diagnostics.exit_code = status.code();
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        diagnostics.signal = status.signal();
+    }
+    (verdict(status, &diagnostics), Some(diagnostics))
+}
+
+/// the panic of the test on the submission (see source_map), the file where it happened is read
+/// from the project: the one of the test, or its module in the binary with all the tests
+async fn locate(
+    panic: &mut PanicReport,
+    project: &Path,
+    test: &str,
+    single_binary: bool,
+    submission: Option<&str>,
+    template: &HashSet<String>,
+) {
+    let file = format!("src/bin/{}.rs", if single_binary { HARNESS } else { test });
+    let source = if panic.file == file {
+        fs::read_to_string(project.join(&file)).await.ok()
+    } else {
+        None
+    };
+    let items = source.and_then(|source| {
+        if single_binary {
+            test_items(&source, test)
+        } else {
+            parse_file(&source).ok().map(|x| x.items)
+        }
+    });
+    let submission = submission.and_then(|x| parse_lenient(x).ok());
+    let map = items
+        .zip(submission)
+        .map(|(items, submission)| SourceMap::from_items(&items, &submission, template));
+    locate_panic(panic, map.as_ref());
+}
+
 impl RustCompiled {
-    /// execute and collect results
-    pub async fn run(self) -> Result<ExerciseResult, RunError> {
+    /// execute and collect results, each test is executed by the runner within its limits
+    pub async fn run(self, config: RunConfig) -> Result<ExerciseResult, RunError> {
         let mut set: JoinSet<(String, TestResult)> = JoinSet::new();
+        // paths of the server are not shown
+        let project = self.path.to_string_lossy().into_owned();
 
         //let's start executing all test in parallel
         for (name, mut test_result) in self.results {
-            let exec = self.path.join("target").join("debug").join(&name);
+            let exec = self
+                .path
+                .join("target")
+                .join("debug")
+                .join(self.binary.as_ref().unwrap_or(&name));
+            let single_binary = self.binary.is_some();
+            let limits = self
+                .limits
+                .get(&name)
+                .copied()
+                .unwrap_or_default()
+                .or(&config.limits);
+            let runner = config.runner.clone();
+            let io = self.io.get(&name).cloned();
+            let max_output = config.max_output_bytes;
+            let project = project.clone();
+            let path = self.path.clone();
+            let submission = self.submission.clone();
+            let template = self.template.get(&name).cloned().unwrap_or_default();
             set.spawn(async move {
-                if let CompilationResult::Built = test_result.compiled {
-                    //let t = Command::new(exec).output().await?;
-                    match Command::new(&exec).output().await {
-                        Ok(output) if output.status.success() => {
-                            //test_result.points_given = test_result.points;
-                            test_result.runned = RunResult::Ok;
+                if let CompilationResult::Built(_) = test_result.compiled {
+                    let stdin = io.as_ref().map(|x| x.stdin.clone());
+                    let (mut result, mut diagnostics) = match runner.command(&exec, &limits) {
+                        // run keeps the sandbox alive until the test ends
+                        Ok(mut run) => {
+                            if single_binary {
+                                run.command.arg(&name);
+                            }
+                            execute(&mut run.command, &limits, stdin, max_output).await
                         }
-                        Ok(output) => {
-                            test_result.points_given = 0.0;
-                            test_result.runned = RunResult::Error(format!(
-                                "Got this error while executing {} {}",
-                                exec.to_str().unwrap(),
-                                String::from_utf8(output.stderr).unwrap()
-                            ));
+                        Err(err) => (RunResult::Error(err.to_string()), None),
+                    };
+                    if let Some(panic) = diagnostics.as_mut().and_then(|x| x.panic.as_mut()) {
+                        let shown = RunResult::Error(panic.to_string());
+                        let submission = submission.as_deref();
+                        locate(panic, &path, &name, single_binary, submission, &template).await;
+                        if result == shown {
+                            result = RunResult::Error(panic.to_string());
                         }
-                        Err(err) => {
-                            test_result.points_given = 0.0;
-                            test_result.runned = RunResult::Error(err.to_string())
+                    }
+                    // a test of the output passes only if it printed the expected one
+                    if result == RunResult::Ok {
+                        let difference = io
+                            .as_ref()
+                            .zip(diagnostics.as_ref())
+                            .and_then(|(io, diagnostics)| io.check(&diagnostics.stdout));
+                        if let Some(difference) = difference {
+                            result = RunResult::WrongOutput(difference);
                         }
                     }
+                    test_result.runned = result;
+                    test_result.diagnostics = diagnostics.map(|mut x| {
+                        x.stdout = x.stdout.replace(&project, ".");
+                        x.stderr = x.stderr.replace(&project, ".");
+                        x
+                    });
+                    if test_result.runned != RunResult::Ok {
+                        test_result.points_given = 0.0;
+                    }
                 }
                 (name, test_result)
             });
@@ -54,3 +348,123 @@ impl RustCompiled {
         Ok(ExerciseResult { tests })
     }
 }
+
+#[cfg(all(test, unix))]
+mod test {
+    use std::{os::unix::process::ExitStatusExt, path::Path, process::ExitStatus, time::Instant};
+
+    use orchestrator::prelude::{PanicReport, RunDiagnostics, RunResult};
+
+    use super::{execute, parse_panic, verdict, Runner, TestLimits};
+
+    /// runs a shell script on the host with the given limits, keeping 1000 bytes of output
+    async fn sh(script: &str, limits: TestLimits) -> (RunResult, RunDiagnostics) {
+        let mut run = Runner::Host.command(Path::new("/bin/sh"), &limits).unwrap();
+        run.command.arg("-c").arg(script);
+        let (result, diagnostics) = execute(&mut run.command, &limits, None, 1000).await;
+        (result, diagnostics.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_verdicts() {
+        let none = TestLimits::default();
+        assert_eq!(sh("exit 0", none).await.0, RunResult::Ok);
+        assert_eq!(
+            sh("exit 3", none).await.0,
+            RunResult::Error("exit code 3".into())
+        );
+        assert_eq!(sh("kill -9 $$", none).await.0, RunResult::Killed(9));
+
+        let start = Instant::now();
+        let limits = TestLimits {
+            timeout_ms: Some(200),
+            ..none
+        };
+        let (result, diagnostics) = sh("echo started; sleep 5", limits).await;
+        assert_eq!(result, RunResult::Timeout);
+        assert_eq!(diagnostics.stdout, "started\n");
+        assert!(start.elapsed().as_secs() < 4);
+
+        let limits = TestLimits {
+            cpu_ms: Some(500),
+            ..none
+        };
+        assert_eq!(
+            sh("while :; do :; done", limits).await.0,
+            RunResult::Timeout
+        );
+
+        // status of a process killed by SIGABRT
+        let abort = RunDiagnostics {
+            stderr: "memory allocation of 1048576 bytes failed\n".into(),
+            signal: Some(libc::SIGABRT),
+            ..Default::default()
+        };
+        let status = ExitStatus::from_raw(libc::SIGABRT);
+        assert_eq!(verdict(status, &abort), RunResult::MemoryLimitExceeded);
+        let abort = RunDiagnostics {
+            signal: Some(libc::SIGABRT),
+            ..Default::default()
+        };
+        assert_eq!(verdict(status, &abort), RunResult::Killed(libc::SIGABRT));
+    }
+
+    #[tokio::test]
+    async fn test_diagnostics() {
+        let stderr = "thread 'main' panicked at src/bin/test_1.rs:10:5:\nassertion `left == right` failed\n  left: 1\n right: 2\nnote: run with `RUST_BACKTRACE=1` environment variable to display a backtrace\n";
+        let script = "echo out; printf '%s' \"$PANIC_OUTPUT\" >&2; exit 101";
+        let limits = TestLimits::default();
+        let mut run = Runner::Host.command(Path::new("/bin/sh"), &limits).unwrap();
+        run.command
+            .arg("-c")
+            .arg(script)
+            .env("PANIC_OUTPUT", stderr);
+        let (result, diagnostics) = execute(&mut run.command, &limits, None, 1000).await;
+        let diagnostics = diagnostics.unwrap();
+        let panic = PanicReport {
+            message: "assertion `left == right` failed\n  left: 1\n right: 2".into(),
+            file: "src/bin/test_1.rs".into(),
+            line: 10,
+            column: 5,
+            internal: false,
+        };
+        assert_eq!(result, RunResult::Error(panic.to_string()));
+        assert_eq!(diagnostics.panic, Some(panic));
+        assert_eq!(diagnostics.stdout, "out\n");
+        assert_eq!(diagnostics.stderr, stderr);
+        assert_eq!(diagnostics.exit_code, Some(101));
+        assert_eq!(diagnostics.signal, None);
+        assert_eq!(parse_panic("error: no panic"), None);
+        let panic = parse_panic("thread 'main' (42) panicked at src/bin/test_2.rs:1:2:\nboom\n");
+        assert_eq!(panic.map(|x| (x.message, x.line)), Some(("boom".into(), 1)));
+
+        // the output is truncated, without stopping the test
+        let (result, diagnostics) = sh("yes | head -c 100000; exit 0", TestLimits::default()).await;
+        assert_eq!(result, RunResult::Ok);
+        assert_eq!(
+            diagnostics.stdout,
+            "y\n".repeat(500) + "\n[output truncated]"
+        );
+    }
+
+    #[test]
+    fn test_global_limits() {
+        let global = TestLimits {
+            timeout_ms: Some(1000),
+            cpu_ms: Some(1000),
+            memory_mb: Some(64),
+        };
+        let test = TestLimits {
+            timeout_ms: Some(500),
+            ..Default::default()
+        };
+        assert_eq!(
+            test.or(&global),
+            TestLimits {
+                timeout_ms: Some(500),
+                cpu_ms: Some(1000),
+                memory_mb: Some(64)
+            }
+        );
+    }
+}
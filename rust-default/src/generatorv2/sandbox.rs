@@ -0,0 +1,540 @@
+//! How the compiled tests get executed: directly on the host, or inside a Linux sandbox.
+//!
+//! The sandbox is built with the same pieces used by container runtimes:
+//! - user, mount, network, ipc and uts namespaces: no network, and a private view of the filesystem
+//! - a read-only root with only the system libraries, the test binary and a private /tmp
+//! - rlimits on cpu time, memory, processes and file size
+//! - no capabilities, and a seccomp filter on the syscalls that could escape the sandbox
+use std::{io, path::Path};
+
+use orchestrator::prelude::{Deserialize, Serialize};
+use tempdir::TempDir;
+use tokio::process::Command;
+
+use super::run::TestLimits;
+
+/// Which runner executes the tests
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "orchestrator::prelude::serde")]
+pub enum Runner {
+    /// the test runs directly on the host, as the server user
+    #[default]
+    Host,
+    /// the test runs in a Linux sandbox, with the given limits on top of the ones of the test
+    Sandboxed(SandboxLimits),
+}
+
+/// Resources available to a sandboxed test, time and memory are limited by TestLimits
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "orchestrator::prelude::serde")]
+pub struct SandboxLimits {
+    /// processes and threads. NB: the kernel counts all the ones of the server user
+    pub processes: u64,
+    /// size of each written file, in bytes
+    pub file_size_bytes: u64,
+}
+
+impl Default for SandboxLimits {
+    fn default() -> Self {
+        Self {
+            processes: 512,
+            file_size_bytes: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// A command ready to be spawned
+pub struct RunCommand {
+    /// the command, arguments can still be added
+    pub command: Command,
+    /// root and private /tmp of the sandbox, they are deleted on drop
+    _dir: Option<TempDir>,
+}
+
+impl Runner {
+    /// prepares the command that executes the binary, with the cpu and memory limits of the test
+    pub fn command(&self, exec: &Path, limits: &TestLimits) -> io::Result<RunCommand> {
+        match self {
+            Runner::Host => {
+                let mut command = Command::new(exec);
+                #[cfg(unix)]
+                {
+                    let limits = *limits;
+                    // SAFETY: set_limits only calls setrlimit
+                    unsafe {
+                        command.pre_exec(move || set_limits(&limits));
+                    }
+                }
+                Ok(RunCommand {
+                    command,
+                    _dir: None,
+                })
+            }
+            Runner::Sandboxed(sandbox) => {
+                let (command, dir) = sandboxed(exec, sandbox, limits)?;
+                Ok(RunCommand {
+                    command,
+                    _dir: Some(dir),
+                })
+            }
+        }
+    }
+}
+
+/// fails with the last os error if ret is -1
+#[cfg(unix)]
+fn check(ret: libc::c_int) -> io::Result<()> {
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// sets the cpu time and address space of the current process, it's async-signal-safe.
+///
+/// The hard cpu limit is a second later, so the process gets SIGXCPU and not SIGKILL
+#[cfg(unix)]
+fn set_limits(limits: &TestLimits) -> io::Result<()> {
+    let cpu = limits.cpu_ms.map(|ms| {
+        let seconds = ms.div_ceil(1000).max(1);
+        (seconds, seconds + 1)
+    });
+    let memory = limits
+        .memory_mb
+        .map(|mb| (mb * 1024 * 1024, mb * 1024 * 1024));
+    for (resource, limit) in [(libc::RLIMIT_CPU, cpu), (libc::RLIMIT_AS, memory)] {
+        let Some((soft, hard)) = limit else {
+            continue;
+        };
+        let limit = libc::rlimit {
+            rlim_cur: soft as libc::rlim_t,
+            rlim_max: hard as libc::rlim_t,
+        };
+        check(unsafe { libc::setrlimit(resource, &limit) })?;
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn sandboxed(_: &Path, _: &SandboxLimits, _: &TestLimits) -> io::Result<(Command, TempDir)> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the sandbox is only available on linux",
+    ))
+}
+
+#[cfg(target_os = "linux")]
+use linux::sandboxed;
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::{
+        ffi::{CStr, CString},
+        fs, io,
+        os::unix::{ffi::OsStrExt, fs::symlink},
+        path::Path,
+        ptr,
+    };
+
+    use tempdir::TempDir;
+    use tokio::process::Command;
+
+    use super::{check, set_limits, SandboxLimits, TestLimits};
+
+    /// host directories visible (read-only) in the sandbox, symlinks are copied
+    const SYSTEM_DIRS: [&str; 6] = ["usr", "lib", "lib64", "lib32", "bin", "sbin"];
+    /// host files visible in the sandbox, and if they are writable
+    const SYSTEM_FILES: [(&str, bool); 4] = [
+        ("etc/ld.so.cache", false),
+        ("dev/null", true),
+        ("dev/zero", true),
+        ("dev/urandom", true),
+    ];
+    /// where the test binary is placed in the sandbox
+    const EXEC: &str = "/test";
+
+    /// syscalls that fail with EPERM: mounts, namespaces, tracing, kernel keys and modules
+    const DENIED: [libc::c_long; 22] = [
+        libc::SYS_mount,
+        libc::SYS_umount2,
+        libc::SYS_pivot_root,
+        libc::SYS_chroot,
+        libc::SYS_unshare,
+        libc::SYS_setns,
+        libc::SYS_ptrace,
+        libc::SYS_process_vm_readv,
+        libc::SYS_process_vm_writev,
+        libc::SYS_bpf,
+        libc::SYS_perf_event_open,
+        libc::SYS_userfaultfd,
+        libc::SYS_open_by_handle_at,
+        libc::SYS_keyctl,
+        libc::SYS_add_key,
+        libc::SYS_request_key,
+        libc::SYS_init_module,
+        libc::SYS_finit_module,
+        libc::SYS_delete_module,
+        libc::SYS_kexec_load,
+        libc::SYS_swapon,
+        libc::SYS_reboot,
+    ];
+
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: u32 = 0xC000_003E;
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: u32 = 0xC000_00B7;
+
+    /// a bind mount done in the sandbox
+    struct Bind {
+        source: CString,
+        target: CString,
+        writable: bool,
+    }
+
+    /// everything needed by the child. It's prepared before the fork,
+    /// because after it only async-signal-safe functions can be called (no allocations)
+    struct Setup {
+        root: CString,
+        binds: Vec<Bind>,
+        maps: [(CString, CString); 3],
+        limits: SandboxLimits,
+        test: TestLimits,
+        filter: Vec<libc::sock_filter>,
+    }
+
+    fn c_path(path: &Path) -> io::Result<CString> {
+        CString::new(path.as_os_str().as_bytes())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+    }
+
+    fn c_string(s: String) -> CString {
+        // built from numbers and paths without nul
+        CString::new(s).unwrap()
+    }
+
+    /// prepares the root of the sandbox and the command that enters it
+    pub(super) fn sandboxed(
+        exec: &Path,
+        limits: &SandboxLimits,
+        test: &TestLimits,
+    ) -> io::Result<(Command, TempDir)> {
+        let dir = TempDir::new("sandbox")?;
+        let root = dir.path().join("root");
+        let tmp = dir.path().join("tmp");
+        fs::create_dir(&root)?;
+        fs::create_dir(&tmp)?;
+
+        let mut binds = Vec::new();
+        for name in SYSTEM_DIRS {
+            let host = Path::new("/").join(name);
+            let Ok(metadata) = fs::symlink_metadata(&host) else {
+                continue;
+            };
+            if metadata.is_symlink() {
+                symlink(fs::read_link(&host)?, root.join(name))?;
+            } else if metadata.is_dir() {
+                fs::create_dir(root.join(name))?;
+                binds.push(Bind {
+                    source: c_path(&host)?,
+                    target: c_path(&root.join(name))?,
+                    writable: false,
+                });
+            }
+        }
+        for (name, writable) in SYSTEM_FILES {
+            let host = Path::new("/").join(name);
+            if !host.exists() {
+                continue;
+            }
+            let target = root.join(name);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&target, "")?;
+            binds.push(Bind {
+                source: c_path(&host)?,
+                target: c_path(&target)?,
+                writable,
+            });
+        }
+        let target = root.join(&EXEC[1..]);
+        fs::write(&target, "")?;
+        binds.push(Bind {
+            source: c_path(exec)?,
+            target: c_path(&target)?,
+            writable: false,
+        });
+        fs::create_dir(root.join("tmp"))?;
+        binds.push(Bind {
+            source: c_path(&tmp)?,
+            target: c_path(&root.join("tmp"))?,
+            writable: true,
+        });
+
+        // root in the sandbox is the server user outside
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        let maps = [
+            (
+                c_string("/proc/self/setgroups".into()),
+                c_string("deny".into()),
+            ),
+            (
+                c_string("/proc/self/uid_map".into()),
+                c_string(format!("0 {} 1", uid)),
+            ),
+            (
+                c_string("/proc/self/gid_map".into()),
+                c_string(format!("0 {} 1", gid)),
+            ),
+        ];
+        let setup = Setup {
+            root: c_path(&root)?,
+            binds,
+            maps,
+            limits: limits.clone(),
+            test: *test,
+            filter: seccomp_filter(),
+        };
+
+        let mut command = Command::new(EXEC);
+        command
+            .env_clear()
+            .env("PATH", "/usr/bin:/bin")
+            .env("HOME", "/tmp")
+            .env("TMPDIR", "/tmp")
+            .kill_on_drop(true);
+        // SAFETY: enter only calls async-signal-safe functions, on data prepared before the fork
+        unsafe {
+            command.pre_exec(move || enter(&setup));
+        }
+        Ok((command, dir))
+    }
+
+    fn write_file(path: &CStr, content: &CStr) -> io::Result<()> {
+        unsafe {
+            let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+            check(fd)?;
+            let len = content.to_bytes().len();
+            let written = libc::write(fd, content.as_ptr().cast(), len);
+            libc::close(fd);
+            if written != len as isize {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// flags of the mount containing path, a bind mount in a user namespace must keep them
+    fn locked_flags(path: &CStr) -> io::Result<libc::c_ulong> {
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+        check(unsafe { libc::statvfs(path.as_ptr(), &mut stat) })?;
+        let mut flags = 0;
+        for (st, ms) in [
+            (libc::ST_NOSUID, libc::MS_NOSUID),
+            (libc::ST_NODEV, libc::MS_NODEV),
+            (libc::ST_NOEXEC, libc::MS_NOEXEC),
+            (libc::ST_NOATIME, libc::MS_NOATIME),
+            (libc::ST_NODIRATIME, libc::MS_NODIRATIME),
+            (libc::ST_RELATIME, libc::MS_RELATIME),
+        ] {
+            if stat.f_flag & st != 0 {
+                flags |= ms;
+            }
+        }
+        Ok(flags)
+    }
+
+    fn bind(source: &CStr, target: &CStr, writable: bool) -> io::Result<()> {
+        check(unsafe {
+            libc::mount(
+                source.as_ptr(),
+                target.as_ptr(),
+                ptr::null(),
+                libc::MS_BIND,
+                ptr::null(),
+            )
+        })?;
+        if !writable {
+            read_only(target)?;
+        }
+        Ok(())
+    }
+
+    /// remounts the bind mount on target as read-only
+    fn read_only(target: &CStr) -> io::Result<()> {
+        let flags = locked_flags(target)?;
+        check(unsafe {
+            libc::mount(
+                ptr::null(),
+                target.as_ptr(),
+                ptr::null(),
+                libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY | flags,
+                ptr::null(),
+            )
+        })
+    }
+
+    /// runs in the child, between fork and exec
+    fn enter(setup: &Setup) -> io::Result<()> {
+        unsafe {
+            check(libc::unshare(
+                libc::CLONE_NEWUSER
+                    | libc::CLONE_NEWNS
+                    | libc::CLONE_NEWNET
+                    | libc::CLONE_NEWIPC
+                    | libc::CLONE_NEWUTS,
+            ))?;
+            for (path, content) in &setup.maps {
+                write_file(path, content)?;
+            }
+            // the mounts done here must not propagate to the host
+            check(libc::mount(
+                ptr::null(),
+                c"/".as_ptr(),
+                ptr::null(),
+                libc::MS_REC | libc::MS_PRIVATE,
+                ptr::null(),
+            ))?;
+            bind(&setup.root, &setup.root, true)?;
+            for x in &setup.binds {
+                bind(&x.source, &x.target, x.writable)?;
+            }
+            // mount points are created, now the root can be read-only
+            read_only(&setup.root)?;
+            check(libc::chroot(setup.root.as_ptr()))?;
+            check(libc::chdir(c"/tmp".as_ptr()))?;
+
+            set_limits(&setup.test)?;
+            let limits = &setup.limits;
+            for (resource, limit) in [
+                (libc::RLIMIT_NPROC, limits.processes),
+                (libc::RLIMIT_FSIZE, limits.file_size_bytes),
+                (libc::RLIMIT_CORE, 0),
+            ] {
+                let limit = libc::rlimit {
+                    rlim_cur: limit,
+                    rlim_max: limit,
+                };
+                check(libc::setrlimit(resource, &limit))?;
+            }
+            // capabilities are recomputed on exec from the bounding set: an empty one leaves none
+            for cap in 0..64 {
+                libc::prctl(libc::PR_CAPBSET_DROP, cap, 0, 0, 0);
+            }
+            check(libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0))?;
+            let program = libc::sock_fprog {
+                len: setup.filter.len() as u16,
+                filter: setup.filter.as_ptr() as *mut _,
+            };
+            check(libc::prctl(
+                libc::PR_SET_SECCOMP,
+                libc::SECCOMP_MODE_FILTER,
+                &program,
+            ))?;
+        }
+        Ok(())
+    }
+
+    fn statement(code: u32, k: u32) -> libc::sock_filter {
+        libc::sock_filter {
+            code: code as u16,
+            jt: 0,
+            jf: 0,
+            k,
+        }
+    }
+
+    /// compare the accumulator with k, then skip jt statements if true, jf if false
+    fn jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+        libc::sock_filter {
+            code: (libc::BPF_JMP | code | libc::BPF_K) as u16,
+            jt,
+            jf,
+            k,
+        }
+    }
+
+    /// seccomp program: other architectures are killed, the denied syscalls return EPERM
+    fn seccomp_filter() -> Vec<libc::sock_filter> {
+        let load = libc::BPF_LD | libc::BPF_W | libc::BPF_ABS;
+        let ret = libc::BPF_RET | libc::BPF_K;
+        let eperm = libc::SECCOMP_RET_ERRNO | libc::EPERM as u32;
+        // offsets in seccomp_data
+        let (nr, arch) = (0, 4);
+        let mut filter = vec![
+            statement(load, arch),
+            jump(libc::BPF_JEQ, AUDIT_ARCH, 1, 0),
+            statement(ret, libc::SECCOMP_RET_KILL_PROCESS),
+            statement(load, nr),
+        ];
+        // x32 syscalls have a different number, and they are not needed
+        #[cfg(target_arch = "x86_64")]
+        filter.extend([
+            jump(libc::BPF_JGE, 0x4000_0000, 0, 1),
+            statement(ret, eperm),
+        ]);
+        for syscall in DENIED {
+            filter.push(jump(libc::BPF_JEQ, syscall as u32, 0, 1));
+            filter.push(statement(ret, eperm));
+        }
+        filter.push(statement(ret, libc::SECCOMP_RET_ALLOW));
+        filter
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use std::{path::Path, process::Output, time::Instant};
+
+    use super::{Runner, SandboxLimits, TestLimits};
+
+    /// runs a shell script in the sandbox
+    async fn sh(script: &str, limits: TestLimits) -> Output {
+        let mut run = Runner::Sandboxed(SandboxLimits::default())
+            .command(Path::new("/bin/sh"), &limits)
+            .unwrap();
+        run.command.arg("-c").arg(script).output().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_filesystem() {
+        let out = sh(
+            "echo ok > /tmp/file && cat /tmp/file",
+            TestLimits::default(),
+        )
+        .await;
+        assert!(out.status.success(), "{:?}", out);
+        assert_eq!(out.stdout, b"ok\n");
+        // read-only system, and nothing else of the host
+        for script in [
+            "echo x > /usr/file",
+            "echo x > /file",
+            "cat /etc/passwd",
+            "ls /root",
+        ] {
+            assert!(!sh(script, TestLimits::default()).await.status.success());
+        }
+        // the environment of the server is not inherited
+        let out = sh("env", TestLimits::default()).await;
+        let env = String::from_utf8(out.stdout).unwrap();
+        for (name, _) in std::env::vars() {
+            if ["PATH", "HOME", "TMPDIR", "PWD"].contains(&name.as_str()) {
+                continue;
+            }
+            let inherited = env.lines().any(|x| x.starts_with(&format!("{}=", name)));
+            assert!(!inherited, "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cpu_limit() {
+        let limits = TestLimits {
+            cpu_ms: Some(1000),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let out = sh("while :; do :; done", limits).await;
+        assert!(!out.status.success());
+        assert!(start.elapsed().as_secs() < 10);
+    }
+}
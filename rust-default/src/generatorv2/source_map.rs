@@ -0,0 +1,555 @@
+//! Compile errors shown on the code written by the student.
+//!
+//! rustc reports the errors on the generated file: it's formatted again, and it has the test and
+//! the items of the template. The tokens of the items of the student are matched with the ones of
+//! the submission (the generator keeps them in the same order), so the positions of the errors
+//! can be moved back. Errors in the code of the template are shown without the code, as internal.
+//! The same is done with the location of the panics of the tests.
+use std::collections::HashSet;
+
+use orchestrator::prelude::{CompilerDiagnostic, DiagnosticSpan, PanicReport, Suggestion};
+use proc_macro2::{LineColumn, TokenStream, TokenTree};
+use quote::ToTokens;
+use serde_json::Value;
+use syn::{
+    parse_file, punctuated::Punctuated, spanned::Spanned, File, ImplItem, Item, PathSegment, Token,
+    Type,
+};
+
+use super::{harness::file_span, isolation::parse_lenient, parser::ImplementationPath};
+
+/// name of the file of the student in the errors
+pub const SUBMISSION: &str = "submission.rs";
+
+/// how many tokens can be skipped to match the two files again
+const LOOKAHEAD: usize = 16;
+
+/// a token: its text, where it starts and where it ends
+type Leaf = (String, LineColumn, LineColumn);
+
+/// Position in the submission of the tokens of a generated file
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    /// start and end in the generated file, start in the submission, ordered
+    tokens: Vec<(LineColumn, LineColumn, LineColumn)>,
+}
+
+impl SourceMap {
+    /// map of a generated file, template are the items it overwrites (as in TestDefinition)
+    pub fn new(generated: &str, submission: &str, template: &HashSet<String>) -> Option<Self> {
+        Some(Self::from_items(
+            &parse_file(generated).ok()?.items,
+            &parse_lenient(submission).ok()?,
+            template,
+        ))
+    }
+
+    /// map of the generated items, like new
+    pub fn from_items(generated: &[Item], submission: &File, template: &HashSet<String>) -> Self {
+        let generated = student_tokens(generated, template);
+        let submission = student_tokens(&submission.items, template);
+        let mut tokens = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        while i < generated.len() && j < submission.len() {
+            if generated[i].0 == submission[j].0 {
+                tokens.push((generated[i].1, generated[i].2, submission[j].1));
+                i += 1;
+                j += 1;
+                continue;
+            }
+            // the formatter can add or remove some tokens (like commas)
+            let skip = (1..=LOOKAHEAD).find_map(|d| {
+                if generated.get(i + d).is_some_and(|x| x.0 == submission[j].0) {
+                    Some((d, 0))
+                } else if submission.get(j + d).is_some_and(|x| x.0 == generated[i].0) {
+                    Some((0, d))
+                } else {
+                    None
+                }
+            });
+            let (a, b) = skip.unwrap_or((1, 1));
+            i += a;
+            j += b;
+        }
+        tokens.sort_by_key(|x| (x.0.line, x.0.column));
+        Self { tokens }
+    }
+
+    /// position in the submission, None if it's not in the code of the student
+    pub fn original(&self, position: LineColumn) -> Option<LineColumn> {
+        let index = self
+            .tokens
+            .partition_point(|x| (x.0.line, x.0.column) <= (position.line, position.column));
+        let (start, end, original) = self.tokens.get(index.checked_sub(1)?)?;
+        let inside = start.line == end.line
+            && position.line == start.line
+            && position.column < end.column.max(start.column + 1);
+        inside.then(|| LineColumn {
+            line: original.line,
+            column: original.column + position.column - start.column,
+        })
+    }
+}
+
+/// the panic on the submission, the map is the one of the file where it happened: a panic in the
+/// code of the student is moved on it, the others are internal
+pub fn locate_panic(panic: &mut PanicReport, map: Option<&SourceMap>) {
+    // the column of the panic starts from 1
+    let position = LineColumn {
+        line: panic.line as usize,
+        column: (panic.column as usize).saturating_sub(1),
+    };
+    match map.and_then(|x| x.original(position)) {
+        Some(original) => {
+            panic.file = SUBMISSION.to_string();
+            panic.line = original.line as u32;
+            panic.column = original.column as u32 + 1;
+        }
+        None => {
+            panic.internal = true;
+            panic.file.clear();
+            panic.line = 0;
+            panic.column = 0;
+        }
+    }
+}
+
+/// the tokens of the items, without the ones of the template:
+/// the overwritten items and the test (the main function, when it's in template)
+fn student_tokens(items: &[Item], template: &HashSet<String>) -> Vec<Leaf> {
+    let mut excluded = Vec::new();
+    template_items(items, template, &mut Punctuated::new(), &mut excluded);
+    let mut ret = Vec::new();
+    for item in items {
+        leaves(item.to_token_stream(), &mut ret);
+    }
+    ret.retain(|(_, start, _)| {
+        !excluded.iter().any(|(a, b): &(LineColumn, LineColumn)| {
+            (a.line, a.column) <= (start.line, start.column)
+                && (start.line, start.column) < (b.line, b.column)
+        })
+    });
+    ret
+}
+
+fn template_items(
+    items: &[Item],
+    template: &HashSet<String>,
+    mod_path: &mut Punctuated<PathSegment, Token![::]>,
+    excluded: &mut Vec<(LineColumn, LineColumn)>,
+) {
+    let is_template =
+        |key: ImplementationPath| template.contains(&key.to_token_stream().to_string());
+    for item in items {
+        let span = item.span();
+        match item {
+            Item::Fn(function) if is_template(ImplementationPath::from_fn(function, mod_path)) => {
+                excluded.push((span.start(), span.end()))
+            }
+            Item::Impl(implementation)
+                if implementation.trait_.is_some()
+                    && is_template(ImplementationPath::from_impl(implementation, mod_path)) =>
+            {
+                excluded.push((span.start(), span.end()))
+            }
+            Item::Impl(implementation) => {
+                for method in &implementation.items {
+                    let ImplItem::Fn(function) = method else {
+                        continue;
+                    };
+                    let mut key = ImplementationPath::from_impl(implementation, mod_path);
+                    if let Type::Path(p) = &mut key.type_ {
+                        p.path.segments.push(function.sig.ident.clone().into());
+                    }
+                    if is_template(key) {
+                        let span = method.span();
+                        excluded.push((span.start(), span.end()));
+                    }
+                }
+            }
+            Item::Mod(module) => {
+                if let Some((_, items)) = &module.content {
+                    mod_path.push(module.ident.clone().into());
+                    template_items(items, template, mod_path, excluded);
+                    mod_path.pop();
+                    mod_path.pop_punct();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// all the tokens, with the delimiters of the groups
+fn leaves(tokens: TokenStream, ret: &mut Vec<Leaf>) {
+    for tree in tokens {
+        match tree {
+            TokenTree::Group(group) => {
+                let open = group.span_open();
+                let close = group.span_close();
+                let delimiters = match group.delimiter() {
+                    proc_macro2::Delimiter::Parenthesis => ("(", ")"),
+                    proc_macro2::Delimiter::Brace => ("{", "}"),
+                    proc_macro2::Delimiter::Bracket => ("[", "]"),
+                    proc_macro2::Delimiter::None => ("", ""),
+                };
+                ret.push((delimiters.0.to_string(), open.start(), open.end()));
+                leaves(group.stream(), ret);
+                ret.push((delimiters.1.to_string(), close.start(), close.end()));
+            }
+            tree => {
+                let span = tree.span();
+                ret.push((tree.to_string(), span.start(), span.end()));
+            }
+        }
+    }
+}
+
+/// the message of rustc is shown to the student: errors and warnings, but not the summaries
+/// (like "aborting due to 2 previous errors") and the code never used: each test uses only
+/// a part of the submission
+pub(crate) fn is_shown(message: &Value) -> bool {
+    let text = message["message"].as_str().unwrap_or_default();
+    let summary = message["spans"].as_array().is_none_or(Vec::is_empty)
+        && (text.starts_with("aborting due to") || text.ends_with("emitted"));
+    match message["level"].as_str() {
+        Some("error") => !summary,
+        Some("warning") => !summary && message["code"]["code"] != "dead_code",
+        _ => false,
+    }
+}
+
+/// the part of the generated file in the span of rustc, moved on the submission.
+/// None if it's not in the file, or if it's not in the code of the student
+fn map_span(span: &Value, file: &str, map: &SourceMap) -> Option<DiagnosticSpan> {
+    let position = file_span(span, file)?;
+    let number = |name: &str| position[name].as_u64().map(|x| x as usize);
+    let (line, column) = (number("line_start")?, number("column_start")?);
+    let (line_end, column_end) = (number("line_end")?, number("column_end")?);
+    let start = map.original(LineColumn {
+        line,
+        column: column.saturating_sub(1),
+    })?;
+    // the last character, the end is excluded
+    let last = LineColumn {
+        line: line_end,
+        column: column_end.saturating_sub(2),
+    };
+    let length = if line == line_end {
+        column_end.saturating_sub(column).max(1)
+    } else {
+        1
+    };
+    let last = map
+        .original(last)
+        .filter(|x| (x.line, x.column) >= (start.line, start.column))
+        .unwrap_or(LineColumn {
+            line: start.line,
+            column: start.column + length - 1,
+        });
+    Some(DiagnosticSpan {
+        line_start: start.line,
+        column_start: start.column + 1,
+        line_end: last.line,
+        column_end: last.column + 2,
+        primary: span["is_primary"] == true,
+        label: span["label"].as_str().map(str::to_string),
+    })
+}
+
+/// the message of rustc (a message of cargo) on the submission, if there is a map to it.
+///
+/// file is the end of the name of the generated file. If the error is not in the code of the
+/// student it is internal: its code is not shown
+pub fn diagnostic(
+    message: &Value,
+    file: &str,
+    source: Option<(&SourceMap, &str)>,
+) -> CompilerDiagnostic {
+    let text = |value: &Value| value.as_str().unwrap_or_default().to_string();
+    let mut diagnostic = CompilerDiagnostic {
+        level: text(&message["level"]),
+        code: message["code"]["code"].as_str().map(str::to_string),
+        message: text(&message["message"]),
+        rendered: text(&message["rendered"]),
+        ..Default::default()
+    };
+    let Some((map, submission)) = source else {
+        return diagnostic;
+    };
+    let spans = message["spans"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or(&[]);
+    let in_file = spans
+        .iter()
+        .any(|x| x["is_primary"] == true && file_span(x, file).is_some());
+    diagnostic.spans = spans
+        .iter()
+        .filter_map(|x| map_span(x, file, map))
+        .collect();
+    diagnostic.spans.sort_by_key(|x| !x.primary);
+    diagnostic.internal = in_file && !diagnostic.spans.first().is_some_and(|x| x.primary);
+    if diagnostic.internal {
+        diagnostic.spans.clear();
+    }
+    for child in message["children"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or(&[])
+    {
+        let note = format!("{}: {}", text(&child["level"]), text(&child["message"]));
+        let suggestions: Vec<Suggestion> = child["spans"]
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or(&[])
+            .iter()
+            .filter_map(|x| {
+                Some(Suggestion {
+                    message: text(&child["message"]),
+                    replacement: x["suggested_replacement"].as_str()?.to_string(),
+                    span: map_span(x, file, map)?,
+                })
+            })
+            .collect();
+        if suggestions.is_empty() {
+            diagnostic.notes.push(note);
+        } else if !diagnostic.internal {
+            diagnostic.suggestions.extend(suggestions);
+        }
+    }
+    diagnostic.rendered = render(&diagnostic, submission);
+    diagnostic
+}
+
+/// the diagnostic as rustc would show it, on the submission
+fn render(diagnostic: &CompilerDiagnostic, submission: &str) -> String {
+    let mut ret = diagnostic.level.clone();
+    if let Some(code) = &diagnostic.code {
+        ret += &format!("[{}]", code);
+    }
+    ret += &format!(": {}\n", diagnostic.message);
+    let lines: Vec<&str> = submission.lines().collect();
+    if let Some(first) = diagnostic.spans.first() {
+        let width = diagnostic
+            .spans
+            .iter()
+            .map(|x| x.line_start.to_string().len())
+            .max()
+            .unwrap_or(1);
+        let gutter = " ".repeat(width);
+        ret += &format!(
+            "{}--> {}:{}:{}\n",
+            gutter, SUBMISSION, first.line_start, first.column_start
+        );
+        for span in &diagnostic.spans {
+            let text = lines.get(span.line_start - 1).copied().unwrap_or_default();
+            // only the first line is underlined
+            let end = if span.line_end == span.line_start {
+                span.column_end
+            } else {
+                text.chars().count() + 1
+            };
+            let length = end.saturating_sub(span.column_start).max(1);
+            let marker = if span.primary { "^" } else { "-" };
+            ret += &format!("{} |\n", gutter);
+            ret += &format!("{:>width$} | {}\n", span.line_start, text, width = width);
+            ret += &format!(
+                "{} | {}{} {}\n",
+                gutter,
+                " ".repeat(span.column_start - 1),
+                marker.repeat(length),
+                span.label.as_deref().unwrap_or_default()
+            );
+        }
+    } else if diagnostic.internal {
+        ret += " = note: internal error, in the code of the exercise: \
+               it can be caused by the items of the submission it uses\n";
+    }
+    for note in &diagnostic.notes {
+        ret += &format!(" = {}\n", note);
+    }
+    for suggestion in &diagnostic.suggestions {
+        ret += &format!(
+            " = help: {} (`{}` at {}:{})\n",
+            suggestion.message,
+            suggestion.replacement,
+            suggestion.span.line_start,
+            suggestion.span.column_start
+        );
+    }
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use orchestrator::prelude::{CompilationResult, DiagnosticSpan, RunResult, TestResult};
+    use proc_macro2::LineColumn;
+
+    use super::SourceMap;
+    use crate::generatorv2::{
+        compiled::{CompileConfig, RustCompiled},
+        file_generator::GeneratedFiles,
+        parser::RustExercise,
+        run::RunConfig,
+    };
+
+    #[test]
+    fn test_source_map() {
+        let submission = "fn a() -> u8 {\n\n      2 }\nfn b() {}";
+        let generated = "fn a() -> u8 {\n    2\n}\nfn b() {}\nfn main() {\n    a();\n}\n";
+        let map = SourceMap::new(
+            generated,
+            submission,
+            &HashSet::from(["impl b".to_string(), "impl main".to_string()]),
+        )
+        .unwrap();
+        let at = |line, column| LineColumn { line, column };
+        assert_eq!(map.original(at(2, 4)), Some(at(3, 6)));
+        assert_eq!(map.original(at(1, 3)), Some(at(1, 3)));
+        // the overwritten item and the test are not of the student
+        assert_eq!(map.original(at(4, 3)), None);
+        assert_eq!(map.original(at(6, 4)), None);
+    }
+
+    async fn compile(student: &str, config: CompileConfig) -> TestResult {
+        let template = "
+            #[runtest(1.0)]
+            #[overwrite(impl helper)]
+            fn test_good() { assert_eq!(good(), 1); }
+            fn helper() -> u8 { 40 + 2 }
+            fn good() -> u8 { 1 }
+        ";
+        let exercise = RustExercise::parse(template).unwrap();
+        let generated = GeneratedFiles::generate(exercise, student.to_string()).unwrap();
+        let mut compiled = RustCompiled::compile(generated, config).await.unwrap();
+        compiled.results.remove("test_good").unwrap()
+    }
+
+    async fn compile_error(student: &str) -> String {
+        let result = compile(student, CompileConfig::default()).await;
+        let CompilationResult::Error(error, _) = result.compiled else {
+            panic!("it should not compile");
+        };
+        error
+    }
+
+    #[tokio::test]
+    async fn test_render() {
+        let student = "fn helper() -> u8 {\n    1\n}\n\nfn good() -> u8 {\n        let x: u8 = \"one\";\n    x\n}\n";
+        let error = compile_error(student).await;
+        assert!(error.contains("--> submission.rs:6:21"), "{}", error);
+        assert!(
+            error.contains("6 |         let x: u8 = \"one\";"),
+            "{}",
+            error
+        );
+        assert!(!error.contains("internal"), "{}", error);
+
+        // the test can't use the function of the student
+        let error = compile_error("fn good() -> String { String::new() }").await;
+        assert!(error.contains("internal error"), "{}", error);
+        assert!(
+            !error.contains("assert_eq") && !error.contains("40 + 2"),
+            "{}",
+            error
+        );
+    }
+    #[tokio::test]
+    async fn test_diagnostics() {
+        let student = "fn good() -> u8 {\n    let unused = 2;\n    1\n}\n";
+        for single_binary in [false, true] {
+            let config = CompileConfig {
+                single_binary,
+                ..Default::default()
+            };
+            let result = compile(student, config).await;
+            let CompilationResult::Built(warnings) = result.compiled else {
+                panic!("{:?}", result.compiled);
+            };
+            let [warning] = warnings.as_slice() else {
+                panic!("{:?}", warnings);
+            };
+            assert_eq!(warning.level, "warning");
+            assert_eq!(warning.code.as_deref(), Some("unused_variables"));
+            assert!(!warning.internal);
+            assert_eq!(
+                warning.spans[0],
+                DiagnosticSpan {
+                    line_start: 2,
+                    column_start: 9,
+                    line_end: 2,
+                    column_end: 15,
+                    primary: true,
+                    label: None,
+                }
+            );
+            assert_eq!(warning.suggestions[0].replacement, "_unused");
+            assert!(warning.rendered.contains("--> submission.rs:2:9"));
+        }
+
+        let student = "fn good() -> u8 {\n    let x: u8 = \"one\";\n    x\n}\n";
+        let result = compile(student, CompileConfig::default()).await;
+        let CompilationResult::Error(rendered, diagnostics) = &result.compiled else {
+            panic!("{:?}", result.compiled);
+        };
+        // the errors are rendered as a fallback
+        let error = &diagnostics[0];
+        assert_eq!(rendered, &error.rendered);
+        assert_eq!(error.level, "error");
+        assert_eq!(error.code.as_deref(), Some("E0308"));
+        assert_eq!(error.spans[0].line_start, 2);
+        assert!(error.spans[0].primary);
+    }
+
+    #[tokio::test]
+    async fn test_panic() {
+        let template = "
+            #[runtest(1.0)]
+            fn test_student() { bad(); }
+            #[runtest(1.0)]
+            fn test_template() { assert_eq!(good(), 2, \"two\"); }
+            fn good() -> u8 { 1 }
+            fn bad() {}
+        ";
+        let student = "fn good() -> u8 {\n    1\n}\n\nfn bad() {\n        panic!(\"boom\")\n}\n";
+        for single_binary in [false, true] {
+            let config = CompileConfig {
+                single_binary,
+                ..Default::default()
+            };
+            let exercise = RustExercise::parse(template).unwrap();
+            let generated = GeneratedFiles::generate(exercise, student.to_string()).unwrap();
+            let compiled = RustCompiled::compile(generated, config).await.unwrap();
+            let mut results = compiled.run(RunConfig::default()).await.unwrap().tests;
+
+            let result = results.remove("test_student").unwrap();
+            let panic = result.diagnostics.unwrap().panic.unwrap();
+            assert_eq!(
+                (
+                    panic.file.as_str(),
+                    panic.line,
+                    panic.column,
+                    panic.internal
+                ),
+                ("submission.rs", 6, 9, false)
+            );
+            assert_eq!(
+                result.runned,
+                RunResult::Error("panicked at submission.rs:6:9: boom".into())
+            );
+
+            // the assertion is in the test
+            let result = results.remove("test_template").unwrap();
+            let panic = result.diagnostics.unwrap().panic.unwrap();
+            assert!(panic.internal);
+            assert!(panic
+                .message
+                .starts_with("assertion `left == right` failed: two"));
+            assert!(!panic.to_string().contains("src/bin"), "{}", panic);
+            assert_eq!(result.runned, RunResult::Error(panic.to_string()));
+        }
+    }
+}
@@ -1,9 +1,12 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use quote::ToTokens;
-use syn::{parse_str, Item, ItemFn};
+use quote::{format_ident, ToTokens};
+use syn::{parse_quote, File, Item, ItemFn};
 
-use super::{error::RustError, parser::ImplementationPath};
+use super::{
+    difftest::DiffTest, error::RustError, iotest::IoTest, isolation::identifiers,
+    parser::ImplementationPath, run::TestLimits,
+};
 
 #[derive(Clone)]
 pub struct TestDefinition {
@@ -11,54 +14,91 @@ pub struct TestDefinition {
     pub(crate) test: ItemFn,
     pub(crate) description: String,
     pub(crate) points: f32,
+    pub(crate) limits: TestLimits,
+    /// it runs the main of the student instead of the test, see iotest
+    pub(crate) io: Option<IoTest>,
 }
 
-#[derive(Clone, Debug)]
+/// An item of the template, already written as it is in the file of a test
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct TemplateItem {
+    /// the modules that contain it
+    pub(crate) module: Vec<String>,
+    /// the source, indented as it is inside those modules
+    pub(crate) source: String,
+}
+
+impl TemplateItem {
+    fn new(module: Vec<String>, item: Item) -> Self {
+        // written inside its modules, so that it is formatted like in the whole file
+        let wrapped = module.iter().rev().fold(item, |item, name| {
+            let name = format_ident!("{}", name);
+            parse_quote!(mod #name { #item })
+        });
+        let file = prettyplease::unparse(&File {
+            shebang: None,
+            attrs: Vec::new(),
+            items: vec![wrapped],
+        });
+        let lines: Vec<&str> = file.lines().collect();
+        let depth = module.len();
+        let source = lines[depth..lines.len() - depth]
+            .iter()
+            .map(|x| format!("{}\n", x))
+            .collect();
+        Self { module, source }
+    }
+}
+
+/// syn items can't be shared between threads, so the definition is kept already written:
+/// the files of the tests are generated parsing only the code of the student (see file_generator)
+#[derive(Clone, Debug, PartialEq)]
 pub struct SendableTestDefinition {
     pub(crate) name: String,
-    pub(crate) to_overwrite: HashMap<String, String>,
+    /// the items of the template, by key
+    pub(crate) to_overwrite: HashMap<String, TemplateItem>,
+    /// the test, as the main of its file
     pub(crate) test: String,
+    /// identifiers used by the test and by the items it overwrites, see isolation
+    pub(crate) uses: HashSet<String>,
     pub(crate) description: String,
     pub(crate) points: f32,
+    pub(crate) limits: TestLimits,
+    pub(crate) io: Option<IoTest>,
 }
 
 impl From<TestDefinition> for SendableTestDefinition {
     fn from(value: TestDefinition) -> Self {
         let name = value.test.sig.ident.to_string();
+        let mut uses = identifiers(value.test.to_token_stream());
+        for item in value.to_overwrite.values() {
+            uses.extend(identifiers(item.to_token_stream()));
+        }
+        let to_overwrite = value
+            .to_overwrite
+            .into_iter()
+            .map(|(key, item)| {
+                let module = key.path.iter().map(|x| x.ident.to_string()).collect();
+                (
+                    key.to_token_stream().to_string(),
+                    TemplateItem::new(module, item),
+                )
+            })
+            .collect();
+        let mut test = value.test;
+        test.sig.ident = format_ident!("main");
         Self {
             name,
-            to_overwrite: value
-                .to_overwrite
-                .into_iter()
-                .map(|(a, b)| {
-                    (
-                        a.to_token_stream().to_string(),
-                        b.to_token_stream().to_string(),
-                    )
-                })
-                .collect(),
-            test: value.test.to_token_stream().to_string(),
+            to_overwrite,
+            test: TemplateItem::new(Vec::new(), test.into()).source,
+            uses,
             description: value.description,
             points: value.points,
+            limits: value.limits,
+            io: value.io,
         }
     }
 }
-impl TryFrom<SendableTestDefinition> for TestDefinition {
-    fn try_from(value: SendableTestDefinition) -> Result<Self, RustError> {
-        Ok(Self {
-            to_overwrite: value
-                .to_overwrite
-                .into_iter()
-                .map(|(a, b)| Ok((parse_str(&a)?, parse_str(&b)?)))
-                .collect::<Result<HashMap<ImplementationPath, Item>, RustError>>()?,
-            test: parse_str::<ItemFn>(&value.test)?,
-            description: value.description,
-            points: value.points,
-        })
-    }
-
-    type Error = RustError;
-}
 
 #[derive(Debug, Clone)]
 pub struct UnfinishedTestDefinition {
@@ -66,14 +106,18 @@ pub struct UnfinishedTestDefinition {
     pub(crate) test: ItemFn,
     pub(crate) description: String,
     pub(crate) points: f32,
+    pub(crate) limits: TestLimits,
+    pub(crate) io: Option<IoTest>,
+    /// the body of the test is generated when it is finished
+    pub(crate) diff: Option<DiffTest>,
 }
 impl UnfinishedTestDefinition {
     pub fn finish(
-        self,
+        mut self,
         default_impl: &HashMap<ImplementationPath, Item>,
     ) -> Result<TestDefinition, RustError> {
         let all = default_impl.keys().map(|x| x.to_token_stream().to_string()).fold(String::new(), |a, b| a+"\n"+&b);
-        let to_overwrite = self
+        let mut to_overwrite: HashMap<_, _> = self
             .to_overwrite
             .into_iter()
             .map(|o| {
@@ -83,11 +127,47 @@ impl UnfinishedTestDefinition {
                     .ok_or(RustError::MatchNotFound(format!("Not found: {}\n but instead found:\n {}", o.to_token_stream(), all)))
             })
             .collect::<Result<_, RustError>>()?;
+        if let Some(diff) = &self.diff {
+            to_overwrite.extend(diff.generate(&mut self.test, default_impl)?);
+        }
         Ok(TestDefinition {
             to_overwrite,
             test: self.test,
             description: self.description,
             points: self.points,
+            limits: self.limits,
+            io: self.io,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::RustExercise2;
+
+    #[test]
+    fn check_sendable() {
+        let q = "
+            #[runtest(1.0)]
+            #[overwrite(impl inner in outer)]
+            fn test_1() { let a = outer::inner(); }
+            mod outer {
+                pub fn inner() -> u8 { 1 }
+            }
+        ";
+        let exercise = RustExercise2::parse(q).unwrap();
+        let sendable = &exercise.tests[0];
+        assert_eq!(sendable.name, "test_1");
+        assert_eq!(
+            sendable.test,
+            "fn main() {\n    let a = outer::inner();\n}\n"
+        );
+        let item = &sendable.to_overwrite["impl inner in outer"];
+        assert_eq!(item.module, ["outer"]);
+        assert_eq!(
+            item.source,
+            "    pub fn inner() -> u8 {\n        1\n    }\n"
+        );
+        assert!(sendable.uses.contains("inner"));
+    }
+}
@@ -7,6 +7,8 @@ pub mod embed;
 /// How to generate a rust exercise? how to compile it? ALl of this is present inside this module
 pub(crate) mod generator;
 pub(crate) mod generatorv2;
+/// Module that finds similar submissions of the same exercise
+pub mod plagiarism;
 /// Module where all the plugins gets defined
 pub(crate) mod plugins;
 #[cfg(test)]
@@ -0,0 +1,615 @@
+//! Plagiarism detection between submissions of the same exercise.
+//!
+//! Every submission is normalised with syn: comments and documentation are dropped, identifiers are renamed
+//! to a single placeholder (keywords and primitive types are kept) and literals lose their value.
+//! Working on tokens makes the comparison independent from the formatting.
+//!
+//! The normalised tokens are fingerprinted with winnowing (see "Winnowing: Local Algorithms for Document Fingerprinting"):
+//! all k-grams are hashed, and from every window of consecutive hashes only the minimum is kept.
+//! Two submissions are similar if they share many fingerprints; shared fingerprints are then mapped back to
+//! the lines of the sources, to show the matching regions.
+//!
+//! The k-grams of the template skeleton (what every student receives) are ignored.
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap, HashSet},
+    fmt::Display,
+    hash::{Hash, Hasher},
+    ops::RangeInclusive,
+    str::FromStr,
+};
+
+use orchestrator::{
+    executor::ExecutorGlobalState,
+    memory::{Submission, SubmissionFilter},
+    orchestrator::Orchestrator,
+};
+use proc_macro2::{Delimiter, TokenStream, TokenTree};
+use quote::ToTokens;
+use syn::{visit_mut::VisitMut, Block, Item, ItemFn};
+
+/// Identifiers that are kept during the normalisation: renaming them would hide the structure of the code
+const KEPT_IDENTS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "type",
+    "unsafe", "use", "where", "while", "bool", "char", "str", "u8", "u16", "u32", "u64", "u128",
+    "usize", "i8", "i16", "i32", "i64", "i128", "isize", "f32", "f64",
+];
+
+#[derive(thiserror::Error, Debug)]
+/// Errors generated during the analysis
+pub enum Error {
+    /// the source is not even valid rust tokens (for example unbalanced delimiters)
+    #[error("Lex Error {0}")]
+    Lex(#[from] proc_macro2::LexError),
+    /// the template is not a valid rust file
+    #[error("Syn Error {0}")]
+    Syn(#[from] syn::Error),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// A token after the normalisation, with the line where it was found
+pub struct NormalizedToken {
+    /// what remains of the token
+    pub kind: String,
+    /// line in the original source (starting from 1)
+    pub line: usize,
+}
+
+/// normalise a rust source into a sequence of tokens.
+///
+/// If the source is a valid rust file it is parsed with syn, otherwise (a submission that doesn't compile)
+/// it is only tokenized.
+pub fn normalize(source: &str) -> Result<Vec<NormalizedToken>, Error> {
+    let stream = match syn::parse_file(source) {
+        Ok(file) => file.to_token_stream(),
+        Err(_) => TokenStream::from_str(source)?,
+    };
+    let mut ret = Vec::new();
+    flatten(stream, &mut ret);
+    Ok(ret)
+}
+
+/// push all normalized tokens of the stream, skipping doc attributes
+fn flatten(stream: TokenStream, out: &mut Vec<NormalizedToken>) {
+    let mut iter = stream.into_iter().peekable();
+    while let Some(tree) = iter.next() {
+        let line = tree.span().start().line;
+        match tree {
+            TokenTree::Punct(p) if p.as_char() == '#' => {
+                // #[doc = "..."] and #![doc = "..."] are comments
+                let mut lookahead = iter.clone();
+                if matches!(lookahead.peek(), Some(TokenTree::Punct(x)) if x.as_char() == '!') {
+                    lookahead.next();
+                }
+                if let Some(TokenTree::Group(g)) = lookahead.peek() {
+                    let is_doc = g.delimiter() == Delimiter::Bracket
+                        && matches!(g.stream().into_iter().next(), Some(TokenTree::Ident(x)) if x == "doc");
+                    if is_doc {
+                        lookahead.next();
+                        iter = lookahead;
+                        continue;
+                    }
+                }
+                out.push(NormalizedToken {
+                    kind: "#".to_string(),
+                    line,
+                });
+            }
+            TokenTree::Punct(p) => out.push(NormalizedToken {
+                kind: p.as_char().to_string(),
+                line,
+            }),
+            TokenTree::Ident(i) => {
+                let i = i.to_string();
+                let kind = if KEPT_IDENTS.contains(&i.as_str()) {
+                    i
+                } else {
+                    "$id".to_string()
+                };
+                out.push(NormalizedToken { kind, line });
+            }
+            TokenTree::Literal(_) => out.push(NormalizedToken {
+                kind: "$lit".to_string(),
+                line,
+            }),
+            TokenTree::Group(g) => {
+                let (open, close) = match g.delimiter() {
+                    Delimiter::Parenthesis => ("(", ")"),
+                    Delimiter::Brace => ("{", "}"),
+                    Delimiter::Bracket => ("[", "]"),
+                    Delimiter::None => ("", ""),
+                };
+                if !open.is_empty() {
+                    out.push(NormalizedToken {
+                        kind: open.to_string(),
+                        line,
+                    });
+                }
+                flatten(g.stream(), out);
+                if !close.is_empty() {
+                    out.push(NormalizedToken {
+                        kind: close.to_string(),
+                        line: g.span_close().end().line,
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Removes what students write from a template: tests are dropped, and function bodies emptied
+struct SkeletonExtractor;
+impl VisitMut for SkeletonExtractor {
+    fn visit_file_mut(&mut self, i: &mut syn::File) {
+        i.items.retain(
+            |x| !matches!(x, Item::Fn(f) if f.attrs.iter().any(|a| a.path().is_ident("runtest"))),
+        );
+        syn::visit_mut::visit_file_mut(self, i);
+    }
+    fn visit_item_fn_mut(&mut self, i: &mut ItemFn) {
+        *i.block = Block {
+            brace_token: i.block.brace_token,
+            stmts: Vec::new(),
+        };
+    }
+    fn visit_block_mut(&mut self, i: &mut Block) {
+        i.stmts.clear();
+    }
+}
+
+/// extract the skeleton of a template: the code that every student receives
+pub fn skeleton(template: &str) -> Result<String, Error> {
+    let mut file = syn::parse_file(template)?;
+    SkeletonExtractor.visit_file_mut(&mut file);
+    Ok(prettyplease::unparse(&file))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Parameters of the analysis
+pub struct PlagiarismConfig {
+    /// length (in tokens) of the k-grams, shorter matches are not detected
+    pub k: usize,
+    /// winnowing window, matches longer than k + window - 1 tokens are always detected
+    pub window: usize,
+    /// pairs with a lower score are not reported
+    pub threshold: f64,
+}
+
+impl Default for PlagiarismConfig {
+    fn default() -> Self {
+        Self {
+            k: 12,
+            window: 6,
+            threshold: 0.5,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// fingerprints of a normalised source
+pub struct Fingerprints {
+    tokens: Vec<NormalizedToken>,
+    /// hash, positions (index of the first token of the k-gram)
+    hashes: HashMap<u64, Vec<usize>>,
+}
+
+impl Fingerprints {
+    /// number of distinct fingerprints
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+    /// does the source have no fingerprints (too short, or only skeleton)?
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+    /// lines covered by the tokens in the range
+    fn lines(&self, tokens: RangeInclusive<usize>) -> RangeInclusive<usize> {
+        let lines = self.tokens[tokens].iter().map(|x| x.line);
+        let start = lines.clone().min().unwrap_or(0);
+        let end = lines.max().unwrap_or(0);
+        start..=end
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A region of code found in both submissions, as line ranges
+pub struct MatchedRegion {
+    /// lines in the first submission
+    pub first: RangeInclusive<usize>,
+    /// lines in the second submission
+    pub second: RangeInclusive<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Two submissions (of different users) that look too similar
+pub struct SimilarPair {
+    /// submission id of the first
+    pub first: i64,
+    /// submission id of the second
+    pub second: i64,
+    /// shared fingerprints over the fingerprints of the smaller submission, from 0 to 1
+    pub score: f64,
+    /// where the submissions match
+    pub regions: Vec<MatchedRegion>,
+}
+
+#[derive(Debug, Clone, Default)]
+/// Outcome of the analysis of an exercise
+pub struct PlagiarismReport {
+    /// similar pairs, the most similar first
+    pub pairs: Vec<SimilarPair>,
+    /// submissions that could not be analysed
+    pub skipped: Vec<i64>,
+}
+
+impl Display for PlagiarismReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.pairs.is_empty() {
+            writeln!(f, "no similar submissions found")?;
+        }
+        for pair in &self.pairs {
+            writeln!(
+                f,
+                "submissions {} and {}: {:.0}% similar",
+                pair.first,
+                pair.second,
+                pair.score * 100.0
+            )?;
+            for region in &pair.regions {
+                writeln!(
+                    f,
+                    "\tlines {}-{} match lines {}-{}",
+                    region.first.start(),
+                    region.first.end(),
+                    region.second.start(),
+                    region.second.end()
+                )?;
+            }
+        }
+        if !self.skipped.is_empty() {
+            writeln!(f, "not analysed (invalid rust): {:?}", self.skipped)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// Compares the submissions of an exercise
+pub struct PlagiarismDetector {
+    config: PlagiarismConfig,
+    /// k-grams of the skeleton
+    ignored: HashSet<u64>,
+}
+
+impl PlagiarismDetector {
+    /// detector with the given parameters, a k of 0 is taken as 1
+    pub fn new(mut config: PlagiarismConfig) -> Self {
+        config.k = config.k.max(1);
+        Self {
+            config,
+            ignored: HashSet::new(),
+        }
+    }
+    /// ignore the code in the skeleton of this template
+    pub fn set_template(mut self, template: &str) -> Result<Self, Error> {
+        let tokens = normalize(&skeleton(template)?)?;
+        self.ignored.extend(self.kgrams(&tokens));
+        Ok(self)
+    }
+
+    /// hash of every k-gram
+    fn kgrams(&self, tokens: &[NormalizedToken]) -> Vec<u64> {
+        tokens
+            .windows(self.config.k)
+            .map(|x| {
+                let mut hasher = DefaultHasher::new();
+                for t in x {
+                    t.kind.hash(&mut hasher);
+                }
+                hasher.finish()
+            })
+            .collect()
+    }
+
+    /// normalise and fingerprint a source
+    pub fn fingerprint(&self, source: &str) -> Result<Fingerprints, Error> {
+        let tokens = normalize(source)?;
+        let kgrams = self.kgrams(&tokens);
+        let mut hashes: HashMap<u64, Vec<usize>> = HashMap::new();
+        let mut last = None;
+        for (start, window) in kgrams.windows(self.config.window.max(1)).enumerate() {
+            // rightmost minimum
+            let (offset, hash) = window
+                .iter()
+                .enumerate()
+                .rev()
+                .min_by_key(|(_, x)| **x)
+                .expect("windows are never empty");
+            let position = start + offset;
+            if last == Some(position) {
+                continue;
+            }
+            last = Some(position);
+            if !self.ignored.contains(hash) {
+                hashes.entry(*hash).or_default().push(position);
+            }
+        }
+        Ok(Fingerprints { tokens, hashes })
+    }
+
+    /// similarity score and matching regions of two fingerprinted sources
+    pub fn compare(&self, a: &Fingerprints, b: &Fingerprints) -> (f64, Vec<MatchedRegion>) {
+        let smaller = a.len().min(b.len());
+        if smaller == 0 {
+            return (0.0, Vec::new());
+        }
+        // first occurrence in b of every k-gram of a (ordered by position in a)
+        let mut shared = 0;
+        let mut pairs = BTreeMap::new();
+        for (hash, positions) in &a.hashes {
+            if let Some(other) = b.hashes.get(hash) {
+                shared += 1;
+                for p in positions {
+                    pairs.insert(*p, other[0]);
+                }
+            }
+        }
+        let score = shared as f64 / smaller as f64;
+
+        // merge near k-grams into regions
+        let gap = self.config.k + self.config.window;
+        let mut regions: Vec<(RangeInclusive<usize>, RangeInclusive<usize>)> = Vec::new();
+        for (pa, pb) in pairs {
+            let ra = pa..=pa + self.config.k - 1;
+            let rb = pb..=pb + self.config.k - 1;
+            match regions.last_mut() {
+                Some((la, lb))
+                    if pa <= la.end() + gap && pb >= *lb.start() && pb <= lb.end() + gap =>
+                {
+                    *la = *la.start()..=*ra.end().max(la.end());
+                    *lb = *lb.start()..=*rb.end().max(lb.end());
+                }
+                _ => regions.push((ra, rb)),
+            }
+        }
+        let regions = regions
+            .into_iter()
+            .map(|(ra, rb)| MatchedRegion {
+                first: a.lines(ra),
+                second: b.lines(rb),
+            })
+            .collect();
+        (score, regions)
+    }
+
+    /// compare all submissions of an exercise.
+    ///
+    /// Only the last submission of every user is considered, and the submissions of the same user are never compared
+    pub fn check(&self, submissions: &[Submission]) -> PlagiarismReport {
+        let mut last: HashMap<i64, &Submission> = HashMap::new();
+        for s in submissions {
+            let entry = last.entry(s.user_id).or_insert(s);
+            if entry.submission_id < s.submission_id {
+                *entry = s;
+            }
+        }
+        let mut last: Vec<&Submission> = last.into_values().collect();
+        last.sort_by_key(|x| x.submission_id);
+
+        let mut report = PlagiarismReport::default();
+        let mut fingerprinted = Vec::new();
+        for s in last {
+            match self.fingerprint(&s.source) {
+                Ok(f) => fingerprinted.push((s.submission_id, f)),
+                Err(_) => report.skipped.push(s.submission_id),
+            }
+        }
+        for (i, (first, a)) in fingerprinted.iter().enumerate() {
+            for (second, b) in &fingerprinted[i + 1..] {
+                let (score, regions) = self.compare(a, b);
+                if score >= self.config.threshold && score > 0.0 {
+                    report.pairs.push(SimilarPair {
+                        first: *first,
+                        second: *second,
+                        score,
+                        regions,
+                    });
+                }
+            }
+        }
+        report.pairs.sort_by(|a, b| b.score.total_cmp(&a.score));
+        report
+    }
+}
+
+/// analyse all the stored submissions of an exercise, ignoring the skeleton of its template
+pub async fn check_exercise<S: ExecutorGlobalState>(
+    o: &Orchestrator<S>,
+    exercise_name: &str,
+    config: PlagiarismConfig,
+) -> Result<PlagiarismReport, Box<dyn std::error::Error>> {
+    let (_, template) = o.memory().get_exercise(exercise_name.to_string()).await?;
+    let detector = PlagiarismDetector::new(config).set_template(&template)?;
+    let filter = SubmissionFilter {
+        exercise_name: Some(exercise_name.to_string()),
+        ..Default::default()
+    };
+    let submissions = o.memory().list_submissions(&filter).await?;
+    Ok(detector.check(&submissions))
+}
+
+#[cfg(test)]
+mod test {
+    use orchestrator::memory::Submission;
+
+    use super::{normalize, PlagiarismConfig, PlagiarismDetector};
+
+    const ORIGINAL: &str = r#"
+fn bigger(x: i32, y: i32) -> i32 {
+    // the bigger one
+    if x > y {
+        x
+    } else {
+        y
+    }
+}
+fn sum_all(v: &[i32]) -> i32 {
+    let mut total = 0;
+    for value in v {
+        total += value;
+    }
+    total
+}
+"#;
+
+    /// same code, with other names, comments and formatting
+    const COPIED: &str = r#"
+/// documented
+fn bigger(first: i32, second: i32) -> i32 {
+    if first > second { first } else { second }
+}
+
+fn sum_all(numbers: &[i32]) -> i32 {
+    let mut acc = 0; for n in numbers { acc += n; } acc
+}
+"#;
+
+    const DIFFERENT: &str = r#"
+fn bigger(x: i32, y: i32) -> i32 {
+    std::cmp::max(x, y)
+}
+fn sum_all(v: &[i32]) -> i32 {
+    v.iter().sum()
+}
+"#;
+
+    fn submission(submission_id: i64, user_id: i64, source: &str) -> Submission {
+        Submission {
+            submission_id,
+            user_id,
+            exercise_name: "exercise".to_string(),
+            source: source.to_string(),
+            submitted_at: Default::default(),
+            result: None,
+        }
+    }
+
+    #[test]
+    fn test_normalize() {
+        let a: Vec<_> = normalize(ORIGINAL)
+            .unwrap()
+            .into_iter()
+            .map(|x| x.kind)
+            .collect();
+        let b: Vec<_> = normalize(COPIED)
+            .unwrap()
+            .into_iter()
+            .map(|x| x.kind)
+            .collect();
+        assert_eq!(a, b);
+        // invalid rust is still tokenized
+        assert!(normalize("fn f( { x").is_err());
+        assert!(!normalize("fn f() -> { x + }").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_similarity() {
+        let detector = PlagiarismDetector::new(PlagiarismConfig {
+            k: 5,
+            window: 3,
+            threshold: 0.5,
+        });
+        let report = detector.check(&[
+            submission(0, 0, ORIGINAL),
+            submission(1, 1, COPIED),
+            submission(2, 2, DIFFERENT),
+            // submissions of the same user are never compared, only the last submission counts
+            submission(3, 0, ORIGINAL),
+            submission(4, 3, "fn f( {"),
+        ]);
+        assert_eq!(report.skipped, vec![4]);
+        assert_eq!(report.pairs.len(), 1);
+        let pair = &report.pairs[0];
+        assert_eq!((pair.first, pair.second), (1, 3));
+        assert_eq!(pair.score, 1.0);
+        assert_eq!(pair.regions.len(), 1);
+        assert_eq!(pair.regions[0].first, 3..=9);
+        assert_eq!(pair.regions[0].second, 2..=16);
+
+        let detector = PlagiarismDetector::new(PlagiarismConfig {
+            k: 0,
+            ..Default::default()
+        });
+        let report = detector.check(&[submission(0, 0, ORIGINAL), submission(1, 1, COPIED)]);
+        assert_eq!(report.pairs.len(), 1);
+    }
+
+    #[test]
+    fn test_template_is_ignored() {
+        let template = r#"
+struct Point {
+    x: i32,
+    y: i32,
+}
+impl Point {
+    fn new(x: i32, y: i32) -> Point {
+        Point { x, y }
+    }
+}
+fn distance(a: &Point, b: &Point) -> i32 {
+    (a.x - b.x).abs() + (a.y - b.y).abs()
+}
+#[runtest]
+fn test_distance() {
+    assert_eq!(distance(&Point::new(0, 0), &Point::new(1, 1)), 2);
+}
+"#;
+        let first = r#"
+struct Point {
+    x: i32,
+    y: i32,
+}
+impl Point {
+    fn new(x: i32, y: i32) -> Point {
+        Point { x, y }
+    }
+}
+fn distance(a: &Point, b: &Point) -> i32 {
+    let dx = if a.x > b.x { a.x - b.x } else { b.x - a.x };
+    dx + (a.y - b.y).abs()
+}
+"#;
+        let second = r#"
+struct Point {
+    x: i32,
+    y: i32,
+}
+impl Point {
+    fn new(x: i32, y: i32) -> Point {
+        Point { x, y }
+    }
+}
+fn distance(a: &Point, b: &Point) -> i32 {
+    let mut total = 0;
+    for (p, q) in [(a.x, b.x), (a.y, b.y)] {
+        total += (p - q).abs();
+    }
+    total
+}
+"#;
+        let config = PlagiarismConfig {
+            k: 5,
+            window: 3,
+            threshold: 0.3,
+        };
+        let submissions = [submission(0, 0, first), submission(1, 1, second)];
+        let without = PlagiarismDetector::new(config).check(&submissions);
+        assert_eq!(without.pairs.len(), 1);
+        let with = PlagiarismDetector::new(config)
+            .set_template(template)
+            .unwrap()
+            .check(&submissions);
+        assert!(with.pairs.is_empty());
+    }
+}
@@ -8,6 +8,8 @@ use inquire::{
 use orchestrator::prelude::*;
 use tokio::sync::Notify;
 
+use crate::plagiarism::{check_exercise, PlagiarismConfig};
+
 #[derive(Clone)]
 /// AutoComplete struct
 struct AutoComplete {
@@ -18,7 +20,11 @@ impl Autocomplete for AutoComplete {
     fn get_suggestions(&mut self, input: &str) -> Result<Vec<String>, inquire::CustomUserError> {
         let v: Vec<&str> = input.split_ascii_whitespace().collect();
 
-        let cmd_list = vec!["quit".to_string(), "process".to_string()];
+        let cmd_list = vec![
+            "quit".to_string(),
+            "process".to_string(),
+            "plagiarism".to_string(),
+        ];
         let Some(command) = v.first() else {
             return Ok(cmd_list);
         };
@@ -42,6 +48,14 @@ impl Autocomplete for AutoComplete {
                 };
                 vec![format!("{} {}", available_esercise[0], path)]
             }
+            &"plagiarism" => {
+                let s = v.get(1).unwrap_or(&"");
+                self.valid_exercises
+                    .iter()
+                    .filter(|x| x.starts_with(s))
+                    .map(|x| format!("plagiarism {}", x))
+                    .collect()
+            }
             s => cmd_list.into_iter().filter(|x| x.starts_with(s)).collect(),
         })
     }
@@ -80,6 +94,14 @@ impl StringValidator for Validator {
                 }
             }
             Some(&"process") => Validation::Invalid("invalid parameter count, expect 2".into()),
+            Some(&"plagiarism") if v.len() == 2 => {
+                if self.valid_exercises.contains(v[1]) {
+                    Validation::Valid
+                } else {
+                    Validation::Invalid(format!("{} is not a valid exercise", v[1]).into())
+                }
+            }
+            Some(&"plagiarism") => Validation::Invalid("invalid parameter count, expect 1".into()),
             Some(&"quit") if v.len() == 1 => Validation::Valid,
             Some(&"quit") => Validation::Invalid("quit doesn't take any parameters".into()),
             Some(_) => Validation::Invalid("not a known command".into()),
@@ -142,6 +164,10 @@ impl<S: ExecutorGlobalState> Plugin<S> for CLIPlugin {
                         }
                     }
                 }
+                "plagiarism" => match check_exercise(&o, v[1], PlagiarismConfig::default()).await {
+                    Ok(report) => print!("{}", report),
+                    Err(x) => println!("got error: {x}"),
+                },
                 "quit" => {
                     should_stop.notify_one();
                     break;
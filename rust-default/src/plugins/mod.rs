@@ -1,7 +1,5 @@
 //! Contains Plugin definitions
 
-#[cfg(feature="cli")]
-pub(crate) mod cli;
 #[cfg(feature="cli")]
 pub(crate) mod cli_v2;
 pub(crate) mod rust_default;
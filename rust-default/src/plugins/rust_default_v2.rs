@@ -1,11 +1,13 @@
 use orchestrator::prelude::*;
-use std::{error::Error, path::PathBuf};
+use std::error::Error;
 
 use crate::prelude::*;
 /// adds normal rust compilation pipeline:
-/// from RustGeneratedFile to RustCompiled accept where to save the file as a parameter
+/// from RustGeneratedFile to RustCompiled accept how to compile and how to run the tests as parameters
 pub async fn register_rust_exercise<S>(
     o: &mut Orchestrator<S>,
+    compile: CompileConfig,
+    run: RunConfig,
 ) -> Result<(), Box<dyn Error + Send + Sync + 'static>>
 where
     S: ExecutorGlobalState
@@ -17,15 +19,27 @@ where
     RustCompiled2: TryFrom<S>,
     RustExercise2: TryFrom<S>,
 {
+    // the dependencies of an exercise are built when it's added, not by its first submission
+    let cache = compile.build_cache.clone();
+
     //add executors
-    o.add_executor(RustCompiled2::compile, None).await?;
+    o.add_executor(RustCompiled2::compile, compile).await?;
 
-    o.add_executor(|s, _| RustCompiled2::run(s), ()).await?;
+    o.add_executor(RustCompiled2::run, run).await?;
 
     // add exercise generators
-    let f1 = |c: String| async move {
-        let t = RustExercise2::parse(&c)?;
-        Ok(t)
+    let f1 = move |c: String| {
+        let cache = cache.clone();
+        async move {
+            let t = RustExercise2::parse(&c)?;
+            if let Some(cache) = cache {
+                if let Err(err) = cache.prebuild(&t.dependencies).await {
+                    // the submissions build them, so their errors are shown in the results
+                    eprintln!("dependencies of the exercise not prebuilt: {}", err);
+                }
+            }
+            Ok(t)
+        }
     };
     let f2 = |def: RustExercise2, source: String| async move {
         Ok(GeneratedFiles2::generate(def, source)?)
@@ -39,6 +53,8 @@ where
 #[derive(Default)]
 pub struct RustDefaultPlugin2 {
     activate_default: bool,
+    compile: CompileConfig,
+    config: RunConfig,
 }
 
 impl RustDefaultPlugin2 {
@@ -47,6 +63,32 @@ impl RustDefaultPlugin2 {
         self.activate_default = true;
         self
     }
+    /// how the tests are executed, by default directly on the host.
+    /// It can be changed later replacing the data of the executor from RustCompiled
+    pub fn set_runner(mut self, runner: Runner) -> Self {
+        self.config.runner = runner;
+        self
+    }
+    /// dependencies of the exercises are built once in the cache, and shared by the submissions
+    pub fn set_build_cache(mut self, cache: BuildCache) -> Self {
+        self.compile.build_cache = Some(cache);
+        self
+    }
+    /// all the tests of an exercise are compiled in a single binary, instead of one binary for each test
+    pub fn set_single_binary(mut self) -> Self {
+        self.compile.single_binary = true;
+        self
+    }
+    /// a compile error in an item of the student fails only the tests that use it
+    pub fn set_isolate_errors(mut self) -> Self {
+        self.compile.isolate_errors = true;
+        self
+    }
+    /// limits of the tests that don't set their own in the template
+    pub fn set_limits(mut self, limits: TestLimits) -> Self {
+        self.config.limits = limits;
+        self
+    }
 }
 
 impl<S: ExecutorGlobalState> Plugin<S> for RustDefaultPlugin2
@@ -68,13 +110,13 @@ where
         &'a mut self,
         o: &'a mut Orchestrator<S>,
     ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
-        register_rust_exercise(o).await?;
+        register_rust_exercise(o, self.compile.clone(), self.config.clone()).await?;
         if self.activate_default {
             // enable executors
-            o.enable_executor::<GeneratedFiles2, RustCompiled2, _>(None::<PathBuf>)
+            o.enable_executor::<GeneratedFiles2, RustCompiled2, _>(self.compile.clone())
                 .await
                 .unwrap();
-            o.enable_executor::<RustCompiled2, ExerciseResult, _>(())
+            o.enable_executor::<RustCompiled2, ExerciseResult, _>(self.config.clone())
                 .await
                 .unwrap();
         }
@@ -1,8 +1,17 @@
 use clap::{Parser, Subcommand};
+use orchestrator::archive::{export, import_checked, Archive};
 use orchestrator::prelude::*;
-use std::{error::Error, path::PathBuf, sync::Arc};
+use std::{
+    error::Error,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::PathBuf,
+    sync::Arc,
+};
 use tokio::fs;
 
+use crate::plagiarism::{check_exercise, PlagiarismConfig};
+
 /// Simple program to greet a person
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -23,6 +32,46 @@ enum Commands {
         #[arg(short, long)]
         file_path: PathBuf,
     },
+    /// compares the stored submissions of an exercise, and reports the similar ones
+    Plagiarism {
+        /// exercise to analyse
+        #[arg(short, long)]
+        exercise_name: String,
+
+        /// minimum similarity (from 0 to 1) to report a pair
+        #[arg(short, long, default_value_t = PlagiarismConfig::default().threshold)]
+        threshold: f64,
+    },
+    /// writes users, exercises, executors and submissions into an archive (JSON lines)
+    Export {
+        /// archive to create
+        #[arg(short, long)]
+        file_path: PathBuf,
+    },
+    /// reads an archive written by export, ids are reassigned
+    Import {
+        /// archive to read
+        #[arg(short, long)]
+        file_path: PathBuf,
+    },
+    /// lists the enabled executors: input -> output: data
+    Executors,
+    /// disables the executor triggered by a state
+    DisableExecutor {
+        /// input state, as listed by executors
+        #[arg(short, long)]
+        input: String,
+    },
+    /// replaces the data given to the executor triggered by a state
+    ExecutorData {
+        /// input state, as listed by executors
+        #[arg(short, long)]
+        input: String,
+
+        /// new data, in json
+        #[arg(short, long)]
+        data: String,
+    },
 }
 
 pub struct StatelessCLIPlugin;
@@ -36,8 +85,6 @@ impl<S: ExecutorGlobalState> Plugin<S> for StatelessCLIPlugin {
     }
 
     async fn run(self, o: OrchestratorReference<S>, should_stop: Arc<Notify>) {
-        let _ = o.memory().register("cli_plugin", "cli_plugin").await;
-        let login = o.memory().login("cli_plugin", "cli_plugin").await.unwrap();
         let a = Args::parse();
         let names = o.memory().list_exercise_names().await.unwrap();
         match a.command {
@@ -45,6 +92,9 @@ impl<S: ExecutorGlobalState> Plugin<S> for StatelessCLIPlugin {
                 exercise_name,
                 file_path,
             } => {
+                // the cli user is registered only when needed, so that it does not end up in archives
+                let _ = o.memory().register("cli_plugin", "cli_plugin").await;
+                let login = o.memory().login("cli_plugin", "cli_plugin").await.unwrap();
                 if !names.contains(&exercise_name) {
                     println!("Exercise not found, choose from the following:");
                     for x in names {
@@ -65,6 +115,68 @@ impl<S: ExecutorGlobalState> Plugin<S> for StatelessCLIPlugin {
                         println!("got error: {x}")
                     }
                 }
+            }
+            Commands::Plagiarism {
+                exercise_name,
+                threshold,
+            } => {
+                let config = PlagiarismConfig {
+                    threshold,
+                    ..Default::default()
+                };
+                match check_exercise(&o, &exercise_name, config).await {
+                    Ok(report) => print!("{}", report),
+                    Err(x) => println!("got error: {x}"),
+                }
+            }
+            Commands::Export { file_path } => {
+                let exported = async {
+                    let archive = export(o.memory()).await?;
+                    archive.write(BufWriter::new(File::create(file_path)?))?;
+                    Ok::<Archive, Box<dyn Error>>(archive)
+                };
+                match exported.await {
+                    Ok(x) => println!(
+                        "exported {} users and {} submissions",
+                        x.users.len(),
+                        x.submissions.len()
+                    ),
+                    Err(x) => println!("got error: {x}"),
+                }
+            }
+            Commands::Import { file_path } => {
+                let imported = async {
+                    let archive = Archive::read(BufReader::new(File::open(file_path)?))?;
+                    import_checked(Orchestrator::memory(&o), &archive).await
+                };
+                match imported.await {
+                    Ok(x) => println!(
+                        "imported {} users and {} submissions",
+                        x.users.len(),
+                        x.submissions.len()
+                    ),
+                    Err(x) => println!("got error: {x}"),
+                }
+            }
+            Commands::Executors => match o.memory().export_executors().await {
+                Ok(executors) => {
+                    for x in executors {
+                        println!("{} -> {}: {}", x.input, x.output, x.data);
+                    }
+                }
+                Err(x) => println!("got error: {x}"),
+            },
+            Commands::DisableExecutor { input } => {
+                match o.disable_executor_by_variant(&input, None).await {
+                    Ok(()) => println!("disabled {input}"),
+                    Err(x) => println!("got error: {x}"),
+                }
+            }
+            Commands::ExecutorData { input, data } => {
+                match o.replace_executor_data_by_variant(&input, data, None).await {
+                    Ok(()) => println!("replaced the data of {input}"),
+                    Err(x) => println!("got error: {x}"),
+                }
             } // _ => {}
         }
         should_stop.notify_one();
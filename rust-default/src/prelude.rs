@@ -1,5 +1,9 @@
 pub use crate::generatorv2::{
-    compiled::RustCompiled as RustCompiled2, error::RustError as RustError2,
+    build_cache::BuildCache,
+    compiled::{CompileConfig, RustCompiled as RustCompiled2},
+    error::RustError as RustError2,
     file_generator::GeneratedFiles as GeneratedFiles2, parser::RustExercise as RustExercise2,
+    run::{RunConfig, TestLimits},
+    sandbox::{Runner, SandboxLimits},
 };
 pub use crate::plugins::{rust_default::RustDefaultPlugin, rust_default_v2::RustDefaultPlugin2};
@@ -108,8 +108,8 @@ async fn test_substitution() {
                 let mut e = HashMap::new();
                 use orchestrator::prelude::CompilationResult::*;
                 use orchestrator::prelude::RunResult;
-                e.insert("test_display".to_string(), TestResult { compiled: Built, runned: RunResult::Ok, points_given: 1.0 });
-                e.insert("test_new".to_string(), TestResult { compiled: CompilationResult::Error("error[E0599]: no function or associated item named `new` found for struct `S` in the current scope\n  --> src/bin/test_new.rs:10:19\n   |\n2  | struct S;\n   | -------- function or associated item `new` not found for this struct\n...\n10 |     let s: S = S::new();\n   |                   ^^^ function or associated item not found in `S`\n\n\nerror: aborting due to 1 previous error\n\n".to_string()), runned:RunResult::NotRun, points_given: 0.0 });
+                e.insert("test_display".to_string(), TestResult { compiled: Built(Vec::new()), runned: RunResult::Ok, points_given: 1.0, diagnostics: None });
+                e.insert("test_new".to_string(), TestResult { compiled: CompilationResult::Error("error[E0599]: no function or associated item named `new` found for struct `S` in the current scope\n  --> src/bin/test_new.rs:10:19\n   |\n2  | struct S;\n   | -------- function or associated item `new` not found for this struct\n...\n10 |     let s: S = S::new();\n   |                   ^^^ function or associated item not found in `S`\n\n\nerror: aborting due to 1 previous error\n\n".to_string(), Vec::new()), runned:RunResult::NotRun, points_given: 0.0, diagnostics: None });
 
                 assert_eq!(t.tests, e);
                 //println!("{t}");
@@ -1,32 +1,99 @@
 use orchestrator::prelude::*;
 use sqlx::{
     prelude::*,
-    query,
+    query, query_as,
     types::chrono::{DateTime, Utc},
-    Pool,
+    PgExecutor, Pool,
 };
-use std::{error::Error, marker::PhantomData};
+use std::{collections::HashMap, marker::PhantomData};
+
+/// database errors in terms of the memory:
+/// a missing row is not found, a duplicated key already exists, a missing reference is not found,
+/// everything else is a failure of the database
+pub fn db(err: sqlx::Error) -> MemoryError {
+    if let sqlx::Error::Database(x) = &err {
+        let what = x.constraint().unwrap_or("record").to_string();
+        if x.is_unique_violation() {
+            return MemoryError::AlreadyExists(what);
+        }
+        if x.is_foreign_key_violation() {
+            return MemoryError::NotFound(what);
+        }
+    }
+    match err {
+        sqlx::Error::RowNotFound => MemoryError::NotFound("record".to_string()),
+        err => MemoryError::backend(err),
+    }
+}
 
 /// This is an helper functions
-pub async fn add_test_result(
-    pool: &Pool<sqlx::Postgres>,
+pub async fn add_test_result<'c, E: PgExecutor<'c>>(
+    executor: E,
     name: String,
     result: TestResult,
     submission_id: i64,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
+) -> Result<(), MemoryError> {
     let compiled = serde_json::to_string(&result.compiled)?;
     let runned = serde_json::to_string(&result.runned)?;
-    query("INSERT INTO test_results(name, compiled, runned, points, refers_to) VALUES ($1, $2, $3, $4, $5)")
+    let diagnostics = result
+        .diagnostics
+        .as_ref()
+        .map(serde_json::to_string)
+        .transpose()?;
+    query("INSERT INTO test_results(name, compiled, runned, points, refers_to, diagnostics) VALUES ($1, $2, $3, $4, $5, $6)")
         .bind(name)
         .bind(compiled)
         .bind(runned)
         .bind(result.points_given)
         .bind(submission_id)
-        .execute(pool)
-        .await?;
+        .bind(diagnostics)
+        .execute(executor)
+        .await.map_err(db)?;
     Ok(())
 }
 
+/// rebuild the results of the given submissions from their test results
+pub async fn get_exercise_results(
+    pool: &Pool<sqlx::Postgres>,
+    submission_ids: &[i64],
+) -> Result<HashMap<i64, ExerciseResult>, MemoryError> {
+    let rows = query_as::<sqlx::Postgres, TestResultRow>(
+        "SELECT name, compiled, runned, points, refers_to, diagnostics FROM test_results WHERE refers_to = ANY($1)",
+    )
+    .bind(submission_ids)
+    .fetch_all(pool)
+    .await.map_err(db)?;
+    let mut ret: HashMap<i64, ExerciseResult> = HashMap::new();
+    for row in rows {
+        let result = TestResult {
+            compiled: serde_json::from_str(&row.compiled)?,
+            runned: serde_json::from_str(&row.runned)?,
+            points_given: row.points,
+            diagnostics: row
+                .diagnostics
+                .as_deref()
+                .map(serde_json::from_str)
+                .transpose()?,
+        };
+        ret.entry(row.refers_to)
+            .or_default()
+            .tests
+            .insert(row.name, result);
+    }
+    Ok(ret)
+}
+
+#[derive(FromRow)]
+/// Internal and private struct, used to parse test_results rows
+pub struct TestResultRow {
+    pub name: String,
+    pub compiled: String,
+    pub runned: String,
+    pub points: f64,
+    pub refers_to: i64,
+    pub diagnostics: Option<String>,
+}
+
 #[derive(FromRow)]
 /// Struct used to retrive/set user information:
 pub struct UserWrapper {
@@ -53,6 +120,17 @@ impl<S: UserState> From<UserWrapper> for User<S> {
     }
 }
 
+impl From<UserWrapper> for UserRecord {
+    fn from(value: UserWrapper) -> Self {
+        Self {
+            user_id: value.user_id,
+            username: value.username,
+            password_hash: value.password_hash,
+            is_admin: value.is_admin,
+        }
+    }
+}
+
 #[derive(FromRow)]
 /// Internal and private struct, used to parse incoming SQL rows
 pub struct Problem {
@@ -61,6 +139,16 @@ pub struct Problem {
     pub source: String,
 }
 
+impl From<Problem> for ExerciseRecord {
+    fn from(value: Problem) -> Self {
+        Self {
+            name: value.name,
+            variant: value.ty,
+            source: value.source,
+        }
+    }
+}
+
 #[derive(FromRow)]
 /// Internal and private struct, used to parse incoming SQL rows
 pub struct Enabled {
@@ -68,3 +156,65 @@ pub struct Enabled {
     pub outgoing: String,
     pub additional_data: String,
 }
+
+impl From<Enabled> for ExecutorRecord {
+    fn from(value: Enabled) -> Self {
+        Self {
+            input: value.incoming,
+            output: value.outgoing,
+            data: value.additional_data,
+        }
+    }
+}
+
+#[derive(FromRow)]
+/// Internal and private struct, used to parse audit_log rows
+pub struct AuditRow {
+    pub id: i64,
+    pub time: DateTime<Utc>,
+    pub actor: Option<String>,
+    pub action: String,
+    pub details: String,
+}
+
+impl TryFrom<AuditRow> for AuditEvent {
+    type Error = serde_json::Error;
+
+    fn try_from(value: AuditRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: value.id,
+            time: value.time,
+            actor: value.actor,
+            action: serde_json::from_str(&value.action)?,
+            details: value.details,
+        })
+    }
+}
+
+#[derive(FromRow)]
+/// Internal and private struct, used to parse submissions rows
+pub struct SubmissionRow {
+    pub submission_id: i64,
+    pub user_id: i64,
+    pub name: String,
+    pub source: String,
+    pub submitted_at: DateTime<Utc>,
+    pub graded_at: Option<DateTime<Utc>>,
+}
+
+impl SubmissionRow {
+    /// a graded submission without test results has an empty result
+    pub fn into_submission(self, results: &mut HashMap<i64, ExerciseResult>) -> Submission {
+        let result = self
+            .graded_at
+            .map(|_| results.remove(&self.submission_id).unwrap_or_default());
+        Submission {
+            submission_id: self.submission_id,
+            user_id: self.user_id,
+            exercise_name: self.name,
+            source: self.source,
+            submitted_at: self.submitted_at,
+            result,
+        }
+    }
+}
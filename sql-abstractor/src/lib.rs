@@ -2,23 +2,24 @@
 //!
 //! It connects to a PosgreSQL Database, and handle the creation of all the necessary tables.
 //! (See the example for an example)
-use helpers::{add_test_result, Enabled, Problem, UserWrapper};
+//!
+//! Sqlite has the same semantics, but it does not need a server.
+use helpers::{
+    add_test_result, db, get_exercise_results, AuditRow, Enabled, Problem, SubmissionRow,
+    UserWrapper,
+};
+use migrations::{AppliedRow, POSTGRES};
 use orchestrator::default_memory::{has_cycles, new_token};
 use orchestrator::executor::ExecutorGlobalState;
+use orchestrator::password::{PasswordError, PasswordPolicy, Verification, DISABLED_PASSWORD};
 use orchestrator::prelude::*;
 use std::any::TypeId;
 use std::collections::HashMap;
-use std::error::Error as StdError;
 
 use async_trait::async_trait;
-use rand::thread_rng;
-use scrypt::password_hash::PasswordHasher;
-use scrypt::{
-    password_hash::{PasswordHash, PasswordVerifier, SaltString},
-    Params, Scrypt,
-};
-use sqlx::{query, query_as, Pool};
-use tokio::task::{spawn_blocking, JoinError};
+use chrono::{Duration, Utc};
+use sqlx::{query, query_as, Executor, PgExecutor, Pool};
+use tokio::task::JoinError;
 
 /// Private module
 ///
@@ -26,20 +27,28 @@ use tokio::task::{spawn_blocking, JoinError};
 ///
 mod helpers;
 
+/// Versioned schema migrations
+mod migrations;
+pub use migrations::MigrationStatus;
+
+/// SQLite implementation of the memory
+mod sqlite;
+pub use sqlite::Sqlite;
+
 #[cfg(test)]
 /// test module, contains all tests
 mod test;
 
 #[derive(thiserror::Error, Debug)]
-/// All possible error variants from this crate
+/// Errors while connecting or migrating, the memory methods return a MemoryError
 pub enum Error {
     ///generic error as string
     #[error("string")]
     String(String),
 
-    /// Hash Error from scrypt
-    #[error("Hash Error {0}")]
-    Hash(#[from] scrypt::password_hash::Error),
+    /// Error while hashing or verifying a password
+    #[error("Password Error {0}")]
+    Password(#[from] PasswordError),
 
     /// Generic SQLX Error
     #[error("Sqlx Error {0}")]
@@ -49,18 +58,21 @@ pub enum Error {
     #[error("Join Error {0}")]
     TokioJoin(#[from] JoinError),
 
-    /// Unauthorized
-    #[error("Unauthoraized")]
-    Unauthoraized,
+    /// The database was migrated by a newer version
+    #[error("Schema version {found} is newer than the latest known {known}")]
+    SchemaTooNew {
+        /// version of the database
+        found: i64,
+        /// last version known by this crate
+        known: i64,
+    },
+}
 
-    /// Already present in memory
-    #[error("Already present")]
-    AlreadyPresent,
+/// table that records the applied migrations
+const SCHEMA_VERSION: &str = "CREATE TABLE IF NOT EXISTS schema_version(version BIGINT PRIMARY KEY, name VARCHAR(255) NOT NULL, applied_at TIMESTAMPTZ NOT NULL)";
 
-    /// Not Found
-    #[error("Not found")]
-    NotFound,
-}
+/// key of the advisory lock held while migrating
+const MIGRATION_LOCK: i64 = 0x5c4e_3a11;
 
 ///Postgress implementation.
 ///
@@ -90,57 +102,112 @@ pub enum Error {
 pub struct Postgres {
     /// inner type, it is a Pool from sqlx (Postgres version)
     pool: Pool<sqlx::Postgres>,
+    /// how passwords get hashed
+    policy: PasswordPolicy,
 }
 
 /// implement some initialization
 impl Postgres {
-    /// initialize connector
+    /// connect to the database, without touching the schema.
+    ///
+    /// Used to inspect or apply migrations, see init for the usual way to obtain Self
+    pub async fn connect(builder: &str) -> Result<Self, Error> {
+        let pool: Pool<sqlx::Postgres> = Pool::connect(builder).await?;
+        Ok(Self {
+            pool,
+            policy: PasswordPolicy::default(),
+        })
+    }
+    /// initialize connector, applying all pending migrations.
     /// It could be used multiple times without loosing any data.
     ///
     /// This is the correct way to obtain Self
     pub async fn init(builder: &str) -> Result<Self, Error> {
-        let pool: Pool<sqlx::Postgres> = Pool::connect(builder).await?;
-        //create table users (if not present)
-        let _ = query(include_str!("sql/create_db/1_users.sql"))
-            .execute(&pool)
-            .await
-            .map_err(|x| Error::String(format!("Error while creating user table {x}")))?;
-        //create table problems (if not present)
-        let _ = query(include_str!("sql/create_db/2_problems.sql"))
-            .execute(&pool)
-            .await
-            .map_err(|x| Error::String(format!("Error while creating problems table {x}")))?;
-
-        //create table problems (if not present)
-        let _ = query(include_str!("sql/create_db/3_enabled_executors.sql"))
-            .execute(&pool)
-            .await
-            .map_err(|x| {
-                Error::String(format!("Error while creating enabled_executors table {x}"))
-            })?;
-
-        //create table submissions (if not present)
-        let _ = query(include_str!("sql/create_db/4_submissions.sql"))
-            .execute(&pool)
-            .await
-            .map_err(|x| Error::String(format!("Error while creating submission table {x}")))?;
-
-        //create table test_result (if not present)
-        let _ = query(include_str!("sql/create_db/5_test_results.sql"))
-            .execute(&pool)
+        let memory = Self::connect(builder).await?;
+        memory.migrate().await?;
+        Ok(memory)
+    }
+    /// current schema version, 0 for an empty database
+    async fn schema_version<'c, E: PgExecutor<'c>>(executor: E) -> Result<i64, Error> {
+        let (version,): (i64,) = query_as("SELECT COALESCE(MAX(version), 0) FROM schema_version")
+            .fetch_one(executor)
             .await?;
-
-        Ok(Self { pool })
+        Ok(version)
+    }
+    /// all known and applied migrations
+    pub async fn migration_status(&self) -> Result<Vec<MigrationStatus>, Error> {
+        query(SCHEMA_VERSION).execute(&self.pool).await?;
+        let applied = query_as::<sqlx::Postgres, AppliedRow>("SELECT * FROM schema_version")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(migrations::status(POSTGRES, applied))
+    }
+    /// applies the pending migrations in order, each one in its own transaction.
+    /// Returns the applied versions.
+    ///
+    /// It fails if the database was migrated by a newer version of this crate
+    pub async fn migrate(&self) -> Result<Vec<i64>, Error> {
+        query(SCHEMA_VERSION).execute(&self.pool).await?;
+        let current = Self::schema_version(&self.pool).await?;
+        let mut applied = Vec::new();
+        for migration in migrations::pending(POSTGRES, current)? {
+            let mut transaction = self.pool.begin().await?;
+            // other instances starting at the same time wait, and then find it applied
+            query("SELECT pg_advisory_xact_lock($1)")
+                .bind(MIGRATION_LOCK)
+                .execute(&mut *transaction)
+                .await?;
+            if Self::schema_version(&mut *transaction).await? >= migration.version {
+                continue;
+            }
+            (&mut *transaction)
+                .execute(migration.sql)
+                .await
+                .map_err(|x| {
+                    Error::String(format!(
+                        "Error while applying migration {} {}: {x}",
+                        migration.version, migration.name
+                    ))
+                })?;
+            query("INSERT INTO schema_version(version, name, applied_at) VALUES ($1, $2, NOW())")
+                .bind(migration.version)
+                .bind(migration.name)
+                .execute(&mut *transaction)
+                .await?;
+            transaction.commit().await?;
+            applied.push(migration.version);
+        }
+        Ok(applied)
+    }
+    /// use the given policy to hash passwords.
+    ///
+    /// Already saved hashes are still valid, and they get updated on the next login.
+    pub fn with_password_policy(mut self, policy: PasswordPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+    /// attach the results to the submissions
+    async fn with_results(&self, rows: Vec<SubmissionRow>) -> Result<Vec<Submission>, MemoryError> {
+        let ids: Vec<i64> = rows.iter().map(|x| x.submission_id).collect();
+        let mut results = get_exercise_results(&self.pool, &ids).await?;
+        Ok(rows
+            .into_iter()
+            .map(|x| x.into_submission(&mut results))
+            .collect())
     }
     /// WARNING: THIS WILL ERASE ALL THE DATA CONTAINED IN THE DATABASE, AND THEN INIT
     pub async fn clean_init(builder: &str) -> Result<Self, Error> {
         let pool: Pool<sqlx::Postgres> = Pool::connect(builder).await?;
 
         let _ = query("DROP TABLE test_results").execute(&pool).await;
+        let _ = query("DROP TABLE password_resets").execute(&pool).await;
+        let _ = query("DROP TABLE external_identities").execute(&pool).await;
         let _ = query("DROP TABLE submissions").execute(&pool).await;
         let _ = query("DROP TABLE users").execute(&pool).await;
         let _ = query("DROP TABLE problems").execute(&pool).await;
         let _ = query("DROP TABLE enabled_executors").execute(&pool).await;
+        let _ = query("DROP TABLE audit_log").execute(&pool).await;
+        let _ = query("DROP TABLE schema_version").execute(&pool).await;
 
         Self::init(builder).await
     }
@@ -152,29 +219,20 @@ impl StatelessMemory for Postgres {
         &self,
         username: &str,
         password: &str,
-    ) -> Result<User<Unauthenticated>, Box<dyn StdError>> {
-        //Hash password, it's cpu expensive, so it's better execute it in a blocking way
-        let password = password.to_string();
-        let hash = spawn_blocking(move || {
-            let salt = SaltString::generate(thread_rng());
-            let params = Params::new(
-                10,
-                Params::RECOMMENDED_R,
-                Params::RECOMMENDED_P,
-                Params::RECOMMENDED_LEN,
-            )
-            .unwrap();
-            Scrypt
-                .hash_password_customized(password.as_bytes(), None, None, params, &salt)
-                .map(|x| x.to_string())
-        })
-        .await??;
+    ) -> Result<User<Unauthenticated>, MemoryError> {
+        //Hash password, it's cpu expensive, so it's executed in a blocking way
+        let hash = self.policy.hash_async(password).await?;
         //insert new user
         query("INSERT INTO users(username, password_hash, is_admin) VALUES ($1, $2, false) RETURNING user_id")
             .bind(username)
             .bind(&hash)
             .execute(&self.pool)
-            .await?;
+            .await.map_err(|x| match db(x) {
+                MemoryError::AlreadyExists(_) => {
+                    MemoryError::AlreadyExists(format!("user {username}"))
+                }
+                x => x,
+            })?;
         self.get_by_username(username).await
     }
 
@@ -183,16 +241,32 @@ impl StatelessMemory for Postgres {
         &self,
         username: &str,
         password: &str,
-    ) -> Result<User<Authenticated>, Box<dyn StdError>> {
-        let user = self.get_by_username(username).await?;
-        let hash = user.password_hash.clone();
-        let password = password.to_string();
+    ) -> Result<User<Authenticated>, MemoryError> {
+        // an unknown user is not told apart from a wrong password
+        let user = self.get_by_username(username).await.map_err(|x| match x {
+            MemoryError::NotFound(_) => MemoryError::Unauthorized,
+            x => x,
+        })?;
         //check password
-        spawn_blocking(move || {
-            let parsed_hash = PasswordHash::new(&hash)?;
-            Scrypt.verify_password(password.as_bytes(), &parsed_hash)
-        })
-        .await??;
+        match self
+            .policy
+            .verify_async(password, &user.password_hash)
+            .await?
+        {
+            Verification::Invalid => Err(MemoryError::Unauthorized)?,
+            Verification::Valid => {}
+            Verification::NeedsRehash => {
+                let hash = self.policy.hash_async(password).await?;
+                // not if the password has been changed in the meantime
+                query("UPDATE users SET password_hash=$1 WHERE user_id=$2 AND password_hash=$3")
+                    .bind(hash)
+                    .bind(user.user_id)
+                    .bind(&user.password_hash)
+                    .execute(&self.pool)
+                    .await
+                    .map_err(db)?;
+            }
+        }
 
         //update token
         let token = new_token();
@@ -200,64 +274,190 @@ impl StatelessMemory for Postgres {
             .bind(token)
             .bind(user.user_id)
             .fetch_one(&self.pool)
-            .await?.into();
+            .await.map_err(db)?.into();
         Ok(user)
     }
-    /// gets a user by his username
-    async fn get_by_username(
+    /// finds the user linked to the identity, or registers it. Then updates its token
+    async fn login_external(
+        &self,
+        identity: &ExternalIdentity,
+    ) -> Result<User<Authenticated>, MemoryError> {
+        let mut transaction = self.pool.begin().await.map_err(db)?;
+        let linked: Option<(i64,)> =
+            query_as("SELECT user_id FROM external_identities WHERE issuer=$1 AND subject=$2")
+                .bind(&identity.issuer)
+                .bind(&identity.subject)
+                .fetch_optional(&mut *transaction)
+                .await
+                .map_err(db)?;
+        let user_id = match linked {
+            Some((user_id,)) => user_id,
+            None => {
+                // first login, register it
+                let mut username = identity.preferred_username();
+                let taken: Option<(i64,)> = query_as("SELECT user_id FROM users WHERE username=$1")
+                    .bind(&username)
+                    .fetch_optional(&mut *transaction)
+                    .await
+                    .map_err(db)?;
+                if taken.is_some() {
+                    username = format!("{} ({})", username, identity.subject);
+                }
+                let (user_id,): (i64,) = query_as("INSERT INTO users(username, password_hash, is_admin) VALUES ($1, $2, false) RETURNING user_id")
+                    .bind(&username)
+                    .bind(DISABLED_PASSWORD)
+                    .fetch_one(&mut *transaction)
+                    .await.map_err(db)?;
+                query(
+                    "INSERT INTO external_identities(issuer, subject, user_id) VALUES ($1, $2, $3)",
+                )
+                .bind(&identity.issuer)
+                .bind(&identity.subject)
+                .bind(user_id)
+                .execute(&mut *transaction)
+                .await
+                .map_err(db)?;
+                user_id
+            }
+        };
+        let user: User<Authenticated> = query_as::<sqlx::Postgres, UserWrapper>("UPDATE users SET logged_in_time=NOW(), logged_in_token=$1 WHERE user_id=$2 RETURNING *")
+            .bind(new_token())
+            .bind(user_id)
+            .fetch_one(&mut *transaction)
+            .await.map_err(db)?
+            .into();
+        transaction.commit().await.map_err(db)?;
+        Ok(user)
+    }
+
+    /// checks the old password, and then saves the new one
+    async fn change_password(
+        &self,
+        user: User<Authenticated>,
+        old_password: &str,
+        new_password: &str,
+    ) -> Result<(), MemoryError> {
+        let stored = self.get_by_username(&user.username).await?;
+        if !self
+            .policy
+            .verify_async(old_password, &stored.password_hash)
+            .await?
+            .is_valid()
+        {
+            Err(MemoryError::Unauthorized)?
+        }
+        let hash = self.policy.hash_async(new_password).await?;
+        query("UPDATE users SET password_hash=$1 WHERE user_id=$2")
+            .bind(hash)
+            .bind(stored.user_id)
+            .execute(&self.pool)
+            .await
+            .map_err(db)?;
+        Ok(())
+    }
+
+    /// saves a new reset token for the user
+    async fn create_password_reset(
         &self,
         username: &str,
-    ) -> Result<User<Unauthenticated>, Box<dyn StdError>> {
+        validity: Duration,
+    ) -> Result<PasswordReset, MemoryError> {
+        let user = self.get_by_username(username).await?;
+        // it would give a password to an external user
+        if user.password_hash == DISABLED_PASSWORD {
+            Err(MemoryError::NotFound(format!("user {username}")))?
+        }
+        let reset = PasswordReset {
+            username: user.username,
+            token: new_token(),
+            expires_at: Utc::now() + validity,
+        };
+        query("INSERT INTO password_resets(token, user_id, expires_at) VALUES ($1, $2, $3)")
+            .bind(&reset.token)
+            .bind(user.user_id)
+            .bind(reset.expires_at)
+            .execute(&self.pool)
+            .await
+            .map_err(db)?;
+        Ok(reset)
+    }
+
+    /// marks the token as used (only if it is still valid), then updates the password and logs out the user
+    async fn reset_password(&self, token: &str, new_password: &str) -> Result<(), MemoryError> {
+        let hash = self.policy.hash_async(new_password).await?;
+        let mut transaction = self.pool.begin().await.map_err(db)?;
+        let user_id: Option<(i64,)> = query_as(
+            "UPDATE password_resets SET used=true WHERE token=$1 AND NOT used AND expires_at > NOW() RETURNING user_id",
+        )
+        .bind(token)
+        .fetch_optional(&mut *transaction)
+        .await.map_err(db)?;
+        let (user_id,) = user_id.ok_or(MemoryError::Unauthorized)?;
+        query("UPDATE users SET password_hash=$1, logged_in_token=NULL WHERE user_id=$2")
+            .bind(hash)
+            .bind(user_id)
+            .execute(&mut *transaction)
+            .await
+            .map_err(db)?;
+        transaction.commit().await.map_err(db)?;
+        Ok(())
+    }
+
+    /// gets a user by his username
+    async fn get_by_username(&self, username: &str) -> Result<User<Unauthenticated>, MemoryError> {
         Ok(
             query_as::<sqlx::Postgres, UserWrapper>(
                 "SELECT * FROM users WHERE users.username = $1",
             )
             .bind(username)
-            .fetch_one(&self.pool)
-            .await?
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(db)?
+            .ok_or_else(|| MemoryError::NotFound(format!("user {username}")))?
             .into(),
         )
     }
 
     ///returns all user present in the DB.
-    async fn get_all_users(&self) -> Result<Vec<User<Unauthenticated>>, Box<dyn StdError>> {
+    async fn get_all_users(&self) -> Result<Vec<User<Unauthenticated>>, MemoryError> {
         Ok(
             query_as::<sqlx::Postgres, UserWrapper>("SELECT * FROM users")
                 .fetch_all(&self.pool)
-                .await?
+                .await
+                .map_err(db)?
                 .into_iter()
                 .map(|x| x.into())
                 .collect(),
         )
     }
     /// check if the given token is valid, and if so returns the correct user
-    async fn get_authenticate(
-        &self,
-        token: &str,
-    ) -> Result<User<Authenticated>, Box<dyn StdError>> {
+    async fn get_authenticate(&self, token: &str) -> Result<User<Authenticated>, MemoryError> {
         Ok(sqlx::query_as::<sqlx::Postgres, UserWrapper>(
             "SELECT * FROM users WHERE logged_in_token = $1",
         )
         .bind(token.to_string())
-        .fetch_one(&self.pool)
-        .await?
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(db)?
+        .ok_or(MemoryError::Unauthorized)?
         .into())
     }
 
     /// if the token is valid, it get's the user, and if so checks if it is an Admin
-    async fn get_admin(&self, token: &str) -> Result<User<Admin>, Box<dyn StdError>> {
+    async fn get_admin(&self, token: &str) -> Result<User<Admin>, MemoryError> {
         let user = self.get_authenticate(token).await?;
         if user.is_admin {
             Ok(user.transmute())
         } else {
-            Err(Error::Unauthoraized.into())
+            Err(MemoryError::Unauthorized)
         }
     }
-    async fn list_exercise_names(&self) -> Result<Vec<String>, Box<dyn StdError>> {
+    async fn list_exercise_names(&self) -> Result<Vec<String>, MemoryError> {
         let data: Vec<Problem> =
             sqlx::query_as::<sqlx::Postgres, Problem>("SELECT * FROM problems")
                 .fetch_all(&self.pool)
-                .await?;
+                .await
+                .map_err(db)?;
         let res = data.into_iter().map(|x| x.name).collect();
         Ok(res)
     }
@@ -267,83 +467,315 @@ impl StatelessMemory for Postgres {
         exercise_name: String,
         source: String,
         user: User<Authenticated>,
-    ) -> Result<i64, Box<dyn StdError + Send + Sync>> {
+    ) -> Result<i64, MemoryError> {
         let id: (i64,) = query_as(
             "INSERT INTO submissions(user_id, name, source) VALUES ($1, $2, $3) RETURNING submission_id",
         )
-        .bind(user.user_id as i32)
-        .bind(exercise_name)
+        .bind(user.user_id)
+        .bind(&exercise_name)
         .bind(source)
         .fetch_one(&self.pool)
-        .await?;
+        .await.map_err(|x| match db(x) {
+            MemoryError::NotFound(_) => MemoryError::NotFound(format!("exercise {exercise_name}")),
+            x => x,
+        })?;
         Ok(id.0 as i64)
     }
 
-    ///add exercise result
+    ///add exercise result, only the user that submitted can add it
     async fn add_exercise_result(
         &self,
         submission_id: i64,
         user: User<Authenticated>,
         result: ExerciseResult,
-    ) -> Result<(), Box<dyn StdError + Send + Sync>> {
+    ) -> Result<(), MemoryError> {
+        let mut transaction = self.pool.begin().await.map_err(db)?;
         //check if the user owns the current
-
+        let graded = query_as::<sqlx::Postgres, (i64,)>(
+            "UPDATE submissions SET graded_at=NOW() WHERE submission_id=$1 AND user_id=$2 RETURNING submission_id",
+        )
+        .bind(submission_id)
+        .bind(user.user_id)
+        .fetch_optional(&mut *transaction)
+        .await
+        .map_err(db)?;
+        if graded.is_none() {
+            // a missing submission is told apart from one of another user
+            let found: Option<(i64,)> =
+                query_as("SELECT submission_id FROM submissions WHERE submission_id=$1")
+                    .bind(submission_id)
+                    .fetch_optional(&mut *transaction)
+                    .await
+                    .map_err(db)?;
+            return Err(match found {
+                Some(_) => MemoryError::Unauthorized,
+                None => MemoryError::NotFound(format!("submission {submission_id}")),
+            });
+        }
         for (name, c) in result.tests {
-            add_test_result(&self.pool, name, c, submission_id).await?;
+            add_test_result(&mut *transaction, name, c, submission_id).await?;
         }
-        //println!("{}", submission_id);
-        let _ = sqlx::query_as::<sqlx::Postgres, (i32,)>(
-            "SELECT user_id FROM submissions WHERE submission_id=$1 AND user_id=$2",
+        transaction.commit().await.map_err(db)?;
+        Ok(())
+    }
+
+    async fn get_submission(&self, submission_id: i64) -> Result<Submission, MemoryError> {
+        let row = query_as::<sqlx::Postgres, SubmissionRow>(
+            "SELECT * FROM submissions WHERE submission_id=$1",
         )
         .bind(submission_id)
-        .bind(user.user_id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(db)?
+        .ok_or_else(|| MemoryError::NotFound(format!("submission {submission_id}")))?;
+        Ok(self.with_results(vec![row]).await?.remove(0))
+    }
+
+    /// NULL parameters match everything, and a NULL limit returns all rows
+    async fn list_submissions(
+        &self,
+        filter: &SubmissionFilter,
+    ) -> Result<Vec<Submission>, MemoryError> {
+        let rows = query_as::<sqlx::Postgres, SubmissionRow>(
+            "SELECT * FROM submissions WHERE ($1::BIGINT IS NULL OR user_id=$1) AND ($2::VARCHAR IS NULL OR name=$2) ORDER BY submission_id OFFSET $3 LIMIT $4",
+        )
+        .bind(filter.user_id)
+        .bind(&filter.exercise_name)
+        .bind(filter.offset as i64)
+        .bind(filter.limit.map(|x| x as i64))
+        .fetch_all(&self.pool)
+        .await.map_err(db)?;
+        self.with_results(rows).await
+    }
+
+    async fn get_latest_submission(
+        &self,
+        user_id: i64,
+        exercise_name: &str,
+    ) -> Result<Option<Submission>, MemoryError> {
+        let rows = query_as::<sqlx::Postgres, SubmissionRow>(
+            "SELECT * FROM submissions WHERE user_id=$1 AND name=$2 ORDER BY submission_id DESC LIMIT 1",
+        )
+        .bind(user_id)
+        .bind(exercise_name)
+        .fetch_all(&self.pool)
+        .await.map_err(db)?;
+        Ok(self.with_results(rows).await?.pop())
+    }
+
+    /// the points are summed from test_results
+    async fn get_best_submission(
+        &self,
+        user_id: i64,
+        exercise_name: &str,
+    ) -> Result<Option<Submission>, MemoryError> {
+        let rows = query_as::<sqlx::Postgres, SubmissionRow>(
+            "SELECT s.* FROM submissions s LEFT JOIN test_results t ON t.refers_to=s.submission_id WHERE s.user_id=$1 AND s.name=$2 AND s.graded_at IS NOT NULL GROUP BY s.submission_id ORDER BY COALESCE(SUM(t.points), 0) DESC, s.submission_id DESC LIMIT 1",
+        )
+        .bind(user_id)
+        .bind(exercise_name)
+        .fetch_all(&self.pool)
+        .await.map_err(db)?;
+        Ok(self.with_results(rows).await?.pop())
+    }
+
+    /// appends an event, the table is never updated
+    async fn audit(
+        &self,
+        actor: Option<&str>,
+        action: AuditAction,
+        details: &str,
+    ) -> Result<(), MemoryError> {
+        query("INSERT INTO audit_log(actor, action, details) VALUES ($1, $2, $3)")
+            .bind(actor)
+            .bind(serde_json::to_string(&action)?)
+            .bind(details)
+            .execute(&self.pool)
+            .await
+            .map_err(db)?;
+        Ok(())
+    }
+
+    /// every filter field is optional: a NULL parameter matches everything
+    async fn get_audit_log(
+        &self,
+        _admin: &User<Admin>,
+        filter: &AuditFilter,
+    ) -> Result<Vec<AuditEvent>, MemoryError> {
+        let action = filter
+            .action
+            .map(|x| serde_json::to_string(&x))
+            .transpose()?;
+        let rows = query_as::<sqlx::Postgres, AuditRow>(
+            "SELECT * FROM audit_log WHERE ($1::VARCHAR IS NULL OR actor=$1) AND ($2::VARCHAR IS NULL OR action=$2) AND ($3::TIMESTAMPTZ IS NULL OR time>=$3) AND ($4::TIMESTAMPTZ IS NULL OR time<$4) ORDER BY id",
+        )
+        .bind(&filter.actor)
+        .bind(action)
+        .bind(filter.from)
+        .bind(filter.to)
+        .fetch_all(&self.pool)
+        .await.map_err(db)?;
+        Ok(rows
+            .into_iter()
+            .map(|x| x.try_into())
+            .collect::<Result<_, _>>()?)
+    }
+
+    async fn export_users(&self) -> Result<Vec<UserRecord>, MemoryError> {
+        Ok(
+            query_as::<sqlx::Postgres, UserWrapper>("SELECT * FROM users ORDER BY user_id")
+                .fetch_all(&self.pool)
+                .await
+                .map_err(db)?
+                .into_iter()
+                .map(|x| x.into())
+                .collect(),
+        )
+    }
+
+    async fn export_exercises(&self) -> Result<Vec<ExerciseRecord>, MemoryError> {
+        Ok(
+            query_as::<sqlx::Postgres, Problem>("SELECT * FROM problems ORDER BY name")
+                .fetch_all(&self.pool)
+                .await
+                .map_err(db)?
+                .into_iter()
+                .map(|x| x.into())
+                .collect(),
+        )
+    }
+
+    async fn export_executors(&self) -> Result<Vec<ExecutorRecord>, MemoryError> {
+        Ok(query_as::<sqlx::Postgres, Enabled>(
+            "SELECT * FROM enabled_executors ORDER BY incoming COLLATE \"C\"",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(db)?
+        .into_iter()
+        .map(|x| x.into())
+        .collect())
+    }
+
+    async fn import_user(&self, user: &UserRecord) -> Result<i64, MemoryError> {
+        let id: (i64,) = query_as(
+            "INSERT INTO users(username, password_hash, is_admin) VALUES ($1, $2, $3) RETURNING user_id",
+        )
+        .bind(&user.username)
+        .bind(&user.password_hash)
+        .bind(user.is_admin)
         .fetch_one(&self.pool)
-        .await?;
-        //TODO ADD EXERCISE RESULT IN SQL
+        .await.map_err(db)?;
+        Ok(id.0)
+    }
+
+    async fn import_exercise(&self, exercise: &ExerciseRecord) -> Result<(), MemoryError> {
+        query("INSERT INTO problems(name, ty, source) VALUES ($1, $2, $3) ON CONFLICT (name) DO UPDATE SET ty=EXCLUDED.ty, source=EXCLUDED.source")
+            .bind(&exercise.name)
+            .bind(&exercise.variant)
+            .bind(&exercise.source)
+            .execute(&self.pool)
+            .await.map_err(db)?;
         Ok(())
     }
+
+    async fn import_executor(&self, executor: &ExecutorRecord) -> Result<(), MemoryError> {
+        let mut transaction = self.pool.begin().await.map_err(db)?;
+        query("DELETE FROM enabled_executors WHERE incoming=$1")
+            .bind(&executor.input)
+            .execute(&mut *transaction)
+            .await
+            .map_err(db)?;
+        query("INSERT INTO enabled_executors(incoming, outgoing, additional_data) VALUES ($1, $2, $3)")
+            .bind(&executor.input)
+            .bind(&executor.output)
+            .bind(&executor.data)
+            .execute(&mut *transaction)
+            .await.map_err(db)?;
+        transaction.commit().await.map_err(db)?;
+        Ok(())
+    }
+
+    /// the submission is graded at the time it was submitted, the original one is not known
+    async fn import_submission(&self, submission: &Submission) -> Result<i64, MemoryError> {
+        let mut transaction = self.pool.begin().await.map_err(db)?;
+        let graded_at = submission.result.as_ref().map(|_| submission.submitted_at);
+        let id: (i64,) = query_as(
+            "INSERT INTO submissions(user_id, name, source, submitted_at, graded_at) VALUES ($1, $2, $3, $4, $5) RETURNING submission_id",
+        )
+        .bind(submission.user_id)
+        .bind(&submission.exercise_name)
+        .bind(&submission.source)
+        .bind(submission.submitted_at)
+        .bind(graded_at)
+        .fetch_one(&mut *transaction)
+        .await.map_err(db)?;
+        for (name, c) in submission.result.iter().flat_map(|x| x.tests.clone()) {
+            add_test_result(&mut *transaction, name, c, id.0)
+                .await
+                .map_err(|x| x as MemoryError)?;
+        }
+        transaction.commit().await.map_err(db)?;
+        Ok(id.0)
+    }
 }
 
 #[async_trait]
 /// state memory implementation
 impl<S: ExecutorGlobalState> StateMemory<S> for Postgres {
+    /// it replaces the executor enabled from the same input.
+    /// The cycle check and the write are done in the same transaction
     async fn enable_executor(
         &self,
         input: &S,
         output: &S,
         data: String,
-    ) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+    ) -> Result<(), MemoryError> {
+        let input = input.serialize_variant();
+        let output = output.serialize_variant();
+        let mut transaction = self.pool.begin().await.map_err(db)?;
+        // concurrent writers wait here, so that the cycle check sees the latest executors
+        query("LOCK TABLE enabled_executors IN SHARE ROW EXCLUSIVE MODE")
+            .execute(&mut *transaction)
+            .await
+            .map_err(db)?;
         let enabled: Vec<Enabled> =
             sqlx::query_as::<sqlx::Postgres, Enabled>("SELECT * FROM enabled_executors")
-                .fetch_all(&self.pool)
-                .await?;
+                .fetch_all(&mut *transaction)
+                .await
+                .map_err(db)?;
         let mut temp: HashMap<String, String> = enabled
-            .iter()
-            .map(|x| (x.incoming.clone(), x.outgoing.clone()))
+            .into_iter()
+            .map(|x| (x.incoming, x.outgoing))
             .collect();
-        let input = input.serialize_variant();
-        let output = output.serialize_variant();
         temp.insert(input.clone(), output.clone());
         if has_cycles(&temp) {
-            Err("cycle detected")?
+            Err(MemoryError::Conflict("cycle detected".to_string()))?
         }
+        query("DELETE FROM enabled_executors WHERE incoming=$1")
+            .bind(&input)
+            .execute(&mut *transaction)
+            .await
+            .map_err(db)?;
         query("INSERT INTO enabled_executors(incoming, outgoing, additional_data) VALUES ($1, $2, $3)")
             .bind(input)
             .bind(output)
             .bind(data)
-            .execute(&self.pool)
-            .await?;
+            .execute(&mut *transaction)
+            .await.map_err(db)?;
+        transaction.commit().await.map_err(db)?;
         Ok(())
     }
 
     async fn get_execution_plan(
         &self,
         input: &S,
-    ) -> Result<Vec<(TypeId, TypeId, String)>, Box<dyn StdError + Send + Sync + 'static>> {
+    ) -> Result<Vec<(TypeId, TypeId, String)>, MemoryError> {
         let enabled: Vec<Enabled> =
             sqlx::query_as::<sqlx::Postgres, Enabled>("SELECT * FROM enabled_executors")
                 .fetch_all(&self.pool)
-                .await?;
+                .await
+                .map_err(db)?;
         let enabled: HashMap<String, (String, String)> = enabled
             .into_iter()
             .map(|x| (x.incoming, (x.outgoing, x.additional_data)))
@@ -351,43 +783,83 @@ impl<S: ExecutorGlobalState> StateMemory<S> for Postgres {
         let mut cur = input.serialize_variant();
         let mut ret = Vec::new();
         while let Some((next, data)) = enabled.get(&cur) {
-            let cur_ty = S::deserialize_variant(&cur).map_err(|_| "Not deserializable")?;
-            let next_ty = S::deserialize_variant(next).map_err(|_| "Not deserializable")?;
+            let cur_ty = S::deserialize_variant(&cur).map_err(MemoryError::Backend)?;
+            let next_ty = S::deserialize_variant(next).map_err(MemoryError::Backend)?;
             ret.push((cur_ty, next_ty, data.clone()));
             cur.clone_from(next);
         }
         Ok(ret)
     }
 
+    async fn disable_executor(&self, input: &S) -> Result<(), MemoryError> {
+        let res = query("DELETE FROM enabled_executors WHERE incoming=$1")
+            .bind(input.serialize_variant())
+            .execute(&self.pool)
+            .await
+            .map_err(db)?;
+        if res.rows_affected() == 0 {
+            Err(MemoryError::NotFound("executor".to_string()))?
+        }
+        Ok(())
+    }
+
+    async fn replace_executor_data(&self, input: &S, data: String) -> Result<(), MemoryError> {
+        let res = query("UPDATE enabled_executors SET additional_data=$1 WHERE incoming=$2")
+            .bind(data)
+            .bind(input.serialize_variant())
+            .execute(&self.pool)
+            .await
+            .map_err(db)?;
+        if res.rows_affected() == 0 {
+            Err(MemoryError::NotFound("executor".to_string()))?
+        }
+        Ok(())
+    }
+
+    async fn list_enabled_executors(&self) -> Result<Vec<(TypeId, TypeId, String)>, MemoryError> {
+        let enabled: Vec<Enabled> = sqlx::query_as::<sqlx::Postgres, Enabled>(
+            "SELECT * FROM enabled_executors ORDER BY incoming COLLATE \"C\"",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(db)?;
+        let mut ret = Vec::new();
+        for x in enabled {
+            let from = S::deserialize_variant(&x.incoming).map_err(MemoryError::Backend)?;
+            let into = S::deserialize_variant(&x.outgoing).map_err(MemoryError::Backend)?;
+            ret.push((from, into, x.additional_data));
+        }
+        Ok(ret)
+    }
+
     async fn add_exercise(
         &self,
         name: String,
         exercise_type: S,
         source: String,
-    ) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+    ) -> Result<(), MemoryError> {
         let ty = exercise_type.serialize_variant();
         println!("adding {} {}", name, ty);
-        query("INSERT INTO problems(name, ty, source) VALUES ($1, $2, $3)")
+        query("INSERT INTO problems(name, ty, source) VALUES ($1, $2, $3) ON CONFLICT (name) DO UPDATE SET ty=EXCLUDED.ty, source=EXCLUDED.source")
             .bind(name)
             .bind(ty)
             .bind(source)
             .execute(&self.pool)
-            .await?;
+            .await.map_err(db)?;
         Ok(())
     }
 
     /// get an exercise from memory
     /// type, source
-    async fn get_exercise(
-        &self,
-        name: String,
-    ) -> Result<(TypeId, String), Box<dyn StdError + Send + Sync + 'static>> {
+    async fn get_exercise(&self, name: String) -> Result<(TypeId, String), MemoryError> {
         let data: Problem =
             sqlx::query_as::<sqlx::Postgres, Problem>("SELECT * FROM problems WHERE name = $1")
-                .bind(name)
-                .fetch_one(&self.pool)
-                .await?;
-        let ty = S::deserialize_variant(&data.ty)?;
+                .bind(&name)
+                .fetch_optional(&self.pool)
+                .await
+                .map_err(db)?
+                .ok_or_else(|| MemoryError::NotFound(format!("exercise {name}")))?;
+        let ty = S::deserialize_variant(&data.ty).map_err(MemoryError::Backend)?;
 
         Ok((ty, data.source))
     }
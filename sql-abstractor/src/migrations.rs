@@ -0,0 +1,156 @@
+use crate::Error;
+use chrono::{DateTime, Utc};
+use sqlx::FromRow;
+
+/// A forward migration: once released it must never be modified, changes go in a new one.
+///
+/// The sql could contain more than one statement.
+pub(crate) struct Migration {
+    /// versions are applied in increasing order
+    pub version: i64,
+    /// short description
+    pub name: &'static str,
+    /// statements to execute
+    pub sql: &'static str,
+}
+
+/// all Postgres migrations, in order.
+///
+/// The first one is the schema that was created before versioning,
+/// so it does not fail on a database that already has those tables
+pub(crate) const POSTGRES: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial",
+        sql: include_str!("sql/postgres/1_initial.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "password_resets",
+        sql: include_str!("sql/postgres/2_password_resets.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "external_identities",
+        sql: include_str!("sql/postgres/3_external_identities.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "audit_log",
+        sql: include_str!("sql/postgres/4_audit_log.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "submission_history",
+        sql: include_str!("sql/postgres/5_submission_history.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "test_diagnostics",
+        sql: include_str!("sql/postgres/6_test_diagnostics.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "compiler_diagnostics",
+        sql: include_str!("sql/postgres/7_compiler_diagnostics.sql"),
+    },
+];
+
+/// all SQLite migrations, in order.
+pub(crate) const SQLITE: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial",
+        sql: include_str!("sql/sqlite/1_initial.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "test_diagnostics",
+        sql: include_str!("sql/sqlite/2_test_diagnostics.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "compiler_diagnostics",
+        sql: include_str!("sql/sqlite/3_compiler_diagnostics.sql"),
+    },
+];
+
+#[derive(Debug, Clone, PartialEq)]
+/// A migration known by this crate or recorded in the database
+pub struct MigrationStatus {
+    /// version of the migration
+    pub version: i64,
+    /// short description
+    pub name: String,
+    /// None if it is still pending
+    pub applied_at: Option<DateTime<Utc>>,
+}
+
+#[derive(FromRow)]
+/// Internal and private struct, used to parse schema_version rows
+pub(crate) struct AppliedRow {
+    pub version: i64,
+    pub name: String,
+    pub applied_at: DateTime<Utc>,
+}
+
+/// the migrations that still need to be applied on a database at version current.
+///
+/// A database with a newer version was written by a newer release, so it is refused
+pub(crate) fn pending(migrations: &[Migration], current: i64) -> Result<&[Migration], Error> {
+    let known = migrations.last().map_or(0, |x| x.version);
+    if current > known {
+        return Err(Error::SchemaTooNew {
+            found: current,
+            known,
+        });
+    }
+    Ok(&migrations[migrations.partition_point(|x| x.version <= current)..])
+}
+
+/// merges the known migrations with the applied ones, ordered by version
+pub(crate) fn status(migrations: &[Migration], applied: Vec<AppliedRow>) -> Vec<MigrationStatus> {
+    let mut ret: Vec<MigrationStatus> = migrations
+        .iter()
+        .map(|x| MigrationStatus {
+            version: x.version,
+            name: x.name.to_string(),
+            applied_at: None,
+        })
+        .collect();
+    for row in applied {
+        match ret.iter_mut().find(|x| x.version == row.version) {
+            Some(known) => known.applied_at = Some(row.applied_at),
+            None => ret.push(MigrationStatus {
+                version: row.version,
+                name: row.name,
+                applied_at: Some(row.applied_at),
+            }),
+        }
+    }
+    ret.sort_by_key(|x| x.version);
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use super::{pending, POSTGRES, SQLITE};
+    use crate::Error;
+
+    #[test]
+    fn test_pending() {
+        for migrations in [POSTGRES, SQLITE] {
+            assert!(migrations
+                .windows(2)
+                .all(|x| x[0].version + 1 == x[1].version));
+            assert_eq!(pending(migrations, 0).unwrap().len(), migrations.len());
+            let last = migrations.last().unwrap().version;
+            assert!(pending(migrations, last).unwrap().is_empty());
+            assert!(matches!(
+                pending(migrations, last + 1),
+                Err(Error::SchemaTooNew { .. })
+            ));
+        }
+        assert_eq!(pending(POSTGRES, 3).unwrap()[0].version, 4);
+    }
+}
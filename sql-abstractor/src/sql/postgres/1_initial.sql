@@ -0,0 +1,40 @@
+CREATE TABLE IF NOT EXISTS users(
+	user_id BIGSERIAL PRIMARY KEY,
+	username VARCHAR(255) NOT NULL UNIQUE,
+	password_hash VARCHAR(255) NOT NULL,
+	logged_in_time TIMESTAMPTZ,
+    logged_in_token CHAR(20),
+	is_admin BOOLEAN NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS problems(
+	name VARCHAR(255) PRIMARY KEY,
+	ty VARCHAR(255),
+    source TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS enabled_executors(
+    incoming VARCHAR(255) ,
+    outgoing VARCHAR(255) ,
+	additional_data TEXT,
+    PRIMARY KEY (incoming, outgoing)
+);
+
+CREATE TABLE IF NOT EXISTS submissions(
+    submission_id BIGSERIAL PRIMARY KEY,
+    user_id Integer NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    source VARCHAR(255) NOT NULL,
+    FOREIGN KEY (name) REFERENCES Problems(name),
+    FOREIGN KEY (user_id) REFERENCES Users(user_id)
+);
+
+CREATE TABLE IF NOT EXISTS test_results(
+    test_results_id BIGSERIAL PRIMARY KEY,
+    name VARCHAR(255) NOT NULL,
+    compiled TEXT NOT NULL,
+    runned TEXT NOT NULL,
+    points FLOAT NOT NULL,
+    refers_to BIGINT,
+    FOREIGN KEY (refers_to) REFERENCES submissions(submission_id)
+);
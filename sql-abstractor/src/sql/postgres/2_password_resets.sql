@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS password_resets(
+    token CHAR(20) PRIMARY KEY,
+    user_id BIGINT NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    used BOOLEAN NOT NULL DEFAULT false,
+    FOREIGN KEY (user_id) REFERENCES users(user_id)
+);
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS external_identities(
+    issuer VARCHAR(255) NOT NULL,
+    subject VARCHAR(255) NOT NULL,
+    user_id BIGINT NOT NULL,
+    PRIMARY KEY (issuer, subject),
+    FOREIGN KEY (user_id) REFERENCES users(user_id)
+);
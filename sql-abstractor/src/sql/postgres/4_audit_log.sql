@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS audit_log(
+    id BIGSERIAL PRIMARY KEY,
+    time TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    actor VARCHAR(255),
+    action VARCHAR(255) NOT NULL,
+    details TEXT NOT NULL
+);
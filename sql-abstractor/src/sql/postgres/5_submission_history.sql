@@ -0,0 +1,8 @@
+-- full sources, and the time of submission and grading
+ALTER TABLE submissions ALTER COLUMN user_id TYPE BIGINT;
+ALTER TABLE submissions ALTER COLUMN source TYPE TEXT;
+ALTER TABLE submissions ADD COLUMN IF NOT EXISTS submitted_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+ALTER TABLE submissions ADD COLUMN IF NOT EXISTS graded_at TIMESTAMPTZ;
+-- before this version results were saved without marking the submission
+UPDATE submissions SET graded_at=submitted_at
+    WHERE graded_at IS NULL AND EXISTS (SELECT 1 FROM test_results WHERE refers_to=submission_id);
@@ -0,0 +1,6 @@
+-- the errors and warnings of the compiler are in the result of the compilation:
+-- "Built" becomes {"Built": []} and {"Error": "..."} becomes {"Error": ["...", []]}
+UPDATE test_results SET compiled = '{"Built":[]}' WHERE compiled = '"Built"';
+UPDATE test_results
+SET compiled = json_build_object('Error', json_build_array(compiled::json -> 'Error', '[]'::json))::text
+WHERE json_typeof(compiled::json -> 'Error') = 'string';
@@ -0,0 +1,67 @@
+CREATE TABLE IF NOT EXISTS users(
+	user_id INTEGER PRIMARY KEY AUTOINCREMENT,
+	username VARCHAR(255) NOT NULL UNIQUE,
+	password_hash VARCHAR(255) NOT NULL,
+	logged_in_time DATETIME,
+    logged_in_token CHAR(20),
+	is_admin BOOLEAN NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS problems(
+	name VARCHAR(255) PRIMARY KEY,
+	ty VARCHAR(255),
+    source TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS enabled_executors(
+    incoming VARCHAR(255) ,
+    outgoing VARCHAR(255) ,
+	additional_data TEXT,
+    PRIMARY KEY (incoming, outgoing)
+);
+
+CREATE TABLE IF NOT EXISTS submissions(
+    submission_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    source TEXT NOT NULL,
+    submitted_at DATETIME NOT NULL,
+    graded_at DATETIME,
+    FOREIGN KEY (name) REFERENCES problems(name),
+    FOREIGN KEY (user_id) REFERENCES users(user_id)
+);
+
+CREATE TABLE IF NOT EXISTS test_results(
+    test_results_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name VARCHAR(255) NOT NULL,
+    compiled TEXT NOT NULL,
+    runned TEXT NOT NULL,
+    points REAL NOT NULL,
+    refers_to INTEGER,
+    FOREIGN KEY (refers_to) REFERENCES submissions(submission_id)
+);
+
+CREATE TABLE IF NOT EXISTS password_resets(
+    token CHAR(20) PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    expires_at DATETIME NOT NULL,
+    used BOOLEAN NOT NULL DEFAULT false,
+    FOREIGN KEY (user_id) REFERENCES users(user_id)
+);
+
+CREATE TABLE IF NOT EXISTS external_identities(
+    issuer VARCHAR(255) NOT NULL,
+    subject VARCHAR(255) NOT NULL,
+    user_id INTEGER NOT NULL,
+    PRIMARY KEY (issuer, subject),
+    FOREIGN KEY (user_id) REFERENCES users(user_id)
+);
+
+CREATE TABLE IF NOT EXISTS audit_log(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    time DATETIME NOT NULL,
+    actor VARCHAR(255),
+    action VARCHAR(255) NOT NULL,
+    details TEXT NOT NULL
+);
+
@@ -0,0 +1,2 @@
+-- stdout, stderr, panic and exit status of each test, as json
+ALTER TABLE test_results ADD COLUMN diagnostics TEXT;
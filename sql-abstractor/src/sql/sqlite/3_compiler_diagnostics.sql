@@ -0,0 +1,6 @@
+-- the errors and warnings of the compiler are in the result of the compilation:
+-- "Built" becomes {"Built": []} and {"Error": "..."} becomes {"Error": ["...", []]}
+UPDATE test_results SET compiled = '{"Built":[]}' WHERE compiled = '"Built"';
+UPDATE test_results
+SET compiled = json_object('Error', json_array(json_extract(compiled, '$.Error'), json('[]')))
+WHERE json_type(compiled, '$.Error') = 'text';
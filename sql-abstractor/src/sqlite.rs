@@ -0,0 +1,1112 @@
+use crate::helpers::{db, AuditRow, Enabled, Problem, SubmissionRow, TestResultRow, UserWrapper};
+use crate::migrations::{self, AppliedRow, SQLITE};
+use crate::{Error, MigrationStatus};
+use orchestrator::default_memory::{has_cycles, new_token};
+use orchestrator::executor::ExecutorGlobalState;
+use orchestrator::password::{PasswordPolicy, Verification, DISABLED_PASSWORD};
+use orchestrator::prelude::*;
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions};
+use sqlx::{query, query_as, Executor, Pool, SqliteExecutor};
+
+/// all the tables, children before parents (because of foreign keys)
+const TABLES: [&str; 9] = [
+    "audit_log",
+    "external_identities",
+    "password_resets",
+    "test_results",
+    "submissions",
+    "enabled_executors",
+    "problems",
+    "users",
+    "schema_version",
+];
+
+/// table that records the applied migrations
+const SCHEMA_VERSION: &str = "CREATE TABLE IF NOT EXISTS schema_version(version INTEGER PRIMARY KEY, name VARCHAR(255) NOT NULL, applied_at DATETIME NOT NULL)";
+
+///SQLite implementation.
+///
+/// It has the same semantics as Postgres, but it does not need a server:
+/// the database is a single file (or it lives in memory), so a whole deployment fits in one binary.
+///
+/// SQLite allows a single writer at a time, so every query goes through the same connection.
+/// Timestamps are stored as RFC 3339 strings, always written by this crate.
+///
+/// # Examples
+/// ```
+/// use sql_abstractor::Sqlite;
+/// use orchestrator::prelude::*;
+/// # tokio_test::block_on(
+///  #   async {
+/// GenerateState!(ExerciseResult, DummyExercise);
+/// let m = Box::new(Sqlite::memory().await.unwrap());
+/// let mut o: Orchestrator<State> = Orchestrator::new(1, false, m);
+/// #       let def = DefaultTest::new_default();
+/// #       o.add_plugin(def).await.unwrap();
+/// o.run().await;
+/// #    }
+/// # );
+///```
+pub struct Sqlite {
+    /// inner type, it is a Pool from sqlx (SQLite version)
+    pool: Pool<sqlx::Sqlite>,
+    /// how passwords get hashed
+    policy: PasswordPolicy,
+}
+
+/// implement some initialization
+impl Sqlite {
+    /// connect to the database, without touching the schema.
+    /// The url could be a path (sqlite://data.db) or sqlite::memory:, the file is created if it is missing.
+    ///
+    /// Used to inspect or apply migrations, see init for the usual way to obtain Self
+    pub async fn connect(url: &str) -> Result<Self, Error> {
+        let options = SqliteConnectOptions::from_str(url)?
+            .create_if_missing(true)
+            .foreign_keys(true)
+            .journal_mode(SqliteJournalMode::Wal);
+        // the only connection is never closed, otherwise an in memory database would be lost
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .min_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect_with(options)
+            .await?;
+        Ok(Self {
+            pool,
+            policy: PasswordPolicy::default(),
+        })
+    }
+    /// initialize connector, applying all pending migrations.
+    /// It could be used multiple times without loosing any data.
+    ///
+    /// This is the correct way to obtain Self
+    pub async fn init(url: &str) -> Result<Self, Error> {
+        let memory = Self::connect(url).await?;
+        memory.migrate().await?;
+        Ok(memory)
+    }
+    /// current schema version, 0 for an empty database
+    async fn schema_version<'c, E: SqliteExecutor<'c>>(executor: E) -> Result<i64, Error> {
+        let (version,): (i64,) = query_as("SELECT COALESCE(MAX(version), 0) FROM schema_version")
+            .fetch_one(executor)
+            .await?;
+        Ok(version)
+    }
+    /// all known and applied migrations
+    pub async fn migration_status(&self) -> Result<Vec<MigrationStatus>, Error> {
+        query(SCHEMA_VERSION).execute(&self.pool).await?;
+        let applied = query_as::<sqlx::Sqlite, AppliedRow>("SELECT * FROM schema_version")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(migrations::status(SQLITE, applied))
+    }
+    /// applies the pending migrations in order, each one in its own transaction.
+    /// Returns the applied versions.
+    ///
+    /// It fails if the database was migrated by a newer version of this crate
+    pub async fn migrate(&self) -> Result<Vec<i64>, Error> {
+        query(SCHEMA_VERSION).execute(&self.pool).await?;
+        let current = Self::schema_version(&self.pool).await?;
+        let mut applied = Vec::new();
+        for migration in migrations::pending(SQLITE, current)? {
+            let mut transaction = self.pool.begin().await?;
+            (&mut *transaction)
+                .execute(migration.sql)
+                .await
+                .map_err(|x| {
+                    Error::String(format!(
+                        "Error while applying migration {} {}: {x}",
+                        migration.version, migration.name
+                    ))
+                })?;
+            query("INSERT INTO schema_version(version, name, applied_at) VALUES ($1, $2, $3)")
+                .bind(migration.version)
+                .bind(migration.name)
+                .bind(Utc::now())
+                .execute(&mut *transaction)
+                .await?;
+            transaction.commit().await?;
+            applied.push(migration.version);
+        }
+        Ok(applied)
+    }
+    /// a new empty database, that lives as long as Self
+    pub async fn memory() -> Result<Self, Error> {
+        Self::init("sqlite::memory:").await
+    }
+    /// use the given policy to hash passwords.
+    ///
+    /// Already saved hashes are still valid, and they get updated on the next login.
+    pub fn with_password_policy(mut self, policy: PasswordPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+    /// WARNING: THIS WILL ERASE ALL THE DATA CONTAINED IN THE DATABASE, AND THEN INIT
+    pub async fn clean_init(url: &str) -> Result<Self, Error> {
+        let memory = Self::connect(url).await?;
+        for table in TABLES {
+            query(&format!("DROP TABLE IF EXISTS {table}"))
+                .execute(&memory.pool)
+                .await?;
+        }
+        memory.migrate().await?;
+        Ok(memory)
+    }
+    /// attach the results to the submissions
+    async fn with_results(&self, rows: Vec<SubmissionRow>) -> Result<Vec<Submission>, MemoryError> {
+        let ids: Vec<i64> = rows.iter().map(|x| x.submission_id).collect();
+        let mut results = get_exercise_results(&self.pool, &ids).await?;
+        Ok(rows
+            .into_iter()
+            .map(|x| x.into_submission(&mut results))
+            .collect())
+    }
+}
+
+/// saves a single test result
+async fn add_test_result<'c, E: SqliteExecutor<'c>>(
+    executor: E,
+    name: String,
+    result: TestResult,
+    submission_id: i64,
+) -> Result<(), MemoryError> {
+    let compiled = serde_json::to_string(&result.compiled)?;
+    let runned = serde_json::to_string(&result.runned)?;
+    let diagnostics = result
+        .diagnostics
+        .as_ref()
+        .map(serde_json::to_string)
+        .transpose()?;
+    query("INSERT INTO test_results(name, compiled, runned, points, refers_to, diagnostics) VALUES ($1, $2, $3, $4, $5, $6)")
+        .bind(name)
+        .bind(compiled)
+        .bind(runned)
+        .bind(result.points_given)
+        .bind(submission_id)
+        .bind(diagnostics)
+        .execute(executor)
+        .await.map_err(db)?;
+    Ok(())
+}
+
+/// rebuild the results of the given submissions from their test results.
+///
+/// SQLite has no arrays, so the ids are passed as a json array
+async fn get_exercise_results(
+    pool: &Pool<sqlx::Sqlite>,
+    submission_ids: &[i64],
+) -> Result<HashMap<i64, ExerciseResult>, MemoryError> {
+    let rows = query_as::<sqlx::Sqlite, TestResultRow>(
+        "SELECT name, compiled, runned, points, refers_to, diagnostics FROM test_results WHERE refers_to IN (SELECT value FROM json_each($1))",
+    )
+    .bind(serde_json::to_string(submission_ids)?)
+    .fetch_all(pool)
+    .await.map_err(db)?;
+    let mut ret: HashMap<i64, ExerciseResult> = HashMap::new();
+    for row in rows {
+        let result = TestResult {
+            compiled: serde_json::from_str(&row.compiled)?,
+            runned: serde_json::from_str(&row.runned)?,
+            points_given: row.points,
+            diagnostics: row
+                .diagnostics
+                .as_deref()
+                .map(serde_json::from_str)
+                .transpose()?,
+        };
+        ret.entry(row.refers_to)
+            .or_default()
+            .tests
+            .insert(row.name, result);
+    }
+    Ok(ret)
+}
+
+#[async_trait]
+/// implementation of the StatelessMemory trait
+impl StatelessMemory for Sqlite {
+    async fn register(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<User<Unauthenticated>, MemoryError> {
+        //Hash password, it's cpu expensive, so it's executed in a blocking way
+        let hash = self.policy.hash_async(password).await?;
+        //insert new user
+        query("INSERT INTO users(username, password_hash, is_admin) VALUES ($1, $2, false)")
+            .bind(username)
+            .bind(&hash)
+            .execute(&self.pool)
+            .await
+            .map_err(|x| match db(x) {
+                MemoryError::AlreadyExists(_) => {
+                    MemoryError::AlreadyExists(format!("user {username}"))
+                }
+                x => x,
+            })?;
+        self.get_by_username(username).await
+    }
+
+    /// tries to login the current user, returning an authenticated user instance
+    async fn login(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<User<Authenticated>, MemoryError> {
+        // an unknown user is not told apart from a wrong password
+        let user = self.get_by_username(username).await.map_err(|x| match x {
+            MemoryError::NotFound(_) => MemoryError::Unauthorized,
+            x => x,
+        })?;
+        //check password
+        match self
+            .policy
+            .verify_async(password, &user.password_hash)
+            .await?
+        {
+            Verification::Invalid => Err(MemoryError::Unauthorized)?,
+            Verification::Valid => {}
+            Verification::NeedsRehash => {
+                let hash = self.policy.hash_async(password).await?;
+                // not if the password has been changed in the meantime
+                query("UPDATE users SET password_hash=$1 WHERE user_id=$2 AND password_hash=$3")
+                    .bind(hash)
+                    .bind(user.user_id)
+                    .bind(&user.password_hash)
+                    .execute(&self.pool)
+                    .await
+                    .map_err(db)?;
+            }
+        }
+
+        //update token
+        let user: User<Authenticated> = query_as::<sqlx::Sqlite, UserWrapper>(
+            "UPDATE users SET logged_in_time=$1, logged_in_token=$2 WHERE user_id=$3 RETURNING *",
+        )
+        .bind(Utc::now())
+        .bind(new_token())
+        .bind(user.user_id)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(db)?
+        .into();
+        Ok(user)
+    }
+    /// finds the user linked to the identity, or registers it. Then updates its token
+    async fn login_external(
+        &self,
+        identity: &ExternalIdentity,
+    ) -> Result<User<Authenticated>, MemoryError> {
+        let mut transaction = self.pool.begin().await.map_err(db)?;
+        let linked: Option<(i64,)> =
+            query_as("SELECT user_id FROM external_identities WHERE issuer=$1 AND subject=$2")
+                .bind(&identity.issuer)
+                .bind(&identity.subject)
+                .fetch_optional(&mut *transaction)
+                .await
+                .map_err(db)?;
+        let user_id = match linked {
+            Some((user_id,)) => user_id,
+            None => {
+                // first login, register it
+                let mut username = identity.preferred_username();
+                let taken: Option<(i64,)> = query_as("SELECT user_id FROM users WHERE username=$1")
+                    .bind(&username)
+                    .fetch_optional(&mut *transaction)
+                    .await
+                    .map_err(db)?;
+                if taken.is_some() {
+                    username = format!("{} ({})", username, identity.subject);
+                }
+                let (user_id,): (i64,) = query_as("INSERT INTO users(username, password_hash, is_admin) VALUES ($1, $2, false) RETURNING user_id")
+                    .bind(&username)
+                    .bind(DISABLED_PASSWORD)
+                    .fetch_one(&mut *transaction)
+                    .await.map_err(db)?;
+                query(
+                    "INSERT INTO external_identities(issuer, subject, user_id) VALUES ($1, $2, $3)",
+                )
+                .bind(&identity.issuer)
+                .bind(&identity.subject)
+                .bind(user_id)
+                .execute(&mut *transaction)
+                .await
+                .map_err(db)?;
+                user_id
+            }
+        };
+        let user: User<Authenticated> = query_as::<sqlx::Sqlite, UserWrapper>(
+            "UPDATE users SET logged_in_time=$1, logged_in_token=$2 WHERE user_id=$3 RETURNING *",
+        )
+        .bind(Utc::now())
+        .bind(new_token())
+        .bind(user_id)
+        .fetch_one(&mut *transaction)
+        .await
+        .map_err(db)?
+        .into();
+        transaction.commit().await.map_err(db)?;
+        Ok(user)
+    }
+
+    /// checks the old password, and then saves the new one
+    async fn change_password(
+        &self,
+        user: User<Authenticated>,
+        old_password: &str,
+        new_password: &str,
+    ) -> Result<(), MemoryError> {
+        let stored = self.get_by_username(&user.username).await?;
+        if !self
+            .policy
+            .verify_async(old_password, &stored.password_hash)
+            .await?
+            .is_valid()
+        {
+            Err(MemoryError::Unauthorized)?
+        }
+        let hash = self.policy.hash_async(new_password).await?;
+        query("UPDATE users SET password_hash=$1 WHERE user_id=$2")
+            .bind(hash)
+            .bind(stored.user_id)
+            .execute(&self.pool)
+            .await
+            .map_err(db)?;
+        Ok(())
+    }
+
+    /// saves a new reset token for the user
+    async fn create_password_reset(
+        &self,
+        username: &str,
+        validity: Duration,
+    ) -> Result<PasswordReset, MemoryError> {
+        let user = self.get_by_username(username).await?;
+        // it would give a password to an external user
+        if user.password_hash == DISABLED_PASSWORD {
+            Err(MemoryError::NotFound(format!("user {username}")))?
+        }
+        let reset = PasswordReset {
+            username: user.username,
+            token: new_token(),
+            expires_at: Utc::now() + validity,
+        };
+        query("INSERT INTO password_resets(token, user_id, expires_at) VALUES ($1, $2, $3)")
+            .bind(&reset.token)
+            .bind(user.user_id)
+            .bind(reset.expires_at)
+            .execute(&self.pool)
+            .await
+            .map_err(db)?;
+        Ok(reset)
+    }
+
+    /// marks the token as used (only if it is still valid), then updates the password and logs out the user
+    async fn reset_password(&self, token: &str, new_password: &str) -> Result<(), MemoryError> {
+        let hash = self.policy.hash_async(new_password).await?;
+        let mut transaction = self.pool.begin().await.map_err(db)?;
+        let user_id: Option<(i64,)> = query_as(
+            "UPDATE password_resets SET used=true WHERE token=$1 AND NOT used AND expires_at > $2 RETURNING user_id",
+        )
+        .bind(token)
+        .bind(Utc::now())
+        .fetch_optional(&mut *transaction)
+        .await.map_err(db)?;
+        let (user_id,) = user_id.ok_or(MemoryError::Unauthorized)?;
+        query("UPDATE users SET password_hash=$1, logged_in_token=NULL WHERE user_id=$2")
+            .bind(hash)
+            .bind(user_id)
+            .execute(&mut *transaction)
+            .await
+            .map_err(db)?;
+        transaction.commit().await.map_err(db)?;
+        Ok(())
+    }
+
+    /// gets a user by its username
+    async fn get_by_username(&self, username: &str) -> Result<User<Unauthenticated>, MemoryError> {
+        Ok(
+            query_as::<sqlx::Sqlite, UserWrapper>("SELECT * FROM users WHERE users.username = $1")
+                .bind(username)
+                .fetch_optional(&self.pool)
+                .await
+                .map_err(db)?
+                .ok_or_else(|| MemoryError::NotFound(format!("user {username}")))?
+                .into(),
+        )
+    }
+
+    ///returns all user present in the DB.
+    async fn get_all_users(&self) -> Result<Vec<User<Unauthenticated>>, MemoryError> {
+        Ok(query_as::<sqlx::Sqlite, UserWrapper>("SELECT * FROM users")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(db)?
+            .into_iter()
+            .map(|x| x.into())
+            .collect())
+    }
+    /// check if the given token is valid, and if so returns the correct user
+    async fn get_authenticate(&self, token: &str) -> Result<User<Authenticated>, MemoryError> {
+        Ok(
+            query_as::<sqlx::Sqlite, UserWrapper>("SELECT * FROM users WHERE logged_in_token = $1")
+                .bind(token)
+                .fetch_optional(&self.pool)
+                .await
+                .map_err(db)?
+                .ok_or(MemoryError::Unauthorized)?
+                .into(),
+        )
+    }
+
+    /// if the token is valid, it get's the user, and if so checks if it is an Admin
+    async fn get_admin(&self, token: &str) -> Result<User<Admin>, MemoryError> {
+        let user = self.get_authenticate(token).await?;
+        if user.is_admin {
+            Ok(user.transmute())
+        } else {
+            Err(MemoryError::Unauthorized)
+        }
+    }
+    async fn list_exercise_names(&self) -> Result<Vec<String>, MemoryError> {
+        let data: Vec<Problem> = query_as::<sqlx::Sqlite, Problem>("SELECT * FROM problems")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(db)?;
+        Ok(data.into_iter().map(|x| x.name).collect())
+    }
+    ///add submission (on success returns submission id)
+    async fn add_submission(
+        &self,
+        exercise_name: String,
+        source: String,
+        user: User<Authenticated>,
+    ) -> Result<i64, MemoryError> {
+        let id: (i64,) = query_as(
+            "INSERT INTO submissions(user_id, name, source, submitted_at) VALUES ($1, $2, $3, $4) RETURNING submission_id",
+        )
+        .bind(user.user_id)
+        .bind(&exercise_name)
+        .bind(source)
+        .bind(Utc::now())
+        .fetch_one(&self.pool)
+        .await.map_err(|x| match db(x) {
+            MemoryError::NotFound(_) => MemoryError::NotFound(format!("exercise {exercise_name}")),
+            x => x,
+        })?;
+        Ok(id.0)
+    }
+
+    ///add exercise result, only the user that submitted can add it
+    async fn add_exercise_result(
+        &self,
+        submission_id: i64,
+        user: User<Authenticated>,
+        result: ExerciseResult,
+    ) -> Result<(), MemoryError> {
+        let mut transaction = self.pool.begin().await.map_err(db)?;
+        //check if the user owns the current
+        let graded = query_as::<sqlx::Sqlite, (i64,)>(
+            "UPDATE submissions SET graded_at=$1 WHERE submission_id=$2 AND user_id=$3 RETURNING submission_id",
+        )
+        .bind(Utc::now())
+        .bind(submission_id)
+        .bind(user.user_id)
+        .fetch_optional(&mut *transaction)
+        .await
+        .map_err(db)?;
+        if graded.is_none() {
+            // a missing submission is told apart from one of another user
+            let found: Option<(i64,)> =
+                query_as("SELECT submission_id FROM submissions WHERE submission_id=$1")
+                    .bind(submission_id)
+                    .fetch_optional(&mut *transaction)
+                    .await
+                    .map_err(db)?;
+            return Err(match found {
+                Some(_) => MemoryError::Unauthorized,
+                None => MemoryError::NotFound(format!("submission {submission_id}")),
+            });
+        }
+        for (name, c) in result.tests {
+            add_test_result(&mut *transaction, name, c, submission_id).await?;
+        }
+        transaction.commit().await.map_err(db)?;
+        Ok(())
+    }
+
+    async fn get_submission(&self, submission_id: i64) -> Result<Submission, MemoryError> {
+        let row = query_as::<sqlx::Sqlite, SubmissionRow>(
+            "SELECT * FROM submissions WHERE submission_id=$1",
+        )
+        .bind(submission_id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(db)?
+        .ok_or_else(|| MemoryError::NotFound(format!("submission {submission_id}")))?;
+        Ok(self.with_results(vec![row]).await?.remove(0))
+    }
+
+    /// NULL parameters match everything, and a negative limit returns all rows
+    async fn list_submissions(
+        &self,
+        filter: &SubmissionFilter,
+    ) -> Result<Vec<Submission>, MemoryError> {
+        let rows = query_as::<sqlx::Sqlite, SubmissionRow>(
+            "SELECT * FROM submissions WHERE ($1 IS NULL OR user_id=$1) AND ($2 IS NULL OR name=$2) ORDER BY submission_id LIMIT $4 OFFSET $3",
+        )
+        .bind(filter.user_id)
+        .bind(&filter.exercise_name)
+        .bind(filter.offset as i64)
+        .bind(filter.limit.map_or(-1, |x| x as i64))
+        .fetch_all(&self.pool)
+        .await.map_err(db)?;
+        self.with_results(rows).await
+    }
+
+    async fn get_latest_submission(
+        &self,
+        user_id: i64,
+        exercise_name: &str,
+    ) -> Result<Option<Submission>, MemoryError> {
+        let rows = query_as::<sqlx::Sqlite, SubmissionRow>(
+            "SELECT * FROM submissions WHERE user_id=$1 AND name=$2 ORDER BY submission_id DESC LIMIT 1",
+        )
+        .bind(user_id)
+        .bind(exercise_name)
+        .fetch_all(&self.pool)
+        .await.map_err(db)?;
+        Ok(self.with_results(rows).await?.pop())
+    }
+
+    /// the points are summed from test_results
+    async fn get_best_submission(
+        &self,
+        user_id: i64,
+        exercise_name: &str,
+    ) -> Result<Option<Submission>, MemoryError> {
+        let rows = query_as::<sqlx::Sqlite, SubmissionRow>(
+            "SELECT s.* FROM submissions s LEFT JOIN test_results t ON t.refers_to=s.submission_id WHERE s.user_id=$1 AND s.name=$2 AND s.graded_at IS NOT NULL GROUP BY s.submission_id ORDER BY COALESCE(SUM(t.points), 0) DESC, s.submission_id DESC LIMIT 1",
+        )
+        .bind(user_id)
+        .bind(exercise_name)
+        .fetch_all(&self.pool)
+        .await.map_err(db)?;
+        Ok(self.with_results(rows).await?.pop())
+    }
+
+    /// appends an event, the table is never updated
+    async fn audit(
+        &self,
+        actor: Option<&str>,
+        action: AuditAction,
+        details: &str,
+    ) -> Result<(), MemoryError> {
+        query("INSERT INTO audit_log(time, actor, action, details) VALUES ($1, $2, $3, $4)")
+            .bind(Utc::now())
+            .bind(actor)
+            .bind(serde_json::to_string(&action)?)
+            .bind(details)
+            .execute(&self.pool)
+            .await
+            .map_err(db)?;
+        Ok(())
+    }
+
+    /// every filter field is optional: a NULL parameter matches everything.
+    ///
+    /// Times are compared as strings, it works because they are all RFC 3339 in UTC
+    async fn get_audit_log(
+        &self,
+        _admin: &User<Admin>,
+        filter: &AuditFilter,
+    ) -> Result<Vec<AuditEvent>, MemoryError> {
+        let action = filter
+            .action
+            .map(|x| serde_json::to_string(&x))
+            .transpose()?;
+        let rows = query_as::<sqlx::Sqlite, AuditRow>(
+            "SELECT * FROM audit_log WHERE ($1 IS NULL OR actor=$1) AND ($2 IS NULL OR action=$2) AND ($3 IS NULL OR time>=$3) AND ($4 IS NULL OR time<$4) ORDER BY id",
+        )
+        .bind(&filter.actor)
+        .bind(action)
+        .bind(filter.from)
+        .bind(filter.to)
+        .fetch_all(&self.pool)
+        .await.map_err(db)?;
+        Ok(rows
+            .into_iter()
+            .map(|x| x.try_into())
+            .collect::<Result<_, _>>()?)
+    }
+
+    async fn export_users(&self) -> Result<Vec<UserRecord>, MemoryError> {
+        Ok(
+            query_as::<sqlx::Sqlite, UserWrapper>("SELECT * FROM users ORDER BY user_id")
+                .fetch_all(&self.pool)
+                .await
+                .map_err(db)?
+                .into_iter()
+                .map(|x| x.into())
+                .collect(),
+        )
+    }
+
+    async fn export_exercises(&self) -> Result<Vec<ExerciseRecord>, MemoryError> {
+        Ok(
+            query_as::<sqlx::Sqlite, Problem>("SELECT * FROM problems ORDER BY name")
+                .fetch_all(&self.pool)
+                .await
+                .map_err(db)?
+                .into_iter()
+                .map(|x| x.into())
+                .collect(),
+        )
+    }
+
+    async fn export_executors(&self) -> Result<Vec<ExecutorRecord>, MemoryError> {
+        Ok(
+            query_as::<sqlx::Sqlite, Enabled>("SELECT * FROM enabled_executors ORDER BY incoming")
+                .fetch_all(&self.pool)
+                .await
+                .map_err(db)?
+                .into_iter()
+                .map(|x| x.into())
+                .collect(),
+        )
+    }
+
+    async fn import_user(&self, user: &UserRecord) -> Result<i64, MemoryError> {
+        let id: (i64,) = query_as(
+            "INSERT INTO users(username, password_hash, is_admin) VALUES ($1, $2, $3) RETURNING user_id",
+        )
+        .bind(&user.username)
+        .bind(&user.password_hash)
+        .bind(user.is_admin)
+        .fetch_one(&self.pool)
+        .await.map_err(db)?;
+        Ok(id.0)
+    }
+
+    async fn import_exercise(&self, exercise: &ExerciseRecord) -> Result<(), MemoryError> {
+        query("INSERT INTO problems(name, ty, source) VALUES ($1, $2, $3) ON CONFLICT (name) DO UPDATE SET ty=EXCLUDED.ty, source=EXCLUDED.source")
+            .bind(&exercise.name)
+            .bind(&exercise.variant)
+            .bind(&exercise.source)
+            .execute(&self.pool)
+            .await.map_err(db)?;
+        Ok(())
+    }
+
+    async fn import_executor(&self, executor: &ExecutorRecord) -> Result<(), MemoryError> {
+        let mut transaction = self.pool.begin().await.map_err(db)?;
+        query("DELETE FROM enabled_executors WHERE incoming=$1")
+            .bind(&executor.input)
+            .execute(&mut *transaction)
+            .await
+            .map_err(db)?;
+        query("INSERT INTO enabled_executors(incoming, outgoing, additional_data) VALUES ($1, $2, $3)")
+            .bind(&executor.input)
+            .bind(&executor.output)
+            .bind(&executor.data)
+            .execute(&mut *transaction)
+            .await.map_err(db)?;
+        transaction.commit().await.map_err(db)?;
+        Ok(())
+    }
+
+    /// the submission is graded at the time it was submitted, the original one is not known
+    async fn import_submission(&self, submission: &Submission) -> Result<i64, MemoryError> {
+        let mut transaction = self.pool.begin().await.map_err(db)?;
+        let graded_at = submission.result.as_ref().map(|_| submission.submitted_at);
+        let id: (i64,) = query_as(
+            "INSERT INTO submissions(user_id, name, source, submitted_at, graded_at) VALUES ($1, $2, $3, $4, $5) RETURNING submission_id",
+        )
+        .bind(submission.user_id)
+        .bind(&submission.exercise_name)
+        .bind(&submission.source)
+        .bind(submission.submitted_at)
+        .bind(graded_at)
+        .fetch_one(&mut *transaction)
+        .await.map_err(db)?;
+        for (name, c) in submission.result.iter().flat_map(|x| x.tests.clone()) {
+            add_test_result(&mut *transaction, name, c, id.0)
+                .await
+                .map_err(|x| x as MemoryError)?;
+        }
+        transaction.commit().await.map_err(db)?;
+        Ok(id.0)
+    }
+}
+
+#[async_trait]
+/// state memory implementation
+impl<S: ExecutorGlobalState> StateMemory<S> for Sqlite {
+    /// it replaces the executor enabled from the same input.
+    /// The cycle check and the write are done in the same transaction
+    async fn enable_executor(
+        &self,
+        input: &S,
+        output: &S,
+        data: String,
+    ) -> Result<(), MemoryError> {
+        let input = input.serialize_variant();
+        let output = output.serialize_variant();
+        let mut transaction = self.pool.begin().await.map_err(db)?;
+        // the pool has a single connection: reading inside the transaction is enough to be atomic
+        let enabled: Vec<Enabled> =
+            query_as::<sqlx::Sqlite, Enabled>("SELECT * FROM enabled_executors")
+                .fetch_all(&mut *transaction)
+                .await
+                .map_err(db)?;
+        let mut temp: HashMap<String, String> = enabled
+            .into_iter()
+            .map(|x| (x.incoming, x.outgoing))
+            .collect();
+        temp.insert(input.clone(), output.clone());
+        if has_cycles(&temp) {
+            Err(MemoryError::Conflict("cycle detected".to_string()))?
+        }
+        query("DELETE FROM enabled_executors WHERE incoming=$1")
+            .bind(&input)
+            .execute(&mut *transaction)
+            .await
+            .map_err(db)?;
+        query("INSERT INTO enabled_executors(incoming, outgoing, additional_data) VALUES ($1, $2, $3)")
+            .bind(input)
+            .bind(output)
+            .bind(data)
+            .execute(&mut *transaction)
+            .await.map_err(db)?;
+        transaction.commit().await.map_err(db)?;
+        Ok(())
+    }
+
+    async fn get_execution_plan(
+        &self,
+        input: &S,
+    ) -> Result<Vec<(TypeId, TypeId, String)>, MemoryError> {
+        let enabled: Vec<Enabled> =
+            query_as::<sqlx::Sqlite, Enabled>("SELECT * FROM enabled_executors")
+                .fetch_all(&self.pool)
+                .await
+                .map_err(db)?;
+        let enabled: HashMap<String, (String, String)> = enabled
+            .into_iter()
+            .map(|x| (x.incoming, (x.outgoing, x.additional_data)))
+            .collect();
+        let mut cur = input.serialize_variant();
+        let mut ret = Vec::new();
+        while let Some((next, data)) = enabled.get(&cur) {
+            let cur_ty = S::deserialize_variant(&cur).map_err(MemoryError::Backend)?;
+            let next_ty = S::deserialize_variant(next).map_err(MemoryError::Backend)?;
+            ret.push((cur_ty, next_ty, data.clone()));
+            cur.clone_from(next);
+        }
+        Ok(ret)
+    }
+
+    async fn disable_executor(&self, input: &S) -> Result<(), MemoryError> {
+        let res = query("DELETE FROM enabled_executors WHERE incoming=$1")
+            .bind(input.serialize_variant())
+            .execute(&self.pool)
+            .await
+            .map_err(db)?;
+        if res.rows_affected() == 0 {
+            Err(MemoryError::NotFound("executor".to_string()))?
+        }
+        Ok(())
+    }
+
+    async fn replace_executor_data(&self, input: &S, data: String) -> Result<(), MemoryError> {
+        let res = query("UPDATE enabled_executors SET additional_data=$1 WHERE incoming=$2")
+            .bind(data)
+            .bind(input.serialize_variant())
+            .execute(&self.pool)
+            .await
+            .map_err(db)?;
+        if res.rows_affected() == 0 {
+            Err(MemoryError::NotFound("executor".to_string()))?
+        }
+        Ok(())
+    }
+
+    async fn list_enabled_executors(&self) -> Result<Vec<(TypeId, TypeId, String)>, MemoryError> {
+        let enabled: Vec<Enabled> =
+            query_as::<sqlx::Sqlite, Enabled>("SELECT * FROM enabled_executors ORDER BY incoming")
+                .fetch_all(&self.pool)
+                .await
+                .map_err(db)?;
+        let mut ret = Vec::new();
+        for x in enabled {
+            let from = S::deserialize_variant(&x.incoming).map_err(MemoryError::Backend)?;
+            let into = S::deserialize_variant(&x.outgoing).map_err(MemoryError::Backend)?;
+            ret.push((from, into, x.additional_data));
+        }
+        Ok(ret)
+    }
+
+    async fn add_exercise(
+        &self,
+        name: String,
+        exercise_type: S,
+        source: String,
+    ) -> Result<(), MemoryError> {
+        query("INSERT INTO problems(name, ty, source) VALUES ($1, $2, $3) ON CONFLICT (name) DO UPDATE SET ty=EXCLUDED.ty, source=EXCLUDED.source")
+            .bind(name)
+            .bind(exercise_type.serialize_variant())
+            .bind(source)
+            .execute(&self.pool)
+            .await.map_err(db)?;
+        Ok(())
+    }
+
+    /// get an exercise from memory
+    /// type, source
+    async fn get_exercise(&self, name: String) -> Result<(TypeId, String), MemoryError> {
+        let data: Problem =
+            query_as::<sqlx::Sqlite, Problem>("SELECT * FROM problems WHERE name = $1")
+                .bind(&name)
+                .fetch_optional(&self.pool)
+                .await
+                .map_err(db)?
+                .ok_or_else(|| MemoryError::NotFound(format!("exercise {name}")))?;
+        let ty = S::deserialize_variant(&data.ty).map_err(MemoryError::Backend)?;
+
+        Ok((ty, data.source))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use chrono::Duration;
+    use orchestrator::{prelude::*, GenerateState};
+    use sqlx::Executor;
+
+    use super::{migrations::SQLITE, Sqlite};
+
+    GenerateState!(ExerciseResult, DummyExercise);
+
+    #[tokio::test]
+    /// the same checks of the Postgres test, on an in memory database
+    async fn test_memory() {
+        let m = Box::new(Sqlite::memory().await.unwrap());
+        let mut o: Orchestrator<State> = Orchestrator::new(16, true, m);
+
+        let def = DefaultTest::new_default();
+        o.add_plugin(def).await.unwrap();
+        let _ = o.run().await;
+    }
+
+    #[tokio::test]
+    async fn test_users() {
+        let m = Sqlite::memory().await.unwrap();
+        let user = m.register("ciao", "mondo").await.unwrap();
+        assert_ne!(user.password_hash, "mondo");
+        assert!(m.register("ciao", "altro").await.is_err());
+        assert!(m.login("ciao", "sbagliata").await.is_err());
+        let logged = m.login("ciao", "mondo").await.unwrap();
+        let token = logged.logged_in_token.clone().unwrap();
+        assert_eq!(
+            m.get_authenticate(&token).await.unwrap().user_id,
+            user.user_id
+        );
+        // new users are never admins
+        assert!(m.get_admin(&token).await.is_err());
+
+        let reset = m
+            .create_password_reset("ciao", Duration::minutes(5))
+            .await
+            .unwrap();
+        m.reset_password(&reset.token, "nuova").await.unwrap();
+        assert!(m.reset_password(&reset.token, "ancora").await.is_err());
+        assert!(m.get_authenticate(&token).await.is_err());
+        m.login("ciao", "nuova").await.unwrap();
+
+        let identity = ExternalIdentity {
+            issuer: "https://issuer".to_string(),
+            subject: "1234".to_string(),
+            email: Some("ciao@example.com".to_string()),
+            name: None,
+        };
+        let first = m.login_external(&identity).await.unwrap();
+        let second = m.login_external(&identity).await.unwrap();
+        assert_eq!(first.user_id, second.user_id);
+        assert_eq!(m.get_all_users().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_submissions() {
+        let m = Sqlite::memory().await.unwrap();
+        m.register("ciao", "mondo").await.unwrap();
+        let user = m.login("ciao", "mondo").await.unwrap();
+        let state = State::ExerciseResult(ExerciseResult::default());
+        StateMemory::<State>::add_exercise(&m, "somma".to_string(), state, String::new())
+            .await
+            .unwrap();
+        let result = |points| ExerciseResult {
+            tests: HashMap::from([(
+                "test".to_string(),
+                TestResult {
+                    points_given: points,
+                    ..Default::default()
+                },
+            )]),
+        };
+        let mut ids = Vec::new();
+        for points in [2.0, 5.0, 5.0] {
+            let id = m
+                .add_submission("somma".to_string(), "x".repeat(5000), user.clone())
+                .await
+                .unwrap();
+            m.add_exercise_result(id, user.clone(), result(points))
+                .await
+                .unwrap();
+            ids.push(id);
+        }
+        assert!(m
+            .add_submission("divisione".to_string(), String::new(), user.clone())
+            .await
+            .is_err());
+
+        let submission = m.get_submission(ids[1]).await.unwrap();
+        assert_eq!(submission.source.len(), 5000);
+        assert_eq!(submission.result, Some(result(5.0)));
+        let filter = SubmissionFilter {
+            offset: 1,
+            ..Default::default()
+        };
+        let page = m.list_submissions(&filter).await.unwrap();
+        assert_eq!(page.len(), 2);
+        let best = m.get_best_submission(user.user_id, "somma").await.unwrap();
+        assert_eq!(best.unwrap().submission_id, ids[2]);
+    }
+
+    #[tokio::test]
+    async fn test_executors_and_audit() {
+        let m = Sqlite::memory().await.unwrap();
+        let a = State::ExerciseResult(ExerciseResult::default());
+        let b = State::DummyExercise(DummyExercise {});
+        StateMemory::<State>::enable_executor(&m, &b, &a, String::new())
+            .await
+            .unwrap();
+        assert!(
+            StateMemory::<State>::enable_executor(&m, &a, &b, String::new())
+                .await
+                .is_err()
+        );
+        let plan = StateMemory::<State>::get_execution_plan(&m, &b)
+            .await
+            .unwrap();
+        assert_eq!(plan.len(), 1);
+
+        m.audit(Some("ciao"), AuditAction::LoginFailure, "")
+            .await
+            .unwrap();
+        m.audit(None, AuditAction::ExerciseAdded, "somma")
+            .await
+            .unwrap();
+        let admin: User<Admin> = m.register("admin", "mondo").await.unwrap().transmute();
+        let events = m
+            .get_audit_log(&admin, &AuditFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(events.len(), 2);
+        let filter = AuditFilter {
+            from: Some(events[1].time),
+            action: Some(AuditAction::ExerciseAdded),
+            ..Default::default()
+        };
+        let added = m.get_audit_log(&admin, &filter).await.unwrap();
+        assert_eq!(added.len(), 1);
+        assert_eq!(added[0].details, "somma");
+        let filter = AuditFilter {
+            to: Some(events[0].time),
+            ..Default::default()
+        };
+        assert!(m.get_audit_log(&admin, &filter).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_migrations() {
+        let m = Sqlite::memory().await.unwrap();
+        let status = m.migration_status().await.unwrap();
+        assert!(status.iter().all(|x| x.applied_at.is_some()));
+        // already up to date
+        assert!(m.migrate().await.unwrap().is_empty());
+
+        sqlx::query(
+            "INSERT INTO schema_version(version, name, applied_at) VALUES (1000, 'future', $1)",
+        )
+        .bind(chrono::Utc::now())
+        .execute(&m.pool)
+        .await
+        .unwrap();
+        assert!(matches!(
+            m.migrate().await,
+            Err(crate::Error::SchemaTooNew { found: 1000, .. })
+        ));
+        assert_eq!(
+            m.migration_status().await.unwrap().last().unwrap().name,
+            "future"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compilation_migrated() {
+        let m = Sqlite::connect("sqlite::memory:").await.unwrap();
+        sqlx::query(super::SCHEMA_VERSION)
+            .execute(&m.pool)
+            .await
+            .unwrap();
+        // the results saved before the diagnostics were in the compilation
+        for migration in SQLITE.iter().filter(|x| x.name != "compiler_diagnostics") {
+            (&m.pool).execute(migration.sql).await.unwrap();
+            sqlx::query(
+                "INSERT INTO schema_version(version, name, applied_at) VALUES ($1, $2, $3)",
+            )
+            .bind(migration.version)
+            .bind(migration.name)
+            .bind(chrono::Utc::now())
+            .execute(&m.pool)
+            .await
+            .unwrap();
+        }
+        for compiled in [r#""Built""#, r#"{"Error":"error[E0308]"}"#, r#""NotBuilt""#] {
+            sqlx::query("INSERT INTO test_results(name, compiled, runned, points) VALUES ('test', $1, '\"NotRun\"', 0)")
+                .bind(compiled)
+                .execute(&m.pool)
+                .await
+                .unwrap();
+        }
+        assert_eq!(m.migrate().await.unwrap().len(), 1);
+
+        let rows: Vec<(String,)> =
+            sqlx::query_as("SELECT compiled FROM test_results ORDER BY test_results_id")
+                .fetch_all(&m.pool)
+                .await
+                .unwrap();
+        let compiled: Vec<CompilationResult> = rows
+            .iter()
+            .map(|x| serde_json::from_str(&x.0).unwrap())
+            .collect();
+        assert_eq!(
+            compiled,
+            vec![
+                CompilationResult::Built(Vec::new()),
+                CompilationResult::Error("error[E0308]".to_string(), Vec::new()),
+                CompilationResult::NotBuilt,
+            ]
+        );
+    }
+}